@@ -0,0 +1,75 @@
+use super::packet::{RayPacket, PACKET};
+use super::simd::F64x4;
+use super::vec3::{Point3, Vec3};
+
+
+
+//Axis-aligned bounding box
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    //Box with a and b at opposite corners, in any order
+    pub fn new(a: Point3, b: Point3) -> Aabb {
+        Aabb {
+            min: Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            max: Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    //Smallest box around a set of points, None if there aren't any
+    pub fn around(points: impl IntoIterator<Item = Point3>) -> Option<Aabb> {
+        points.into_iter().map(|p| Aabb::new(p, p)).reduce(|a, b| a.surrounding(&b))
+    }
+
+    pub fn surrounding(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point3::new(self.min.x().min(other.min.x()), self.min.y().min(other.min.y()), self.min.z().min(other.min.z())),
+            max: Point3::new(self.max.x().max(other.max.x()), self.max.y().max(other.max.y()), self.max.z().max(other.max.z())),
+        }
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    //Slab test. inv_dir is 1 / ray direction per component, worked out once per ray.
+    //An axis the ray is parallel to gives NaN when the origin is on a face; f64::max
+    //and min drop NaNs, so that axis just doesn't narrow the interval.
+    pub fn hit(&self, origin: Point3, inv_dir: Vec3, t_min: f64, t_max: f64) -> bool {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let a = (self.min[axis] - origin[axis]) * inv_dir[axis];
+            let b = (self.max[axis] - origin[axis]) * inv_dir[axis];
+            t0 = t0.max(a.min(b));
+            t1 = t1.min(a.max(b));
+            if t1 < t0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    //Slab test for a whole packet, a ray to a lane, each with its own t_max: whether
+    //any of them hits. NaNs are dropped as hit drops them.
+    pub fn hit_packet(&self, packet: &RayPacket, t_min: f64, t_max: &[f64; PACKET]) -> bool {
+        let mut t0 = F64x4::splat(t_min);
+        let mut t1 = F64x4::load(t_max);
+        for axis in 0..3 {
+            let a = (F64x4::splat(self.min[axis]) - packet.origin[axis]) * packet.inv_direction[axis];
+            let b = (F64x4::splat(self.max[axis]) - packet.origin[axis]) * packet.inv_direction[axis];
+            t0 = t0.max_num(a.min_num(b));
+            t1 = t1.min_num(a.max_num(b));
+        }
+        t0.le(t1).any()
+    }
+}
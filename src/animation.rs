@@ -0,0 +1,147 @@
+use super::camera::{Camera, Projection};
+use super::vec3::{Point3, Vec3};
+
+
+
+//How a keyframe's values blend into the next keyframe's
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    //At a constant rate
+    Linear,
+    //Starting and stopping gently (smoothstep)
+    Ease,
+}
+
+impl Easing {
+    //Fraction of the way from one key to the next at fraction t of the time between them
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::Ease => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+//Where the camera is at one frame of an animation
+#[derive(Clone, Copy)]
+pub struct CameraKey {
+    pub frame: f64,
+    pub from: Point3,
+    pub at: Point3,
+    pub aperture: f64,
+    //Distance to the plane in focus, None for the distance from from to at
+    pub focus: Option<f64>,
+    //Towards the next key
+    pub easing: Easing,
+}
+
+//A camera moving between keyframes. Before the first key and after the last it stays
+//put; in between, position, target, aperture and focus are all interpolated.
+#[derive(Clone)]
+pub struct CameraPath {
+    keys: Vec<CameraKey>,
+    up: Vec3,
+    vfov: f64,
+    aspect_ratio: f64,
+    shutter: (f64, f64),
+    projection: Projection,
+}
+
+impl CameraPath {
+    //keys can be in any order, but there must be at least one
+    pub fn new(mut keys: Vec<CameraKey>, up: Vec3, vfov: f64, aspect_ratio: f64) -> CameraPath {
+        assert!(!keys.is_empty(), "a camera path needs at least one key");
+        keys.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+        CameraPath { keys, up, vfov, aspect_ratio, shutter: (0.0, 0.0), projection: Projection::Perspective }
+    }
+
+    //Shutter for every frame's camera, see Camera::with_shutter
+    pub fn with_shutter(mut self, open: f64, close: f64) -> CameraPath {
+        self.shutter = (open, close);
+        self
+    }
+
+    //Projection for every frame's camera, see Camera::with_projection
+    pub fn with_projection(mut self, projection: Projection) -> CameraPath {
+        self.projection = projection;
+        self
+    }
+
+    //The camera at frame, which needn't be a whole number
+    pub fn camera(&self, frame: f64) -> Camera {
+        let next = self.keys.partition_point(|k| k.frame <= frame);
+        let key = if next == 0 || next == self.keys.len() {
+            self.keys[next.saturating_sub(1)]
+        } else {
+            let (a, b) = (self.keys[next - 1], self.keys[next]);
+            let t = a.easing.apply((frame - a.frame) / (b.frame - a.frame));
+            let lerp = |x: f64, y: f64| x + t * (y - x);
+            CameraKey {
+                frame,
+                from: a.from + t * (b.from - a.from),
+                at: a.at + t * (b.at - a.at),
+                aperture: lerp(a.aperture, b.aperture),
+                focus: match (a.focus, b.focus) {
+                    (Some(fa), Some(fb)) => Some(lerp(fa, fb)),
+                    _ => None,
+                },
+                easing: a.easing,
+            }
+        };
+
+        let focus = key.focus.unwrap_or_else(|| (key.from - key.at).length());
+        Camera::new(key.from, key.at, self.up, self.vfov, self.aspect_ratio, key.aperture, focus)
+            .with_shutter(self.shutter.0, self.shutter.1)
+            .with_projection(self.projection)
+    }
+}
+
+//The camera going once round a pivot over a number of frames, always looking at it from
+//the same distance and height: the usual way to show off a model. It circles the
+//camera's vup, starting from whichever side of the pivot the camera is on.
+pub struct Turntable {
+    pivot: Point3,
+    radius: f64,
+    //Degrees above level
+    elevation: f64,
+    frames: u64,
+    //Level unit vectors from the pivot towards the camera at the start, and a quarter
+    //turn on from it
+    start: Vec3,
+    across: Vec3,
+}
+
+impl Turntable {
+    //Round pivot, at camera's distance and elevation from it unless told otherwise
+    pub fn new(camera: &Camera, pivot: Point3, frames: u64) -> Turntable {
+        let up = camera.vup().normalized();
+        let offset = camera.lookfrom() - pivot;
+        let level = offset - offset.dot(up) * up;
+        //Straight above or below, so any way round will do
+        let start = if level.length() > 1e-9 * offset.length() { level.normalized() } else { up.cross(camera.forward()).normalized() };
+        let radius = offset.length();
+        let elevation = if radius > 0.0 { (offset.dot(up) / radius).asin().to_degrees() } else { 0.0 };
+        Turntable { pivot, radius, elevation, frames: frames.max(1), start, across: up.cross(start) }
+    }
+
+    pub fn with_radius(mut self, radius: f64) -> Turntable {
+        self.radius = radius;
+        self
+    }
+
+    pub fn with_elevation(mut self, elevation: f64) -> Turntable {
+        self.elevation = elevation;
+        self
+    }
+
+    //camera moved to where it is at frame, looking at the pivot
+    pub fn camera(&self, camera: &Camera, frame: u64) -> Camera {
+        let angle = 2.0 * std::f64::consts::PI * frame as f64 / self.frames as f64;
+        //Kept off the poles, where looking at the pivot leaves no way to tell which way is up
+        let elevation = self.elevation.clamp(-89.9, 89.9).to_radians();
+        let around = angle.cos() * self.start + angle.sin() * self.across;
+        let up = camera.vup().normalized();
+        let from = self.pivot + self.radius * (elevation.cos() * around + elevation.sin() * up);
+        camera.looking(from, self.pivot)
+    }
+}
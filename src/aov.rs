@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+
+use super::cryptomatte::id_color;
+use super::hit::{Hit, HitRecord, OccludingHit};
+use super::image::Image;
+use super::ray::{self, Ray};
+use super::scene::Scene;
+use super::vec3::Color;
+
+
+
+//Auxiliary buffers (arbitrary output variables) describing what the camera sees first,
+//rendered alongside the image for denoisers and compositing. Each is antialiased the
+//same way as the image, by averaging jittered samples.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Aov {
+    //World-space normal of the first surface, facing the camera
+    Normal,
+    //Distance from the camera to the first surface, 0 for the background
+    Depth,
+    //Unlit colour of the first surface, the background's colour where nothing's hit
+    Albedo,
+    //Fraction of the point and area lights the first surface can see, 1 for the background
+    Visibility,
+    //Coverage: 1 where the camera sees something, 0 for the background, for compositing
+    Alpha,
+    //Colour standing for the ID of the first object (see cryptomatte), black for the
+    //background and objects without one
+    ObjectId,
+}
+
+impl Aov {
+    //Name used for the output files
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Albedo => "albedo",
+            Aov::Visibility => "visibility",
+            Aov::Alpha => "alpha",
+            Aov::ObjectId => "object-id",
+        }
+    }
+
+    //Value for one camera ray. Curved space is ignored: it's where the ray would hit
+    //going straight.
+    pub fn sample(self, r: &Ray, scene: &Scene) -> Color {
+        let limits = ray::limits();
+        let mut rec = match scene.world.hit(r, limits.epsilon, limits.max_distance) {
+            Some(rec) => rec,
+            None => return match self {
+                Aov::Normal | Aov::Depth | Aov::Alpha | Aov::ObjectId => Color::new(0.0, 0.0, 0.0),
+                Aov::Albedo => scene.background.color(r),
+                Aov::Visibility => Color::new(1.0, 1.0, 1.0),
+            },
+        };
+        Arc::clone(&rec.mat).perturb(r, &mut rec);
+        match self {
+            Aov::Normal => rec.normal,
+            Aov::Depth => rec.t * r.direction().length() * Color::new(1.0, 1.0, 1.0),
+            Aov::Albedo => rec.mat.albedo(&rec),
+            Aov::Visibility => visibility(&rec, scene, r.time()) * Color::new(1.0, 1.0, 1.0),
+            Aov::Alpha => Color::new(1.0, 1.0, 1.0),
+            Aov::ObjectId => id_color(rec.object_id),
+        }
+    }
+
+    //Squeeze a rendered buffer into [0, 1] for 8-bit formats, which are written without
+    //gamma (see output::save_linear) except for albedo, which is a colour like any other.
+    //Normals go to 0.5 * (n + 1) as in a normal map, depth to 1 at the camera fading to
+    //0 at the furthest surface, so the background is black.
+    pub fn encode(self, image: &Image) -> Vec<Color> {
+        match self {
+            Aov::Normal => image.pixels.iter().map(|&n| 0.5 * (n + Color::new(1.0, 1.0, 1.0))).collect(),
+            Aov::Depth => {
+                let far = image.pixels.iter().map(|c| c[0]).fold(0.0, f64::max);
+                image.pixels.iter().map(|&d| {
+                    if d[0] > 0.0 { (1.0 - d[0] / far) * Color::new(1.0, 1.0, 1.0) } else { d }
+                }).collect()
+            }
+            Aov::Albedo | Aov::Visibility | Aov::Alpha | Aov::ObjectId => image.pixels.clone(),
+        }
+    }
+
+    //Whether the 8-bit encoding should skip the display gamma
+    pub fn is_data(self) -> bool {
+        !matches!(self, Aov::Albedo)
+    }
+}
+
+fn visibility(rec: &HitRecord, scene: &Scene, time: f64) -> f64 {
+    //Only the lights linked to the surface
+    let linked: Vec<_> = scene.lights.iter().filter(|light| light.lights(rec)).collect();
+    if linked.is_empty() {
+        return 1.0;
+    }
+    let limits = ray::limits();
+    let visible = linked.iter().filter(|light| {
+        let lpos = light.sample_point();
+        let to_light = lpos - rec.p;
+        if !rec.mat.in_medium() && rec.normal.dot(to_light) < 0.0 {
+            return false;
+        }
+        let ray = Ray::new(rec.p, to_light.normalized()).with_time(time);
+        !scene.world.occluding_hit(&ray, lpos, limits.epsilon, limits.max_distance)
+    }).count();
+    visible as f64 / linked.len() as f64
+}
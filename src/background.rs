@@ -0,0 +1,257 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::io;
+use std::path::Path;
+
+use super::image::Image;
+use super::light::DirectionalLight;
+use super::ray::Ray;
+use super::vec3::{Color, Vec3};
+
+
+
+//What escaped rays see
+pub trait Background: Send + Sync {
+    fn color(&self, r: &Ray) -> Color;
+
+    //Colours straight down and straight up, for backgrounds that blend linearly between
+    //them with the height of the unit direction, so renderers that can't call color (the
+    //GPU one) can work them out
+    fn gradient(&self) -> Option<(Color, Color)> {
+        None
+    }
+}
+
+//White at the horizon to light blue overhead
+pub struct SkyGradient;
+
+impl Background for SkyGradient {
+    fn color(&self, r: &Ray) -> Color {
+        //Linearly blend white and blue depending on height of y coord after scaling ray
+        //direction to get a unit length (so -1.0 < y < 1.0)
+        //Will be a horizontal gradient too because look at y component after normalizing
+        let unit_direction = r.direction().normalized();
+        let t = 0.5 * (unit_direction.y() + 1.0);
+        (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+    }
+
+    fn gradient(&self) -> Option<(Color, Color)> {
+        Some((Color::new(1.0, 1.0, 1.0), Color::new(0.5, 0.7, 1.0)))
+    }
+}
+
+pub struct Solid(pub Color);
+
+impl Background for Solid {
+    fn color(&self, _r: &Ray) -> Color {
+        self.0
+    }
+
+    fn gradient(&self) -> Option<(Color, Color)> {
+        Some((self.0, self.0))
+    }
+}
+
+//Equirectangular (latitude-longitude) environment map, usually an HDR photo of a real
+//place, so escaped rays pick up its lighting. +y is straight up, the top row of the image.
+//With no rotation the middle of the image is in the -z direction.
+pub struct EnvironmentMap {
+    image: Image,
+    //Turn about the vertical, as a fraction of a full turn
+    rotation: f64,
+    intensity: f64,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Image) -> EnvironmentMap {
+        EnvironmentMap { image, rotation: 0.0, intensity: 1.0 }
+    }
+
+    //Any format Image::read knows; HDR or EXR to get real light levels
+    pub fn load(path: &Path) -> io::Result<EnvironmentMap> {
+        let image = Image::read(path)?;
+        if image.width == 0 || image.height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: empty image", path.display())));
+        }
+        Ok(EnvironmentMap::new(image))
+    }
+
+    //Spin the map about the vertical, anticlockwise seen from above
+    pub fn with_rotation(mut self, degrees: f64) -> EnvironmentMap {
+        self.rotation = degrees / 360.0;
+        self
+    }
+
+    //Scale every value, to make the map brighter or dimmer than it was captured
+    pub fn with_intensity(mut self, intensity: f64) -> EnvironmentMap {
+        self.intensity = intensity;
+        self
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let (w, h) = (self.image.width as i64, self.image.height as i64);
+        //Wraps round horizontally, stops at the poles
+        let x = x.rem_euclid(w);
+        let y = y.clamp(0, h - 1);
+        self.image.pixels[(y * w + x) as usize]
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn color(&self, r: &Ray) -> Color {
+        let d = r.direction().normalized();
+        let u = (0.5 + d.x().atan2(-d.z()) / (2.0 * PI) - self.rotation).rem_euclid(1.0);
+        let v = d.y().clamp(-1.0, 1.0).acos() / PI;
+
+        //Bilinear between the four nearest texel centres
+        let x = u * self.image.width as f64 - 0.5;
+        let y = v * self.image.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+        self.intensity * ((1.0 - fy) * top + fy * bottom)
+    }
+}
+
+//Clear daytime sky, from the analytic model of Preetham, Shirley and Smits (1999): the
+//Perez formulae, fitted to a physically based simulation, for its luminance and
+//chromaticity in terms of the angles to the zenith and the sun. Turbidity is how hazy
+//the air is, 2 for very clear to 10 or so for thin fog. Below the horizon the sky is as
+//it is at the horizon. As the sun sets past it the whole sky fades out, gone 6 degrees
+//down (the end of civil twilight).
+pub struct PreethamSky {
+    //Unit vector towards the sun
+    sun: Vec3,
+    turbidity: f64,
+    intensity: f64,
+    //Luminance and x and y chromaticity straight up, and the Perez coefficients A to E
+    //for each
+    zenith: [f64; 3],
+    perez: [[f64; 5]; 3],
+    //Fraction of the sky left with the sun this far down
+    twilight: f64,
+    sun_disc: bool,
+}
+
+//Scale from the model's luminance, in kcd/m^2, to image values: a clear midday sky
+//comes out about 0.3 straight up
+const SKY_SCALE: f64 = 0.04;
+//How bright the midday sun is, so a white surface square on to it comes out white. It
+//gives the ground a few times the light the rest of the sky does, as in real daylight.
+const SUN_STRENGTH: f64 = PI;
+//Angle the sun takes up, in degrees, and the solid angle that makes
+const SUN_ANGLE: f64 = 0.53;
+
+impl PreethamSky {
+    //Sky lit by a sun in direction sun (towards it), through air of this turbidity,
+    //clamped to 1.7 to 10 where the model holds
+    pub fn new(sun: Vec3, turbidity: f64) -> PreethamSky {
+        let sun = sun.normalized();
+        let t = turbidity.clamp(1.7, 10.0);
+        let elevation = sun.y().clamp(-1.0, 1.0).asin();
+        let twilight = (1.0 + elevation.to_degrees() / 6.0).clamp(0.0, 1.0);
+        //Zenith angle of the sun, no lower than the horizon for the fits
+        let theta = FRAC_PI_2 - elevation.max(0.0);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [f64; 4]| ((c[0] * theta + c[1]) * theta + c[2]) * theta + c[3];
+        let chromaticity = |t2: [f64; 4], t1: [f64; 4], t0: [f64; 4]| t * t * cubic(t2) + t * cubic(t1) + cubic(t0);
+        let x = chromaticity([0.00166, -0.00375, 0.00209, 0.0], [-0.02903, 0.06377, -0.03202, 0.00394], [0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = chromaticity([0.00275, -0.00610, 0.00317, 0.0], [-0.04214, 0.08970, -0.04153, 0.00516], [0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+        let mut sky = PreethamSky { sun, turbidity: t, intensity: 1.0, zenith: [luminance, x, y], perez, twilight, sun_disc: true };
+        //Stored already divided by the Perez function at the zenith, which is the same
+        //for every direction
+        for (zenith, coefficients) in sky.zenith.iter_mut().zip(&perez) {
+            *zenith /= perez_function(coefficients, 0.0, theta);
+        }
+        sky
+    }
+
+    //Scale every value, to make the sky brighter or dimmer
+    pub fn with_intensity(mut self, intensity: f64) -> PreethamSky {
+        self.intensity = intensity;
+        self
+    }
+
+    //Leave the sun itself out of the sky, for when a light stands in for it (see
+    //sun_light). Otherwise escaped rays that find it light the scene, far more noisily.
+    pub fn without_sun_disc(mut self) -> PreethamSky {
+        self.sun_disc = false;
+        self
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun
+    }
+
+    //Colour of the sunlight reaching the ground, reddened by the air it comes through:
+    //Rayleigh scattering and haze as in the paper's appendix, for red, green and blue
+    //taken as 680, 550 and 440nm. About white for a clear midday sun.
+    pub fn sun_color(&self) -> Color {
+        let elevation = self.sun.y().clamp(-1.0, 1.0).asin().to_degrees();
+        if elevation <= -SUN_ANGLE / 2.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        //Relative air mass (Kasten and Young 1989)
+        let zenith = 90.0 - elevation.max(0.0);
+        let mass = 1.0 / (zenith.to_radians().cos() + 0.50572 * (96.07995 - zenith).powf(-1.6364));
+        let beta = 0.04608365822050 * self.turbidity - 0.04586025928522;
+        let through = |micrometres: f64| {
+            let rayleigh = 0.008735 * micrometres.powf(-4.08);
+            let haze = beta * micrometres.powf(-1.3);
+            (-(rayleigh + haze) * mass).exp()
+        };
+        //Relative to clear air straight overhead
+        let clear = |micrometres: f64| through(micrometres) / (-(0.008735 * micrometres.powf(-4.08) + 0.0460 * micrometres.powf(-1.3))).exp();
+        self.twilight * Color::new(clear(0.68), clear(0.55), clear(0.44))
+    }
+
+    //Directional light for the sun, as bright and as coloured as sun_color has it, times
+    //strength
+    pub fn sun_light(&self, strength: f64) -> DirectionalLight {
+        let color = strength * SUN_STRENGTH * self.intensity * self.sun_color();
+        DirectionalLight::new(color, color, self.sun).with_angle(SUN_ANGLE)
+    }
+}
+
+//Perez et al's luminance distribution: how a sky quantity varies with the zenith angle
+//theta of a direction and its angle gamma from the sun
+fn perez_function(c: &[f64; 5], theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / theta.cos().max(0.01)).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+}
+
+impl Background for PreethamSky {
+    fn color(&self, r: &Ray) -> Color {
+        let d = r.direction().normalized();
+        let theta = d.y().clamp(0.0, 1.0).acos();
+        let gamma = d.dot(self.sun).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| self.zenith[i] * perez_function(&self.perez[i], theta, gamma));
+        if y <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        //xyY to XYZ to linear sRGB
+        let (big_x, big_y, big_z) = (x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        let rgb = Color::new(
+            (3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z).max(0.0),
+            (-0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z).max(0.0),
+            (0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z).max(0.0),
+        );
+        let mut color = SKY_SCALE * self.twilight * self.intensity * rgb;
+
+        //The sun, bright enough that what it shines on gets what sun_light(1) would give
+        if self.sun_disc && d.y() >= 0.0 && gamma < (SUN_ANGLE / 2.0).to_radians() {
+            let solid_angle = 2.0 * PI * (1.0 - (SUN_ANGLE / 2.0).to_radians().cos());
+            color += SUN_STRENGTH * self.intensity / solid_angle * self.sun_color();
+        }
+        color
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use serde::Deserialize;
+
+
+
+//Batch manifests, for rendering many scenes (or one scene from many cameras) in one go:
+//
+//    args = ["--width", "640", "-s", "200"]   #optional, for every job
+//
+//    [[jobs]]
+//    scene_file = "scenes/textures.toml"
+//    output = "renders/textures.png"
+//
+//    [[jobs]]
+//    scene = "cornell"                        #a built-in scene instead
+//    output = "renders/cornell.exr"
+//    args = ["--integrator", "path"]          #optional, after the shared ones
+//
+//    [[jobs]]
+//    scene_file = "scenes/room.toml"
+//    cameras = ["front", "overhead"]          #one render from each [cameras.NAME]
+//    output = "renders/room.png"              #written as room_front.png, room_overhead.png
+//
+//Paths are relative to the manifest. Each render is its own run of this program, with
+//the manifest's args, then the job's, then the scene, camera and output.
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    args: Vec<String>,
+    jobs: Vec<Job>,
+}
+
+#[derive(Deserialize)]
+struct Job {
+    scene_file: Option<PathBuf>,
+    scene: Option<String>,
+    #[serde(default)]
+    cameras: Vec<String>,
+    output: PathBuf,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+//One run of the renderer
+struct Render {
+    args: Vec<String>,
+    output: PathBuf,
+}
+
+//Render everything path lists, parallel renders at a time sharing the machine's cores
+//between them, and report how each went. False if any failed.
+pub fn run(path: &Path, parallel: usize) -> Result<bool, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let manifest: Manifest = toml::from_str(&text).map_err(|e| e.to_string())?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let renders = renders(manifest, base)?;
+
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let parallel = parallel.clamp(1, renders.len().max(1));
+    //Only for jobs that don't ask for their own, as it can't be given twice
+    let threads = thread::available_parallelism().map_or(1, |n| n.get()).div_ceil(parallel).max(1);
+    let share = |render: &Render| {
+        let own = render.args.iter().any(|a| a == "--threads" || a.starts_with("--threads="));
+        if parallel > 1 && !own { vec!["--threads".to_string(), threads.to_string()] } else { Vec::new() }
+    };
+
+    let next = AtomicUsize::new(0);
+    let failed = Mutex::new(0);
+    let total = renders.len();
+    thread::scope(|s| {
+        for _ in 0..parallel {
+            s.spawn(|| loop {
+                let k = next.fetch_add(1, Ordering::Relaxed);
+                let Some(render) = renders.get(k) else { break };
+                if let Some(dir) = render.output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    let _ = fs::create_dir_all(dir);
+                }
+                let started = Instant::now();
+                let result = Command::new(&exe).args(share(render)).args(&render.args).output();
+                let seconds = started.elapsed().as_secs_f64();
+                match result {
+                    Ok(out) if out.status.success() => {
+                        eprintln!("[{}/{}] wrote {} ({:.1}s)", k + 1, total, render.output.display(), seconds);
+                    }
+                    Ok(out) => {
+                        //All of it, as clap's last line only says to try --help
+                        let log = String::from_utf8_lossy(&out.stderr);
+                        let log: Vec<String> = log.trim_end().lines().map(|line| format!("    {}", line).trim_end().to_string()).collect();
+                        let log = if log.is_empty() { "    no output".to_string() } else { log.join("\n") };
+                        eprintln!("[{}/{}] {} failed:\n{}", k + 1, total, render.output.display(), log);
+                        *failed.lock().unwrap() += 1;
+                    }
+                    Err(e) => {
+                        eprintln!("[{}/{}] {} failed: couldn't start the render: {}", k + 1, total, render.output.display(), e);
+                        *failed.lock().unwrap() += 1;
+                    }
+                }
+            });
+        }
+    });
+
+    let failed = failed.into_inner().unwrap();
+    if failed > 0 {
+        eprintln!("{} of {} renders failed", failed, total);
+    }
+    Ok(failed == 0)
+}
+
+//The renders the manifest's jobs come to, with paths made relative to base
+fn renders(manifest: Manifest, base: &Path) -> Result<Vec<Render>, String> {
+    let mut renders = Vec::new();
+    for (k, job) in manifest.jobs.into_iter().enumerate() {
+        let mut args = manifest.args.clone();
+        args.extend(job.args);
+        match (job.scene_file, job.scene) {
+            (Some(file), None) => args.extend(["--scene-file".to_string(), base.join(file).display().to_string()]),
+            (None, Some(name)) => args.extend(["--scene".to_string(), name]),
+            _ => return Err(format!("job {} needs exactly one of scene_file and scene", k + 1)),
+        }
+        let output = base.join(job.output);
+        if job.cameras.is_empty() {
+            renders.push(Render { args: output_args(args, &output), output });
+            continue;
+        }
+        for camera in job.cameras {
+            let output = per_camera(&output, &camera);
+            let args = [args.clone(), vec!["--camera".to_string(), camera]].concat();
+            renders.push(Render { args: output_args(args, &output), output });
+        }
+    }
+    Ok(renders)
+}
+
+fn output_args(mut args: Vec<String>, output: &Path) -> Vec<String> {
+    args.extend(["--output".to_string(), output.display().to_string()]);
+    args
+}
+
+//output with _camera on the end of its name, before the extension
+fn per_camera(output: &Path, camera: &str) -> PathBuf {
+    let stem = output.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    let name = match output.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, camera, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, camera),
+    };
+    output.with_file_name(name)
+}
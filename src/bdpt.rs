@@ -0,0 +1,263 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::hit::{Hit, HitRecord};
+use super::light::Light;
+use super::random::random_f64;
+use super::ray::{self, Ray};
+use super::scene::Scene;
+use super::stats::{self, Counter};
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+//Bidirectional path tracing (Veach 1997): as well as the path from the camera, each
+//sample follows a path out from one of the point and area lights, and joins every
+//vertex of one to every vertex of the other with a shadow ray. Each way of making a
+//path is weighted against all the others that could have made it (the balance
+//heuristic), so none is counted twice. Light shut away where eye paths rarely reach,
+//like a bulb inside a lamp shade that only lights the room off the shade and ceiling,
+//gets found from the light's side.
+//
+//Emitters and the background aren't sampled from their side: eye paths pick them up
+//when they hit them, as in plain path tracing. Joins to the camera itself (light
+//tracing) aren't made, as they'd land in other pixels.
+struct Vertex {
+    p: Point3,
+    //None at the light and the camera, which have no surface
+    rec: Option<HitRecord>,
+    //None there and inside media, where light comes from any direction
+    normal: Option<Vec3>,
+    //What the path carries up to here, over the chance of having made it
+    beta: Color,
+    //Density per unit area of making this vertex from the one before it along its own
+    //path, and from the one after it, as if the path had been made the other way
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    //Whether it scatters only in set directions (mirrors, glass), so can't be joined to
+    delta: bool,
+}
+
+impl Vertex {
+    fn end(p: Point3, beta: Color) -> Vertex {
+        Vertex { p, rec: None, normal: None, beta, pdf_fwd: 0.0, pdf_rev: 0.0, delta: false }
+    }
+}
+
+//Bounces after which paths start being cut short at random, and the chance of going on
+const ROULETTE_AFTER: usize = 3;
+const SURVIVAL: f64 = 0.8;
+
+//Radiance back along the camera ray r, which first hits hit, from paths of up to depth
+//bounces, along with how far the eye path went before it ended
+pub fn radiance(r: &Ray, hit: Option<HitRecord>, scene: &Scene, depth: u64) -> (Color, f64) {
+    let max_vertices = depth as usize + 1;
+    let mut eye = vec![Vertex::end(r.origin(), Color::new(1.0, 1.0, 1.0))];
+    let (mut total, length) = walk(scene, None, *r, hit, Color::new(1.0, 1.0, 1.0), 0.0, max_vertices, &mut eye);
+    if scene.lights.is_empty() {
+        return (total, length);
+    }
+
+    //One light per sample, the light path's first vertex, sending light out evenly
+    let count = scene.lights.len();
+    let light = scene.lights[((random_f64() * count as f64) as usize).min(count - 1)].as_ref();
+    let start = Vertex::end(light.sample_point(), count as f64 * light.diffuse());
+    let out = Ray::new(start.p, Vec3::random_in_unit_sphere().normalized()).with_time(r.time());
+    let beta = 4.0 * PI * start.beta;
+    let mut lit = vec![start];
+    let limits = ray::limits();
+    let hit = scene.world.hit(&out, limits.epsilon, limits.max_distance);
+    walk(scene, Some(light), out, hit, beta, 1.0 / (4.0 * PI), max_vertices, &mut lit);
+
+    for t in 2..=eye.len() {
+        for s in 1..=lit.len().min(depth as usize + 2 - t) {
+            total += join(scene, light, &lit, s, &eye, t, r.time());
+        }
+    }
+    (total, length)
+}
+
+//Follow ray (whose first hit is hit) from the last vertex of path, adding a vertex at each
+//surface it scatters off until it has max_vertices, leaves the scene or is absorbed.
+//beta is what it carries and pdf the density it was sent out with. From the light, the
+//first bounce also takes in the light's falloff; from the eye, the emitters and
+//background it finds are added up and handed back with the length of the path.
+#[allow(clippy::too_many_arguments)]
+fn walk(scene: &Scene, light: Option<&dyn Light>, mut ray: Ray, mut hit: Option<HitRecord>, mut beta: Color, mut pdf: f64,
+        max_vertices: usize, path: &mut Vec<Vertex>) -> (Color, f64) {
+    let limits = ray::limits();
+    let mut found = Color::new(0.0, 0.0, 0.0);
+    let mut length = 0.0;
+    let mut travelled = 0.0;
+    while path.len() < max_vertices {
+        let Some(mut rec) = hit.take() else {
+            if light.is_none() {
+                found += beta * scene.background.color(&ray);
+            }
+            break;
+        };
+        Arc::clone(&rec.mat).perturb(&ray, &mut rec);
+        let segment = (rec.p - ray.origin()).length();
+        length += segment;
+        travelled += segment;
+        beta *= ray.interiors().transmittance(segment);
+
+        //Boundaries inside something of higher priority aren't there, as in shading
+        let interior = rec.mat.interior();
+        if let Some(interior) = interior.filter(|i| ray.interiors().without(i.id).outranks(i.priority)) {
+            ray = Ray::new(rec.p, ray.direction()).with_time(ray.time())
+                .with_interiors(ray.interiors().crossing(interior, rec.front_face))
+                .with_channel(ray.channel());
+            stats::count(Counter::Rays);
+            hit = scene.world.hit(&ray, limits.epsilon, limits.max_distance);
+            continue;
+        }
+
+        match light {
+            Some(light) if path.len() == 1 => {
+                if !light.lights(&rec) {
+                    break;
+                }
+                beta *= light.attenuation(travelled) * travelled * travelled;
+            }
+            Some(_) => {}
+            None => found += beta * rec.mat.emitted(&rec),
+        }
+        travelled = 0.0;
+
+        let wo = (-1.0) * ray.direction().normalized();
+        let delta = rec.mat.eval(&rec, wo, rec.normal).is_none();
+        let normal = if rec.mat.in_medium() { None } else { Some(rec.normal) };
+        let prev = path.len() - 1;
+        let mut vertex = Vertex { p: rec.p, rec: None, normal, beta, pdf_fwd: 0.0, pdf_rev: 0.0, delta };
+        vertex.pdf_fwd = to_area(pdf, path[prev].p, &vertex);
+        let Some(record) = rec.mat.scatter_record(ray.origin(), &scene.lights, &scene.world, &ray, &rec) else {
+            vertex.rec = Some(rec);
+            path.push(vertex);
+            break;
+        };
+
+        //How likely the vertex before would have been, sent out from this one
+        let next = record.ray.direction().normalized();
+        if !delta {
+            let back = rec.mat.eval(&rec, next, wo).map_or(0.0, |(_, pdf)| pdf);
+            path[prev].pdf_rev = to_area(back, rec.p, &path[prev]);
+        }
+        pdf = if delta { 0.0 } else { record.pdf.unwrap_or(0.0) };
+
+        beta *= record.attenuation;
+        //Narrowed to one channel, which it goes on with alone
+        if let (None, Some(c)) = (ray.channel(), record.ray.channel()) {
+            let kept = 3.0 * beta[c];
+            beta = Color::new(0.0, 0.0, 0.0);
+            beta[c] = kept;
+        }
+        let interiors = match interior {
+            Some(interior) if record.ray.direction().dot(rec.normal) < 0.0 => ray.interiors().crossing(interior, rec.front_face),
+            _ => ray.interiors(),
+        };
+        ray = record.ray.with_interiors(interiors).with_channel(record.ray.channel().or(ray.channel()));
+        vertex.rec = Some(rec);
+        path.push(vertex);
+
+        if path.len() > ROULETTE_AFTER {
+            if random_f64() >= SURVIVAL {
+                break;
+            }
+            beta /= SURVIVAL;
+        }
+        stats::count(Counter::Bounces);
+        stats::count(Counter::Rays);
+        hit = scene.world.hit(&ray, limits.epsilon, limits.max_distance);
+    }
+    (found, length)
+}
+
+//Light along the path made of the first s vertices of the light path and the first t
+//of the eye path, joined with a shadow ray, weighted against the other ways of making it
+fn join(scene: &Scene, light: &dyn Light, lit: &[Vertex], s: usize, eye: &[Vertex], t: usize, time: f64) -> Color {
+    let none = Color::new(0.0, 0.0, 0.0);
+    let (q, p) = (&lit[s - 1], &eye[t - 1]);
+    let (Some(p_rec), false) = (&p.rec, p.delta || q.delta) else { return none };
+    let to_q = q.p - p.p;
+    let distance = to_q.length();
+    if distance <= 0.0 {
+        return none;
+    }
+    let dir = to_q / distance;
+    let wo_p = (eye[t - 2].p - p.p).normalized();
+    let Some((f_p, pdf_p)) = p_rec.mat.eval(p_rec, wo_p, dir) else { return none };
+
+    //The light itself, falling off as it does for direct light, or a surface lit from
+    //the vertex before it
+    let (contribution, pdf_q) = match &q.rec {
+        None => {
+            if !light.lights(p_rec) {
+                return none;
+            }
+            (light.attenuation(distance) * q.beta * f_p * p.beta, 1.0 / (4.0 * PI))
+        }
+        Some(q_rec) => {
+            let wo_q = (lit[s - 2].p - q.p).normalized();
+            let Some((f_q, pdf_q)) = q_rec.mat.eval(q_rec, wo_q, (-1.0) * dir) else { return none };
+            (q.beta * f_q * f_p * p.beta / (distance * distance), pdf_q)
+        }
+    };
+    if contribution.near_zero() {
+        return none;
+    }
+
+    let limits = ray::limits();
+    stats::count(Counter::ShadowRays);
+    if scene.world.hit(&Ray::new(p.p, dir).with_time(time), limits.epsilon, distance - limits.epsilon).is_some() {
+        return none;
+    }
+
+    //Densities the join changes: of each end made from the other, and of the vertex
+    //before each made from its end
+    let p_rev = to_area(pdf_q, q.p, p);
+    let q_rev = to_area(pdf_p, p.p, q);
+    let before_p = p_rec.mat.eval(p_rec, dir, wo_p).map_or(0.0, |(_, pdf)| to_area(pdf, p.p, &eye[t - 2]));
+    let before_q = match (&q.rec, s) {
+        (Some(q_rec), 3..) => {
+            let wo_q = (lit[s - 2].p - q.p).normalized();
+            q_rec.mat.eval(q_rec, (-1.0) * dir, wo_q).map_or(0.0, |(_, pdf)| to_area(pdf, q.p, &lit[s - 2]))
+        }
+        _ => 0.0,
+    };
+    let eye_rev = |i: usize| if i == t - 1 { p_rev } else if i + 2 == t { before_p } else { eye[i].pdf_rev };
+    let lit_rev = |i: usize| if i == s - 1 { q_rev } else if i + 2 == s { before_q } else { lit[i].pdf_rev };
+
+    //Balance heuristic, as the ratios of each other way's density to this one's. Eye
+    //paths can't be cut back to the camera alone (no light tracing) and light paths
+    //can't be cut back to nothing (lights can't be hit).
+    let remap = |pdf: f64| if pdf == 0.0 { 1.0 } else { pdf };
+    let mut others = 0.0;
+    let mut ratio = 1.0;
+    for i in (2..t).rev() {
+        ratio *= remap(eye_rev(i)) / remap(eye[i].pdf_fwd);
+        if !eye[i].delta && !eye[i - 1].delta {
+            others += ratio;
+        }
+    }
+    ratio = 1.0;
+    for i in (1..s).rev() {
+        ratio *= remap(lit_rev(i)) / remap(lit[i].pdf_fwd);
+        if !lit[i].delta && !lit[i - 1].delta {
+            others += ratio;
+        }
+    }
+    contribution / (1.0 + others)
+}
+
+//Density per unit area at vertex to of a direction picked at from with density pdf per
+//unit solid angle
+fn to_area(pdf: f64, from: Point3, to: &Vertex) -> f64 {
+    let d = to.p - from;
+    let distance2 = d.dot(d);
+    if distance2 <= 0.0 {
+        return 0.0;
+    }
+    let cosine = to.normal.map_or(1.0, |n| n.dot(d).abs() / distance2.sqrt());
+    pdf * cosine / distance2
+}
@@ -0,0 +1,100 @@
+use std::sync::OnceLock;
+
+use super::random::hash;
+
+
+
+//Blue-noise threshold mask made with void-and-cluster (Ulichney 1993): every value in
+//[0, 1) appears once in the tile, and the pixels under any threshold are spread out
+//evenly with no clumps, so neighbouring pixels get values far apart. The tile wraps
+//round, so it can be repeated across an image of any size.
+
+//Pixels along each side of the tile
+pub const SIZE: usize = 64;
+
+//How far each pixel's energy reaches, in pixels
+const SIGMA: f64 = 1.9;
+
+//The mask at (x, y), repeating every SIZE pixels each way
+pub fn value(x: u64, y: u64) -> f64 {
+    let mask = MASK.get_or_init(generate);
+    mask[(y % SIZE as u64) as usize * SIZE + (x % SIZE as u64) as usize]
+}
+
+static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+
+//Ranks every pixel of the tile by the order it's switched on in, so that each prefix is
+//as evenly spread as can be. Done once, the first time the mask is wanted.
+fn generate() -> Vec<f64> {
+    let n = SIZE * SIZE;
+
+    //Energy a pixel on at the origin lends each pixel of the tile, wrapping round
+    let mut kernel = vec![0.0; n];
+    for (i, k) in kernel.iter_mut().enumerate() {
+        let wrap = |d: usize| d.min(SIZE - d) as f64;
+        let (dx, dy) = (wrap(i % SIZE), wrap(i / SIZE));
+        *k = (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp();
+    }
+
+    //Start from a tenth of the pixels on at random, then move the most crowded one to
+    //the emptiest spot until that would put it back where it was (which it soon does,
+    //but n moves is plenty)
+    let mut on = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let mut seeded = 0;
+    for i in 0..n as u64 {
+        if hash(i).is_multiple_of(10) {
+            toggle(&mut on, &mut energy, &kernel, i as usize);
+            seeded += 1;
+        }
+    }
+    for _ in 0..n {
+        let cluster = tightest_cluster(&on, &energy);
+        toggle(&mut on, &mut energy, &kernel, cluster);
+        let void = largest_void(&on, &energy);
+        toggle(&mut on, &mut energy, &kernel, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+    //Take the starting pixels away most crowded first, so they rank below the rest...
+    let (mut less_on, mut less_energy) = (on.clone(), energy.clone());
+    for r in (0..seeded).rev() {
+        let cluster = tightest_cluster(&less_on, &less_energy);
+        toggle(&mut less_on, &mut less_energy, &kernel, cluster);
+        rank[cluster] = r;
+    }
+    //...then fill the emptiest spot left until there are none. Past half full this is
+    //also the densest cluster of pixels still off, which is what Ulichney picks there.
+    for r in seeded..n {
+        let void = largest_void(&on, &energy);
+        toggle(&mut on, &mut energy, &kernel, void);
+        rank[void] = r;
+    }
+
+    rank.into_iter().map(|r| (r as f64 + 0.5) / n as f64).collect()
+}
+
+//Switch pixel i on or off, keeping every pixel's energy up to date
+fn toggle(on: &mut [bool], energy: &mut [f64], kernel: &[f64], i: usize) {
+    let sign = if on[i] { -1.0 } else { 1.0 };
+    on[i] = !on[i];
+    let (x, y) = (i % SIZE, i / SIZE);
+    for (j, e) in energy.iter_mut().enumerate() {
+        let dx = (j % SIZE + SIZE - x) % SIZE;
+        let dy = (j / SIZE + SIZE - y) % SIZE;
+        *e += sign * kernel[dy * SIZE + dx];
+    }
+}
+
+//The pixel that's on with the most energy
+fn tightest_cluster(on: &[bool], energy: &[f64]) -> usize {
+    (0..on.len()).filter(|&i| on[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap_or(0)
+}
+
+//The pixel that's off with the least energy
+fn largest_void(on: &[bool], energy: &[f64]) -> usize {
+    (0..on.len()).filter(|&i| !on[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap_or(0)
+}
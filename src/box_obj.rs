@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord, World};
+use super::material::Scatter;
+use super::ray::Ray;
+use super::rect::{XyRect, XzRect, YzRect};
+use super::vec3::Point3;
+
+
+
+//Axis-aligned box between two opposite corners, made of six rects facing outwards
+pub struct BoxObj {
+    bbox: Aabb,
+    sides: World,
+}
+
+impl BoxObj {
+    pub fn new(a: Point3, b: Point3, mat: Arc<dyn Scatter>) -> BoxObj {
+        let bbox = Aabb::new(a, b);
+        let (p0, p1) = (bbox.min, bbox.max);
+
+        let sides: World = vec![
+            Box::new(XyRect::new(p0.x(), p1.x(), p0.y(), p1.y(), p1.z(), mat.clone())),
+            Box::new(XyRect::new(p0.x(), p1.x(), p0.y(), p1.y(), p0.z(), mat.clone()).flipped()),
+            Box::new(XzRect::new(p0.x(), p1.x(), p0.z(), p1.z(), p1.y(), mat.clone())),
+            Box::new(XzRect::new(p0.x(), p1.x(), p0.z(), p1.z(), p0.y(), mat.clone()).flipped()),
+            Box::new(YzRect::new(p0.y(), p1.y(), p0.z(), p1.z(), p1.x(), mat.clone())),
+            Box::new(YzRect::new(p0.y(), p1.y(), p0.z(), p1.z(), p0.x(), mat).flipped()),
+        ];
+
+        BoxObj { bbox, sides }
+    }
+}
+
+impl Hit for BoxObj {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.sides.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
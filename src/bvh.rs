@@ -0,0 +1,317 @@
+use std::ops::Range;
+
+use super::aabb::Aabb;
+use super::hit::{record_closest, Hit, HitRecord, World};
+use super::packet::{RayPacket, PACKET};
+use super::ray::Ray;
+use super::sphere_batch::{SphereBatch, LANES};
+use super::stats::{self, Counter};
+use super::vec3::Vec3;
+
+
+
+//Candidate split positions tried per axis when building
+const BINS: usize = 12;
+//Cost of visiting a node relative to intersecting one primitive
+const TRAVERSAL_COST: f64 = 1.0;
+//Never split below this many primitives
+const MAX_LEAF_SIZE: usize = 2;
+
+//Flattened bounding volume hierarchy over a list of boxes. It only deals in indices
+//into that list, so the same tree works for whole objects (BvhNode) and for the
+//triangles inside a mesh.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    //Primitive indices, ordered so each leaf covers a contiguous run
+    indices: Vec<usize>,
+}
+
+struct Node {
+    bbox: Aabb,
+    //Leaf: first index into indices, and count > 0.
+    //Branch: count == 0; the left child is the next node and start is the right child.
+    start: usize,
+    count: usize,
+}
+
+impl Bvh {
+    //Splits are chosen with the surface area heuristic: the chance a ray that hits a
+    //node also hits a child is about the ratio of their surface areas, so pick the
+    //split minimizing area(left) * count(left) + area(right) * count(right), and
+    //don't split at all if that's no cheaper than testing everything in the leaf.
+    pub fn new(boxes: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh { nodes: Vec::new(), indices: (0..boxes.len()).collect() };
+        if !boxes.is_empty() {
+            bvh.build(boxes, 0, boxes.len());
+        }
+        bvh
+    }
+
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.bbox)
+    }
+
+    //Each node's box, start and count (see Node), root first, for laying the tree out
+    //somewhere else (the GPU renderer's buffers)
+    pub fn nodes(&self) -> impl ExactSizeIterator<Item = (Aabb, usize, usize)> + '_ {
+        self.nodes.iter().map(|node| (node.bbox, node.start, node.count))
+    }
+
+    //Primitive indices, in the order the leaves cover them
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    //Add a node for indices[start..end], returning its position in nodes
+    fn build(&mut self, boxes: &[Aabb], start: usize, end: usize) -> usize {
+        let items = &self.indices[start..end];
+        let bbox = items.iter().map(|&i| boxes[i]).reduce(|a, b| a.surrounding(&b)).unwrap();
+        let node = self.nodes.len();
+        self.nodes.push(Node { bbox, start, count: end - start });
+
+        let count = end - start;
+        if count <= MAX_LEAF_SIZE {
+            return node;
+        }
+
+        let Some((axis, split)) = self.best_split(boxes, start, end, &bbox) else {
+            return node;
+        };
+
+        //Partition around the split plane
+        let items = &mut self.indices[start..end];
+        items.sort_by(|&a, &b| boxes[a].centroid()[axis].total_cmp(&boxes[b].centroid()[axis]));
+        let mid = start + items.partition_point(|&i| boxes[i].centroid()[axis] < split);
+        if mid == start || mid == end {
+            return node;
+        }
+
+        self.nodes[node].count = 0;
+        self.build(boxes, start, mid);
+        let right = self.build(boxes, mid, end);
+        self.nodes[node].start = right;
+        node
+    }
+
+    //Binned SAH over the centroids on each axis. Returns the axis and position of
+    //the cheapest split, if it beats leaving the node as a leaf.
+    fn best_split(&self, boxes: &[Aabb], start: usize, end: usize, bbox: &Aabb) -> Option<(usize, f64)> {
+        let items = &self.indices[start..end];
+        let centroids = Aabb::around(items.iter().map(|&i| boxes[i].centroid()))?;
+        let leaf_cost = items.len() as f64;
+        let area = bbox.surface_area().max(f64::MIN_POSITIVE);
+
+        let mut best: Option<(f64, usize, f64)> = None;
+        for axis in 0..3 {
+            let lo = centroids.min[axis];
+            let extent = centroids.max[axis] - lo;
+            if extent <= 0.0 {
+                continue;
+            }
+
+            let mut bins: [(Option<Aabb>, usize); BINS] = [(None, 0); BINS];
+            for &i in items {
+                let b = (((boxes[i].centroid()[axis] - lo) / extent * BINS as f64) as usize).min(BINS - 1);
+                bins[b].0 = Some(bins[b].0.map_or(boxes[i], |bb| bb.surrounding(&boxes[i])));
+                bins[b].1 += 1;
+            }
+
+            //Sweep from the right to get the cost of everything above each boundary
+            let mut right_cost = [0.0; BINS];
+            let mut acc: Option<Aabb> = None;
+            let mut n = 0;
+            for b in (1..BINS).rev() {
+                acc = merge(acc, bins[b].0);
+                n += bins[b].1;
+                right_cost[b] = acc.map_or(0.0, |a| a.surface_area()) * n as f64;
+            }
+
+            let mut acc: Option<Aabb> = None;
+            let mut n = 0;
+            for b in 0..BINS - 1 {
+                acc = merge(acc, bins[b].0);
+                n += bins[b].1;
+                let left_cost = acc.map_or(0.0, |a| a.surface_area()) * n as f64;
+                let cost = TRAVERSAL_COST + (left_cost + right_cost[b + 1]) / area;
+                if best.is_none_or(|(c, _, _)| cost < c) {
+                    best = Some((cost, axis, lo + extent * (b + 1) as f64 / BINS as f64));
+                }
+            }
+        }
+
+        best.filter(|&(cost, _, _)| cost < leaf_cost).map(|(_, axis, split)| (axis, split))
+    }
+
+    //The primitive indices in runs of at most size that sit together in the tree: each
+    //subtree that small whole, and any bigger leaf cut up
+    pub fn clusters(&self, size: usize) -> Vec<&[usize]> {
+        let mut clusters = Vec::new();
+        let mut stack = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            let span = self.span(n);
+            if node.count > 0 || span.len() <= size {
+                clusters.extend(self.indices[span].chunks(size));
+            } else {
+                stack.push(node.start);
+                stack.push(n + 1);
+            }
+        }
+        clusters
+    }
+
+    //Where node n's primitives are in indices: from its leftmost leaf to its rightmost
+    fn span(&self, n: usize) -> Range<usize> {
+        let (mut first, mut last) = (n, n);
+        while self.nodes[first].count == 0 {
+            first += 1;
+        }
+        while self.nodes[last].count == 0 {
+            last = self.nodes[last].start;
+        }
+        self.nodes[first].start..self.nodes[last].start + self.nodes[last].count
+    }
+
+    //Visit every primitive whose box the ray might reach before the closest hit found
+    //so far. hit(index, t_max) tests one primitive and returns the distance of any hit
+    //closer than t_max, which then becomes the new limit.
+    pub fn traverse(&self, r: &Ray, t_min: f64, t_max: f64, mut hit: impl FnMut(usize, f64) -> Option<f64>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let d = r.direction();
+        let inv_dir = Vec3::new(1.0 / d.x(), 1.0 / d.y(), 1.0 / d.z());
+        let mut closest = t_max;
+
+        let mut stack = vec![0];
+        let mut visited = 0;
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            visited += 1;
+            if !node.bbox.hit(r.origin(), inv_dir, t_min, closest) {
+                continue;
+            }
+
+            if node.count > 0 {
+                for &i in &self.indices[node.start..node.start + node.count] {
+                    if let Some(t) = hit(i, closest) {
+                        closest = t;
+                    }
+                }
+            } else {
+                //Right pushed first so the left child is visited first
+                stack.push(node.start);
+                stack.push(n + 1);
+            }
+        }
+        stats::add(Counter::BvhNodes, visited);
+    }
+
+    //traverse for a packet of rays together. A node is visited if any of them might
+    //reach it, and hit(index, closest) tests one primitive against the whole packet,
+    //bringing in the closest hit distance for each ray that hits it.
+    pub fn traverse_packet(&self, packet: &RayPacket, t_min: f64, t_max: [f64; PACKET], mut hit: impl FnMut(usize, &mut [f64; PACKET])) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut closest = t_max;
+        let mut stack = vec![0];
+        let mut visited = 0;
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            visited += 1;
+            if !node.bbox.hit_packet(packet, t_min, &closest) {
+                continue;
+            }
+
+            if node.count > 0 {
+                for &i in &self.indices[node.start..node.start + node.count] {
+                    hit(i, &mut closest);
+                }
+            } else {
+                stack.push(node.start);
+                stack.push(n + 1);
+            }
+        }
+        stats::add(Counter::BvhNodes, visited);
+    }
+}
+
+fn merge(a: Option<Aabb>, b: Option<Aabb>) -> Option<Aabb> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.surrounding(&b)),
+        (a, b) => a.or(b),
+    }
+}
+
+//BVH over whole objects
+pub struct BvhNode {
+    bvh: Bvh,
+    objects: World,
+}
+
+impl BvhNode {
+    //Every object needs a bounding box; use accelerate for a world that might have
+    //unbounded ones in it
+    pub fn new(objects: World) -> BvhNode {
+        let boxes: Vec<Aabb> = objects.iter()
+            .map(|o| o.bounding_box().expect("object in a BVH must have a bounding box"))
+            .collect();
+        BvhNode { bvh: Bvh::new(&boxes), objects }
+    }
+}
+
+impl Hit for BvhNode {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut best = None;
+        self.bvh.traverse(r, t_min, t_max, |i, closest| {
+            let rec = self.objects[i].hit(r, t_min, closest)?;
+            let t = rec.t;
+            best = Some(rec);
+            Some(t)
+        });
+        best
+    }
+
+    fn hit_packet(&self, packet: &RayPacket, t_min: f64, t_max: [f64; PACKET]) -> [Option<HitRecord>; PACKET] {
+        let mut best = std::array::from_fn(|_| None);
+        self.bvh.traverse_packet(packet, t_min, t_max, |i, closest| {
+            record_closest(self.objects[i].hit_packet(packet, t_min, *closest), closest, &mut best);
+        });
+        best
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounding_box()
+    }
+}
+
+//Put everything with a bounding box into a BVH, leaving anything unbounded alongside it.
+//Plain spheres go in as SphereBatches of neighbours, so leaves test them a few at once.
+pub fn accelerate(world: World) -> World {
+    let (bounded, mut unbounded): (World, World) = world.into_iter().partition(|o| o.bounding_box().is_some());
+    let bounded = batch_spheres(bounded);
+    if bounded.len() > 1 {
+        unbounded.push(Box::new(BvhNode::new(bounded)));
+    } else {
+        unbounded.extend(bounded);
+    }
+    unbounded
+}
+
+//world with its plain spheres packed into SphereBatches, each of spheres close together
+//(under the same branch of a BVH over them) so the batch's box stays small
+fn batch_spheres(world: World) -> World {
+    let (spheres, mut rest): (World, World) = world.into_iter().partition(|o| o.sphere().is_some());
+    let boxes: Vec<Aabb> = spheres.iter().filter_map(|s| s.bounding_box()).collect();
+    for cluster in Bvh::new(&boxes).clusters(LANES) {
+        let mut batch = SphereBatch::new();
+        for (centre, radius, mat) in cluster.iter().filter_map(|&i| spheres[i].sphere()) {
+            batch.push(centre, radius, mat);
+        }
+        rest.push(Box::new(batch));
+    }
+    rest
+}
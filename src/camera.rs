@@ -1,7 +1,81 @@
 
+use std::f64::consts::PI;
+
+use super::random::random_range;
 use super::ray::Ray;
+use super::sampler::{next_2d, to_unit_disk};
 use super::vec3::{Point3, Vec3};
 
+//How the camera maps the image onto directions
+#[derive(Clone, Copy)]
+pub enum Projection {
+    //Thin lens, as from an ordinary camera: the vertical field of view is vfov
+    Perspective,
+    //Parallel rays from a rectangle height tall (in scene units) facing the way the
+    //camera looks, so things don't get smaller with distance
+    Orthographic { height: f64 },
+    //Equidistant fisheye: the angle away from straight ahead grows in step with the
+    //distance from the middle of the image, reaching fov / 2 (degrees) at the top and
+    //bottom edges. The corners see further round, up to straight behind.
+    Fisheye { fov: f64 },
+    //Every direction, longitude across and latitude up the image, as for an
+    //environment map. Meant for images twice as wide as they are tall.
+    Equirectangular,
+}
+
+//A real camera's lens and sensor, in millimetres, for setting a camera up as a
+//photographer would rather than with a field of view and an aperture in scene units.
+//The image is the biggest of its shape that fits on the sensor.
+#[derive(Clone, Copy)]
+pub struct Lens {
+    pub focal_length: f64,
+    //f-number: focal length over the diameter of the opening
+    pub f_stop: f64,
+    //Width and height
+    pub sensor: (f64, f64),
+}
+
+//Names Lens::parse knows, with what they stand for
+pub const LENS_PRESETS: [(&str, &str); 4] = [
+    ("normal", "50mm f/1.8"),
+    ("wide", "24mm f/8"),
+    ("portrait", "85mm f/1.4"),
+    ("telephoto", "200mm f/2.8"),
+];
+
+impl Lens {
+    //On a full frame (36 by 24mm) sensor
+    pub fn new(focal_length: f64, f_stop: f64) -> Lens {
+        Lens { focal_length, f_stop, sensor: (36.0, 24.0) }
+    }
+
+    pub fn with_sensor(mut self, width: f64, height: f64) -> Lens {
+        self.sensor = (width, height);
+        self
+    }
+
+    //A lens written as photographers do, e.g. "50mm f/1.8", or one of LENS_PRESETS
+    pub fn parse(text: &str) -> Option<Lens> {
+        let text = LENS_PRESETS.iter().find(|(name, _)| name.eq_ignore_ascii_case(text.trim())).map_or(text, |(_, lens)| lens);
+        let (focal_length, f_stop) = text.trim().split_once(char::is_whitespace)?;
+        let focal_length: f64 = focal_length.trim().strip_suffix("mm")?.parse().ok()?;
+        let f_stop: f64 = f_stop.trim().strip_prefix("f/")?.parse().ok()?;
+        (focal_length > 0.0 && f_stop > 0.0).then(|| Lens::new(focal_length, f_stop))
+    }
+
+    //Vertical field of view, in degrees, of an image aspect_ratio wide to 1 tall
+    pub fn vfov(&self, aspect_ratio: f64) -> f64 {
+        let height = self.sensor.1.min(self.sensor.0 / aspect_ratio);
+        2.0 * (height / (2.0 * self.focal_length)).atan().to_degrees()
+    }
+
+    //Diameter of the opening, in scene units that are unit metres each
+    pub fn aperture(&self, unit: f64) -> f64 {
+        self.focal_length / self.f_stop / 1000.0 / unit
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct Camera {
     origin: Point3,
     lower_left_corner: Point3,
@@ -9,7 +83,18 @@ pub struct Camera {
     vertical: Vec3,
     cu: Vec3,
     cv: Vec3,
+    cw: Vec3,
+    aspect_ratio: f64,
+    //As given to new, so the camera can be pointed somewhere else
+    vup: Vec3,
+    vfov: f64,
+    focus_dist: f64,
     lens_radius: f64,
+    projection: Projection,
+    //Rays are sent at random times between these, so anything that moves is blurred
+    shutter: (f64, f64),
+    //Point on the image to focus on, see with_autofocus
+    autofocus: Option<(f64, f64)>,
 }
 
 impl Camera {
@@ -21,9 +106,6 @@ impl Camera {
         aspect_ratio: f64,
         aperture: f64,
         focus_dist: f64) -> Camera {
-        //Image
-        const FOCAL_LENGTH: f64 = 1.0;
-
         //Vertical fov in degrees
         let theta = std::f64::consts::PI/180.0 * vfov;
         let viewport_height = 2.0 * (theta/2.0).tan();
@@ -53,19 +135,145 @@ impl Camera {
             lower_left_corner: llc,
             horizontal: h,
             vertical: v,
-            cu: cu,
-            cv: cv,
+            cu,
+            cv,
+            cw,
+            aspect_ratio,
+            vup,
+            vfov,
+            focus_dist,
             lens_radius: aperture/2.0,
+            projection: Projection::Perspective,
+            shutter: (0.0, 0.0),
+            autofocus: None,
+        }
+    }
+
+    //Keep the shutter open from open to close instead of taking an instant at time 0
+    pub fn with_shutter(mut self, open: f64, close: f64) -> Camera {
+        self.shutter = (open, close);
+        self
+    }
+
+    //Project the image some other way. Only perspective and orthographic cameras
+    //have a lens, so the others are always in focus.
+    pub fn with_projection(mut self, projection: Projection) -> Camera {
+        self.projection = projection;
+        self
+    }
+
+    //Focus on whatever is seen at (s, t) on the image, 0 to 1 across from the left and up
+    //from the bottom as for get_ray, rather than at focus_dist. It takes the scene, so is
+    //done once that's built, by Scene::autofocus.
+    pub fn with_autofocus(mut self, s: f64, t: f64) -> Camera {
+        self.autofocus = Some((s, t));
+        self
+    }
+
+    pub fn autofocus(&self) -> Option<(f64, f64)> {
+        self.autofocus
+    }
+
+    //The same camera moved to lookfrom and turned towards lookat, with the same lens,
+    //shutter, projection and autofocus
+    pub fn looking(&self, lookfrom: Point3, lookat: Point3) -> Camera {
+        Camera { autofocus: self.autofocus, ..self.rebuilt(lookfrom, lookat, self.focus_dist) }
+    }
+
+    //The same camera focused focus_dist away
+    pub fn focused(&self, focus_dist: f64) -> Camera {
+        let lookat = self.origin - self.cw;
+        Camera { autofocus: self.autofocus, ..self.rebuilt(self.origin, lookat, focus_dist) }
+    }
+
+    fn rebuilt(&self, lookfrom: Point3, lookat: Point3, focus_dist: f64) -> Camera {
+        Camera::new(lookfrom, lookat, self.vup, self.vfov, self.aspect_ratio, self.aperture(), focus_dist)
+            .with_shutter(self.shutter.0, self.shutter.1)
+            .with_projection(self.projection)
+    }
+
+    pub fn lookfrom(&self) -> Point3 {
+        self.origin
+    }
+
+    //Unit vector the camera looks along
+    pub fn forward(&self) -> Vec3 {
+        (-1.0) * self.cw
+    }
+
+    pub fn vup(&self) -> Vec3 {
+        self.vup
+    }
+
+    pub fn aperture(&self) -> f64 {
+        2.0 * self.lens_radius
+    }
+
+    pub fn focus_dist(&self) -> f64 {
+        self.focus_dist
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    //Cone (see Ray::cone) for rays through one pixel of an image height pixels tall
+    pub fn pixel_cone(&self, height: u64) -> (f64, f64) {
+        let rows = height.max(1) as f64;
+        match self.projection {
+            //Rays aren't normalized, so the spread is per unit of distance along them
+            Projection::Perspective => (0.0, self.vertical.length() / self.focus_dist / rows),
+            Projection::Orthographic { height } => (height / rows, 0.0),
+            Projection::Fisheye { fov } => (0.0, fov.to_radians() / rows),
+            Projection::Equirectangular => (0.0, PI / rows),
         }
     }
 
+    //The same camera with its shutter opening by later, for frame by of an animation
+    pub fn delayed(mut self, by: f64) -> Camera {
+        self.shutter = (self.shutter.0 + by, self.shutter.1 + by);
+        self
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
-        let offset = self.cu * rd.x() + self.cv * rd.y();
+        let (dx, dy) = to_unit_disk(next_2d());
+        let offset = self.lens_radius * (self.cu * dx + self.cv * dy);
+
+        let (open, close) = self.shutter;
+        let time = if close > open { random_range(open..close) } else { open };
+        self.ray_through(s, t, offset, time)
+    }
 
-        Ray::new(self.origin + offset, 
-            self.lower_left_corner + s * self.horizontal + t * self.vertical 
-            - self.origin - offset)
+    //The ray get_ray gives through the middle of the lens as the shutter opens, with no
+    //blur from either
+    pub fn central_ray(&self, s: f64, t: f64) -> Ray {
+        self.ray_through(s, t, Vec3::new(0.0, 0.0, 0.0), self.shutter.0)
+    }
+
+    //Ray for (s, t) on the image through the lens, offset from its middle, at time
+    fn ray_through(&self, s: f64, t: f64, offset: Vec3, time: f64) -> Ray {
+        match self.projection {
+            Projection::Perspective => Ray::new(self.origin + offset, 
+                self.lower_left_corner + s * self.horizontal + t * self.vertical 
+                - self.origin - offset).with_time(time),
+            Projection::Orthographic { height } => {
+                let start = self.origin + (s - 0.5) * height * self.aspect_ratio * self.cu + (t - 0.5) * height * self.cv;
+                //Aimed through the lens at the point in focus straight ahead
+                Ray::new(start + offset, -self.focus_dist * self.cw - offset).with_time(time)
+            }
+            Projection::Fisheye { fov } => {
+                let (x, y) = ((2.0 * s - 1.0) * self.aspect_ratio, 2.0 * t - 1.0);
+                let theta = ((x * x + y * y).sqrt() * fov.to_radians() / 2.0).min(PI);
+                let phi = y.atan2(x);
+                let sideways = phi.cos() * self.cu + phi.sin() * self.cv;
+                Ray::new(self.origin, theta.sin() * sideways - theta.cos() * self.cw).with_time(time)
+            }
+            Projection::Equirectangular => {
+                let (longitude, latitude) = ((s - 0.5) * 2.0 * PI, (t - 0.5) * PI);
+                let level = longitude.sin() * self.cu - longitude.cos() * self.cw;
+                Ray::new(self.origin, latitude.cos() * level + latitude.sin() * self.cv).with_time(time)
+            }
+        }
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::image::Image;
+use super::renderer::RenderSettings;
+use super::scheduler::{Scheduler, TileAccum};
+use super::vec3::Color;
+
+
+
+const MAGIC: &[u8; 8] = b"PHCKPT01";
+
+//Where a render had got to, so it can be carried on after it's stopped or killed.
+//With a seed every sample's random numbers come from the seed, the pixel and the
+//sample's number, so the seed and each tile's running sums and sample count are all
+//the state there is: carrying on gives the same image as never having stopped. Without
+//one the rest of the samples just get fresh random numbers.
+//
+//Nothing about the scene is kept, so it's up to whoever resumes to use the same one.
+pub struct Checkpoint {
+    pub width: u64,
+    pub height: u64,
+    pub tile_size: u64,
+    pub pass_samples: u64,
+    pub seed: Option<u64>,
+    pub tiles: Vec<TileAccum>,
+}
+
+impl Checkpoint {
+    //tiles as got from Snapshot::accumulated or Renderer::render_resumable
+    pub fn new(settings: &RenderSettings, tiles: Vec<TileAccum>) -> Checkpoint {
+        Checkpoint {
+            width: settings.width,
+            height: settings.height,
+            tile_size: settings.tile_size,
+            pass_samples: settings.pass_samples,
+            seed: settings.seed,
+            tiles,
+        }
+    }
+
+    //Whether a render with settings can carry on from here: the image has to be cut up
+    //into the same tiles and batches, and the seed has to match
+    pub fn check(&self, settings: &RenderSettings) -> Result<(), String> {
+        if (self.width, self.height) != (settings.width, settings.height) {
+            return Err(format!("checkpoint is of a {}x{} image, not {}x{}", self.width, self.height, settings.width, settings.height));
+        }
+        if self.tile_size != settings.tile_size || self.pass_samples != settings.pass_samples {
+            return Err(format!("checkpoint has tiles of {} and passes of {} samples, not {} and {}",
+                self.tile_size, self.pass_samples, settings.tile_size, settings.pass_samples));
+        }
+        if self.seed != settings.seed {
+            return Err("checkpoint was rendered with a different seed".to_string());
+        }
+        let tiles = Scheduler::new(self.width, self.height, self.tile_size, 1, 1);
+        let matches = self.tiles.len() == tiles.tiles().len() && self.tiles.iter().zip(tiles.tiles()).all(|(acc, tile)| {
+            let pixels = (tile.width * tile.height) as usize;
+            acc.sum.len() == pixels && acc.sum_sq.len() == pixels && acc.counts.len() == pixels
+        });
+        if !matches {
+            return Err("checkpoint's tiles don't fit the image".to_string());
+        }
+        Ok(())
+    }
+
+    //Samples per pixel the tiles have had, fewest first
+    pub fn samples(&self) -> (u64, u64) {
+        let counts = self.tiles.iter().map(|t| t.samples);
+        (counts.clone().min().unwrap_or(0), counts.max().unwrap_or(0))
+    }
+
+    //Written next to path first and then moved over it, so being killed part way
+    //through leaves the last checkpoint as it was
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let temp = path.with_extension("tmp");
+        let mut out = BufWriter::new(fs::File::create(&temp)?);
+        out.write_all(MAGIC)?;
+        for n in [self.width, self.height, self.tile_size, self.pass_samples] {
+            out.write_all(&n.to_le_bytes())?;
+        }
+        out.write_all(&[self.seed.is_some() as u8])?;
+        out.write_all(&self.seed.unwrap_or(0).to_le_bytes())?;
+        out.write_all(&(self.tiles.len() as u64).to_le_bytes())?;
+        for tile in &self.tiles {
+            write_tile(&mut out, tile)?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Checkpoint> {
+        let data = fs::read(path)?;
+        decode(&mut data.as_slice()).map_err(|e| invalid(format!("{}: {}", path.display(), e)))
+    }
+
+    //Take on the tiles of a checkpoint of another part of the same render, keeping
+    //whichever of each tile has had more samples
+    pub fn merge(&mut self, other: Checkpoint) -> Result<(), String> {
+        let same = (self.width, self.height, self.tile_size, self.pass_samples, self.seed)
+            == (other.width, other.height, other.tile_size, other.pass_samples, other.seed);
+        if !same || self.tiles.len() != other.tiles.len() {
+            return Err("checkpoints are of different renders".to_string());
+        }
+        for (mine, theirs) in self.tiles.iter_mut().zip(other.tiles) {
+            if theirs.samples > mine.samples {
+                *mine = theirs;
+            }
+        }
+        Ok(())
+    }
+
+    //Mean of the samples for each pixel so far
+    pub fn image(&self) -> Image {
+        let scheduler = Scheduler::new(self.width, self.height, self.tile_size, 1, 1);
+        Image { width: self.width, height: self.height, pixels: scheduler.averaged(&self.tiles) }
+    }
+}
+
+fn decode(input: &mut &[u8]) -> io::Result<Checkpoint> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a checkpoint".to_string()));
+    }
+    let (width, height, tile_size, pass_samples) = (read_u64(input)?, read_u64(input)?, read_u64(input)?, read_u64(input)?);
+    let mut has_seed = [0];
+    input.read_exact(&mut has_seed)?;
+    let seed = read_u64(input)?;
+
+    //A tile takes 16 bytes and then 40 a pixel (see write_tile), so sizes the rest of
+    //the file couldn't hold are refused before anything is allocated for them
+    let tile_pixels = tile_size.checked_mul(tile_size).ok_or_else(|| invalid(format!("tiles of {} pixels across are too big", tile_size)))?;
+    let count = read_u64(input)?;
+    if count > input.len() as u64 / 16 {
+        return Err(invalid(format!("{} tiles is more than the file holds", count)));
+    }
+    let tiles = (0..count).map(|_| {
+        let most = tile_pixels.min(input.len() as u64 / 40);
+        read_tile(input, most)
+    }).collect::<io::Result<_>>()?;
+    Ok(Checkpoint { width, height, tile_size, pass_samples, seed: (has_seed[0] != 0).then_some(seed), tiles })
+}
+
+//One tile's running sums, as saved in checkpoints and sent back by render workers
+pub fn write_tile(out: &mut impl Write, tile: &TileAccum) -> io::Result<()> {
+    out.write_all(&tile.samples.to_le_bytes())?;
+    out.write_all(&(tile.sum.len() as u64).to_le_bytes())?;
+    for k in 0..tile.sum.len() {
+        let c = tile.sum[k];
+        for x in [c[0], c[1], c[2], tile.sum_sq[k]] {
+            out.write_all(&x.to_le_bytes())?;
+        }
+        out.write_all(&tile.counts[k].to_le_bytes())?;
+    }
+    Ok(())
+}
+
+//A tile written by write_tile, refusing any of more than max_pixels so a corrupt
+//count can't ask for a huge allocation
+pub fn read_tile(input: &mut impl Read, max_pixels: u64) -> io::Result<TileAccum> {
+    let samples = read_u64(input)?;
+    let pixels = read_u64(input)?;
+    if pixels > max_pixels {
+        return Err(invalid(format!("tile of {} pixels is too big", pixels)));
+    }
+    let pixels = pixels as usize;
+    let mut tile = TileAccum { sum: Vec::with_capacity(pixels), sum_sq: Vec::with_capacity(pixels), counts: Vec::with_capacity(pixels), samples };
+    for _ in 0..pixels {
+        let mut f = || read_u64(input).map(f64::from_bits);
+        tile.sum.push(Color::new(f()?, f()?, f()?));
+        tile.sum_sq.push(f()?);
+        tile.counts.push(read_u64(input)?);
+    }
+    Ok(tile)
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut b = [0; 8];
+    input.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use super::aabb::Aabb;
+use super::exr;
+use super::hit::{Hit, HitRecord};
+use super::packet::{RayPacket, PACKET};
+use super::ray::Ray;
+use super::renderer::Renderer;
+use super::scene::Scene;
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+//Object IDs and Cryptomatte (Friedman and Jones 2015): every object gets an ID hashed
+//from its name, so the same object keeps the same ID from one render (or frame) to the
+//next whatever else changes in the scene, and compositing can pick objects out by name
+//with antialiased edges from how much of each pixel each ID covers.
+
+//Coverage ranks stored per pixel, two to each RGBA layer
+pub const RANKS: usize = 6;
+
+//The layer name the ranks are stored under
+const LAYER: &str = "CryptoObject";
+
+//MurmurHash3, 32-bit, as Cryptomatte names are hashed with
+pub fn murmur3_32(data: &[u8], seed: u32) -> u32 {
+    let (c1, c2) = (0xcc9e2d51u32, 0x1b873593u32);
+    let mut h = seed;
+    let mut blocks = data.chunks_exact(4);
+    for block in &mut blocks {
+        let k = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+        h ^= k.wrapping_mul(c1).rotate_left(15).wrapping_mul(c2);
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let k = tail.iter().rev().fold(0u32, |k, &b| (k << 8) | b as u32);
+        h ^= k.wrapping_mul(c1).rotate_left(15).wrapping_mul(c2);
+    }
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^ (h >> 16)
+}
+
+//ID for the object called name. It's stored as the bits of a 32-bit float, so hashes
+//whose exponent would make a denormal, infinity or NaN have it nudged, as Cryptomatte
+//does. Never 0, which is left for things without an ID.
+pub fn object_id(name: &str) -> u32 {
+    let h = murmur3_32(name.as_bytes(), 0);
+    let exponent = (h >> 23) & 0xff;
+    if exponent == 0 || exponent == 0xff { h ^ (1 << 23) } else { h }
+}
+
+//Colour to show id as in an ID map, black for no ID
+pub fn id_color(id: u32) -> Color {
+    let channel = |shift: u32| ((id >> shift) & 0xff) as f64 / 255.0;
+    Color::new(channel(0), channel(8), channel(16))
+}
+
+//object with every hit on it carrying id
+pub struct Identified {
+    object: Arc<dyn Hit>,
+    id: u32,
+}
+
+impl Identified {
+    pub fn new(object: Arc<dyn Hit>, id: u32) -> Identified {
+        Identified { object, id }
+    }
+}
+
+impl Hit for Identified {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rec = self.object.hit(r, t_min, t_max)?;
+        rec.object_id = self.id;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        self.object.random_direction(origin)
+    }
+
+    fn hit_packet(&self, packet: &RayPacket, t_min: f64, t_max: [f64; PACKET]) -> [Option<HitRecord>; PACKET] {
+        self.object.hit_packet(packet, t_min, t_max).map(|rec| rec.map(|mut rec| {
+            rec.object_id = self.id;
+            rec
+        }))
+    }
+}
+
+//For each pixel, row-major from the top, the IDs the camera sees first and the fraction
+//of the pixel's samples that saw each, most first. The samples are the image's own
+//camera rays. The background has no entry, so what's left of the pixel is background.
+pub fn coverage(renderer: &Renderer, scene: &Scene) -> Vec<Vec<(u32, f64)>> {
+    let settings = renderer.settings();
+    let (width, samples) = (settings.width, settings.samples_per_pixel);
+    (0..settings.height).into_par_iter().flat_map_iter(|y| {
+        (0..width).map(move |x| {
+            let mut counts: BTreeMap<u32, u64> = BTreeMap::new();
+            for s in 0..samples {
+                let (r, _) = renderer.camera_sample(scene, x, y, s);
+                if let Some(rec) = scene.world.hit(&r, settings.epsilon, settings.max_distance) {
+                    if rec.object_id != 0 {
+                        *counts.entry(rec.object_id).or_insert(0) += 1;
+                    }
+                }
+            }
+            let mut ids: Vec<(u32, f64)> = counts.into_iter().map(|(id, n)| (id, n as f64 / samples as f64)).collect();
+            ids.sort_by(|a, b| b.1.total_cmp(&a.1));
+            ids
+        }).collect::<Vec<_>>()
+    }).collect()
+}
+
+//Write coverage as a Cryptomatte EXR: RANKS / 2 layers CryptoObject00, 01, ... of 32-bit
+//floats, each holding two (ID, coverage) ranks as R, G and B, A, with a manifest
+//mapping names (of objects that might be in it) to their IDs
+pub fn save(path: &Path, width: u64, height: u64, coverage: &[Vec<(u32, f64)>], names: &[String]) -> io::Result<()> {
+    let mut channels = Vec::new();
+    for layer in 0..RANKS / 2 {
+        let rank = |k: usize, id: bool| -> Vec<f64> {
+            coverage.iter().map(|ids| match ids.get(2 * layer + k) {
+                Some(&(i, c)) => if id { f32::from_bits(i) as f64 } else { c },
+                None => 0.0,
+            }).collect()
+        };
+        let name = |channel: &str| format!("{}{:02}.{}", LAYER, layer, channel);
+        channels.push((name("R"), rank(0, true)));
+        channels.push((name("G"), rank(0, false)));
+        channels.push((name("B"), rank(1, true)));
+        channels.push((name("A"), rank(1, false)));
+    }
+
+    let manifest: BTreeMap<&str, u32> = names.iter().map(|n| (n.as_str(), object_id(n))).collect();
+    let entries: Vec<String> = manifest.iter().map(|(name, id)| format!("\"{}\":\"{:08x}\"", escape(name), id)).collect();
+    let key = &format!("{:08x}", murmur3_32(LAYER.as_bytes(), 0))[..7];
+    let attribute = |field: &str, value: String| (format!("cryptomatte/{}/{}", key, field), value);
+    let strings = [
+        attribute("name", LAYER.to_string()),
+        attribute("hash", "MurmurHash3_32".to_string()),
+        attribute("conversion", "uint32_to_float32".to_string()),
+        attribute("manifest", format!("{{{}}}", entries.join(","))),
+    ];
+    exr::write_channels(BufWriter::new(File::create(path)?), width, height, &channels, false, &strings)
+}
+
+//name as the inside of a JSON string
+fn escape(name: &str) -> String {
+    name.chars().flat_map(|c| match c {
+        '"' | '\\' => vec!['\\', c],
+        c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32).chars().collect(),
+        c => vec![c],
+    }).collect()
+}
@@ -0,0 +1,190 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord};
+use super::material::Scatter;
+use super::ray::Ray;
+use super::vec3::{Point3, Vec3};
+
+
+
+//Cylinder from the centre of its base to the centre of its top, closed with flat caps
+//unless opened up. u goes round the axis and v up it on the side; each cap gets a
+//planar mapping across it.
+pub struct Cylinder(Frustum);
+
+impl Cylinder {
+    pub fn new(base: Point3, top: Point3, radius: f64, mat: Arc<dyn Scatter>) -> Cylinder {
+        Cylinder(Frustum::new(base, top, radius, radius, mat))
+    }
+
+    //Just the curved side, a tube that can be seen into
+    pub fn without_caps(self) -> Cylinder {
+        Cylinder(Frustum { capped: false, ..self.0 })
+    }
+}
+
+impl Hit for Cylinder {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.0.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.0.bounding_box())
+    }
+}
+
+//Cone from the centre of its base up to its apex, closed across the base unless opened
+//up. Given a top radius it's cut off flat short of the apex instead (a frustum, like a
+//lamp shade), with a cap there too. UVs are as for Cylinder.
+pub struct Cone(Frustum);
+
+impl Cone {
+    pub fn new(base: Point3, apex: Point3, radius: f64, mat: Arc<dyn Scatter>) -> Cone {
+        Cone(Frustum::new(base, apex, radius, 0.0, mat))
+    }
+
+    pub fn with_top_radius(self, radius: f64) -> Cone {
+        Cone(Frustum { top_radius: radius, ..self.0 })
+    }
+
+    pub fn without_caps(self) -> Cone {
+        Cone(Frustum { capped: false, ..self.0 })
+    }
+}
+
+impl Hit for Cone {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.0.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.0.bounding_box())
+    }
+}
+
+//What both are: round about an axis, the radius going in a straight line from
+//base_radius at the base to top_radius at the top. Worked out in a frame where the
+//axis is local y, with x along u_axis and z along v_axis.
+struct Frustum {
+    base: Point3,
+    u_axis: Vec3,
+    axis: Vec3,
+    v_axis: Vec3,
+    height: f64,
+    base_radius: f64,
+    top_radius: f64,
+    capped: bool,
+    mat: Arc<dyn Scatter>,
+}
+
+impl Frustum {
+    fn new(base: Point3, top: Point3, base_radius: f64, top_radius: f64, mat: Arc<dyn Scatter>) -> Frustum {
+        let axis = (top - base).normalized();
+        let u_axis = axis.any_perpendicular();
+        Frustum {
+            base,
+            u_axis,
+            axis,
+            v_axis: u_axis.cross(axis),
+            height: (top - base).length(),
+            base_radius,
+            top_radius,
+            capped: true,
+            mat,
+        }
+    }
+
+    fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.u_axis), v.dot(self.axis), v.dot(self.v_axis))
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x() * self.u_axis + v.y() * self.axis + v.z() * self.v_axis
+    }
+
+    //Radius at height y is r0 + k * y
+    fn slope(&self) -> f64 {
+        (self.top_radius - self.base_radius) / self.height
+    }
+
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        //Both frames are orthonormal, so t means the same in each
+        let o = self.to_local(r.origin() - self.base);
+        let d = self.to_local(r.direction());
+
+        let mut closest: Option<(f64, Vec3, Vec3, f64, f64)> = None;
+        let mut consider = |t: f64, normal: Vec3, tangent: Vec3, u: f64, v: f64| {
+            if t >= t_min && t <= closest.map_or(t_max, |c| c.0) {
+                closest = Some((t, normal, tangent, u, v));
+            }
+        };
+
+        //Side: x^2 + z^2 = (r0 + k y)^2 for 0 <= y <= height
+        let (r0, k) = (self.base_radius, self.slope());
+        let radius_o = r0 + k * o.y();
+        let a = d.x() * d.x() + d.z() * d.z() - k * k * d.y() * d.y();
+        let half_b = o.x() * d.x() + o.z() * d.z() - k * d.y() * radius_o;
+        let c = o.x() * o.x() + o.z() * o.z() - radius_o * radius_o;
+        let roots = if a.abs() < 1e-12 {
+            //Along the axis of a cylinder (no hit) or parallel to a cone's side (one)
+            if half_b == 0.0 { vec![] } else { vec![-c / (2.0 * half_b)] }
+        } else {
+            let discrim = half_b * half_b - a * c;
+            if discrim < 0.0 {
+                vec![]
+            } else {
+                let sqrtd = discrim.sqrt();
+                vec![(-half_b - sqrtd) / a, (-half_b + sqrtd) / a]
+            }
+        };
+        for t in roots {
+            let p = o + t * d;
+            if p.y() < 0.0 || p.y() > self.height {
+                continue;
+            }
+            let phi = p.z().atan2(p.x()).rem_euclid(2.0 * PI);
+            let (cos, sin) = (phi.cos(), phi.sin());
+            //Gradient of the implicit surface
+            let normal = Vec3::new(cos, -k, sin);
+            consider(t, normal, Vec3::new(-sin, 0.0, cos), phi / (2.0 * PI), p.y() / self.height);
+        }
+
+        //Caps, flat disks across each end
+        if self.capped && d.y() != 0.0 {
+            for (y, radius, facing) in [(0.0, self.base_radius, -1.0), (self.height, self.top_radius, 1.0)] {
+                let t = (y - o.y()) / d.y();
+                let p = o + t * d;
+                if radius > 0.0 && p.x() * p.x() + p.z() * p.z() <= radius * radius {
+                    let (u, v) = (0.5 + 0.5 * p.x() / radius, 0.5 + 0.5 * facing * p.z() / radius);
+                    consider(t, Vec3::new(0.0, facing, 0.0), Vec3::new(1.0, 0.0, 0.0), u, v);
+                }
+            }
+        }
+
+        let (t, normal, tangent, u, v) = closest?;
+        let outward_normal = self.to_world(normal).normalized();
+        let mut rec = HitRecord::new(r, t, outward_normal, Arc::clone(&self.mat), u, v);
+        rec.tangent = self.to_world(tangent);
+        //Up the side towards the top, and across the caps
+        rec.bitangent = rec.tangent.cross(outward_normal);
+        Some(rec)
+    }
+
+    //Around the two end disks
+    fn bounding_box(&self) -> Aabb {
+        let disk = |centre: Point3, radius: f64| {
+            let w = self.axis;
+            let extent = radius.abs() * Vec3::new(
+                (1.0 - w.x() * w.x()).max(0.0).sqrt(),
+                (1.0 - w.y() * w.y()).max(0.0).sqrt(),
+                (1.0 - w.z() * w.z()).max(0.0).sqrt(),
+            );
+            //Padded so a disk lined up with an axis doesn't get a flat box
+            let pad = 0.0001 * Vec3::new(1.0, 1.0, 1.0);
+            Aabb::new(centre - extent - pad, centre + extent + pad)
+        };
+        disk(self.base, self.base_radius).surrounding(&disk(self.base + self.height * self.axis, self.top_radius))
+    }
+}
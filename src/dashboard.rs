@@ -0,0 +1,133 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::preview::downscale;
+use raytracer::scheduler::Progress;
+use raytracer::tonemap::Tonemap;
+
+
+
+//How often the dashboard redraws, however fast work items finish
+const REFRESH: Duration = Duration::from_millis(250);
+//Size of the ASCII preview in characters
+const PREVIEW_COLUMNS: u64 = 64;
+const PREVIEW_ROWS: u64 = 18;
+//Darkest to brightest
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+//Full-screen progress display on stderr for long renders: a map of every tile's
+//progress, throughput, ETA, memory use and a coarse preview of the image so far.
+//Uses the alternate screen so the terminal is left as it was afterwards.
+pub struct Dashboard {
+    start: Instant,
+    tonemap: Tonemap,
+    last_draw: Mutex<Option<Instant>>,
+}
+
+impl Dashboard {
+    pub fn new(tonemap: Tonemap) -> Dashboard {
+        //Alternate screen, hide cursor
+        eprint!("\x1b[?1049h\x1b[?25l");
+        Dashboard { start: Instant::now(), tonemap, last_draw: Mutex::new(None) }
+    }
+
+    pub fn update(&self, progress: &Progress) {
+        let mut last_draw = self.last_draw.lock().unwrap();
+        let now = Instant::now();
+        if progress.done < progress.total && last_draw.is_some_and(|t| now - t < REFRESH) {
+            return;
+        }
+        *last_draw = Some(now);
+
+        let screen = self.render(progress);
+        let mut stderr = io::stderr().lock();
+        let _ = stderr.write_all(screen.as_bytes());
+        let _ = stderr.flush();
+    }
+
+    //Put the terminal back and leave a one line summary behind
+    pub fn finish(self) {
+        eprintln!("\x1b[?25h\x1b[?1049l");
+        eprintln!("Rendered in {}", format_duration(self.start.elapsed()));
+    }
+
+    fn render(&self, progress: &Progress) -> String {
+        let snapshot = &progress.snapshot;
+        let tiles = snapshot.tile_progress();
+        let spp = snapshot.samples_per_pixel().max(1);
+
+        let report = progress.report();
+        let rate = (report.samples - progress.resumed.min(report.samples)) as f64 / report.elapsed.as_secs_f64().max(1e-3);
+        let eta = report.eta.map_or_else(|| "--".to_string(), format_duration);
+
+        let mut out = String::from("\x1b[H\x1b[2J");
+        let _ = writeln!(out, "parhelia  {}x{} @ {} spp", snapshot.width(), snapshot.height(), spp);
+        let _ = writeln!(out);
+        let _ = writeln!(out, "  progress   {:>5.1}%  ({}/{} work items)", 100.0 * report.fraction(), report.done, report.total);
+        let _ = writeln!(out, "  samples/s  {:>10.0}", rate);
+        let _ = writeln!(out, "  elapsed    {:>10}", format_duration(self.start.elapsed()));
+        let _ = writeln!(out, "  eta        {:>10}", eta);
+        let _ = writeln!(out, "  memory     {:>10}", resident_memory().unwrap_or_else(|| "n/a".to_string()));
+        let _ = writeln!(out);
+
+        //One character per tile, shaded by how many of its samples are done
+        out.push_str("  tiles\n");
+        let columns = tiles.iter().take_while(|(t, _)| t.y0 == 0).count().max(1);
+        for row in tiles.chunks(columns) {
+            out.push_str("  ");
+            for (_, samples) in row {
+                out.push(match samples * 4 / spp {
+                    0 if *samples == 0 => '·',
+                    0 => '░',
+                    1 => '▒',
+                    2 | 3 => '▓',
+                    _ => '█',
+                });
+            }
+            out.push('\n');
+        }
+        out.push('\n');
+
+        //Character cells are about twice as tall as they are wide, so sample the image
+        //at twice the row count and average pairs of rows
+        out.push_str("  preview\n");
+        let (small, w, h) = downscale(&snapshot.averaged(), snapshot.width(), snapshot.height(), PREVIEW_COLUMNS, 2 * PREVIEW_ROWS);
+        for y in (0..h).step_by(2) {
+            out.push_str("  ");
+            for x in 0..w {
+                let mut c = small[(y * w + x) as usize];
+                if y + 1 < h {
+                    c = 0.5 * (c + small[((y + 1) * w + x) as usize]);
+                }
+                let [r, g, b] = self.tonemap.to_display(c);
+                let luma = (0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64) / 256.0;
+                out.push(RAMP[(luma * RAMP.len() as f64) as usize] as char);
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{:.1}s", d.as_secs_f64())
+    }
+}
+
+//Resident set size, where the platform makes it easy to find
+fn resident_memory() -> Option<String> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(format!("{:.1} MiB", kb as f64 / 1024.0))
+}
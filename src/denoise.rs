@@ -0,0 +1,129 @@
+use super::image::Image;
+
+#[cfg(feature = "oidn")]
+use super::vec3::Color;
+
+
+
+//Whether denoise does anything in this build, so callers can say so before rendering
+pub const AVAILABLE: bool = cfg!(feature = "oidn");
+
+//Denoising with Intel Open Image Denoise, through its C API. Needs the oidn feature and
+//the OpenImageDenoise library (2.x) installed where the linker can find it.
+//
+//color is the linear, un-tonemapped render. albedo and normal (world space, in [-1, 1])
+//are the AOVs of the same name; they help keep texture and edges sharp. A normal buffer
+//is only used along with an albedo one.
+#[cfg(feature = "oidn")]
+pub fn denoise(color: &Image, albedo: Option<&Image>, normal: Option<&Image>) -> Result<Image, String> {
+    let (width, height) = (color.width as usize, color.height as usize);
+    let to_f32 = |image: &Image| -> Vec<f32> {
+        image.pixels.iter().flat_map(|c| [c[0] as f32, c[1] as f32, c[2] as f32]).collect()
+    };
+    let mut beauty = to_f32(color);
+    let mut albedo = albedo.map(to_f32);
+    let mut normal = albedo.as_ref().and(normal).map(to_f32);
+    let mut output = vec![0.0f32; beauty.len()];
+
+    //Safety: every buffer is width * height packed float triples and outlives the
+    //filter, which is released before they're dropped
+    unsafe {
+        let device = ffi::oidnNewDevice(ffi::OIDN_DEVICE_TYPE_DEFAULT);
+        if device.is_null() {
+            return Err("couldn't create an OIDN device".to_string());
+        }
+        ffi::oidnCommitDevice(device);
+
+        let filter = ffi::oidnNewFilter(device, c"RT".as_ptr());
+        if filter.is_null() {
+            let error = device_error(device);
+            ffi::oidnReleaseDevice(device);
+            return Err(format!("couldn't create an OIDN filter: {}", error.unwrap_or_else(|| "no reason given".to_string())));
+        }
+        let set_image = |name: &std::ffi::CStr, buffer: &mut [f32]| {
+            ffi::oidnSetSharedFilterImage(filter, name.as_ptr(), buffer.as_mut_ptr().cast(), ffi::OIDN_FORMAT_FLOAT3, width, height, 0, 0, 0);
+        };
+        set_image(c"color", &mut beauty);
+        if let Some(albedo) = &mut albedo {
+            set_image(c"albedo", albedo);
+        }
+        if let Some(normal) = &mut normal {
+            set_image(c"normal", normal);
+        }
+        set_image(c"output", &mut output);
+        //Values aren't limited to [0, 1] before tonemapping
+        ffi::oidnSetFilterBool(filter, c"hdr".as_ptr(), true);
+        ffi::oidnCommitFilter(filter);
+        ffi::oidnExecuteFilter(filter);
+
+        let error = device_error(device);
+        ffi::oidnReleaseFilter(filter);
+        ffi::oidnReleaseDevice(device);
+        if let Some(message) = error {
+            return Err(format!("OIDN failed: {}", message));
+        }
+    }
+
+    let pixels = output.chunks_exact(3).map(|c| Color::new(c[0] as f64, c[1] as f64, c[2] as f64)).collect();
+    Ok(Image { width: color.width, height: color.height, pixels })
+}
+
+//What last went wrong on device, if anything, clearing it.
+//Safety: device has to be a live OIDN device.
+#[cfg(feature = "oidn")]
+unsafe fn device_error(device: ffi::Device) -> Option<String> {
+    let mut message: *const std::ffi::c_char = std::ptr::null();
+    let error = ffi::oidnGetDeviceError(device, &mut message);
+    if error == ffi::OIDN_ERROR_NONE {
+        None
+    } else if message.is_null() {
+        Some(format!("error code {}", error))
+    } else {
+        Some(std::ffi::CStr::from_ptr(message).to_string_lossy().into_owned())
+    }
+}
+
+//Stand-in for builds without the oidn feature, so --denoise can say what's wrong
+#[cfg(not(feature = "oidn"))]
+pub fn denoise(_color: &Image, _albedo: Option<&Image>, _normal: Option<&Image>) -> Result<Image, String> {
+    Err("this build doesn't include the oidn feature (rebuild with --features oidn)".to_string())
+}
+
+//The parts of OpenImageDenoise's C API that denoise uses
+#[cfg(feature = "oidn")]
+mod ffi {
+    use std::ffi::{c_char, c_int, c_void};
+
+    pub type Device = *mut c_void;
+    pub type Filter = *mut c_void;
+
+    pub const OIDN_DEVICE_TYPE_DEFAULT: c_int = 0;
+    pub const OIDN_FORMAT_FLOAT3: c_int = 3;
+    pub const OIDN_ERROR_NONE: c_int = 0;
+
+    #[link(name = "OpenImageDenoise")]
+    extern "C" {
+        pub fn oidnNewDevice(device_type: c_int) -> Device;
+        pub fn oidnCommitDevice(device: Device);
+        pub fn oidnGetDeviceError(device: Device, out_message: *mut *const c_char) -> c_int;
+        pub fn oidnReleaseDevice(device: Device);
+
+        pub fn oidnNewFilter(device: Device, filter_type: *const c_char) -> Filter;
+        //Strides of 0 mean tightly packed
+        pub fn oidnSetSharedFilterImage(
+            filter: Filter,
+            name: *const c_char,
+            ptr: *mut c_void,
+            format: c_int,
+            width: usize,
+            height: usize,
+            byte_offset: usize,
+            pixel_byte_stride: usize,
+            row_byte_stride: usize,
+        );
+        pub fn oidnSetFilterBool(filter: Filter, name: *const c_char, value: bool);
+        pub fn oidnCommitFilter(filter: Filter);
+        pub fn oidnExecuteFilter(filter: Filter);
+        pub fn oidnReleaseFilter(filter: Filter);
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::Path;
+
+use super::image::Image;
+use super::texture::{Gradient, GradientAxis, Texture};
+use super::vec3::{Color, Point3};
+
+
+
+pub struct DiffStats {
+    //Over all channels of all pixels
+    pub rmse: f64,
+    //In dB against a peak value of 1; infinite for identical images
+    pub psnr: f64,
+    //Mean structural similarity of the luminance, 1 for identical images
+    pub ssim: f64,
+    //Largest per-pixel error (mean over channels)
+    pub max_error: f64,
+}
+
+//How close a render has to be to its reference to pass; no limits to begin with
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Threshold {
+    pub max_rmse: Option<f64>,
+    pub min_ssim: Option<f64>,
+}
+
+impl Threshold {
+    pub fn new() -> Threshold {
+        Threshold::default()
+    }
+
+    pub fn with_max_rmse(mut self, rmse: f64) -> Threshold {
+        self.max_rmse = Some(rmse);
+        self
+    }
+
+    pub fn with_min_ssim(mut self, ssim: f64) -> Threshold {
+        self.min_ssim = Some(ssim);
+        self
+    }
+
+    //Err saying which limits stats is past, if any
+    pub fn check(&self, stats: &DiffStats) -> Result<(), String> {
+        let mut failures = Vec::new();
+        if let Some(max) = self.max_rmse.filter(|&max| stats.rmse > max || stats.rmse.is_nan()) {
+            failures.push(format!("RMSE {:.6} is over {}", stats.rmse, max));
+        }
+        if let Some(min) = self.min_ssim.filter(|&min| stats.ssim < min || stats.ssim.is_nan()) {
+            failures.push(format!("SSIM {:.6} is under {}", stats.ssim, min));
+        }
+        if failures.is_empty() { Ok(()) } else { Err(failures.join(", ")) }
+    }
+}
+
+//Golden-image check for regression tests: compare image with the reference image at
+//path and hold it to threshold. With PARHELIA_BLESS set in the environment, image is
+//written there to be the new reference instead, so after a change that's meant to
+//alter renders, the references can be brought up to date in one run.
+//The reference is kept in the format its extension names (see Image::write): .exr or
+//.hdr for radiance as it is, .png or .ppm clamped to 8 bits. image is compared as that
+//format would keep it, and without a limit on RMSE in threshold, it's allowed about a
+//step of the format's precision (see tolerance).
+pub fn check_reference(image: &Image, path: &Path, threshold: &Threshold) -> Result<DiffStats, String> {
+    if std::env::var_os("PARHELIA_BLESS").is_some() {
+        image.write(path).map_err(|e| format!("couldn't write {}: {}", path.display(), e))?;
+    }
+    let reference = Image::read(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    let threshold = match threshold.max_rmse {
+        Some(_) => *threshold,
+        None => threshold.with_max_rmse(tolerance(path)),
+    };
+    let stats = compare(&image.stored(path), &reference)?;
+    threshold.check(&stats).map_err(|e| format!("{} against {}", e, path.display()))?;
+    Ok(stats)
+}
+
+//RMSE a render can be off a reference at path by, going by its format, and count as the
+//same render but for rounding (a different platform's maths library, say)
+pub fn tolerance(path: &Path) -> f64 {
+    match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("exr") => 1.0e-4,
+        //8 bits of mantissa, relative to the brightest channel
+        Some("hdr") => 1.0 / 128.0,
+        _ => 1.0 / 255.0,
+    }
+}
+
+pub fn compare(a: &Image, b: &Image) -> Result<DiffStats, String> {
+    if a.width != b.width || a.height != b.height {
+        return Err(format!(
+            "image sizes differ: {}x{} vs {}x{}",
+            a.width, a.height, b.width, b.height
+        ));
+    }
+
+    let mut sum_sq = 0.0;
+    let mut max_error: f64 = 0.0;
+    for (ca, cb) in a.pixels.iter().zip(&b.pixels) {
+        let d = *ca - *cb;
+        sum_sq += d.dot(d);
+        max_error = max_error.max(pixel_error(*ca, *cb));
+    }
+
+    let mse = sum_sq / (3 * a.pixels.len()).max(1) as f64;
+    let psnr = if mse > 0.0 { -10.0 * mse.log10() } else { f64::INFINITY };
+
+    Ok(DiffStats {
+        rmse: mse.sqrt(),
+        psnr,
+        ssim: ssim(a, b),
+        max_error,
+    })
+}
+
+//Per-pixel error mapped through a black-purple-red-yellow-white ramp, normalized so
+//the worst pixel is white. Returns the heatmap and the error that white stands for.
+pub fn heatmap(a: &Image, b: &Image) -> (Image, f64) {
+    let ramp = Gradient::new(
+        vec![
+            (0.0, Color::new(0.0, 0.0, 0.0)),
+            (0.25, Color::new(0.35, 0.05, 0.55)),
+            (0.5, Color::new(0.85, 0.15, 0.15)),
+            (0.75, Color::new(1.0, 0.75, 0.0)),
+            (1.0, Color::new(1.0, 1.0, 1.0)),
+        ],
+        GradientAxis::U,
+    );
+
+    let errors: Vec<f64> = a.pixels.iter().zip(&b.pixels).map(|(ca, cb)| pixel_error(*ca, *cb)).collect();
+    let scale = errors.iter().cloned().fold(0.0, f64::max);
+    let origin = Point3::new(0.0, 0.0, 0.0);
+
+    let mut map = Image::new(a.width, a.height);
+    for (out, e) in map.pixels.iter_mut().zip(errors) {
+        let t = if scale > 0.0 { e / scale } else { 0.0 };
+        *out = ramp.value(t, 0.0, origin);
+    }
+
+    (map, scale)
+}
+
+fn pixel_error(a: Color, b: Color) -> f64 {
+    ((a[0] - b[0]).abs() + (a[1] - b[1]).abs() + (a[2] - b[2]).abs()) / 3.0
+}
+
+//SSIM (Wang et al. 2004) over every 7x7 window of the luminance, averaged.
+//Images smaller than a window are treated as a single window.
+fn ssim(a: &Image, b: &Image) -> f64 {
+    const WINDOW: u64 = 7;
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+
+    let la: Vec<f64> = a.pixels.iter().map(|&c| c.luminance()).collect();
+    let lb: Vec<f64> = b.pixels.iter().map(|&c| c.luminance()).collect();
+
+    let wx = WINDOW.min(a.width);
+    let wy = WINDOW.min(a.height);
+    if wx == 0 || wy == 0 {
+        return 1.0;
+    }
+    let n = (wx * wy) as f64;
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y0 in 0..=(a.height - wy) {
+        for x0 in 0..=(a.width - wx) {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in y0..y0 + wy {
+                for x in x0..x0 + wx {
+                    let i = (y * a.width + x) as usize;
+                    sa += la[i];
+                    sb += lb[i];
+                    saa += la[i] * la[i];
+                    sbb += lb[i] * lb[i];
+                    sab += la[i] * lb[i];
+                }
+            }
+
+            let (mu_a, mu_b) = (sa / n, sb / n);
+            let var_a = saa / n - mu_a * mu_a;
+            let var_b = sbb / n - mu_b * mu_b;
+            let cov = sab / n - mu_a * mu_b;
+
+            total += ((2.0 * mu_a * mu_b + C1) * (2.0 * cov + C2))
+                / ((mu_a * mu_a + mu_b * mu_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    total / windows as f64
+}
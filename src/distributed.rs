@@ -0,0 +1,224 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use super::checkpoint::{read_tile, write_tile};
+use super::renderer::{RenderSettings, Renderer};
+use super::scene::Scene;
+use super::scheduler::{CancelToken, Scheduler, TileAccum};
+
+
+
+//Tiles handed to a worker at a time: enough to keep all its cores busy, few enough
+//that the work evens out between fast and slow machines
+const CHUNK: usize = 16;
+
+//Rendering one image on several machines. Each runs a worker, set up with the same
+//scene and image options, and a coordinator hands them runs of tiles over TCP and
+//collects each tile's running sums as they come back. A worker that drops out has its
+//tiles handed to the others, as does one that sends nothing back for longer than the
+//timeout (which has to allow for it rendering a run of tiles). Nothing checks the scene
+//is the same everywhere, only the image settings.
+//
+//The coordinator sends lines of text, which the worker answers:
+//
+//    render <width> <height> <samples> <tile size> <pass samples> <seed or ->
+//        "ok", or "error <why>" if the worker's settings are different
+//    tiles <first> <end>
+//        the running sums of tiles first..end in turn, as in a checkpoint
+
+//Describes the image a render makes, for the coordinator and worker to agree on
+fn job(settings: &RenderSettings) -> String {
+    let seed = settings.seed.map_or("-".to_string(), |seed| seed.to_string());
+    format!("render {} {} {} {} {} {}", settings.width, settings.height, settings.samples_per_pixel,
+        settings.tile_size, settings.pass_samples, seed)
+}
+
+//Listen on addr and render tiles of scene for whoever connects, one coordinator at a
+//time, until killed. A coordinator that says nothing for timeout is given up on.
+pub fn serve(addr: &str, renderer: &Renderer, scene: &Scene, timeout: Duration) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    renderer.prepare(scene);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Couldn't accept a connection: {}", e);
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().map_or("coordinator".to_string(), |a| a.to_string());
+        let handled = set_timeouts(&stream, timeout).and_then(|()| handle(stream, renderer, scene));
+        match handled {
+            Ok(tiles) => eprintln!("Rendered {} tiles for {}", tiles, peer),
+            Err(e) if timed_out(&e) => eprintln!("Gave up on {}: nothing from it for {}s", peer, timeout.as_secs()),
+            Err(e) => eprintln!("Gave up on {}: {}", peer, e),
+        }
+    }
+    Ok(())
+}
+
+//Answer one coordinator's requests, returning how many tiles it was sent
+fn handle(stream: TcpStream, renderer: &Renderer, scene: &Scene) -> io::Result<usize> {
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut out = BufWriter::new(stream);
+    let settings = renderer.settings();
+    let tiles = Scheduler::new(settings.width, settings.height, settings.tile_size, 1, 1).tiles().len();
+    let mut agreed = false;
+    let mut sent = 0;
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(sent);
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["render", ..] => {
+                agreed = line.trim() == job(settings);
+                if agreed {
+                    writeln!(out, "ok")?;
+                } else {
+                    writeln!(out, "error worker is set up for {}", job(settings))?;
+                }
+            }
+            ["tiles", first, end] if agreed => {
+                let range = match (first.parse::<usize>(), end.parse::<usize>()) {
+                    (Ok(first), Ok(end)) if first <= end && end <= tiles => first..end,
+                    _ => return Err(invalid(format!("bad request: {}", line.trim()))),
+                };
+                let scheduler = renderer.clone().with_tiles(range.clone()).scheduler();
+                let accumulated = scheduler.accumulate(|x, y, samples, out| renderer.samples(scene, x, y, samples, out), |_| {});
+                for tile in &accumulated[range.clone()] {
+                    write_tile(&mut out, tile)?;
+                }
+                sent += range.len();
+            }
+            _ => return Err(invalid(format!("bad request: {}", line.trim()))),
+        }
+        out.flush()?;
+    }
+}
+
+//Render the image settings describe on the workers at these addresses (host:port),
+//returning every tile's running sums. Stops handing out tiles once cancel is
+//cancelled; tiles that never came back have no samples. Only fails if tiles are left
+//over because every worker dropped out.
+pub fn coordinate(workers: &[String], settings: &RenderSettings, cancel: &CancelToken, timeout: Duration) -> io::Result<Vec<TileAccum>> {
+    let scheduler = Scheduler::new(settings.width, settings.height, settings.tile_size, 1, 1);
+    let tiles = scheduler.tiles();
+    let results = Mutex::new(tiles.iter().map(TileAccum::new).collect::<Vec<_>>());
+    //Popped from the end, so the image fills in from the top
+    let queue: Mutex<Vec<Range<usize>>> = Mutex::new((0..tiles.len()).step_by(CHUNK).rev()
+        .map(|first| first..(first + CHUNK).min(tiles.len()))
+        .collect());
+    let remaining = AtomicUsize::new(tiles.len());
+
+    thread::scope(|s| {
+        for addr in workers {
+            let (queue, results, remaining) = (&queue, &results, &remaining);
+            s.spawn(move || {
+                let work = || -> io::Result<()> {
+                    let stream = connect(addr, timeout)?;
+                    let mut input = BufReader::new(stream.try_clone()?);
+                    let mut out = BufWriter::new(stream);
+                    writeln!(out, "{}", job(settings))?;
+                    out.flush()?;
+                    let mut reply = String::new();
+                    input.read_line(&mut reply)?;
+                    if reply.trim() != "ok" {
+                        return Err(invalid(reply.trim().trim_start_matches("error ").to_string()));
+                    }
+
+                    loop {
+                        let next = queue.lock().unwrap().pop();
+                        let range = match next {
+                            _ if cancel.is_cancelled() => break,
+                            Some(range) => range,
+                            None if remaining.load(Ordering::Relaxed) == 0 => break,
+                            //Tiles still out with other workers, who might drop out
+                            None => {
+                                thread::sleep(Duration::from_millis(100));
+                                continue;
+                            }
+                        };
+                        let fetched = (|| {
+                            writeln!(out, "tiles {} {}", range.start, range.end)?;
+                            out.flush()?;
+                            range.clone().map(|k| {
+                                let tile = read_tile(&mut input, settings.tile_size * settings.tile_size)?;
+                                if tile.sum.len() as u64 != tiles[k].width * tiles[k].height {
+                                    return Err(invalid("tile of the wrong size".to_string()));
+                                }
+                                Ok(tile)
+                            }).collect::<io::Result<Vec<_>>>()
+                        })();
+                        match fetched {
+                            Ok(fetched) => {
+                                let mut results = results.lock().unwrap();
+                                for (k, tile) in range.clone().zip(fetched) {
+                                    results[k] = tile;
+                                }
+                                let left = remaining.fetch_sub(range.len(), Ordering::Relaxed) - range.len();
+                                eprintln!("Tiles remaining: {}", left);
+                            }
+                            Err(e) => {
+                                //Someone else can do them
+                                queue.lock().unwrap().push(range);
+                                return Err(e);
+                            }
+                        }
+                    }
+                    Ok(())
+                };
+                match work() {
+                    Ok(()) => {}
+                    Err(e) if timed_out(&e) => eprintln!("Worker {} dropped out: nothing from it for {}s", addr, timeout.as_secs()),
+                    Err(e) => eprintln!("Worker {} dropped out: {}", addr, e),
+                }
+            });
+        }
+    });
+
+    if !cancel.is_cancelled() && remaining.load(Ordering::Relaxed) > 0 {
+        return Err(io::Error::other(format!("{} tiles weren't rendered: no workers left", remaining.load(Ordering::Relaxed))));
+    }
+    Ok(results.into_inner().unwrap())
+}
+
+//Connected to addr (host:port), giving up on connecting, and later on any read or
+//write, after timeout
+fn connect(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut failed = invalid(format!("no address for {}", addr));
+    for socket in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket, timeout) {
+            Ok(stream) => {
+                set_timeouts(&stream, timeout)?;
+                return Ok(stream);
+            }
+            Err(e) => failed = e,
+        }
+    }
+    Err(failed)
+}
+
+//Clones of stream share these, so they hold for its reader and writer both
+fn set_timeouts(stream: &TcpStream, timeout: Duration) -> io::Result<()> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))
+}
+
+//What a read or write running out of time fails with, which depends on the platform
+fn timed_out(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,444 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use super::image::Image;
+use super::vec3::Color;
+
+
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+
+//Version flags for layouts this reader doesn't handle
+const TILED: u32 = 0x200;
+const DEEP: u32 = 0x800;
+const MULTIPART: u32 = 0x1000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Rle,
+    //zlib, one scanline per block
+    Zips,
+    //zlib, sixteen scanlines per block
+    Zip,
+}
+
+impl Compression {
+    fn lines_per_block(self) -> usize {
+        match self {
+            Compression::Zip => 16,
+            _ => 1,
+        }
+    }
+
+    //Most the data can shrink by: 64 for RLE (2 bytes for a run of 128), and about
+    //1032 for deflate
+    fn max_ratio(self) -> usize {
+        match self {
+            Compression::None => 1,
+            Compression::Rle => 64,
+            Compression::Zips | Compression::Zip => 1032,
+        }
+    }
+}
+
+struct Channel {
+    name: String,
+    //Bytes per value: 2 for half, 4 for float and uint
+    size: usize,
+    pixel_type: i32,
+}
+
+//OpenEXR reader for single-part scanline files, uncompressed or with RLE, ZIPS or
+//ZIP compression, which covers what most tools write by default. R, G and B are read
+//as linear radiance; a file with only Y comes out grey. Anything else is ignored.
+pub fn read(path: &Path) -> io::Result<Image> {
+    let data = fs::read(path)?;
+    decode(&data).map_err(|e| invalid(format!("{}: {}", path.display(), e)))
+}
+
+//read, for a file already in memory. Sizes in the file are checked against how much of
+//it there is before anything is allocated for them, so it's safe on untrusted input.
+pub fn decode(data: &[u8]) -> Result<Image, String> {
+    let mut r = Reader { data, pos: 0 };
+    if r.bytes(4)? != MAGIC {
+        return Err("not an OpenEXR file".to_string());
+    }
+    let version = r.u32()?;
+    if version & (TILED | DEEP | MULTIPART) != 0 {
+        return Err("only single-part scanline files are supported".to_string());
+    }
+
+    let mut channels = Vec::new();
+    let mut compression = None;
+    let mut window = None;
+    loop {
+        let name = r.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let _kind = r.string()?;
+        let size = r.size()?;
+        let value = r.bytes(size)?;
+        let mut v = Reader { data: value, pos: 0 };
+        match name.as_str() {
+            "channels" => {
+                loop {
+                    let name = v.string()?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    let pixel_type = v.i32()?;
+                    v.bytes(4)?;
+                    let (x_sampling, y_sampling) = (v.i32()?, v.i32()?);
+                    if x_sampling != 1 || y_sampling != 1 {
+                        return Err("subsampled channels aren't supported".to_string());
+                    }
+                    let size = match pixel_type {
+                        1 => 2,
+                        0 | 2 => 4,
+                        _ => return Err(format!("bad pixel type {}", pixel_type)),
+                    };
+                    channels.push(Channel { name, size, pixel_type });
+                }
+            }
+            "compression" => {
+                compression = Some(match v.bytes(1)?[0] {
+                    0 => Compression::None,
+                    1 => Compression::Rle,
+                    2 => Compression::Zips,
+                    3 => Compression::Zip,
+                    c => return Err(format!("unsupported compression (type {})", c)),
+                });
+            }
+            "dataWindow" => {
+                window = Some([v.i32()?, v.i32()?, v.i32()?, v.i32()?]);
+            }
+            _ => {}
+        }
+    }
+
+    let compression = compression.ok_or("missing compression attribute")?;
+    let [x_min, y_min, x_max, y_max] = window.ok_or("missing dataWindow attribute")?;
+    if x_max < x_min || y_max < y_min {
+        return Err("empty data window".to_string());
+    }
+    //Widened, as the corners can be anywhere an i32 can
+    let width = (x_max as i64 - x_min as i64 + 1) as usize;
+    let height = (y_max as i64 - y_min as i64 + 1) as usize;
+    if channels.is_empty() {
+        return Err("no channels".to_string());
+    }
+
+    //Where each of R, G and B lives within a scanline: offset of the channel's first
+    //value, and the channel itself. Channels are stored in the order listed.
+    let pixel_bytes: usize = channels.iter().map(|c| c.size).sum();
+    let lines_per_block = compression.lines_per_block();
+    let blocks = height.div_ceil(lines_per_block);
+    //Each block takes 16 bytes of offset and header besides its data, which can't
+    //be smaller than the compression could squeeze it to
+    let fits = pixel_bytes.checked_mul(width)
+        .and_then(|line| line.checked_mul(height))
+        .map(|raw| raw.div_ceil(compression.max_ratio()) as u128 + 16 * blocks as u128 <= (data.len() - r.pos) as u128)
+        .unwrap_or(false);
+    if !fits {
+        return Err(format!("{}x{} is more than the file holds", width, height));
+    }
+    let line_bytes = pixel_bytes * width;
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    for channel in &channels {
+        offsets.push(offset);
+        offset += channel.size * width;
+    }
+    let find = |name: &str| channels.iter().position(|c| c.name == name);
+    let rgb = match (find("R"), find("G"), find("B"), find("Y")) {
+        (None, None, None, Some(y)) => [Some(y); 3],
+        (r, g, b, _) => [r, g, b],
+    };
+
+    //Offset table; blocks are read in order from their own headers instead
+    r.bytes(blocks * 8)?;
+
+    let mut pixels = vec![Color::new(0.0, 0.0, 0.0); width * height];
+    for _ in 0..blocks {
+        let y = r.i32()?;
+        let size = r.size()?;
+        let packed = r.bytes(size)?;
+        if y < y_min || y > y_max {
+            return Err(format!("block for line {} is outside the data window", y));
+        }
+        let first = (y - y_min) as usize;
+        let lines = lines_per_block.min(height - first);
+
+        let expected = line_bytes * lines;
+        let raw = if size == expected {
+            //Blocks that didn't shrink are stored as they are
+            packed.to_vec()
+        } else {
+            match compression {
+                Compression::None => return Err("block has the wrong size".to_string()),
+                Compression::Rle => unpredict(unrle(packed, expected)?),
+                Compression::Zips | Compression::Zip => {
+                    //One byte more than it should hold is enough to tell it's wrong
+                    let mut out = Vec::with_capacity(expected);
+                    ZlibDecoder::new(packed).take(expected as u64 + 1).read_to_end(&mut out).map_err(|e| e.to_string())?;
+                    unpredict(out)
+                }
+            }
+        };
+        if raw.len() != expected {
+            return Err("block has the wrong size".to_string());
+        }
+
+        for line in 0..lines {
+            let row = &raw[line * line_bytes..(line + 1) * line_bytes];
+            for x in 0..width {
+                let value = |k: Option<usize>| k.map_or(0.0, |k| {
+                    let channel = &channels[k];
+                    let at = offsets[k] + x * channel.size;
+                    sample(&row[at..at + channel.size], channel.pixel_type)
+                });
+                pixels[(first + line) * width + x] = Color::new(value(rgb[0]), value(rgb[1]), value(rgb[2]));
+            }
+        }
+    }
+
+    Ok(Image { width: width as u64, height: height as u64, pixels })
+}
+
+//Write linear colours (row-major from the top) as a single-part scanline OpenEXR
+//file with ZIP compression and R, G and B channels, plus A if there's alpha (with the
+//colours premultiplied by it, as EXR expects), as half floats or full 32-bit floats.
+//Values are stored as they are, nothing is clamped or tonemapped.
+pub fn write(out: impl Write, width: u64, height: u64, pixels: &[Color], alpha: Option<&[f64]>, half: bool) -> io::Result<()> {
+    let channel = |c: usize| pixels.iter().map(|p| p[c]).collect::<Vec<f64>>();
+    let mut channels = vec![("B".to_string(), channel(2)), ("G".to_string(), channel(1)), ("R".to_string(), channel(0))];
+    if let Some(alpha) = alpha {
+        channels.insert(0, ("A".to_string(), alpha.to_vec()));
+    }
+    write_channels(out, width, height, &channels, half, &[])
+}
+
+//Write any channels, each a name and its values row-major from the top, with string
+//attributes (name, value) added to the header. Channels are stored in alphabetical
+//order whatever order they're given in.
+pub fn write_channels(mut out: impl Write, width: u64, height: u64, channels: &[(String, Vec<f64>)], half: bool, strings: &[(String, String)]) -> io::Result<()> {
+    let (width, height) = (width as usize, height as usize);
+    let (pixel_type, size) = if half { (1i32, 2) } else { (2, 4) };
+    let mut channels: Vec<&(String, Vec<f64>)> = channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = MAGIC.to_vec();
+    //Long names (over 31 characters) need the flag for them
+    let long_names = channels.iter().map(|c| &c.0).chain(strings.iter().map(|s| &s.0)).any(|n| n.len() > 31);
+    header.extend((2u32 | if long_names { 0x400 } else { 0 }).to_le_bytes());
+    let mut list = Vec::new();
+    for (name, _) in &channels {
+        list.extend(name.bytes().chain([0]));
+        list.extend(pixel_type.to_le_bytes());
+        //pLinear and three reserved bytes, then x and y sampling
+        list.extend([0; 4]);
+        list.extend([1i32.to_le_bytes(), 1i32.to_le_bytes()].concat());
+    }
+    list.push(0);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+    attribute(&mut header, "channels", "chlist", &list);
+    attribute(&mut header, "compression", "compression", &[3]);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    for (name, value) in strings {
+        attribute(&mut header, name, "string", value.as_bytes());
+    }
+    header.push(0);
+
+    let lines_per_block = Compression::Zip.lines_per_block();
+    let mut blocks = Vec::new();
+    for first in (0..height).step_by(lines_per_block) {
+        let mut raw = Vec::with_capacity(lines_per_block * width * channels.len() * size);
+        for y in first..(first + lines_per_block).min(height) {
+            for (_, values) in &channels {
+                for &value in &values[y * width..(y + 1) * width] {
+                    if half {
+                        raw.extend(f64_to_half(value).to_le_bytes());
+                    } else {
+                        raw.extend((value as f32).to_le_bytes());
+                    }
+                }
+            }
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&predict(&raw))?;
+        let packed = encoder.finish()?;
+        //A block that doesn't shrink is stored as it is
+        blocks.push((first as i32, if packed.len() < raw.len() { packed } else { raw }));
+    }
+
+    let mut offset = (header.len() + 8 * blocks.len()) as u64;
+    out.write_all(&header)?;
+    for (_, data) in &blocks {
+        out.write_all(&offset.to_le_bytes())?;
+        offset += 8 + data.len() as u64;
+    }
+    for (y, data) in &blocks {
+        out.write_all(&y.to_le_bytes())?;
+        out.write_all(&(data.len() as i32).to_le_bytes())?;
+        out.write_all(data)?;
+    }
+    out.flush()
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend(name.bytes().chain([0]));
+    header.extend(kind.bytes().chain([0]));
+    header.extend((value.len() as i32).to_le_bytes());
+    header.extend(value);
+}
+
+//One little-endian value of the given type
+fn sample(bytes: &[u8], pixel_type: i32) -> f64 {
+    match pixel_type {
+        0 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        1 => half_to_f64(u16::from_le_bytes([bytes[0], bytes[1]])),
+        _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+    }
+}
+
+pub fn half_to_f64(h: u16) -> f64 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f64;
+    match exponent {
+        0 => sign * mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => sign * f64::INFINITY,
+        31 => f64::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+//Nearest half float, rounding ties to even. Too big for a half becomes infinity.
+fn f64_to_half(x: f64) -> u16 {
+    let bits = (x as f32).to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let e = exponent - 127 + 15;
+    if e >= 31 {
+        return sign | 0x7c00;
+    }
+    if e <= 0 {
+        if e < -10 {
+            return sign;
+        }
+        //Subnormal: put the leading 1 back and shift it down into place
+        let m = mantissa | 0x80_0000;
+        let shift = (14 - e) as u32;
+        let rounded = (m + (1 << (shift - 1)) - 1 + ((m >> shift) & 1)) >> shift;
+        return sign | rounded as u16;
+    }
+    //A carry out of the mantissa moves up the exponent, as it should
+    let h = (e as u32) << 10 | mantissa >> 13;
+    let rest = mantissa & 0x1fff;
+    let round = (rest > 0x1000 || (rest == 0x1000 && h & 1 == 1)) as u32;
+    sign | (h + round) as u16
+}
+
+//The reverse of unpredict: the even bytes then the odd ones, as differences
+fn predict(raw: &[u8]) -> Vec<u8> {
+    let mut t: Vec<u8> = raw.iter().step_by(2).chain(raw.iter().skip(1).step_by(2)).copied().collect();
+    for i in (1..t.len()).rev() {
+        t[i] = t[i].wrapping_sub(t[i - 1]).wrapping_add(128);
+    }
+    t
+}
+
+//RLE and ZIP blocks hold byte differences, with the two halves of the data
+//interleaved. Undo the differences, then the interleaving.
+fn unpredict(mut t: Vec<u8>) -> Vec<u8> {
+    for i in 1..t.len() {
+        t[i] = t[i - 1].wrapping_add(t[i]).wrapping_sub(128);
+    }
+    let half = t.len().div_ceil(2);
+    let mut out = Vec::with_capacity(t.len());
+    for i in 0..half {
+        out.push(t[i]);
+        if half + i < t.len() {
+            out.push(t[half + i]);
+        }
+    }
+    out
+}
+
+//A negative count is followed by that many literal bytes, otherwise one byte repeated
+//count + 1 times
+fn unrle(packed: &[u8], expected: usize) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(expected);
+    let mut i = 0;
+    while i < packed.len() {
+        let count = packed[i] as i8;
+        i += 1;
+        if count < 0 {
+            let n = (-(count as i32)) as usize;
+            out.extend_from_slice(packed.get(i..i + n).ok_or("truncated RLE block")?);
+            i += n;
+        } else {
+            let value = *packed.get(i).ok_or("truncated RLE block")?;
+            out.extend(std::iter::repeat_n(value, count as usize + 1));
+            i += 1;
+        }
+        if out.len() > expected {
+            return Err("RLE block is too big".to_string());
+        }
+    }
+    Ok(out)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let bytes = self.pos.checked_add(n).and_then(|end| self.data.get(self.pos..end)).ok_or("unexpected end of file")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(self.u32()? as i32)
+    }
+
+    //A byte count, which can't be negative
+    fn size(&mut self) -> Result<usize, String> {
+        usize::try_from(self.i32()?).map_err(|_| "negative size".to_string())
+    }
+
+    //Null-terminated
+    fn string(&mut self) -> Result<String, String> {
+        let end = self.data[self.pos..].iter().position(|&b| b == 0).ok_or("unexpected end of file")?;
+        let s = String::from_utf8_lossy(&self.data[self.pos..self.pos + end]).into_owned();
+        self.pos += end + 1;
+        Ok(s)
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+
+
+
+//Pixel reconstruction filters: how much a sample counts towards a pixel by how far it
+//is from the pixel's centre. Tiles are rendered independently, so rather than splatting
+//each sample into its neighbours too, samples are spread around each pixel in
+//proportion to the filter (filter importance sampling) and averaged as usual. That
+//comes to the same image on average, and wider filters cost nothing extra per sample.
+//Every filter here is separable: the weight is evaluate(dx) * evaluate(dy).
+pub trait Filter: Send + Sync {
+    //Half-width in pixels: the filter is zero further than this from the centre
+    fn radius(&self) -> f64;
+    fn evaluate(&self, x: f64) -> f64;
+}
+
+//Falls off linearly to zero at radius
+pub struct Tent {
+    pub radius: f64,
+}
+
+impl Filter for Tent {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        (1.0 - x.abs() / self.radius).max(0.0)
+    }
+}
+
+//Gaussian of standard deviation sigma, shifted down to reach zero at radius
+pub struct Gaussian {
+    pub radius: f64,
+    pub sigma: f64,
+}
+
+impl Filter for Gaussian {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        let g = |x: f64| (-x * x / (2.0 * self.sigma * self.sigma)).exp();
+        (g(x) - g(self.radius)).max(0.0)
+    }
+}
+
+//Mitchell-Netravali cubic. Its negative lobes sharpen edges more than the others, at
+//the cost of slight ringing; b = c = 1/3 is the usual balance.
+pub struct Mitchell {
+    pub radius: f64,
+    pub b: f64,
+    pub c: f64,
+}
+
+impl Filter for Mitchell {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        let (b, c) = (self.b, self.c);
+        let x = (2.0 * x / self.radius).abs();
+        if x < 1.0 {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+        } else if x < 2.0 {
+            ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+        } else {
+            0.0
+        }
+    }
+}
+
+//A filter tabulated for picking sample positions in proportion to its magnitude. Where
+//it's negative the sample's weight is negative; weights average out to 1.
+pub struct FilterSampler {
+    radius: f64,
+    //Running total of |filter| over the bins, normalized to end at 1
+    cdf: Vec<f64>,
+    //What a sample from each bin is weighted by, along one axis
+    weights: Vec<f64>,
+}
+
+impl FilterSampler {
+    const BINS: usize = 64;
+
+    pub fn new(filter: &dyn Filter) -> FilterSampler {
+        let radius = filter.radius();
+        let width = 2.0 * radius / Self::BINS as f64;
+        let values: Vec<f64> = (0..Self::BINS).map(|i| filter.evaluate(-radius + (i as f64 + 0.5) * width)).collect();
+        let signed: f64 = values.iter().sum();
+        let magnitude: f64 = values.iter().map(|v| v.abs()).sum();
+
+        let mut cdf = vec![0.0];
+        for v in &values {
+            cdf.push(cdf.last().unwrap() + v.abs() / magnitude);
+        }
+        let weights = values.iter().map(|v| v.signum() * magnitude / signed).collect();
+        FilterSampler { radius, cdf, weights }
+    }
+
+    //Where a sample goes from (u, v) in [0, 1)^2, relative to the pixel's corner (so
+    //(0.5, 0.5) is its centre and the sample may land in a neighbour), and its weight
+    pub fn sample(&self, u: f64, v: f64) -> (f64, f64, f64) {
+        let (x, wx) = self.sample_1d(u);
+        let (y, wy) = self.sample_1d(v);
+        (x, y, wx * wy)
+    }
+
+    fn sample_1d(&self, u: f64) -> (f64, f64) {
+        let bin = self.cdf.partition_point(|&c| c <= u).clamp(1, Self::BINS) - 1;
+        let (lo, hi) = (self.cdf[bin], self.cdf[bin + 1]);
+        let within = if hi > lo { (u - lo) / (hi - lo) } else { 0.5 };
+        let width = 2.0 * self.radius / Self::BINS as f64;
+        (0.5 - self.radius + (bin as f64 + within) * width, self.weights[bin])
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum FilterKind {
+    //Each sample counts only towards its own pixel, equally
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+impl FilterKind {
+    //None for the box filter, which needs no table
+    pub fn sampler(self) -> Option<Arc<FilterSampler>> {
+        let filter: Box<dyn Filter> = match self {
+            FilterKind::Box => return None,
+            FilterKind::Tent => Box::new(Tent { radius: 1.0 }),
+            FilterKind::Gaussian => Box::new(Gaussian { radius: 1.5, sigma: 0.5 }),
+            FilterKind::Mitchell => Box::new(Mitchell { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 }),
+        };
+        Some(Arc::new(FilterSampler::new(filter.as_ref())))
+    }
+}
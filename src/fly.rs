@@ -0,0 +1,130 @@
+use std::path::Path;
+
+use raytracer::tonemap::Tonemap;
+use raytracer::{RenderSettings, Scene};
+
+#[cfg(feature = "window")]
+use std::fs;
+#[cfg(feature = "window")]
+use std::time::Instant;
+#[cfg(feature = "window")]
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Scale, WindowOptions};
+#[cfg(feature = "window")]
+use raytracer::scene_file;
+#[cfg(feature = "window")]
+use raytracer::vec3::{Color, Vec3};
+#[cfg(feature = "window")]
+use raytracer::Renderer;
+
+
+
+//Fly the camera round scene in a window to find a view. WASD moves, R and F go up and
+//down (faster with Shift), and dragging with the mouse or the arrow keys look around.
+//While the camera moves each frame is a single sample per pixel; once it stops, frames
+//are averaged together so the image clears up. P writes the camera back into
+//scene_file (or prints it, without one) and Esc or Q quits.
+#[cfg(feature = "window")]
+pub fn run(mut scene: Scene, settings: RenderSettings, tonemap: Tonemap, scene_file: Option<&Path>) -> Result<(), String> {
+    let (width, height) = (settings.width as usize, settings.height as usize);
+    let scale = if width <= 640 && height <= 480 { Scale::X2 } else { Scale::X1 };
+    let options = WindowOptions { scale, ..WindowOptions::default() };
+    let mut window = minifb::Window::new("parhelia (WASD/RF: move, drag: look, P: save camera, Esc/Q: quit)", width, height, options)
+        .map_err(|e| e.to_string())?;
+    window.set_target_fps(60);
+
+    //Fresh random numbers every frame, or still frames would all be the same
+    let renderer = Renderer::new(RenderSettings { samples_per_pixel: 1, pass_samples: 1, seed: None, adaptive: None, ..settings });
+
+    //Heading as yaw round vup and pitch up from level, measured from the way the
+    //scene's camera looks
+    let start = scene.camera;
+    let up = start.vup().normalized();
+    let level = start.forward() - start.forward().dot(up) * up;
+    let ahead = if level.near_zero() { up.any_perpendicular() } else { level.normalized() };
+    let left = up.cross(ahead);
+    let (mut yaw, mut pitch) = (0.0_f64, start.forward().dot(up).clamp(-1.0, 1.0).asin());
+    let mut position = start.lookfrom();
+    //Across the scene in a few seconds, going by how far away the camera is focused
+    let speed = start.focus_dist().max(0.1) / 2.0;
+
+    let mut sum = vec![Color::new(0.0, 0.0, 0.0); width * height];
+    let mut frames = 0.0;
+    let mut buffer = vec![0u32; width * height];
+    let mut mouse = window.get_mouse_pos(MouseMode::Pass);
+    let mut last = Instant::now();
+
+    while window.is_open() && !window.is_key_down(Key::Escape) && !window.is_key_down(Key::Q) {
+        let dt = last.elapsed().as_secs_f64();
+        last = Instant::now();
+
+        //Looking round
+        let (old_yaw, old_pitch) = (yaw, pitch);
+        let turn = 1.5 * dt;
+        if window.is_key_down(Key::Left) { yaw += turn }
+        if window.is_key_down(Key::Right) { yaw -= turn }
+        if window.is_key_down(Key::Up) { pitch += turn }
+        if window.is_key_down(Key::Down) { pitch -= turn }
+        let now = window.get_mouse_pos(MouseMode::Pass);
+        if let (Some((x0, y0)), Some((x1, y1))) = (mouse, now) {
+            if window.get_mouse_down(MouseButton::Left) {
+                yaw -= 0.005 * (x1 - x0) as f64;
+                pitch -= 0.005 * (y1 - y0) as f64;
+            }
+        }
+        mouse = now;
+        pitch = pitch.clamp(-1.5, 1.5);
+        let forward = pitch.cos() * (yaw.cos() * ahead + yaw.sin() * left) + pitch.sin() * up;
+        let right = forward.cross(up).normalized();
+
+        //Moving
+        let step = speed * dt * if window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift) { 4.0 } else { 1.0 };
+        let mut moved = Vec3::new(0.0, 0.0, 0.0);
+        for (key, direction) in [(Key::W, forward), (Key::S, (-1.0) * forward), (Key::D, right), (Key::A, (-1.0) * right), (Key::R, up), (Key::F, (-1.0) * up)] {
+            if window.is_key_down(key) {
+                moved += step * direction;
+            }
+        }
+
+        if !moved.near_zero() || yaw != old_yaw || pitch != old_pitch {
+            position += moved;
+            scene.camera = start.looking(position, position + forward);
+            sum.iter_mut().for_each(|c| *c = Color::new(0.0, 0.0, 0.0));
+            frames = 0.0;
+        }
+
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            export(scene_file, position, position + forward);
+        }
+
+        let image = renderer.render(&scene);
+        frames += 1.0;
+        for ((total, &c), pixel) in sum.iter_mut().zip(&image.pixels).zip(buffer.iter_mut()) {
+            *total += c;
+            let [r, g, b] = tonemap.to_display(*total / frames);
+            *pixel = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        }
+        window.update_with_buffer(&buffer, width, height).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+//Put the camera into the scene file, keeping everything else in it as it was, or
+//print it when there's no file to put it in
+#[cfg(feature = "window")]
+fn export(scene_file: Option<&Path>, from: Vec3, at: Vec3) {
+    let Some(path) = scene_file else {
+        print!("{}", scene_file::with_camera("[camera]\n", from, at));
+        return;
+    };
+    match fs::read_to_string(path).and_then(|text| fs::write(path, scene_file::with_camera(&text, from, at))) {
+        Ok(()) => eprintln!("Saved the camera to {}", path.display()),
+        Err(e) => eprintln!("Couldn't save the camera to {}: {}", path.display(), e),
+    }
+}
+
+//Stand-in for builds without the window feature, so --fly can say what's wrong instead
+//of not being there
+#[cfg(not(feature = "window"))]
+pub fn run(_scene: Scene, _settings: RenderSettings, _tonemap: Tonemap, _scene_file: Option<&Path>) -> Result<(), String> {
+    Err("this build doesn't include the window feature (rebuild with --features window)".to_string())
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use super::camera::Camera;
+use super::hit::World;
+use super::light::Lighting;
+use super::material::{Dielectric, Lambertian, Metal, Pbr, PhongMat, Scatter, Subsurface};
+use super::ray::Ray;
+use super::render::ray_color;
+use super::background::Solid;
+use super::scene::Scene;
+use super::scene_graph::Node;
+use super::sphere::Sphere;
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+//White furnace test: a unit-albedo object in a uniform environment of radiance 1
+//should be invisible, since every path that leaves it sees the same radiance and
+//nothing is absorbed. Anything else means the material is gaining or losing energy.
+pub struct FurnaceResult {
+    pub name: &'static str,
+    pub mean: Color,
+}
+
+impl FurnaceResult {
+    //Average over channels of how far the object is from the environment
+    pub fn deviation(&self) -> f64 {
+        (self.mean[0] + self.mean[1] + self.mean[2]) / 3.0 - 1.0
+    }
+}
+
+//Every material type, set up to be energy conserving where it has the parameters for it
+fn materials() -> Vec<(&'static str, Arc<dyn Scatter>)> {
+    let white = Color::new(1.0, 1.0, 1.0);
+    vec![
+        ("lambertian", Arc::new(Lambertian::new(white))),
+        ("metal, fuzz 0", Arc::new(Metal::new(white, 0.0))),
+        ("metal, fuzz 0.5", Arc::new(Metal::new(white, 0.5))),
+        ("metal, fuzz 1", Arc::new(Metal::new(white, 1.0))),
+        ("dielectric, ior 1.5", Arc::new(Dielectric::new(1.5, 1.0))),
+        ("phong, diffuse", Arc::new(PhongMat::new(1.0, 1.0, 0.0, 0.5, 4, white, 0.0, 1.0, 0.0))),
+        ("pbr, plastic, roughness 0.5", Arc::new(Pbr::new(white, 0.0, 0.5))),
+        ("pbr, metal, roughness 0.2", Arc::new(Pbr::new(white, 1.0, 0.2))),
+        ("pbr, metal, roughness 1", Arc::new(Pbr::new(white, 1.0, 1.0))),
+        ("subsurface", Arc::new(Subsurface::new(white, white, 1.3))),
+        ("phong, specular", Arc::new(PhongMat::new(1.0, 0.0, 1.0, 0.5, 4, white, 0.0, 0.0, 0.0))),
+    ]
+}
+
+//Fire samples rays straight down -z at a unit sphere, from points spread evenly over
+//its silhouette so every ray hits, and average what comes back.
+pub fn run(samples: u64, max_depth: u64) -> Vec<FurnaceResult> {
+    materials().into_iter().map(|(name, mat)| {
+        let world: World = vec![Box::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, mat))];
+
+        let scene = Scene {
+            world,
+            graph: Node::group(),
+            lights: Lighting::new(),
+            emitters: Vec::new(),
+            camera: Camera::new(Point3::new(0.0, 0.0, 5.0), Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 45.0, 1.0, 0.0, 5.0),
+            camera_path: None,
+            cameras: HashMap::new(),
+            background: Box::new(Solid(Color::new(1.0, 1.0, 1.0))),
+            space: None,
+            integrator: None,
+            ambient: Color::new(0.0, 0.0, 0.0),
+        };
+
+        let sum = (0..samples).into_par_iter().map(|_| {
+            let d = 0.999 * Vec3::random_in_unit_disk();
+            let r = Ray::new(Point3::new(d.x(), d.y(), 5.0), Vec3::new(0.0, 0.0, -1.0));
+            ray_color(&r, &scene, max_depth)
+        }).reduce(|| Color::new(0.0, 0.0, 0.0), |a, b| a + b);
+
+        FurnaceResult { name, mean: sum / samples.max(1) as f64 }
+    }).collect()
+}
+
+//Print a table of the results; returns whether every material was within tolerance
+pub fn report(results: &[FurnaceResult], tolerance: f64) -> bool {
+    println!("{:<24}{:>28}{:>12}", "material", "mean (r, g, b)", "deviation");
+
+    let mut all_passed = true;
+    for result in results {
+        let dev = result.deviation();
+        let passed = dev.abs() <= tolerance;
+        all_passed &= passed;
+
+        let mean = format!("({:.4}, {:.4}, {:.4})", result.mean[0], result.mean[1], result.mean[2]);
+        println!("{:<24}{:>28}{:>+12.4}  {}", result.name, mean, dev, if passed { "ok" } else { "FAIL" });
+    }
+
+    all_passed
+}
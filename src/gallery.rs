@@ -0,0 +1,564 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use clap::ValueEnum;
+
+use super::background::{Background, SkyGradient, Solid};
+use super::box_obj::BoxObj;
+use super::camera::Camera;
+use super::hit::World;
+use super::light::{Lighting, SimpleLight};
+use super::material::{Dielectric, Lambertian, Metal, PhongMat};
+use super::medium::ConstantMedium;
+use super::mesh::{Triangle, TriangleMesh};
+use super::noise::WorleyMode;
+use super::propagation::{CurvedSpace, GradientIndex, Schwarzschild};
+use super::random::{random_f64, random_range};
+use super::rect::{XyRect, XzRect, YzRect};
+use super::scene::Scene;
+use super::scene_graph::Node;
+use super::sphere::{MovingSphere, Sphere};
+use super::texture::{Brick, Checker, ColorSpace, Fbm, Gradient, GradientAxis, MappedTexture, Marble, UvTransform, VertexColor, Worley};
+use super::transform::{RotateY, Translate};
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+//Scenes compiled into the binary, so there's always something canonical to render
+//(and to compare against) without writing a scene by hand first.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SceneName {
+    //Open-fronted Cornell box with a mirror and a glass ball
+    Cornell,
+    //The big random spheres scene
+    Spheres,
+    //The random spheres with the diffuse ones bouncing while the shutter is open
+    Bouncing,
+    //Glass ball over a diffuse floor, for caustics
+    GlassCaustic,
+    //Phong spheres under a ring of coloured point lights
+    ManyLights,
+    //Unit-albedo objects in a uniform white environment; anything that doesn't
+    //vanish into the background is gaining or losing energy
+    Furnace,
+    HollowSphere,
+    Textures,
+    Pyramid,
+    LowPoly,
+    //Inferior mirage: a layer of hot air over the ground bends low rays back up to the sky
+    Mirage,
+    //A black hole in front of a brick wall, lensing it into an Einstein ring
+    BlackHole,
+    //Night scene lit only by glowing spheres
+    Glow,
+    //The classic closed Cornell box, lit only by the panel in its ceiling
+    CornellBox,
+    //The Cornell box with its two boxes made of smoke, one dark and one pale
+    CornellSmoke,
+    //Checkerboard floor with a marble ball and a checkered mirror ball
+    Checker,
+}
+
+pub fn build(name: SceneName, aspect_ratio: f64) -> Scene {
+    let mut world = World::new();
+    let mut lights = Lighting::new();
+    let mut background: Box<dyn Background> = Box::new(SkyGradient);
+    let mut space = None;
+
+    let camera = match name {
+        SceneName::Cornell => {
+            setup_cornell(&mut world, &mut lights);
+            look(Point3::new(0.0, 1.0, 3.4), Point3::new(0.0, 1.0, -1.0), 40.0, aspect_ratio)
+        }
+        SceneName::Spheres | SceneName::Bouncing => {
+            let bouncing = matches!(name, SceneName::Bouncing);
+            world = random_scene(bouncing);
+            lights.push(Box::new(SimpleLight::new(Color::new(1.0, 1.0, 1.0), Color::new(1.0, 1.0, 1.0), Point3::new(10.0, 30.0, 10.0))));
+
+            let lookfrom = Point3::new(13.0, 2.0, 3.0);
+            let lookat = Point3::new(0.0, 0.0, 0.0);
+            let camera = Camera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), 20.0, aspect_ratio, 0.1, 10.0);
+            if bouncing { camera.with_shutter(0.0, 1.0) } else { camera }
+        }
+        SceneName::GlassCaustic => {
+            setup_glass_caustic(&mut world, &mut lights);
+            look(Point3::new(0.0, 1.5, 2.0), Point3::new(0.0, 0.3, -1.0), 45.0, aspect_ratio)
+        }
+        SceneName::ManyLights => {
+            setup_many_lights(&mut world, &mut lights);
+            look(Point3::new(0.0, 1.0, 2.5), Point3::new(0.0, 0.0, -1.0), 50.0, aspect_ratio)
+        }
+        SceneName::Furnace => {
+            setup_furnace(&mut world);
+            background = Box::new(Solid(Color::new(1.0, 1.0, 1.0)));
+            look(Point3::new(0.0, 0.0, 3.0), Point3::new(0.0, 0.0, 0.0), 45.0, aspect_ratio)
+        }
+        SceneName::HollowSphere => return hollow_sphere(aspect_ratio),
+        SceneName::Glow => return glow(aspect_ratio),
+        SceneName::CornellBox => return cornell_box(aspect_ratio, false),
+        SceneName::CornellSmoke => return cornell_box(aspect_ratio, true),
+        SceneName::Checker => {
+            setup_checker(&mut world, &mut lights);
+            look(Point3::new(0.0, 1.0, 2.0), Point3::new(0.0, 0.3, -1.0), 50.0, aspect_ratio)
+        }
+        SceneName::Textures => {
+            setup_texture_spheres(&mut world, &mut lights);
+            look(Point3::new(0.0, 0.3, 1.2), Point3::new(0.0, 0.0, -1.0), 90.0, aspect_ratio)
+        }
+        SceneName::Pyramid => {
+            setup_vertex_color_pyramid(&mut world, &mut lights);
+            look(Point3::new(0.6, 0.4, 0.8), Point3::new(0.0, 0.0, -1.0), 90.0, aspect_ratio)
+        }
+        SceneName::LowPoly => {
+            setup_low_poly_sphere(&mut world, &mut lights);
+            look(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, -1.0), 90.0, aspect_ratio)
+        }
+        SceneName::Mirage => {
+            space = Some(setup_mirage(&mut world));
+            look(Point3::new(0.0, 0.25, 0.0), Point3::new(0.0, 0.2, -10.0), 12.0, aspect_ratio)
+        }
+        SceneName::BlackHole => {
+            space = Some(setup_black_hole(&mut world));
+            look(Point3::new(0.0, 0.0, 6.0), Point3::new(0.0, 0.0, 0.0), 60.0, aspect_ratio)
+        }
+    };
+
+    Scene { world, graph: Node::group(), lights, emitters: Vec::new(), camera, camera_path: None, cameras: HashMap::new(), background, space, integrator: None,
+        ambient: Color::new(0.0, 0.0, 0.0) }
+}
+
+//Pinhole camera focused on lookat with y up
+fn look(lookfrom: Point3, lookat: Point3, vfov: f64, aspect_ratio: f64) -> Camera {
+    let dist_to_focus = (lookfrom - lookat).length();
+    Camera::new(lookfrom, lookat, Vec3::new(0.0, 1.0, 0.0), vfov, aspect_ratio, 0.0, dist_to_focus)
+}
+
+//The walls are one mesh with a material slot per colour. The box is open at the
+//front, so the sky lights the inside through the opening and the ceiling light
+//decides what's in shadow.
+fn setup_cornell(world: &mut World, lights: &mut Lighting) {
+    let red = Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
+    let green = Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let mirror = Arc::new(Metal::new(Color::new(0.9, 0.9, 0.9), 0.0));
+    let glass = Arc::new(Dielectric::new(1.5, 1.0));
+
+    //Box spans x in [-1, 1], y in [0, 2], z in [-2, 0]
+    let positions = vec![
+        Point3::new(-1.0, 0.0, 0.0),
+        Point3::new(1.0, 0.0, 0.0),
+        Point3::new(1.0, 0.0, -2.0),
+        Point3::new(-1.0, 0.0, -2.0),
+        Point3::new(-1.0, 2.0, 0.0),
+        Point3::new(1.0, 2.0, 0.0),
+        Point3::new(1.0, 2.0, -2.0),
+        Point3::new(-1.0, 2.0, -2.0),
+    ];
+    let (red_slot, green_slot, white_slot) = (0, 1, 2);
+    let quad = |a, b, c, d, material| [
+        Triangle { vertices: [a, b, c], material },
+        Triangle { vertices: [a, c, d], material },
+    ];
+    let triangles = [
+        quad(0, 3, 7, 4, red_slot),
+        quad(1, 5, 6, 2, green_slot),
+        quad(3, 2, 6, 7, white_slot),
+        quad(0, 1, 2, 3, white_slot),
+        quad(4, 7, 6, 5, white_slot),
+    ].concat();
+
+    world.push(Box::new(TriangleMesh::new(positions, triangles, vec![red, green, white])));
+
+    world.push(Box::new(Sphere::new(Point3::new(-0.45, 0.35, -1.3), 0.35, mirror)));
+    world.push(Box::new(Sphere::new(Point3::new(0.45, 0.35, -0.6), 0.35, glass)));
+
+    lights.push(Box::new(SimpleLight::new(Color::new(1.0, 1.0, 1.0), Color::new(1.0, 1.0, 1.0), Point3::new(0.0, 1.9, -1.0))));
+}
+
+fn setup_glass_caustic(world: &mut World, lights: &mut Lighting) {
+    let ground = Arc::new(Lambertian::new(Color::new(0.7, 0.7, 0.7)));
+    let glass = Arc::new(Dielectric::new(1.5, 1.0));
+
+    world.push(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground)));
+    world.push(Box::new(Sphere::new(Point3::new(0.0, 0.5, -1.0), 0.5, glass)));
+
+    lights.push(Box::new(SimpleLight::new(Color::new(1.0, 1.0, 1.0), Color::new(1.0, 1.0, 1.0), Point3::new(1.0, 3.0, -1.5))));
+}
+
+fn setup_many_lights(world: &mut World, lights: &mut Lighting) {
+    const LIGHT_COUNT: usize = 8;
+
+    let ground = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.push(Box::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, ground)));
+
+    for (i, x) in [-1.1, 0.0, 1.1].into_iter().enumerate() {
+        let mat = Arc::new(PhongMat::new(
+            1.0,
+            0.8,
+            0.6,
+            24.0,
+            8,
+            Color::new(0.8, 0.8, 0.8),
+            0.05 * i as f64,
+            0.7,
+            0.0,
+        ));
+        world.push(Box::new(Sphere::new(Point3::new(x, 0.0, -1.0), 0.5, mat)));
+    }
+
+    //Ring of lights above the spheres, hue going round the ring
+    for i in 0..LIGHT_COUNT {
+        let angle = 2.0 * std::f64::consts::PI * i as f64 / LIGHT_COUNT as f64;
+        let color = Color::new(
+            0.5 + 0.5 * angle.cos(),
+            0.5 + 0.5 * (angle + 2.0 * std::f64::consts::PI / 3.0).cos(),
+            0.5 + 0.5 * (angle + 4.0 * std::f64::consts::PI / 3.0).cos(),
+        ) / LIGHT_COUNT as f64 * 3.0;
+        let origin = Point3::new(2.0 * angle.cos(), 1.5, -1.0 + 2.0 * angle.sin());
+
+        lights.push(Box::new(SimpleLight::new(color, color, origin)));
+    }
+}
+
+fn setup_furnace(world: &mut World) {
+    let white = Color::new(1.0, 1.0, 1.0);
+    let lambertian = Arc::new(Lambertian::new(white));
+    let metal = Arc::new(Metal::new(white, 0.5));
+    let glass = Arc::new(Dielectric::new(1.5, 1.0));
+    let phong = Arc::new(PhongMat::new(1.0, 1.0, 0.0, 0.5, 4, white, 0.0, 1.0, 0.0));
+
+    world.push(Box::new(Sphere::new(Point3::new(-1.5, 0.0, 0.0), 0.45, lambertian)));
+    world.push(Box::new(Sphere::new(Point3::new(-0.5, 0.0, 0.0), 0.45, metal)));
+    world.push(Box::new(Sphere::new(Point3::new(0.5, 0.0, 0.0), 0.45, glass)));
+    world.push(Box::new(Sphere::new(Point3::new(1.5, 0.0, 0.0), 0.45, phong)));
+}
+
+//The original test scene: a Phong sphere on a yellow ground, lit from the right
+fn hollow_sphere(aspect_ratio: f64) -> Scene {
+    scene! {
+        camera: { from: (0.0, 0.0, 0.0), at: (0.0, 0.0, -1.0), vfov: 90.0, aspect: aspect_ratio },
+        materials: {
+            ground = lambertian(0.8, 0.8, 0.0),
+            centre = lambertian(0.1, 0.2, 0.5),
+            phong = custom(PhongMat::new(1.0, 1.0, 0.0, 0.5, 4, Color::new(0.1, 0.2, 0.5), 0.0, 1.0, 0.0)),
+            //Hollow glass ball: a negative radius flips the normals of the inner surface
+            //glass = dielectric(1.5),
+            //gold = metal(0.8, 0.6, 0.2, 0.0),
+        },
+        objects: {
+            plane((0.0, -0.5, 0.0), (0.0, 1.0, 0.0), ground),
+            sphere((0.0, 0.0, -1.0), 0.5, centre),
+            //sphere((-1.0, 0.0, -1.0), 0.5, glass),
+            //sphere((-1.0, 0.0, -1.0), -0.4, glass),
+            //sphere((1.0, 0.0, -1.0), 0.5, gold),
+            sphere((0.0, 0.0, -1.0), 0.5, phong),
+        },
+        lights: {
+            point((2.0, 0.0, -1.0)),
+            //point((0.0, 1.0, -1.0), specular: (1.0, 0.0, 0.0)),
+        },
+    }
+}
+
+//No point lights and a black sky, so every bit of light comes from the emitters and
+//reaches the camera by path tracing alone
+fn glow(aspect_ratio: f64) -> Scene {
+    scene! {
+        camera: { from: (0.0, 1.0, 3.0), at: (0.0, 0.4, -1.0), vfov: 45.0, aspect: aspect_ratio },
+        background: Solid(Color::new(0.0, 0.0, 0.0)),
+        materials: {
+            ground = lambertian(0.6, 0.6, 0.6),
+            matte = lambertian(0.2, 0.4, 0.8),
+            glass = dielectric(1.5),
+            chrome = metal(0.9, 0.9, 0.9, 0.05),
+            moon = diffuse_light(3.0, 3.0, 2.7),
+            ember = diffuse_light(6.0, 1.8, 0.3),
+        },
+        objects: {
+            sphere((0.0, -1000.0, 0.0), 1000.0, ground),
+            sphere((-1.1, 0.5, -1.0), 0.5, matte),
+            sphere((0.0, 0.5, -1.0), 0.5, glass),
+            sphere((1.1, 0.5, -1.0), 0.5, chrome),
+        },
+        emitters: {
+            sphere((0.0, 4.0, -2.0), 1.5, moon),
+            sphere((0.5, 0.15, 0.0), 0.15, ember),
+        },
+        lights: {},
+    }
+}
+
+//Dimensions from the original Cornell box, with two plain boxes inside, or two boxes
+//of smoke. The light faces down, since emitters only glow from the front.
+fn cornell_box(aspect_ratio: f64, smoke: bool) -> Scene {
+    let white: Arc<Lambertian> = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let mut scene = scene! {
+        camera: { from: (278.0, 278.0, -800.0), at: (278.0, 278.0, 0.0), vfov: 40.0, aspect: aspect_ratio },
+        background: Solid(Color::new(0.0, 0.0, 0.0)),
+        materials: {
+            red = lambertian(0.65, 0.05, 0.05),
+            green = lambertian(0.12, 0.45, 0.15),
+            light = diffuse_light(15.0, 15.0, 15.0),
+        },
+        objects: {
+            custom(YzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green.clone())),
+            custom(YzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red.clone())),
+            custom(XzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white.clone())),
+            custom(XzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white.clone())),
+            custom(XyRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white.clone())),
+        },
+        emitters: {
+            custom(XzRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light.clone()).flipped()),
+        },
+        lights: {},
+    };
+
+    let tall = Translate::new(
+        Arc::new(RotateY::new(Arc::new(BoxObj::new(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white.clone())), 15.0)),
+        Vec3::new(265.0, 0.0, 295.0),
+    );
+    let short = Translate::new(
+        Arc::new(RotateY::new(Arc::new(BoxObj::new(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 165.0, 165.0), white)), -18.0)),
+        Vec3::new(130.0, 0.0, 65.0),
+    );
+    if smoke {
+        scene.world.push(Box::new(ConstantMedium::new(Arc::new(tall), 0.01, Color::new(0.0, 0.0, 0.0))));
+        scene.world.push(Box::new(ConstantMedium::new(Arc::new(short), 0.01, Color::new(1.0, 1.0, 1.0))));
+    } else {
+        scene.world.push(Box::new(tall));
+        scene.world.push(Box::new(short));
+    }
+    scene
+}
+
+fn setup_checker(world: &mut World, lights: &mut Lighting) {
+    let floor = Arc::new(Lambertian::with_texture(Arc::new(Checker::from_colors(
+        2.0,
+        Color::new(0.2, 0.3, 0.1),
+        Color::new(0.9, 0.9, 0.9),
+    ))));
+    let marble = Arc::new(Lambertian::with_texture(Arc::new(Marble::new(
+        4.0,
+        Color::new(0.15, 0.15, 0.2),
+        Color::new(0.95, 0.93, 0.9),
+        3,
+    ))));
+    //Checks small enough to read as a pattern on the reflection
+    let mirror = Arc::new(Metal::with_texture(Arc::new(Checker::from_colors(
+        10.0,
+        Color::new(0.9, 0.8, 0.5),
+        Color::new(0.6, 0.6, 0.65),
+    )), 0.0));
+
+    world.push(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, floor)));
+    world.push(Box::new(Sphere::new(Point3::new(-0.6, 0.5, -1.0), 0.5, marble)));
+    world.push(Box::new(Sphere::new(Point3::new(0.6, 0.5, -1.0), 0.5, mirror)));
+
+    lights.push(Box::new(SimpleLight::new(Color::new(1.0, 1.0, 1.0), Color::new(1.0, 1.0, 1.0), Point3::new(0.0, 4.0, 1.0))));
+}
+
+//One sphere per procedural texture, lined up along x
+fn setup_texture_spheres(world: &mut World, lights: &mut Lighting) {
+    //Bricks are authored one unit wide and tiled across the ground with a UV transform
+    let bricks = Arc::new(Brick::new(
+        Color::new(0.75, 0.45, 0.35),
+        Color::new(0.9, 0.9, 0.88),
+        1.0,
+        0.5,
+        0.1,
+    ));
+    let ground = Arc::new(Lambertian::with_texture(Arc::new(MappedTexture::new(
+        bricks,
+        UvTransform::new((50.0, 100.0), (0.0, 0.0), 15.0),
+        ColorSpace::Srgb,
+    ))));
+    let fbm = Arc::new(Lambertian::with_texture(Arc::new(Fbm::new(
+        4.0, 6, 2.0, 0.5,
+        Color::new(0.1, 0.1, 0.3),
+        Color::new(0.9, 0.9, 1.0),
+        1,
+    ))));
+    let cells = Arc::new(Lambertian::with_texture(Arc::new(Worley::new(
+        6.0,
+        WorleyMode::F2MinusF1,
+        Color::new(0.1, 0.05, 0.0),
+        Color::new(0.9, 0.7, 0.3),
+        2,
+    ))));
+    //Ramp stops are given as linear values, so they're flagged as such and not decoded
+    let ramp = Arc::new(Lambertian::with_texture(Arc::new(MappedTexture::new(
+        Arc::new(Gradient::new(
+            vec![
+                (0.0, Color::new(0.8, 0.1, 0.1)),
+                (0.5, Color::new(0.9, 0.9, 0.1)),
+                (1.0, Color::new(0.1, 0.2, 0.8)),
+            ],
+            GradientAxis::U,
+        )),
+        UvTransform::identity(),
+        ColorSpace::Linear,
+    ))));
+
+    world.push(Box::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, ground)));
+    world.push(Box::new(Sphere::new(Point3::new(-1.1, 0.0, -1.0), 0.5, fbm)));
+    world.push(Box::new(Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, cells)));
+    world.push(Box::new(Sphere::new(Point3::new(1.1, 0.0, -1.0), 0.5, ramp)));
+
+    lights.push(Box::new(SimpleLight::new(Color::new(1.0, 1.0, 1.0), Color::new(1.0, 1.0, 1.0), Point3::new(0.0, 2.0, 0.0))));
+}
+
+//Square pyramid whose sides use vertex colours and whose base uses a second material slot
+fn setup_vertex_color_pyramid(world: &mut World, lights: &mut Lighting) {
+    let mat_ground = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let mat_sides = Arc::new(Lambertian::with_texture(Arc::new(VertexColor::new(Color::new(0.5, 0.5, 0.5)))));
+    let mat_base = Arc::new(Metal::new(Color::new(0.8, 0.8, 0.8), 0.1));
+
+    let positions = vec![
+        Point3::new(-0.5, -0.5, -1.5),
+        Point3::new(0.5, -0.5, -1.5),
+        Point3::new(0.5, -0.5, -0.5),
+        Point3::new(-0.5, -0.5, -0.5),
+        Point3::new(0.0, 0.5, -1.0),
+    ];
+    let colors = vec![
+        Color::new(0.9, 0.1, 0.1),
+        Color::new(0.1, 0.9, 0.1),
+        Color::new(0.1, 0.1, 0.9),
+        Color::new(0.9, 0.9, 0.1),
+        Color::new(0.9, 0.9, 0.9),
+    ];
+    let sides = 0;
+    let base = 1;
+    let triangles = vec![
+        Triangle { vertices: [0, 4, 1], material: sides },
+        Triangle { vertices: [1, 4, 2], material: sides },
+        Triangle { vertices: [2, 4, 3], material: sides },
+        Triangle { vertices: [3, 4, 0], material: sides },
+        Triangle { vertices: [0, 1, 2], material: base },
+        Triangle { vertices: [0, 2, 3], material: base },
+    ];
+
+    //Planar projection from above for the texture coords
+    let uvs = positions.iter().map(|p| (p.x() + 0.5, p.z() + 1.5)).collect();
+
+    let pyramid = TriangleMesh::new(positions, triangles, vec![mat_sides, mat_base])
+        .with_uvs(uvs)
+        .with_colors(colors);
+
+    world.push(Box::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, mat_ground)));
+    world.push(Box::new(pyramid));
+
+    lights.push(Box::new(SimpleLight::new(Color::new(1.0, 1.0, 1.0), Color::new(1.0, 1.0, 1.0), Point3::new(1.0, 2.0, 0.5))));
+}
+
+//Coarse smooth-shaded mesh sphere lit from the side, where the shadow terminator shows up
+fn setup_low_poly_sphere(world: &mut World, lights: &mut Lighting) {
+    let mat_ground = Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+    let mat_sphere = Arc::new(Lambertian::new(Color::new(0.7, 0.3, 0.3)));
+
+    world.push(Box::new(Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, mat_ground)));
+    world.push(Box::new(TriangleMesh::uv_sphere(Point3::new(0.0, 0.0, -1.0), 0.5, 8, 12, mat_sphere)));
+
+    lights.push(Box::new(SimpleLight::new(Color::new(1.0, 1.0, 1.0), Color::new(1.0, 1.0, 1.0), Point3::new(2.0, 0.5, -1.0))));
+}
+
+//Ground with a few posts running off towards the horizon. The air's refractive index
+//drops towards the ground over the bottom half metre, so rays heading slightly down
+//curve back up before they reach it and the ground near the horizon shows the sky.
+fn setup_mirage(world: &mut World) -> CurvedSpace {
+    let ground = Arc::new(Lambertian::with_texture(Arc::new(Worley::new(2.0, WorleyMode::F1, Color::new(0.12, 0.1, 0.08), Color::new(0.3, 0.25, 0.2), 7))));
+    world.push(Box::new(Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground)));
+
+    for i in 0..6 {
+        let z = -6.0 - 4.0 * i as f64;
+        let x = if i % 2 == 0 { -1.0 } else { 1.0 };
+        let mat = Arc::new(Lambertian::new(Color::new(0.7, 0.15 + 0.1 * i as f64, 0.1)));
+        world.push(Box::new(Sphere::new(Point3::new(x, 0.4, z), 0.4, mat)));
+    }
+
+    let hot_air = |p: Point3| 1.0 - 0.006 * (-p.y().max(0.0) / 0.5).exp();
+    CurvedSpace::new(Box::new(GradientIndex::new(Box::new(hot_air))), Point3::new(0.0, 0.0, -15.0), 40.0, 2.0)
+}
+
+//Wall of bricks behind a black hole. Lensing only matters within a few Schwarzschild
+//radii, so the curved region stops well short of the wall.
+fn setup_black_hole(world: &mut World) -> CurvedSpace {
+    let bricks = MappedTexture::new(
+        Arc::new(Brick::new(Color::new(0.6, 0.2, 0.1), Color::new(0.85, 0.85, 0.8), 0.25, 0.1, 0.02)),
+        UvTransform::new((6.0, 4.0), (0.0, 0.0), 0.0),
+        ColorSpace::Linear,
+    );
+    let wall = Arc::new(Lambertian::with_texture(Arc::new(bricks)));
+
+    let positions = vec![
+        Point3::new(-15.0, -9.0, -10.0),
+        Point3::new(15.0, -9.0, -10.0),
+        Point3::new(15.0, 9.0, -10.0),
+        Point3::new(-15.0, 9.0, -10.0),
+    ];
+    let triangles = vec![
+        Triangle { vertices: [0, 1, 2], material: 0 },
+        Triangle { vertices: [0, 2, 3], material: 0 },
+    ];
+    let uvs = vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+    world.push(Box::new(TriangleMesh::new(positions, triangles, vec![wall]).with_uvs(uvs)));
+
+    let centre = Point3::new(0.0, 0.0, 0.0);
+    CurvedSpace::new(Box::new(Schwarzschild::new(centre, 0.5)), centre, 8.0, 0.25)
+}
+
+//With bouncing, the diffuse spheres jump up by a random amount over the shutter
+//interval [0, 1]
+fn random_scene(bouncing: bool) -> World {
+    let mut world = World::new();
+
+    let ground_mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let ground_sphere = Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_mat);
+
+    world.push(Box::new(ground_sphere));
+
+    for a in -11..=11 {
+        for b in -11..=11 {
+            let choose_mat = random_f64();
+            let center = Point3::new((a as f64) + random_range(0.0..0.9),
+                                     0.2,
+                                     (b as f64) + random_range(0.0..0.9));
+
+            if choose_mat < 0.8 {
+                // Diffuse
+                let albedo = Color::random(0.0..1.0) * Color::random(0.0..1.0);
+                let sphere_mat = Arc::new(Lambertian::new(albedo));
+                if bouncing {
+                    let center1 = center + Vec3::new(0.0, random_range(0.0..0.5), 0.0);
+                    world.push(Box::new(MovingSphere::new(center, center1, 0.0, 1.0, 0.2, sphere_mat)));
+                } else {
+                    world.push(Box::new(Sphere::new(center, 0.2, sphere_mat)));
+                }
+            } else if choose_mat < 0.95 {
+                // Metal
+                let albedo = Color::random(0.4..1.0);
+                let fuzz = random_range(0.0..0.5);
+                let sphere_mat = Arc::new(Metal::new(albedo, fuzz));
+                world.push(Box::new(Sphere::new(center, 0.2, sphere_mat)));
+            } else {
+                // Glass
+                let sphere_mat = Arc::new(Dielectric::new(1.5, 1.0));
+                world.push(Box::new(Sphere::new(center, 0.2, sphere_mat)));
+            }
+        }
+    }
+
+    let mat1 = Arc::new(Dielectric::new(1.5, 1.0));
+    let mat2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    let mat3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+
+    let sphere1 = Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, mat1);
+    let sphere2 = Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, mat2);
+    let sphere3 = Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, mat3);
+
+    world.push(Box::new(sphere1));
+    world.push(Box::new(sphere2));
+    world.push(Box::new(sphere3));
+
+    world
+}
\ No newline at end of file
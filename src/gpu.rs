@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::bvh::Bvh;
+use super::camera::Projection;
+use super::image::Image;
+use super::integrator::{IntegratorKind, Tracer};
+use super::light::LightGroups;
+use super::material::Plain;
+use super::renderer::RenderSettings;
+use super::scene::Scene;
+use super::scheduler::{CancelToken, ProgressReport};
+use super::vec3::{Color, Point3, Vec3};
+
+#[cfg(feature = "gpu")]
+use super::random::{hash, random_f64};
+
+
+
+//Whether render does anything in this build, so callers can say so before rendering
+pub const AVAILABLE: bool = cfg!(feature = "gpu");
+
+//A scene as gpu.wgsl takes it, in the 32-bit words of its buffers: the spheres in the
+//order the BVH's leaves cover them, the BVH's nodes, the materials and the point lights.
+//Only scenes of plain spheres (see Hit::sphere) of Plain materials, seen through a
+//perspective camera against a gradient or one colour, lit by point lights if at all, can
+//be packed; glass is taken to be in air, not overlapping other glass. Only render reads
+//what's packed, so without the gpu feature it's built just to be checked.
+#[cfg_attr(not(feature = "gpu"), allow(dead_code))]
+pub struct GpuScene {
+    spheres: Vec<u32>,
+    nodes: Vec<u32>,
+    materials: Vec<u32>,
+    lights: Vec<u32>,
+    light_count: u32,
+    camera: ThinLens,
+    sky: (Color, Color),
+    //Light hits from the point lights directly, as the hybrid integrator does
+    direct: bool,
+}
+
+//A perspective camera as get_ray works from it
+#[cfg_attr(not(feature = "gpu"), allow(dead_code))]
+struct ThinLens {
+    origin: Point3,
+    corner: Point3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    lens_radius: f64,
+}
+
+//Words per sphere, node, material and light, as the shader's structs are laid out
+const SPHERE_WORDS: usize = 8;
+const NODE_WORDS: usize = 8;
+const MATERIAL_WORDS: usize = 8;
+const LIGHT_WORDS: usize = 12;
+
+//The kinds of material, as gpu.wgsl numbers them
+const LAMBERTIAN: u32 = 0;
+const METAL: u32 = 1;
+const DIELECTRIC: u32 = 2;
+const LIGHT: u32 = 3;
+
+impl GpuScene {
+    //scene, flattened but not yet put into a BVH, to be shaded the way tracer would. Err
+    //says what in it the GPU can't render.
+    pub fn new(scene: &Scene, tracer: Tracer) -> Result<GpuScene, String> {
+        let direct = match tracer.kind {
+            IntegratorKind::Hybrid => true,
+            IntegratorKind::Path => false,
+            _ => return Err("only the hybrid and path integrators run there".to_string()),
+        };
+        if tracer.clamp.is_some() {
+            return Err("it doesn't clamp".to_string());
+        }
+        if scene.space.is_some() {
+            return Err("rays bend in this scene".to_string());
+        }
+        if scene.world.is_empty() {
+            return Err("there's nothing in the scene".to_string());
+        }
+        let sky = scene.background.gradient().ok_or("the background isn't a sky gradient or one colour")?;
+        let camera = &scene.camera;
+        if !matches!(camera.projection(), Projection::Perspective) {
+            return Err("the camera isn't a perspective one".to_string());
+        }
+        //Rays from the middle of the lens through three corners of the image
+        let (bottom_left, bottom_right, top_left) = (camera.central_ray(0.0, 0.0), camera.central_ray(1.0, 0.0), camera.central_ray(0.0, 1.0));
+        let camera = ThinLens {
+            origin: bottom_left.origin(),
+            corner: bottom_left.origin() + bottom_left.direction(),
+            horizontal: bottom_right.direction() - bottom_left.direction(),
+            vertical: top_left.direction() - bottom_left.direction(),
+            lens_radius: camera.aperture() / 2.0,
+        };
+
+        let mut materials = Vec::new();
+        //Each material once, however many spheres share it
+        let mut material_index = HashMap::new();
+        let mut spheres = Vec::new();
+        let mut boxes = Vec::new();
+        for (i, object) in scene.world.iter().enumerate() {
+            let (Some((centre, radius, mat)), Some(bbox)) = (object.sphere(), object.bounding_box()) else {
+                return Err(format!("object {} isn't a plain sphere", i));
+            };
+            let plain = mat.plain().ok_or_else(|| format!("object {}'s material isn't a plain one of a solid colour", i))?;
+            let index = *material_index.entry(Arc::as_ptr(&mat) as *const ()).or_insert_with(|| {
+                let (kind, colour, parameter) = match plain {
+                    Plain::Lambertian { albedo } => (LAMBERTIAN, albedo, 0.0),
+                    Plain::Metal { albedo, fuzz } => (METAL, albedo, fuzz),
+                    Plain::Dielectric { ior } => (DIELECTRIC, Color::new(1.0, 1.0, 1.0), ior),
+                    Plain::Light { emit } => (LIGHT, emit, 0.0),
+                };
+                push_vec3(&mut materials, colour);
+                materials.extend([kind, to_word(parameter), to_word(mat.occlusion()), 0, 0]);
+                (materials.len() / MATERIAL_WORDS - 1) as u32
+            });
+            spheres.push((centre, radius, index));
+            boxes.push(bbox);
+        }
+
+        let bvh = Bvh::new(&boxes);
+        let mut sphere_words = Vec::with_capacity(spheres.len() * SPHERE_WORDS);
+        for &i in bvh.indices() {
+            let (centre, radius, material) = spheres[i];
+            push_vec3(&mut sphere_words, centre);
+            sphere_words.extend([to_word(radius), material, 0, 0, 0]);
+        }
+        let mut nodes = Vec::with_capacity(bvh.nodes().len() * NODE_WORDS);
+        for (bbox, start, count) in bvh.nodes() {
+            push_vec3(&mut nodes, bbox.min);
+            nodes.push(start as u32);
+            push_vec3(&mut nodes, bbox.max);
+            nodes.push(count as u32);
+        }
+
+        //Lights that light nothing plain spheres are lit by are left out
+        let mut lights = Vec::new();
+        for (i, light) in scene.lights.iter().enumerate().filter(|(_, light)| light.groups().overlaps(LightGroups::DEFAULT)) {
+            if !light.is_point() {
+                return Err(format!("light {} isn't a point light", i));
+            }
+            let falloff = light.falloff();
+            push_vec3(&mut lights, light.origin());
+            lights.push(light.gates() as u32);
+            push_vec3(&mut lights, light.diffuse());
+            lights.extend([falloff.constant, falloff.linear, falloff.quadratic].map(to_word));
+            lights.extend([0, 0]);
+        }
+        let light_count = (lights.len() / LIGHT_WORDS) as u32;
+        //A buffer can't be empty, even with nothing in it to read
+        if lights.is_empty() {
+            lights.resize(LIGHT_WORDS, 0);
+        }
+
+        Ok(GpuScene { spheres: sphere_words, nodes, materials, lights, light_count, camera, sky, direct })
+    }
+
+    //Whether settings ask for anything the GPU doesn't do
+    pub fn check(&self, settings: &RenderSettings) -> Result<(), String> {
+        let unsupported = [
+            (!settings.sampler.is_random(), "samplers other than random"),
+            (settings.filter.is_some(), "pixel filters other than box"),
+            (settings.adaptive.is_some(), "adaptive sampling"),
+            (settings.spectral, "spectral rendering"),
+            (settings.transparent, "transparent backgrounds"),
+            (settings.resampling.is_some(), "resampled lights"),
+            (settings.light_tree.is_some(), "light trees"),
+            (settings.photons.is_some(), "photon mapping"),
+        ];
+        match unsupported.iter().find(|(asked, _)| *asked) {
+            Some((_, what)) => Err(format!("it doesn't do {}", what)),
+            None => Ok(()),
+        }
+    }
+
+    //The shader's Params, for samples first_sample.. of each pixel, samples of them
+    #[cfg_attr(not(feature = "gpu"), allow(dead_code))]
+    fn params(&self, settings: &RenderSettings, seed: u64, first_sample: u64, samples: u64) -> Vec<u32> {
+        let c = &self.camera;
+        let (cu, cv) = (c.horizontal.normalized(), c.vertical.normalized());
+        let mut words = Vec::with_capacity(32);
+        push_vec3(&mut words, c.origin);
+        words.push(to_word(c.lens_radius));
+        push_vec3(&mut words, c.corner);
+        words.push(settings.width as u32);
+        push_vec3(&mut words, c.horizontal);
+        words.push(settings.height as u32);
+        push_vec3(&mut words, c.vertical);
+        words.push(first_sample as u32);
+        push_vec3(&mut words, cu);
+        words.push(samples as u32);
+        push_vec3(&mut words, cv);
+        words.push(settings.max_depth as u32);
+        push_vec3(&mut words, self.sky.0);
+        words.push(seed as u32);
+        push_vec3(&mut words, self.sky.1);
+        words.push((seed >> 32) as u32);
+        //Infinity stays finite, as WGSL doesn't promise what it does with it
+        words.extend([to_word(settings.epsilon), to_word(settings.max_distance.min(f32::MAX as f64)), self.light_count, self.direct as u32]);
+        words
+    }
+}
+
+fn to_word(x: f64) -> u32 {
+    (x as f32).to_bits()
+}
+
+fn push_vec3(words: &mut Vec<u32>, v: Vec3) {
+    words.extend([v.x(), v.y(), v.z()].map(to_word));
+}
+
+//Path traces scene with wgpu on whatever GPU it finds (preferring a discrete one), in
+//passes of settings.pass_samples samples per pixel, calling progress after each. The
+//image is the same for the same seed on the same GPU, but not the same as the CPU's:
+//the random numbers are the shader's own, and it works in single precision. Cancelling
+//stops it after the pass it's on, with the image as far as it got. Err says why it
+//couldn't render at all (no GPU, or the scene doesn't fit on it).
+#[cfg(feature = "gpu")]
+pub fn render<P: Fn(&ProgressReport)>(scene: &GpuScene, settings: &RenderSettings, cancel: &CancelToken, progress: P) -> Result<Image, String> {
+    use wgpu::util::DeviceExt;
+
+    scene.check(settings)?;
+    let (width, height) = (settings.width, settings.height);
+    let pixels = (width * height) as usize;
+    //Sum of the samples so far, and 0, for each pixel
+    let accum_size = (pixels * 4 * std::mem::size_of::<f32>()) as u64;
+
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        ..Default::default()
+    })).ok_or("no GPU found")?;
+    let limits = adapter.limits();
+    let largest = [accum_size, 4 * scene.spheres.len() as u64, 4 * scene.nodes.len() as u64].into_iter().max().unwrap_or(0);
+    if largest > (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size) {
+        return Err(format!("the image or scene needs buffers of {} bytes, more than {} allows", largest, adapter.get_info().name));
+    }
+    let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        label: Some("parhelia"),
+        required_features: wgpu::Features::empty(),
+        required_limits: limits,
+        memory_hints: wgpu::MemoryHints::Performance,
+    }, None)).map_err(|e| format!("couldn't open {}: {}", adapter.get_info().name, e))?;
+
+    device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("path tracer"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("gpu.wgsl").into()),
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("path tracer"),
+        layout: None,
+        module: &module,
+        entry_point: Some("main"),
+        compilation_options: Default::default(),
+        cache: None,
+    });
+    let storage = |label, words: &[u32]| device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: bytemuck::cast_slice(words),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    let params = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("params"),
+        size: 4 * scene.params(settings, 0, 0, 0).len() as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let spheres = storage("spheres", &scene.spheres);
+    let nodes = storage("nodes", &scene.nodes);
+    let materials = storage("materials", &scene.materials);
+    let lights = storage("lights", &scene.lights);
+    //Starts out zeroed
+    let accum = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("accum"),
+        size: accum_size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: accum_size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("scene"),
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[&params, &spheres, &nodes, &materials, &lights, &accum].into_iter().enumerate().map(|(binding, buffer)| wgpu::BindGroupEntry {
+            binding: binding as u32,
+            resource: buffer.as_entire_binding(),
+        }).collect::<Vec<_>>(),
+    });
+    if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+        return Err(format!("couldn't set the shader up: {}", e));
+    }
+    if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+        return Err(format!("not enough memory on {}: {}", adapter.get_info().name, e));
+    }
+
+    let seed = settings.seed.unwrap_or_else(|| hash(random_f64().to_bits()));
+    let spp = settings.samples_per_pixel;
+    let pass_samples = settings.pass_samples.clamp(1, spp);
+    let passes = spp.div_ceil(pass_samples) as usize;
+    let started = std::time::Instant::now();
+    let mut done = 0;
+    for pass in 0..passes {
+        if cancel.is_cancelled() {
+            break;
+        }
+        let samples = pass_samples.min(spp - done);
+        queue.write_buffer(&params, 0, bytemuck::cast_slice(&scene.params(settings, seed, done, samples)));
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("pass") });
+        {
+            let mut compute = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("pass"), timestamp_writes: None });
+            compute.set_pipeline(&pipeline);
+            compute.set_bind_group(0, &bind_group, &[]);
+            compute.dispatch_workgroups(width.div_ceil(8) as u32, height.div_ceil(8) as u32, 1);
+        }
+        queue.submit([encoder.finish()]);
+        device.poll(wgpu::Maintain::Wait);
+        done += samples;
+        progress(&ProgressReport::new(pass + 1, passes, done * width * height, spp * width * height, started.elapsed()));
+    }
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("readback") });
+    encoder.copy_buffer_to_buffer(&accum, 0, &readback, 0, accum_size);
+    queue.submit([encoder.finish()]);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let slice = readback.slice(..);
+    slice.map_async(wgpu::MapMode::Read, move |mapped| {
+        let _ = sender.send(mapped);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv().map_err(|e| e.to_string())?.map_err(|e| format!("couldn't read the image back: {}", e))?;
+    let sums = slice.get_mapped_range();
+    let count = done.max(1) as f64;
+    let pixels = bytemuck::cast_slice::<u8, f32>(&sums).chunks_exact(4)
+        .map(|sum| Color::new(sum[0] as f64 / count, sum[1] as f64 / count, sum[2] as f64 / count))
+        .collect();
+    Ok(Image { width, height, pixels })
+}
+
+//Stand-in for builds without the gpu feature, so --gpu can say what's wrong
+#[cfg(not(feature = "gpu"))]
+pub fn render<P: Fn(&ProgressReport)>(_scene: &GpuScene, _settings: &RenderSettings, _cancel: &CancelToken, _progress: P) -> Result<Image, String> {
+    Err("this build doesn't include the gpu feature (rebuild with --features gpu)".to_string())
+}
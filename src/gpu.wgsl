@@ -0,0 +1,358 @@
+//Path tracer for gpu.rs: one invocation per pixel, adding params.samples more samples to
+//its sum in accum each dispatch. The buffers are laid out by GpuScene; the shading
+//follows render.rs's hybrid and path integrators for the materials in material::Plain.
+
+struct Params {
+    origin: vec3<f32>,
+    lens_radius: f32,
+    corner: vec3<f32>,
+    width: u32,
+    horizontal: vec3<f32>,
+    height: u32,
+    vertical: vec3<f32>,
+    first_sample: u32,
+    cu: vec3<f32>,
+    samples: u32,
+    cv: vec3<f32>,
+    max_depth: u32,
+    sky_down: vec3<f32>,
+    seed_low: u32,
+    sky_up: vec3<f32>,
+    seed_high: u32,
+    epsilon: f32,
+    max_distance: f32,
+    light_count: u32,
+    //1 to light each hit from the point lights directly, as the hybrid integrator does
+    direct: u32,
+}
+
+struct Sphere {
+    centre: vec3<f32>,
+    radius: f32,
+    material: u32,
+}
+
+//Leaf: spheres[start..start + count]. Branch: count == 0, the left child is the next
+//node and start the right one.
+struct Node {
+    min: vec3<f32>,
+    start: u32,
+    max: vec3<f32>,
+    count: u32,
+}
+
+const LAMBERTIAN: u32 = 0u;
+const METAL: u32 = 1u;
+const DIELECTRIC: u32 = 2u;
+const LIGHT: u32 = 3u;
+
+struct Material {
+    //Albedo, or what a light gives off
+    colour: vec3<f32>,
+    kind: u32,
+    //Fuzz for metal, index of refraction for glass
+    parameter: f32,
+    occlusion: f32,
+}
+
+struct Light {
+    position: vec3<f32>,
+    gates: u32,
+    colour: vec3<f32>,
+    constant: f32,
+    linear: f32,
+    quadratic: f32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> spheres: array<Sphere>;
+@group(0) @binding(2) var<storage, read> nodes: array<Node>;
+@group(0) @binding(3) var<storage, read> materials: array<Material>;
+@group(0) @binding(4) var<storage, read> lights: array<Light>;
+@group(0) @binding(5) var<storage, read_write> accum: array<vec4<f32>>;
+
+const PI: f32 = 3.14159265358979;
+//As hit.rs's, for shadow rays through glass
+const MAX_CROSSINGS: u32 = 64u;
+const STACK: u32 = 64u;
+
+var<private> rng: u32;
+
+//PCG hash (Jarzynski and Olano 2020)
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+//Uniform in [0, 1)
+fn random() -> f32 {
+    rng = pcg(rng);
+    return f32(rng >> 8u) / 16777216.0;
+}
+
+fn random_in_unit_sphere() -> vec3<f32> {
+    loop {
+        let v = vec3<f32>(random(), random(), random()) * 2.0 - 1.0;
+        if length(v) < 1.0 {
+            return v;
+        }
+    }
+    return vec3<f32>(0.0);
+}
+
+fn random_in_unit_disk() -> vec2<f32> {
+    loop {
+        let v = vec2<f32>(random(), random()) * 2.0 - 1.0;
+        if length(v) < 1.0 {
+            return v;
+        }
+    }
+    return vec2<f32>(0.0);
+}
+
+fn near_zero(v: vec3<f32>) -> bool {
+    return all(abs(v) < vec3<f32>(1.0e-8));
+}
+
+struct Hit {
+    found: bool,
+    t: f32,
+    p: vec3<f32>,
+    //Against the ray, as HitRecord's
+    normal: vec3<f32>,
+    front_face: bool,
+    material: u32,
+}
+
+//Nearest root of sphere i in (t_min, t_max), or -1, as sphere.rs's hit_sphere
+fn hit_sphere(i: u32, origin: vec3<f32>, direction: vec3<f32>, t_min: f32, t_max: f32) -> f32 {
+    let sphere = spheres[i];
+    let x = origin - sphere.centre;
+    let a = dot(direction, direction);
+    let half_b = dot(direction, x);
+    let c = dot(x, x) - sphere.radius * sphere.radius;
+    let discrim = half_b * half_b - a * c;
+    if discrim < 0.0 {
+        return -1.0;
+    }
+    let sqrtd = sqrt(discrim);
+    var root = (-half_b - sqrtd) / a;
+    if root < t_min || root > t_max {
+        root = (-half_b + sqrtd) / a;
+        if root < t_min || root > t_max {
+            return -1.0;
+        }
+    }
+    return root;
+}
+
+fn hit_box(node: Node, origin: vec3<f32>, inv_dir: vec3<f32>, t_min: f32, t_max: f32) -> bool {
+    let t0 = (node.min - origin) * inv_dir;
+    let t1 = (node.max - origin) * inv_dir;
+    let near = max(max(min(t0.x, t1.x), min(t0.y, t1.y)), max(min(t0.z, t1.z), t_min));
+    let far = min(min(max(t0.x, t1.x), max(t0.y, t1.y)), min(max(t0.z, t1.z), t_max));
+    return near <= far;
+}
+
+//Closest sphere along the ray, walking the BVH as Bvh::traverse does
+fn closest(origin: vec3<f32>, direction: vec3<f32>, t_min: f32, t_max: f32) -> Hit {
+    var hit: Hit;
+    hit.found = false;
+    //Axes the ray doesn't move along get a huge inverse rather than relying on 1 / 0
+    let tiny = abs(direction) < vec3<f32>(1.0e-20);
+    let inv_dir = 1.0 / select(direction, vec3<f32>(1.0e-20), tiny);
+    var nearest = t_max;
+    var sphere = 0u;
+    var stack: array<u32, STACK>;
+    var top = 1u;
+    stack[0] = 0u;
+    while top > 0u {
+        top -= 1u;
+        let n = stack[top];
+        let node = nodes[n];
+        if !hit_box(node, origin, inv_dir, t_min, nearest) {
+            continue;
+        }
+        if node.count > 0u {
+            for (var i = node.start; i < node.start + node.count; i++) {
+                let t = hit_sphere(i, origin, direction, t_min, nearest);
+                if t >= 0.0 {
+                    nearest = t;
+                    sphere = i;
+                    hit.found = true;
+                }
+            }
+        } else if top + 2u <= STACK {
+            //Right pushed first so the left child is visited first
+            stack[top] = node.start;
+            stack[top + 1u] = n + 1u;
+            top += 2u;
+        }
+    }
+    if hit.found {
+        let s = spheres[sphere];
+        hit.t = nearest;
+        hit.p = origin + nearest * direction;
+        let outward = (hit.p - s.centre) / s.radius;
+        hit.front_face = dot(direction, outward) < 0.0;
+        hit.normal = select(-outward, outward, hit.front_face);
+        hit.material = s.material;
+    }
+    return hit;
+}
+
+//What a shadow ray from origin keeps on its way to lp, as OccludingHit::transmittance
+//does for World: all of it past clear glass, none past anything opaque
+fn transmittance(origin: vec3<f32>, direction: vec3<f32>, lp: vec3<f32>) -> vec3<f32> {
+    var through = vec3<f32>(1.0);
+    var start = origin;
+    for (var crossing = 0u; crossing < MAX_CROSSINGS; crossing++) {
+        let hit = closest(start, direction, params.epsilon, params.max_distance);
+        if !hit.found || dot(direction, lp - hit.p) <= 0.0 {
+            return through;
+        }
+        let material = materials[hit.material];
+        //Clear glass lets out all that got in
+        if material.kind != DIELECTRIC || hit.front_face {
+            through *= clamp(material.occlusion, 0.0, 1.0);
+        }
+        if near_zero(through) {
+            break;
+        }
+        start = hit.p;
+    }
+    return vec3<f32>(0.0);
+}
+
+fn attenuation(light: Light, distance: f32) -> f32 {
+    let denominator = light.constant + light.linear * distance + light.quadratic * distance * distance;
+    return select(1.0, 1.0 / denominator, denominator > 0.0);
+}
+
+//Whether any light that gates (see Light::gates) can see the hit, where there is one
+fn lit(hit: Hit) -> bool {
+    var gated = false;
+    for (var i = 0u; i < params.light_count; i++) {
+        let light = lights[i];
+        if light.gates == 0u {
+            continue;
+        }
+        gated = true;
+        if dot(hit.normal, light.position - hit.p) < 0.0 {
+            continue;
+        }
+        let direction = normalize(light.position - hit.p);
+        if !near_zero(transmittance(hit.p, direction, light.position)) {
+            return true;
+        }
+    }
+    return !gated;
+}
+
+//Light reaching a diffuse hit straight from the point lights, as render.rs's
+//direct_light
+fn direct_light(hit: Hit, albedo: vec3<f32>) -> vec3<f32> {
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < params.light_count; i++) {
+        let light = lights[i];
+        let to_light = light.position - hit.p;
+        let f = max(dot(hit.normal, normalize(to_light)), 0.0) / PI * albedo;
+        if near_zero(f) {
+            continue;
+        }
+        let shadow = transmittance(hit.p, normalize(to_light), light.position);
+        total += attenuation(light, length(to_light)) * shadow * f * light.colour;
+    }
+    return total;
+}
+
+fn schlick(cosine: f32, ratio: f32) -> f32 {
+    let r0 = pow((1.0 - ratio) / (1.0 + ratio), 2.0);
+    return r0 + (1.0 - r0) * pow(1.0 - cosine, 5.0);
+}
+
+//Light coming back along the ray, bounce by bounce rather than recursively as render.rs's
+//trace: what each hit adds is weighed by what the bounces before it let through
+fn radiance(start: vec3<f32>, start_direction: vec3<f32>) -> vec3<f32> {
+    var origin = start;
+    var direction = start_direction;
+    var total = vec3<f32>(0.0);
+    var throughput = vec3<f32>(1.0);
+    for (var depth = 0u; depth < params.max_depth; depth++) {
+        let hit = closest(origin, direction, params.epsilon, params.max_distance);
+        if !hit.found {
+            let t = 0.5 * (normalize(direction).y + 1.0);
+            total += throughput * mix(params.sky_down, params.sky_up, t);
+            break;
+        }
+        let material = materials[hit.material];
+        if material.kind == LIGHT {
+            if hit.front_face {
+                total += throughput * material.colour;
+            }
+            break;
+        }
+        if params.direct != 0u {
+            if !lit(hit) {
+                break;
+            }
+            if material.kind == LAMBERTIAN {
+                total += throughput * direct_light(hit, material.colour);
+            }
+        }
+
+        if material.kind == LAMBERTIAN {
+            var scattered = hit.normal + normalize(random_in_unit_sphere());
+            if near_zero(scattered) {
+                scattered = hit.normal;
+            }
+            direction = scattered;
+            throughput *= material.colour;
+        } else if material.kind == METAL {
+            let reflected = normalize(reflect(direction, hit.normal));
+            let scattered = reflected + material.parameter * random_in_unit_sphere();
+            if dot(scattered, hit.normal) <= 0.0 {
+                break;
+            }
+            direction = scattered;
+            throughput *= material.colour;
+        } else {
+            let ratio = select(material.parameter, 1.0 / material.parameter, hit.front_face);
+            let unit = normalize(direction);
+            let cos_theta = min(dot(-unit, hit.normal), 1.0);
+            let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+            if ratio * sin_theta > 1.0 || random() < schlick(cos_theta, ratio) {
+                direction = reflect(unit, hit.normal);
+            } else {
+                direction = refract(unit, hit.normal, ratio);
+            }
+        }
+        origin = hit.p;
+    }
+    return total;
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+    let pixel = id.y * params.width + id.x;
+    //Rows count from the top of the image, the camera's v up from the bottom
+    let j = params.height - 1u - id.y;
+    var sum = vec3<f32>(0.0);
+    for (var s = params.first_sample; s < params.first_sample + params.samples; s++) {
+        //Every sample seeded from the render's seed, pixel and number alone, so the image
+        //doesn't depend on how the work was dispatched
+        rng = pcg(params.seed_low ^ pcg(params.seed_high ^ pcg(pixel ^ pcg(s))));
+        let u = (f32(id.x) + random()) / f32(params.width - 1u);
+        let v = (f32(j) + random()) / f32(params.height - 1u);
+        let disk = params.lens_radius * random_in_unit_disk();
+        let offset = disk.x * params.cu + disk.y * params.cv;
+        let origin = params.origin + offset;
+        let direction = params.corner + u * params.horizontal + v * params.vertical - origin;
+        sum += radiance(origin, direction);
+    }
+    accum[pixel] += vec4<f32>(sum, 0.0);
+}
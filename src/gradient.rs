@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use rayon::prelude::*;
+
+use super::random::{reseed, sample_seed};
+use super::scheduler::{CancelToken, ProgressReport};
+use super::vec3::Color;
+
+
+
+//Weight of the primal image against the gradients in the reconstruction. Small values
+//trust the gradients more: less noise, but low-frequency errors can creep in.
+const ALPHA: f64 = 0.2;
+const MAX_ITERATIONS: usize = 500;
+const TOLERANCE: f64 = 1e-6;
+
+//Gradient-domain path tracing (Kettunen et al. 2015). Along with the usual estimate of
+//each pixel, estimate the differences to its right and lower neighbours by tracing the
+//neighbour again with the same random numbers ("random replay"). Paths that only
+//differ by a pixel mostly see the same things, so the noise largely cancels out of
+//the differences, and solving for the image that best fits both the pixels and the
+//differences is much cleaner than the pixels alone.
+//trace(x, y) traces one sample for the pixel at image coords (x, y), taking all its
+//random numbers from super::random. Returns the mean for each pixel, row-major from
+//the top. A seed makes the render repeatable. progress is called from the worker
+//threads as each row is finished, counting rows as the work items.
+pub fn render<F, P>(width: u64, height: u64, samples_per_pixel: u64, seed: Option<u64>, cancel: &CancelToken, trace: F, progress: P) -> Vec<Color>
+where
+    F: Fn(u64, u64) -> Color + Sync,
+    P: Fn(&ProgressReport) + Sync,
+{
+    //Otherwise different for every render, so repeated renders don't give identical noise
+    let salt: u64 = seed.unwrap_or_else(rand::random);
+    let done = AtomicUsize::new(0);
+    let started = Instant::now();
+
+    let rows: Vec<Vec<(Color, Color, Color)>> = (0..height).into_par_iter().map(|y| {
+        if cancel.is_cancelled() {
+            return vec![(Color::new(0.0, 0.0, 0.0), Color::new(0.0, 0.0, 0.0), Color::new(0.0, 0.0, 0.0)); width as usize];
+        }
+
+        let row = (0..width).map(|x| {
+            let mut primal = Color::new(0.0, 0.0, 0.0);
+            let mut dx = Color::new(0.0, 0.0, 0.0);
+            let mut dy = Color::new(0.0, 0.0, 0.0);
+
+            for s in 0..samples_per_pixel {
+                let seed = sample_seed(salt, x, y, s);
+                reseed(seed);
+                let base = trace(x, y);
+                primal += base;
+
+                if x + 1 < width {
+                    reseed(seed);
+                    dx += trace(x + 1, y) - base;
+                }
+                if y + 1 < height {
+                    reseed(seed);
+                    dy += trace(x, y + 1) - base;
+                }
+            }
+
+            let n = samples_per_pixel.max(1) as f64;
+            (primal / n, dx / n, dy / n)
+        }).collect();
+
+        let rows = done.fetch_add(1, Ordering::Relaxed) + 1;
+        progress(&ProgressReport::new(rows, height as usize, rows as u64 * width * samples_per_pixel, height * width * samples_per_pixel, started.elapsed()));
+        row
+    }).collect();
+
+    let buffers: Vec<(Color, Color, Color)> = rows.into_iter().flatten().collect();
+    let mut image = vec![Color::new(0.0, 0.0, 0.0); buffers.len()];
+    for channel in 0..3 {
+        let primal: Vec<f64> = buffers.iter().map(|b| b.0[channel]).collect();
+        let dx: Vec<f64> = buffers.iter().map(|b| b.1[channel]).collect();
+        let dy: Vec<f64> = buffers.iter().map(|b| b.2[channel]).collect();
+
+        let solved = reconstruct(width as usize, height as usize, &primal, &dx, &dy);
+        for (pixel, value) in image.iter_mut().zip(solved) {
+            pixel[channel] = value;
+        }
+    }
+
+    image
+}
+
+//Screened Poisson reconstruction: the image I minimizing
+//  ALPHA^2 |I - primal|^2 + |d/dx I - dx|^2 + |d/dy I - dy|^2
+//with forward differences, where differences off the edge of the image don't count.
+//Setting the derivative to zero gives (ALPHA^2 + L) I = b for the grid Laplacian L,
+//which is symmetric positive definite, so conjugate gradients will do.
+fn reconstruct(width: usize, height: usize, primal: &[f64], dx: &[f64], dy: &[f64]) -> Vec<f64> {
+    let a2 = ALPHA * ALPHA;
+
+    //Each difference pulls its two pixels apart
+    let mut b: Vec<f64> = primal.iter().map(|p| a2 * p).collect();
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            if x + 1 < width {
+                b[i] -= dx[i];
+                b[i + 1] += dx[i];
+            }
+            if y + 1 < height {
+                b[i] -= dy[i];
+                b[i + width] += dy[i];
+            }
+        }
+    }
+
+    let apply = |v: &[f64], out: &mut [f64]| {
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * width + x;
+                let mut sum = a2 * v[i];
+                if x > 0 { sum += v[i] - v[i - 1]; }
+                if x + 1 < width { sum += v[i] - v[i + 1]; }
+                if y > 0 { sum += v[i] - v[i - width]; }
+                if y + 1 < height { sum += v[i] - v[i + width]; }
+                out[i] = sum;
+            }
+        }
+    };
+    let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+
+    //Start from the primal image, which is already close
+    let mut x = primal.to_vec();
+    let mut ax = vec![0.0; x.len()];
+    apply(&x, &mut ax);
+    let mut r: Vec<f64> = b.iter().zip(&ax).map(|(b, ax)| b - ax).collect();
+    let mut p = r.clone();
+    let mut ap = vec![0.0; x.len()];
+    let mut rr = dot(&r, &r);
+    let threshold = TOLERANCE * TOLERANCE * dot(&b, &b).max(1e-30);
+
+    for _ in 0..MAX_ITERATIONS {
+        if rr <= threshold {
+            break;
+        }
+        apply(&p, &mut ap);
+        let step = rr / dot(&p, &ap);
+        for i in 0..x.len() {
+            x[i] += step * p[i];
+            r[i] -= step * ap[i];
+        }
+        let rr_next = dot(&r, &r);
+        let beta = rr_next / rr;
+        for i in 0..p.len() {
+            p[i] = r[i] + beta * p[i];
+        }
+        rr = rr_next;
+    }
+
+    x
+}
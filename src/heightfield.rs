@@ -0,0 +1,208 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord};
+use super::image::Image;
+use super::material::Scatter;
+use super::ray::Ray;
+use super::vec3::{Point3, Vec3};
+
+
+
+//Terrain: heights on a regular grid over the xz plane, each cell split into two
+//triangles. Rays walk the grid cell by cell (a 2D DDA), so only the cells under the
+//ray's path are tested, and a cell is skipped without testing its triangles if the
+//ray passes wholly above or below it. Shading normals come from the slope of the grid
+//and are interpolated across the triangles, so the terrain looks smooth.
+pub struct Heightfield {
+    //Row by row, nx across in x, nz rows in z
+    heights: Vec<f64>,
+    nx: usize,
+    nz: usize,
+    //Lowest corner in x and z, heights are added to its y
+    corner: Point3,
+    dx: f64,
+    dz: f64,
+    normals: Vec<Vec3>,
+    //Lowest and highest height in each cell, row by row
+    ranges: Vec<(f64, f64)>,
+    bbox: Aabb,
+    mat: Arc<dyn Scatter>,
+}
+
+impl Heightfield {
+    //heights[j][i] is the height at corner + (i, j) of the way across size_x by size_z,
+    //so each row runs along x and the rows go along z. Needs at least 2 by 2, with every
+    //row the same length.
+    pub fn new(corner: Point3, size_x: f64, size_z: f64, heights: Vec<Vec<f64>>, mat: Arc<dyn Scatter>) -> Heightfield {
+        let nz = heights.len();
+        let nx = heights.first().map_or(0, |row| row.len());
+        assert!(nx >= 2 && nz >= 2, "heightfield needs at least 2 by 2 heights");
+        assert!(heights.iter().all(|row| row.len() == nx), "heightfield rows must all be the same length");
+        let heights: Vec<f64> = heights.into_iter().flatten().collect();
+        let (dx, dz) = (size_x / (nx - 1) as f64, size_z / (nz - 1) as f64);
+
+        let at = |i: usize, j: usize| heights[j * nx + i];
+        //Central differences, one-sided at the edges
+        let normals = (0..nz).flat_map(|j| (0..nx).map(move |i| (i, j))).map(|(i, j)| {
+            let (i0, i1) = (i.saturating_sub(1), (i + 1).min(nx - 1));
+            let (j0, j1) = (j.saturating_sub(1), (j + 1).min(nz - 1));
+            let slope_x = (at(i1, j) - at(i0, j)) / ((i1 - i0) as f64 * dx);
+            let slope_z = (at(i, j1) - at(i, j0)) / ((j1 - j0) as f64 * dz);
+            Vec3::new(-slope_x, 1.0, -slope_z).normalized()
+        }).collect();
+        let ranges = (0..nz - 1).flat_map(|j| (0..nx - 1).map(move |i| (i, j))).map(|(i, j)| {
+            let corners = [at(i, j), at(i + 1, j), at(i, j + 1), at(i + 1, j + 1)];
+            (corners.iter().copied().fold(f64::INFINITY, f64::min), corners.iter().copied().fold(f64::NEG_INFINITY, f64::max))
+        }).collect();
+
+        let (low, high) = heights.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &h| (lo.min(h), hi.max(h)));
+        let bbox = Aabb::new(corner + Vec3::new(0.0, low, 0.0), corner + Vec3::new(size_x, high, size_z));
+
+        Heightfield { heights, nx, nz, corner, dx, dz, normals, ranges, bbox, mat }
+    }
+
+    //Heights from an image's brightness times height, one per pixel, with the top row
+    //of the image at corner's z
+    pub fn from_image(image: &Image, corner: Point3, size_x: f64, size_z: f64, height: f64, mat: Arc<dyn Scatter>) -> Heightfield {
+        let heights = image.pixels.chunks(image.width as usize)
+            .map(|row| row.iter().map(|c| height * c.luminance()).collect())
+            .collect();
+        Heightfield::new(corner, size_x, size_z, heights, mat)
+    }
+
+    //from_image for an image file, see Image::read
+    pub fn load(path: &Path, corner: Point3, size_x: f64, size_z: f64, height: f64, mat: Arc<dyn Scatter>) -> io::Result<Heightfield> {
+        Ok(Heightfield::from_image(&Image::read(path)?, corner, size_x, size_z, height, mat))
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Point3 {
+        self.corner + Vec3::new(i as f64 * self.dx, self.heights[j * self.nx + i], j as f64 * self.dz)
+    }
+
+    //The nearer hit on cell (i, j)'s two triangles
+    fn hit_cell(&self, r: &Ray, i: usize, j: usize, t_min: f64, t_max: f64) -> Option<CellHit> {
+        //Both wound so their normals face up
+        let triangles = [[(i, j), (i + 1, j + 1), (i + 1, j)], [(i, j), (i, j + 1), (i + 1, j + 1)]];
+        let mut best: Option<CellHit> = None;
+        for tri in triangles {
+            let limit = best.map_or(t_max, |b| b.0);
+            let [a, b, c] = tri.map(|(i, j)| self.vertex(i, j));
+            if let Some((t, b1, b2)) = intersect(r, a, b, c, t_min, limit) {
+                best = Some((t, tri, b1, b2));
+            }
+        }
+        best
+    }
+}
+
+//t, the triangle's vertices as grid points, and the barycentrics of the second and third
+type CellHit = (f64, [(usize, usize); 3], f64, f64);
+
+//Möller-Trumbore: t and the barycentrics of p1 and p2
+fn intersect(r: &Ray, p0: Point3, p1: Point3, p2: Point3, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let pvec = r.direction().cross(e2);
+    let det = e1.dot(pvec);
+    if det.abs() < 1.0e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = r.origin() - p0;
+    let b1 = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec = tvec.cross(e1);
+    let b2 = r.direction().dot(qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    let t = e2.dot(qvec) * inv_det;
+    (t >= t_min && t <= t_max).then_some((t, b1, b2))
+}
+
+impl Hit for Heightfield {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (o, d) = (r.origin(), r.direction());
+
+        //Clip the ray to the box first
+        let (mut t0, mut t1) = (t_min, t_max);
+        for axis in 0..3 {
+            let inv = 1.0 / d[axis];
+            let a = (self.bbox.min[axis] - o[axis]) * inv;
+            let b = (self.bbox.max[axis] - o[axis]) * inv;
+            t0 = t0.max(a.min(b));
+            t1 = t1.min(a.max(b));
+        }
+        if t0 > t1 {
+            return None;
+        }
+
+        //Cell the ray starts in, and how far along the ray the next x and z grid lines are
+        let start = r.at(t0);
+        let cell = |p: f64, size: f64, n: usize| (((p / size).floor().max(0.0)) as usize).min(n - 2);
+        let mut i = cell(start.x() - self.corner.x(), self.dx, self.nx);
+        let mut j = cell(start.z() - self.corner.z(), self.dz, self.nz);
+        let next_line = |cell: usize, origin: f64, size: f64, o: f64, d: f64| {
+            if d > 0.0 {
+                (origin + (cell + 1) as f64 * size - o) / d
+            } else if d < 0.0 {
+                (origin + cell as f64 * size - o) / d
+            } else {
+                f64::INFINITY
+            }
+        };
+        let mut next_x = next_line(i, self.corner.x(), self.dx, o.x(), d.x());
+        let mut next_z = next_line(j, self.corner.z(), self.dz, o.z(), d.z());
+        let (delta_x, delta_z) = ((self.dx / d.x()).abs(), (self.dz / d.z()).abs());
+
+        let mut enter = t0;
+        loop {
+            let exit = next_x.min(next_z).min(t1);
+            let (y_enter, y_exit) = (o.y() + enter * d.y() - self.corner.y(), o.y() + exit * d.y() - self.corner.y());
+            let (low, high) = self.ranges[j * (self.nx - 1) + i];
+            if y_enter.min(y_exit) <= high && y_enter.max(y_exit) >= low {
+                //Cells are visited in order along the ray, so the first hit is the nearest
+                if let Some((t, tri, b1, b2)) = self.hit_cell(r, i, j, t_min, t_max) {
+                    let [a, b, c] = tri.map(|(i, j)| self.vertex(i, j));
+                    let outward_normal = (b - a).cross(c - a).normalized();
+                    let mut rec = HitRecord::new(r, t, outward_normal, Arc::clone(&self.mat), 0.0, 0.0);
+
+                    let b0 = 1.0 - b1 - b2;
+                    let [na, nb, nc] = tri.map(|(i, j)| self.normals[j * self.nx + i]);
+                    let shading_normal = (b0 * na + b1 * nb + b2 * nc).normalized();
+                    rec.normal = if rec.front_face { shading_normal } else { (-1.0) * shading_normal };
+
+                    let local = rec.p - self.corner;
+                    rec.u = local.x() / (self.dx * (self.nx - 1) as f64);
+                    rec.v = local.z() / (self.dz * (self.nz - 1) as f64);
+                    let along_x = Vec3::new(1.0, 0.0, 0.0);
+                    rec.tangent = (along_x - shading_normal.dot(along_x) * shading_normal).normalized();
+                    rec.bitangent = rec.tangent.cross(shading_normal);
+                    return Some(rec);
+                }
+            }
+
+            if exit >= t1 {
+                return None;
+            }
+            if next_x < next_z {
+                if d.x() > 0.0 && i + 2 < self.nx { i += 1 } else if d.x() < 0.0 && i > 0 { i -= 1 } else { return None }
+                next_x += delta_x;
+            } else {
+                if d.z() > 0.0 && j + 2 < self.nz { j += 1 } else if d.z() < 0.0 && j > 0 { j -= 1 } else { return None }
+                next_z += delta_z;
+            }
+            enter = exit;
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
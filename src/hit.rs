@@ -1,21 +1,85 @@
-use std::rc::Rc;
 use std::sync::Arc;
 
+use super::aabb::Aabb;
+use super::packet::{RayPacket, PACKET};
 use super::ray::Ray;
+use super::light::LightGroups;
 use super::material::Scatter;
-use super::vec3::{Vec3, Point3};
+use super::vec3::{Color, Vec3, Point3};
 
 
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
     pub mat: Arc<dyn Scatter>,
     pub t: f64,
+    //Surface coords for texturing, in [0, 1]
+    pub u: f64,
+    pub v: f64,
+    //Interpolated per-vertex colour, for geometry that carries one
+    pub vertex_color: Option<Color>,
+    //Surface tangent frame: tangent follows increasing u, bitangent increasing v,
+    //both perpendicular to the outward normal
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
     pub front_face: bool,
+    //Which lights light the surface, see LightGroups
+    pub light_groups: LightGroups,
+    //Which object was hit, for ID passes; 0 unless it's been given one (see cryptomatte)
+    pub object_id: u32,
+    //Width of the ray's cone (see Ray::cone) where it hit, measured across the surface,
+    //so wider at grazing angles; 0 for rays without one
+    pub footprint: f64,
+    //Distance across the surface per unit of (u, v), for turning footprint into (u, v)
+    //units; 0 where the primitive doesn't say
+    pub uv_size: f64,
 }
 
 impl HitRecord {
+    //Record for a hit at r.at(t) with the face normal set up from outward_normal.
+    //The tangent frame defaults to an arbitrary one around the normal, primitives
+    //with a proper (u, v) parameterization overwrite it.
+    pub fn new(r: &Ray, t: f64, outward_normal: Vec3, mat: Arc<dyn Scatter>, u: f64, v: f64) -> HitRecord {
+        let tangent = outward_normal.any_perpendicular();
+        let mut rec = HitRecord {
+            p: r.at(t),
+            normal: outward_normal,
+            mat,
+            t,
+            u,
+            v,
+            vertex_color: None,
+            tangent,
+            bitangent: outward_normal.cross(tangent),
+            front_face: true,
+            light_groups: LightGroups::DEFAULT,
+            object_id: 0,
+            footprint: 0.0,
+            uv_size: 0.0,
+        };
+        rec.set_face_normal(r, outward_normal);
+        rec.set_footprint(r);
+        rec
+    }
+
+    //Work footprint out from r, which hit at t with the normal already set
+    pub fn set_footprint(&mut self, r: &Ray) {
+        let width = r.cone_width(self.t);
+        self.footprint = if width == 0.0 {
+            0.0
+        } else {
+            //Held back from going on for ever at grazing angles
+            width / r.direction().normalized().dot(self.normal).abs().max(0.05)
+        };
+    }
+
+    //Footprint in (u, v) units, 0 if unknown
+    pub fn uv_footprint(&self) -> f64 {
+        if self.uv_size > 0.0 { self.footprint / self.uv_size } else { 0.0 }
+    }
+
     //Determine whether the ray is hitting the front or back face using 
     //outward normals (always point outwards)
     //ray . out_normal < 0.0 if hitting from front as face opp directions,
@@ -51,24 +115,122 @@ impl Hit for World {
 
         tmp_rec
     }
+
+    fn hit_packet(&self, packet: &RayPacket, t_min: f64, t_max: [f64; PACKET]) -> [Option<HitRecord>; PACKET] {
+        let mut closest = t_max;
+        let mut best = std::array::from_fn(|_| None);
+        for object in self {
+            record_closest(object.hit_packet(packet, t_min, closest), &mut closest, &mut best);
+        }
+        best
+    }
+
+    //Only bounded if everything in it is
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut boxes = self.iter().map(|o| o.bounding_box());
+        let first = boxes.next()??;
+        boxes.try_fold(first, |acc, b| Some(acc.surrounding(&b?)))
+    }
 }
 
+//Most surfaces a shadow ray goes through before it's taken as blocked
+const MAX_CROSSINGS: usize = 64;
+
 impl OccludingHit for World {
-    fn occluding_hit(&self, r:&Ray, lp: Point3, t_min:f64, t_max: f64) -> bool  {
-        for object in self {
-            if let Some(rec) = object.hit(r, t_min, t_max){
-                return rec.mat.occlusion() == 0.0 && r.direction().dot(lp - rec.p) > 0.0;
+    //Walks r through every surface before lp, each letting through what its material's
+    //transmittance says
+    fn transmittance(&self, r: &Ray, lp: Point3, t_min: f64, t_max: f64) -> Color {
+        let mut through = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *r;
+        for _ in 0..MAX_CROSSINGS {
+            let Some(rec) = self.hit(&ray, t_min, t_max) else { return through };
+            if r.direction().dot(lp - rec.p) <= 0.0 {
+                return through;
+            }
+            through *= rec.mat.transmittance(&rec, (rec.p - ray.origin()).length());
+            if through.near_zero() {
+                break;
             }
-        }     
-        false    
+            ray = Ray::new(rec.p, r.direction()).with_time(r.time());
+        }
+        Color::new(0.0, 0.0, 0.0)
     }
 }
 
 
 pub trait Hit: Send + Sync {
     fn hit(&self, r: &Ray, t_min:f64, t_max:f64) -> Option<HitRecord>;
+    //None for anything infinite, which keeps it out of the BVH
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    //Shapes that can be sampled directly as lights (see Scene::emitters) override these
+    //two. pdf_value is the density, per unit solid angle, of random_direction picking
+    //direction from origin; 0 where the shape can't be sampled from there.
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3) -> f64 {
+        0.0
+    }
+    //Direction from origin towards a random point on the shape
+    fn random_direction(&self, _origin: Point3) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    //hit for each ray of a packet, with a t_max per ray. Anything that can do better
+    //than one ray at a time (the BVH, sphere batches) overrides this.
+    fn hit_packet(&self, packet: &RayPacket, t_min: f64, t_max: [f64; PACKET]) -> [Option<HitRecord>; PACKET] {
+        std::array::from_fn(|l| self.hit(&packet.rays[l], t_min, t_max[l]))
+    }
+
+    //Centre, radius and material of a plain sphere, which bvh::accelerate packs in with
+    //its neighbours to test several at once
+    fn sphere(&self) -> Option<(Point3, f64, Arc<dyn Scatter>)> {
+        None
+    }
+}
+
+//Keep the hits in recs that are closer than the closest so far, ray by ray
+pub fn record_closest(recs: [Option<HitRecord>; PACKET], closest: &mut [f64; PACKET], best: &mut [Option<HitRecord>; PACKET]) {
+    for (l, rec) in recs.into_iter().enumerate() {
+        if let Some(rec) = rec {
+            closest[l] = rec.t;
+            best[l] = Some(rec);
+        }
+    }
+}
+
+//So one object can be in the world and in the emitters too
+impl<T: Hit + ?Sized> Hit for Arc<T> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        (**self).hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        (**self).pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        (**self).random_direction(origin)
+    }
+
+    fn hit_packet(&self, packet: &RayPacket, t_min: f64, t_max: [f64; PACKET]) -> [Option<HitRecord>; PACKET] {
+        (**self).hit_packet(packet, t_min, t_max)
+    }
+
+    fn sphere(&self) -> Option<(Point3, f64, Arc<dyn Scatter>)> {
+        (**self).sphere()
+    }
 }
 
 pub trait OccludingHit: Hit {
-    fn occluding_hit(&self, r:&Ray, lp: Point3, t_min:f64, t_max: f64) -> bool;
+    //How much of the light at lp reaches r's origin along r, per channel: none past
+    //anything opaque, some through glass and other things that let light through
+    fn transmittance(&self, r: &Ray, lp: Point3, t_min: f64, t_max: f64) -> Color;
+
+    //Whether no light at all gets from lp to r's origin
+    fn occluding_hit(&self, r: &Ray, lp: Point3, t_min: f64, t_max: f64) -> bool {
+        self.transmittance(r, lp, t_min, t_max).near_zero()
+    }
 }
\ No newline at end of file
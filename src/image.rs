@@ -0,0 +1,347 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::exr;
+use super::vec3::Color;
+
+
+
+//A plain RGB float image, row-major from the top left. Values read from 8-bit
+//files are scaled to [0, 1] and left in whatever encoding the file used.
+pub struct Image {
+    pub width: u64,
+    pub height: u64,
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: u64, height: u64) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![Color::new(0.0, 0.0, 0.0); (width * height) as usize],
+        }
+    }
+
+    //PNG, Radiance HDR or OpenEXR if the extension says so, otherwise PPM
+    pub fn read(path: &Path) -> io::Result<Image> {
+        match extension(path).as_str() {
+            "png" => Image::read_png(path),
+            "hdr" => Image::read_hdr(path),
+            "exr" => exr::read(path),
+            _ => Image::read_ppm(path),
+        }
+    }
+
+    //In the format read would take path to be: OpenEXR is written as 32-bit floats and
+    //Radiance HDR as RGBE, both keeping values over 1, while PNG and PPM are clamped to
+    //[0, 1] and quantized to 8 bits. Nothing is tonemapped.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        match extension(path).as_str() {
+            "png" => self.write_png(path),
+            "hdr" => self.write_hdr(path),
+            "exr" => exr::write(io::BufWriter::new(fs::File::create(path)?), self.width, self.height, &self.pixels, None, false),
+            _ => self.write_ppm(path),
+        }
+    }
+
+    //The image as reading it back would give it after write to path, for comparing
+    //with one that was written that way
+    pub fn stored(&self, path: &Path) -> Image {
+        let ext = extension(path);
+        let pixels = self.pixels.iter().map(|&c| match ext.as_str() {
+            "exr" => Color::new(c[0] as f32 as f64, c[1] as f32 as f64, c[2] as f32 as f64),
+            "hdr" => rgbe_to_color(color_to_rgbe(c)),
+            _ => Color::new(to_byte(c[0]) as f64 / 255.0, to_byte(c[1]) as f64 / 255.0, to_byte(c[2]) as f64 / 255.0),
+        }).collect();
+        Image { width: self.width, height: self.height, pixels }
+    }
+
+    //Radiance RGBE (.hdr), flat or run-length encoded. Values are linear radiance, not
+    //limited to [0, 1]. Only the usual top-to-bottom, left-to-right layout is handled.
+    pub fn read_hdr(path: &Path) -> io::Result<Image> {
+        let data = fs::read(path)?;
+        let fail = |msg: &str| invalid(format!("{}: {}", path.display(), msg));
+        let mut pos = 0;
+
+        //Header lines up to a blank one, then the resolution line
+        let next_line = |pos: &mut usize| -> Option<String> {
+            let end = data[*pos..].iter().position(|&b| b == b'\n')? + *pos;
+            let line = String::from_utf8_lossy(&data[*pos..end]).into_owned();
+            *pos = end + 1;
+            Some(line)
+        };
+        let magic = next_line(&mut pos).ok_or_else(|| fail("not a Radiance HDR file"))?;
+        if !magic.starts_with("#?") {
+            return Err(fail("not a Radiance HDR file"));
+        }
+        loop {
+            let line = next_line(&mut pos).ok_or_else(|| fail("truncated header"))?;
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format.trim() != "32-bit_rle_rgbe" {
+                    return Err(fail(&format!("unsupported format {}", format.trim())));
+                }
+            }
+        }
+        let resolution = next_line(&mut pos).ok_or_else(|| fail("missing resolution"))?;
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", h, "+X", w] => (parse_number(h)?, parse_number(w)?),
+            _ => return Err(fail(&format!("unsupported orientation '{}'", resolution.trim()))),
+        };
+
+        //The smallest a scanline can be is run-length encoded, each component in runs of
+        //127 at 2 bytes a run, or 4 bytes a pixel for widths that can't be. A size the rest
+        //of the file couldn't hold is refused before anything is allocated for it.
+        let smallest = if (8..0x8000).contains(&width) { Some(4 + 8 * width.div_ceil(127)) } else { width.checked_mul(4) };
+        let count = smallest.and_then(|n| n.checked_mul(height.max(1)))
+            .filter(|&n| n <= (data.len() - pos) as u64)
+            .and_then(|_| width.checked_mul(height))
+            .ok_or_else(|| fail(&format!("{}x{} is more than the file holds", width, height)))?;
+        let mut pixels = Vec::with_capacity(count as usize);
+        let mut scanline = vec![[0u8; 4]; width as usize];
+        for _ in 0..height {
+            read_hdr_scanline(&data, &mut pos, &mut scanline).ok_or_else(|| fail("truncated pixel data"))?;
+            pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_color(rgbe)));
+        }
+
+        Ok(Image { width, height, pixels })
+    }
+
+    //Any PNG; palettes and low bit depths are expanded, 16-bit is cut to 8, and alpha
+    //is dropped
+    pub fn read_png(path: &Path) -> io::Result<Image> {
+        let mut decoder = png::Decoder::new(io::BufReader::new(fs::File::open(path)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+        let channels = info.color_type.samples();
+        let data = &buf[..info.buffer_size()];
+
+        let pixels = data.chunks(channels).map(|c| {
+            let f = |x: u8| x as f64 / 255.0;
+            match channels {
+                1 | 2 => Color::new(f(c[0]), f(c[0]), f(c[0])),
+                _ => Color::new(f(c[0]), f(c[1]), f(c[2])),
+            }
+        }).collect();
+
+        Ok(Image { width: info.width as u64, height: info.height as u64, pixels })
+    }
+
+    //Reads both ASCII (P3) and binary (P6) PPMs
+    pub fn read_ppm(path: &Path) -> io::Result<Image> {
+        let data = fs::read(path)?;
+        let mut pos = 0;
+
+        let magic = next_token(&data, &mut pos)?;
+        let binary = match magic.as_str() {
+            "P3" => false,
+            "P6" => true,
+            _ => return Err(invalid(format!("{}: not a PPM file", path.display()))),
+        };
+
+        let width = parse_number(&next_token(&data, &mut pos)?)?;
+        let height = parse_number(&next_token(&data, &mut pos)?)?;
+        let maxval = parse_number(&next_token(&data, &mut pos)?)?;
+        if maxval == 0 || maxval > 65535 {
+            return Err(invalid(format!("{}: bad maxval {}", path.display(), maxval)));
+        }
+        let scale = 1.0 / maxval as f64;
+
+        //Every value takes at least a byte, so a size the rest of the file couldn't hold
+        //is refused before anything is allocated for it
+        let count = width.checked_mul(height).and_then(|n| n.checked_mul(3))
+            .filter(|&n| n <= data.len().saturating_sub(pos) as u64)
+            .ok_or_else(|| invalid(format!("{}: {}x{} is more than the file holds", path.display(), width, height)))? as usize;
+        let mut values = Vec::with_capacity(count);
+        if binary {
+            //Exactly one whitespace byte separates the header from the raster
+            pos += 1;
+            let wide = maxval > 255;
+            let bytes_per = if wide { 2 } else { 1 };
+            let raster = data.get(pos..pos + count * bytes_per)
+                .ok_or_else(|| invalid(format!("{}: truncated raster", path.display())))?;
+            for chunk in raster.chunks(bytes_per) {
+                let v = if wide { u16::from_be_bytes([chunk[0], chunk[1]]) as u64 } else { chunk[0] as u64 };
+                values.push(v as f64 * scale);
+            }
+        } else {
+            for _ in 0..count {
+                values.push(parse_number(&next_token(&data, &mut pos)?)? as f64 * scale);
+            }
+        }
+
+        let pixels = values.chunks(3).map(|c| Color::new(c[0], c[1], c[2])).collect();
+        Ok(Image { width, height, pixels })
+    }
+
+    //Run-length encoded where the width allows (see read_hdr_scanline), though only with
+    //literals, which is all renders would gain much from; flat otherwise
+    pub fn write_hdr(&self, path: &Path) -> io::Result<()> {
+        let mut out = io::BufWriter::new(fs::File::create(path)?);
+        write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", self.height, self.width)?;
+        let rle = (8..0x8000).contains(&self.width);
+        for row in self.pixels.chunks(self.width.max(1) as usize) {
+            let rgbe: Vec<[u8; 4]> = row.iter().map(|&c| color_to_rgbe(c)).collect();
+            if !rle {
+                out.write_all(rgbe.as_flattened())?;
+                continue;
+            }
+            out.write_all(&[2, 2, (self.width >> 8) as u8, self.width as u8])?;
+            for component in 0..4 {
+                let values: Vec<u8> = rgbe.iter().map(|p| p[component]).collect();
+                for literal in values.chunks(128) {
+                    out.write_all(&[literal.len() as u8])?;
+                    out.write_all(literal)?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    //8-bit RGB, values clamped and quantized as for write_ppm and marked as linear
+    pub fn write_png(&self, path: &Path) -> io::Result<()> {
+        let too_big = |_| invalid(format!("{}x{} is too large for PNG", self.width, self.height));
+        let out = io::BufWriter::new(fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(out, self.width.try_into().map_err(too_big)?, self.height.try_into().map_err(too_big)?);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_gamma(png::ScaledFloat::new(1.0));
+        let bytes: Vec<u8> = self.pixels.iter().flat_map(|c| [to_byte(c[0]), to_byte(c[1]), to_byte(c[2])]).collect();
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&bytes)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    //Binary PPM, values clamped to [0, 1] and quantized to 8 bits
+    pub fn write_ppm(&self, path: &Path) -> io::Result<()> {
+        let mut out = io::BufWriter::new(fs::File::create(path)?);
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for c in &self.pixels {
+            let bytes = [to_byte(c[0]), to_byte(c[1]), to_byte(c[2])];
+            out.write_all(&bytes)?;
+        }
+        out.flush()
+    }
+}
+
+//One scanline of RGBE pixels. New-style RLE lines start with 2, 2 and the width, then
+//hold each of the four components in turn as runs (count > 128, one byte repeated
+//count - 128 times) and literals (count bytes). Anything else is flat.
+fn read_hdr_scanline(data: &[u8], pos: &mut usize, scanline: &mut [[u8; 4]]) -> Option<()> {
+    let width = scanline.len();
+    let start = data.get(*pos..*pos + 4)?;
+    let rle = (8..0x8000).contains(&width)
+        && start[0] == 2 && start[1] == 2
+        && ((start[2] as usize) << 8 | start[3] as usize) == width;
+
+    if !rle {
+        for pixel in scanline.iter_mut() {
+            pixel.copy_from_slice(data.get(*pos..*pos + 4)?);
+            *pos += 4;
+        }
+        return Some(());
+    }
+
+    *pos += 4;
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(*pos)? as usize;
+            *pos += 1;
+            if count > 128 {
+                let value = *data.get(*pos)?;
+                *pos += 1;
+                for pixel in scanline.get_mut(x..x + count - 128)? {
+                    pixel[component] = value;
+                }
+                x += count - 128;
+            } else {
+                if count == 0 {
+                    return None;
+                }
+                for (pixel, &value) in scanline.get_mut(x..x + count)?.iter_mut().zip(data.get(*pos..*pos + count)?) {
+                    pixel[component] = value;
+                }
+                *pos += count;
+                x += count;
+            }
+        }
+    }
+    Some(())
+}
+
+//Shared exponent: each mantissa byte times 2^(e - 128 - 8)
+//Shared exponent and 8 bits of mantissa each, the biggest of the three (rounded down)
+//filling its 8 bits. Negative values and NaN come out as 0.
+fn color_to_rgbe(c: Color) -> [u8; 4] {
+    let largest = c[0].max(c[1]).max(c[2]);
+    if largest.is_nan() || largest < 1.0e-32 {
+        return [0; 4];
+    }
+    //largest = m * 2^exponent with m in [0.5, 1)
+    let mut exponent = largest.log2().floor() as i32 + 1;
+    if largest / 2f64.powi(exponent) >= 1.0 {
+        exponent += 1;
+    }
+    let scale = 256.0 / 2f64.powi(exponent);
+    let byte = |x: f64| (x.max(0.0) * scale).min(255.0) as u8;
+    [byte(c[0]), byte(c[1]), byte(c[2]), (exponent + 128).clamp(0, 255) as u8]
+}
+
+fn rgbe_to_color([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let scale = 2f64.powi(e as i32 - 136);
+    Color::new(r as f64 * scale, g as f64 * scale, b as f64 * scale)
+}
+
+//Lower case, empty if there isn't one
+fn extension(path: &Path) -> String {
+    path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase()
+}
+
+fn to_byte(x: f64) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn parse_number(token: &str) -> io::Result<u64> {
+    token.parse().map_err(|_| invalid(format!("expected a number, got '{}'", token)))
+}
+
+//Next whitespace separated token, skipping '#' comments
+fn next_token(data: &[u8], pos: &mut usize) -> io::Result<String> {
+    loop {
+        while *pos < data.len() && data[*pos].is_ascii_whitespace() {
+            *pos += 1;
+        }
+        if *pos < data.len() && data[*pos] == b'#' {
+            while *pos < data.len() && data[*pos] != b'\n' {
+                *pos += 1;
+            }
+        } else {
+            break;
+        }
+    }
+
+    let start = *pos;
+    while *pos < data.len() && !data[*pos].is_ascii_whitespace() {
+        *pos += 1;
+    }
+    if start == *pos {
+        return Err(invalid("unexpected end of file".to_string()));
+    }
+    Ok(String::from_utf8_lossy(&data[start..*pos]).into_owned())
+}
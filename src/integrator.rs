@@ -0,0 +1,76 @@
+use clap::ValueEnum;
+
+use super::hit::HitRecord;
+use super::ray::Ray;
+use super::render;
+use super::scene::Scene;
+use super::vec3::Color;
+
+
+
+//How the light coming back along a camera ray is worked out. The built-in ones are
+//Tracers of some IntegratorKind; anything else (ambient occlusion, say) can be rendered by
+//putting it in RenderSettings::integrator.
+pub trait Integrator: Send + Sync {
+    //Radiance arriving back along r, following it for at most depth bounces
+    fn radiance(&self, r: &Ray, scene: &Scene, depth: u64) -> Color;
+
+    //radiance for a camera ray whose first hit has already been found, e.g. traced in a
+    //packet. Straight space only.
+    fn radiance_from(&self, r: &Ray, hit: Option<HitRecord>, scene: &Scene, depth: u64) -> Color;
+}
+
+//The integrators render's path tracer can act as, from most to least faithful
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IntegratorKind {
+    //Paths from the camera joined to paths from a point or area light at every vertex,
+    //for light that only gets out into the scene indirectly (see bdpt). Slower per
+    //sample; straight space and RGB only, falling back to hybrid otherwise.
+    #[value(alias = "bdpt")]
+    Bidirectional,
+    //Path tracing, with lights and emitters also sampled directly at every bounce and
+    //weighted against being found by chance (the default)
+    Hybrid,
+    //Path tracing alone: light only counts where a path happens to find it, so it's
+    //noisier, and point and area lights (which can't be hit) light nothing
+    Path,
+    //Whitted-style ray tracing: direct light wherever a ray lands, and recursion only
+    //through mirrors, glass and other surfaces that can't be evaluated for a direction.
+    //No indirect light between diffuse surfaces, so quick but flat.
+    Whitted,
+    //Direct light at the first surface only: no reflections, refractions or shadows of
+    //anything but the lights. Quickest, for previews.
+    Direct,
+}
+
+//One of render's integrators, with the options they share
+#[derive(Clone, Copy)]
+pub struct Tracer {
+    pub kind: IntegratorKind,
+    //Most light, in any channel, a path can bring back to a bounce from further along
+    pub clamp: Option<f64>,
+}
+
+impl Tracer {
+    pub fn new(kind: IntegratorKind) -> Tracer {
+        Tracer { kind, clamp: None }
+    }
+
+    //Clamp indirect light to at most max per channel at each bounce, which gets rid of
+    //fireflies at the cost of some energy. Direct light and what the camera sees
+    //straight away are untouched.
+    pub fn with_clamp(mut self, max: f64) -> Tracer {
+        self.clamp = Some(max);
+        self
+    }
+}
+
+impl Integrator for Tracer {
+    fn radiance(&self, r: &Ray, scene: &Scene, depth: u64) -> Color {
+        render::radiance(r, scene, depth, *self).0
+    }
+
+    fn radiance_from(&self, r: &Ray, hit: Option<HitRecord>, scene: &Scene, depth: u64) -> Color {
+        render::radiance_from(r, hit, scene, depth, *self)
+    }
+}
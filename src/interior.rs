@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::vec3::Color;
+
+
+
+//Most interiors a ray keeps track of being inside at once
+const DEPTH: usize = 4;
+
+//Wavelengths in micrometres that stand for the red, green and blue channels when
+//refraction depends on wavelength
+pub const CHANNEL_WAVELENGTHS: [f64; 3] = [0.61, 0.55, 0.465];
+//Where an ior without dispersion is measured, and the wavelength used for paths that
+//haven't been narrowed to one channel
+pub const REFERENCE_WAVELENGTH: f64 = 0.55;
+
+//What fills a closed refractive object, so rays can tell which side of a boundary they're
+//on where objects nest or overlap, like liquid in a glass
+#[derive(Clone, Copy)]
+pub struct Interior {
+    //Tells one object's interior from another's, see Interior::next_id
+    pub id: usize,
+    pub ior: f64,
+    //Cauchy's B coefficient, in square micrometres, see Dielectric::with_dispersion
+    pub dispersion: f64,
+    //Where objects overlap the one with the highest priority fills the overlap, and the
+    //others' boundaries inside it are ignored
+    pub priority: u32,
+    //Per unit distance, per channel, see Dielectric::with_absorption
+    pub absorption: Color,
+}
+
+impl Interior {
+    //Index of refraction at a wavelength in micrometres, by Cauchy's equation
+    pub fn ior_at(&self, wavelength: f64) -> f64 {
+        self.ior + self.dispersion * (1.0 / (wavelength * wavelength) - 1.0 / (REFERENCE_WAVELENGTH * REFERENCE_WAVELENGTH))
+    }
+
+    //An id no other interior has
+    pub fn next_id() -> usize {
+        static NEXT: AtomicUsize = AtomicUsize::new(1);
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+//The interiors a ray is inside, in the order it entered them. What the ray is actually
+//travelling through is the one with the highest priority (the latest of equals), or
+//air if there's none. Fixed size so rays can be copied freely: entering one more than
+//it can hold forgets the first.
+#[derive(Clone, Copy, Default)]
+pub struct InteriorStack {
+    entries: [Option<Interior>; DEPTH],
+}
+
+impl InteriorStack {
+    //Outside everything
+    pub fn new() -> InteriorStack {
+        InteriorStack::default()
+    }
+
+    //What the ray is travelling through
+    pub fn top(&self) -> Option<Interior> {
+        self.entries.iter().flatten().max_by_key(|i| i.priority).copied()
+    }
+
+    //Index of refraction around the ray at a wavelength in micrometres, 1 for air
+    pub fn ior_at(&self, wavelength: f64) -> f64 {
+        self.top().map_or(1.0, |i| i.ior_at(wavelength))
+    }
+
+    //How much light is let through over distance along the ray
+    pub fn transmittance(&self, distance: f64) -> Color {
+        match self.top() {
+            Some(i) => Color::new((-i.absorption[0] * distance).exp(), (-i.absorption[1] * distance).exp(), (-i.absorption[2] * distance).exp()),
+            None => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    //Whether anything the ray is inside takes precedence over priority
+    pub fn outranks(&self, priority: u32) -> bool {
+        self.entries.iter().flatten().any(|i| i.priority > priority)
+    }
+
+    //After going into interior
+    pub fn with(mut self, interior: Interior) -> InteriorStack {
+        let len = self.entries.iter().flatten().count();
+        if len == DEPTH {
+            self.entries.rotate_left(1);
+            self.entries[DEPTH - 1] = Some(interior);
+        } else {
+            self.entries[len] = Some(interior);
+        }
+        self
+    }
+
+    //After coming out of the interior with this id, which needn't be one the ray is in
+    pub fn without(mut self, id: usize) -> InteriorStack {
+        if let Some(k) = self.entries.iter().rposition(|i| i.is_some_and(|i| i.id == id)) {
+            self.entries[k..].rotate_left(1);
+            self.entries[DEPTH - 1] = None;
+        }
+        self
+    }
+
+    //After passing through interior's boundary, going in or coming out
+    pub fn crossing(self, interior: Interior, entering: bool) -> InteriorStack {
+        let outside = self.without(interior.id);
+        if entering { outside.with(interior) } else { outside }
+    }
+}
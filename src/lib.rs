@@ -0,0 +1,85 @@
+//parhelia as a library. Build a Scene by hand, with scene!, from the built-in gallery
+//or from a TOML scene file, then render it to an Image:
+//
+//    use raytracer::{gallery, RenderSettings, Renderer};
+//
+//    let settings = RenderSettings { width: 400, height: 225, ..RenderSettings::default() };
+//    let scene = gallery::build(gallery::SceneName::Cornell, settings.aspect_ratio());
+//    let image = Renderer::new(settings).render(&scene);
+//    raytracer::output::save(path, Format::Png, Tonemap::Aces, image.width, image.height, &image.pixels)?;
+//
+//The parhelia binary is a command line wrapper round this.
+
+#[macro_use]
+mod macros;
+
+pub mod aabb;
+pub mod animation;
+pub mod aov;
+pub mod background;
+pub mod bdpt;
+pub mod box_obj;
+pub mod blue_noise;
+pub mod bvh;
+pub mod camera;
+pub mod checkpoint;
+pub mod cryptomatte;
+pub mod cylinder;
+pub mod denoise;
+pub mod diff;
+pub mod distributed;
+pub mod exr;
+pub mod filter;
+pub mod furnace;
+pub mod gallery;
+pub mod gpu;
+pub mod gradient;
+pub mod heightfield;
+pub mod hit;
+pub mod image;
+pub mod integrator;
+pub mod interior;
+pub mod light;
+pub mod light_tree;
+pub mod material;
+pub mod matrix;
+pub mod medium;
+pub mod mesh;
+pub mod noise;
+pub mod obj;
+pub mod output;
+pub mod packet;
+pub mod photon_map;
+pub mod plane;
+pub mod post;
+pub mod propagation;
+pub mod random;
+pub mod ray;
+pub mod rect;
+pub mod render;
+pub mod renderer;
+pub mod restir;
+pub mod sampler;
+pub mod scene;
+pub mod scene_file;
+pub mod scene_gen;
+pub mod scene_graph;
+pub mod scheduler;
+pub mod simd;
+pub mod solar;
+pub mod spectrum;
+pub mod sphere;
+pub mod sphere_batch;
+pub mod stats;
+pub mod texture;
+pub mod tonemap;
+pub mod torus;
+pub mod transform;
+pub mod transient;
+pub mod vdb;
+pub mod vec3;
+
+pub use camera::Camera;
+pub use image::Image;
+pub use renderer::{render, RenderSettings, Renderer};
+pub use scene::Scene;
@@ -1,14 +1,115 @@
+use std::cell::Cell;
+use std::sync::Arc;
+
+use super::aabb::Aabb;
 use super::hit::{Hit, HitRecord};
+use super::packet::{RayPacket, PACKET};
+use super::ray::Ray;
+use super::sampler::{next_2d, to_unit_disk};
 use super::vec3::{Color, Point3, Vec3};
 
 
 
-//Flat light with the same intensities at all distances
+//Light linking: every light belongs to some groups and every object is lit by some
+//groups, up to 64 of them, and a light only lights objects that share a group with it.
+//Lights and objects start out in DEFAULT alone, so everything lights everything. A rim
+//light put in a group of its own lights only the objects given that group (and emitters,
+//which are geometry, light everything).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LightGroups(pub u64);
+
+impl LightGroups {
+    pub const DEFAULT: LightGroups = LightGroups(1);
+    pub const NONE: LightGroups = LightGroups(0);
+
+    //Group number index alone, 0 being DEFAULT
+    pub fn group(index: u32) -> LightGroups {
+        assert!(index < 64, "there can only be 64 light groups");
+        LightGroups(1 << index)
+    }
+
+    pub fn with(self, other: LightGroups) -> LightGroups {
+        LightGroups(self.0 | other.0)
+    }
+
+    pub fn overlaps(self, other: LightGroups) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+//An object lit only by the lights in groups
+pub struct LightLinked {
+    object: Arc<dyn Hit>,
+    groups: LightGroups,
+}
+
+impl LightLinked {
+    pub fn new(object: Arc<dyn Hit>, groups: LightGroups) -> LightLinked {
+        LightLinked { object, groups }
+    }
+}
+
+impl Hit for LightLinked {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rec = self.object.hit(r, t_min, t_max)?;
+        rec.light_groups = self.groups;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        self.object.random_direction(origin)
+    }
+
+    fn hit_packet(&self, packet: &RayPacket, t_min: f64, t_max: [f64; PACKET]) -> [Option<HitRecord>; PACKET] {
+        self.object.hit_packet(packet, t_min, t_max).map(|rec| rec.map(|mut rec| {
+            rec.light_groups = self.groups;
+            rec
+        }))
+    }
+}
+
+//How a light's intensity drops off with distance d:
+//1 / (constant + linear * d + quadratic * d^2).
+//Physically it's inverse square, but a pure 1/d^2 blows up close to the light, so the
+//constant and linear terms are there to keep nearby surfaces from burning out.
+#[derive(Clone, Copy)]
+pub struct Falloff {
+    pub constant: f64,
+    pub linear: f64,
+    pub quadratic: f64,
+}
+
+impl Falloff {
+    pub fn new(constant: f64, linear: f64, quadratic: f64) -> Falloff {
+        Falloff { constant, linear, quadratic }
+    }
+
+    //Same intensity at all distances
+    pub fn none() -> Falloff {
+        Falloff::new(1.0, 0.0, 0.0)
+    }
+
+    pub fn attenuation(&self, distance: f64) -> f64 {
+        let denominator = self.constant + self.linear * distance + self.quadratic * distance * distance;
+        if denominator > 0.0 { 1.0 / denominator } else { 1.0 }
+    }
+}
+
+//Point light, by default with the same intensities at all distances
 pub struct SimpleLight{
     i_diff: Color,
     i_spec: Color,
     origin: Point3,
-
+    falloff: Falloff,
+    groups: LightGroups,
 }
 
 impl SimpleLight {
@@ -17,13 +118,222 @@ impl SimpleLight {
             i_diff,
             i_spec,
             origin: o,
+            falloff: Falloff::none(),
+            groups: LightGroups::DEFAULT,
+        }
+    }
+
+    pub fn with_falloff(mut self, falloff: Falloff) -> SimpleLight {
+        self.falloff = falloff;
+        self
+    }
+
+    //Only light objects in one of groups
+    pub fn with_groups(mut self, groups: LightGroups) -> SimpleLight {
+        self.groups = groups;
+        self
+    }
+}
+
+//Shape of an AreaLight, around its centre
+#[derive(Clone, Copy)]
+pub enum AreaShape {
+    //Parallelogram with these two edges
+    Rect { u: Vec3, v: Vec3 },
+    //Flat disk facing along normal
+    Disk { normal: Vec3, radius: f64 },
+}
+
+//Light spread over a rectangle or disk. Shadow tests aim at random points on it and
+//average, so a point that can see part of the light is partly lit and shadows get
+//soft edges. The intensities are for the light as a whole, the same as a point light's.
+pub struct AreaLight {
+    i_diff: Color,
+    i_spec: Color,
+    centre: Point3,
+    shape: AreaShape,
+    samples: usize,
+    falloff: Falloff,
+    groups: LightGroups,
+}
+
+impl AreaLight {
+    //Parallelogram centred on centre with edges u and v
+    pub fn rect(i_diff: Color, i_spec: Color, centre: Point3, u: Vec3, v: Vec3) -> AreaLight {
+        AreaLight::new(i_diff, i_spec, centre, AreaShape::Rect { u, v })
+    }
+
+    pub fn disk(i_diff: Color, i_spec: Color, centre: Point3, normal: Vec3, radius: f64) -> AreaLight {
+        AreaLight::new(i_diff, i_spec, centre, AreaShape::Disk { normal: normal.normalized(), radius })
+    }
+
+    fn new(i_diff: Color, i_spec: Color, centre: Point3, shape: AreaShape) -> AreaLight {
+        AreaLight { i_diff, i_spec, centre, shape, samples: 16, falloff: Falloff::none(), groups: LightGroups::DEFAULT }
+    }
+
+    //Shadow rays per shading point. More gives smoother penumbrae for the Phong
+    //lighting at the cost of a ray each.
+    pub fn with_samples(mut self, samples: usize) -> AreaLight {
+        self.samples = samples.max(1);
+        self
+    }
+
+    pub fn with_falloff(mut self, falloff: Falloff) -> AreaLight {
+        self.falloff = falloff;
+        self
+    }
+
+    //Only light objects in one of groups
+    pub fn with_groups(mut self, groups: LightGroups) -> AreaLight {
+        self.groups = groups;
+        self
+    }
+}
+
+impl Light for AreaLight {
+    fn diffuse(&self) -> Color {
+        self.i_diff
+    }
+    fn specular(&self) -> Color {
+        self.i_spec
+    }
+    fn origin(&self) -> Point3 {
+        self.centre
+    }
+    fn attenuation(&self, distance: f64) -> f64 {
+        self.falloff.attenuation(distance)
+    }
+    fn shadow_samples(&self) -> usize {
+        self.samples
+    }
+    fn groups(&self) -> LightGroups {
+        self.groups
+    }
+    fn bounds(&self) -> Aabb {
+        match self.shape {
+            AreaShape::Rect { u, v } => Aabb::around([-1.0, 1.0].into_iter().flat_map(|a| {
+                [-1.0, 1.0].into_iter().map(move |b| self.centre + 0.5 * a * u + 0.5 * b * v)
+            })).unwrap(),
+            AreaShape::Disk { normal, radius } => {
+                let reach = |n: f64| radius * (1.0 - n * n).max(0.0).sqrt();
+                let half = Vec3::new(reach(normal.x()), reach(normal.y()), reach(normal.z()));
+                Aabb::new(self.centre - half, self.centre + half)
+            }
+        }
+    }
+    fn falloff(&self) -> Falloff {
+        self.falloff
+    }
+    fn sample_point(&self) -> Point3 {
+        match self.shape {
+            AreaShape::Rect { u, v } => {
+                let (a, b) = next_2d();
+                self.centre + (a - 0.5) * u + (b - 0.5) * v
+            }
+            AreaShape::Disk { normal, radius } => {
+                let a = normal.any_perpendicular();
+                let b = normal.cross(a);
+                let (x, y) = to_unit_disk(next_2d());
+                self.centre + radius * (x * a + y * b)
+            }
+        }
+    }
+}
+
+
+//Light from so far off, like the sun, that it comes the same way with the same strength
+//everywhere. Shadow rays aim at a point DISTANT away, spread over a disk as wide as the
+//light looks from the scene, so a nonzero angle gives shadows soft edges that widen with
+//the distance to what casts them.
+pub struct DirectionalLight {
+    i_diff: Color,
+    i_spec: Color,
+    //Unit vector towards the light
+    direction: Vec3,
+    //Angular radius, in radians
+    radius: f64,
+    groups: LightGroups,
+}
+
+//How far off a directional light is taken to be. Scenes are much smaller than this, so
+//its light arrives all but parallel.
+const DISTANT: f64 = 1.0e7;
+
+impl DirectionalLight {
+    //Lighting from direction (towards the light), as a point
+    pub fn new(i_diff: Color, i_spec: Color, direction: Vec3) -> DirectionalLight {
+        DirectionalLight { i_diff, i_spec, direction: direction.normalized(), radius: 0.0, groups: LightGroups::DEFAULT }
+    }
+
+    //Make it look degrees across, rather than a point. The sun is about half a degree.
+    pub fn with_angle(mut self, degrees: f64) -> DirectionalLight {
+        self.radius = 0.5 * degrees.max(0.0).to_radians();
+        self
+    }
+
+    //Only light objects in one of groups
+    pub fn with_groups(mut self, groups: LightGroups) -> DirectionalLight {
+        self.groups = groups;
+        self
+    }
+}
+
+impl Light for DirectionalLight {
+    fn diffuse(&self) -> Color {
+        self.i_diff
+    }
+    fn specular(&self) -> Color {
+        self.i_spec
+    }
+    fn origin(&self) -> Point3 {
+        DISTANT * self.direction
+    }
+    fn attenuation(&self, _distance: f64) -> f64 {
+        1.0
+    }
+    fn groups(&self) -> LightGroups {
+        self.groups
+    }
+    //Its shadows are still lit by the sky around it
+    fn gates(&self) -> bool {
+        false
+    }
+    fn bounds(&self) -> Aabb {
+        let half = DISTANT * self.radius.tan() * Vec3::new(1.0, 1.0, 1.0);
+        Aabb::new(self.origin() - half, self.origin() + half)
+    }
+    fn sample_point(&self) -> Point3 {
+        if self.radius <= 0.0 {
+            return self.origin();
         }
+        let a = self.direction.any_perpendicular();
+        let b = self.direction.cross(a);
+        let (x, y) = to_unit_disk(next_2d());
+        self.origin() + DISTANT * self.radius.tan() * (x * a + y * b)
+    }
+    fn is_point(&self) -> bool {
+        self.radius <= 0.0
     }
 }
 
 
 pub type Lighting = Vec<Box<dyn Light>>; 
 
+thread_local! {
+    //Ambient light of the scene this thread is shading, see set_ambient
+    static AMBIENT: Cell<Color> = Cell::new(Color::new(0.0, 0.0, 0.0));
+}
+
+//Have shading on this thread see color as the scene's ambient light (Scene::ambient), so
+//materials with an ambient term needn't be handed the scene
+pub fn set_ambient(color: Color) {
+    AMBIENT.with(|ambient| ambient.set(color));
+}
+
+pub fn ambient() -> Color {
+    AMBIENT.with(Cell::get)
+}
+
 impl Light for SimpleLight {
     fn diffuse(&self) -> Color{
         self.i_diff
@@ -34,6 +344,18 @@ impl Light for SimpleLight {
     fn origin(&self) -> Point3 {
         self.origin    
     }
+    fn attenuation(&self, distance: f64) -> f64 {
+        self.falloff.attenuation(distance)
+    }
+    fn groups(&self) -> LightGroups {
+        self.groups
+    }
+    fn falloff(&self) -> Falloff {
+        self.falloff
+    }
+    fn is_point(&self) -> bool {
+        true
+    }
 }
 
 
@@ -41,4 +363,39 @@ pub trait Light: Send + Sync {
     fn diffuse(&self) -> Color;
     fn specular(&self) -> Color;
     fn origin(&self) -> Point3;
-}
\ No newline at end of file
+    //Fraction of the intensities that reaches a point this far from the light
+    fn attenuation(&self, distance: f64) -> f64;
+    //How many points on the light a shadow test should average over
+    fn shadow_samples(&self) -> usize {
+        1
+    }
+    //A random point on the light to aim a shadow ray at. Point lights only have the one.
+    fn sample_point(&self) -> Point3 {
+        self.origin()
+    }
+    //Whether sample_point is always origin
+    fn is_point(&self) -> bool {
+        false
+    }
+    //Which objects it lights, see LightGroups
+    fn groups(&self) -> LightGroups {
+        LightGroups::DEFAULT
+    }
+    //Box around every point sample_point can give
+    fn bounds(&self) -> Aabb {
+        Aabb::new(self.origin(), self.origin())
+    }
+    //How attenuation drops off, for light_tree to bound it with
+    fn falloff(&self) -> Falloff {
+        Falloff::none()
+    }
+    //Whether it lights what rec hit
+    fn lights(&self, rec: &HitRecord) -> bool {
+        self.groups().overlaps(rec.light_groups)
+    }
+    //Whether the hybrid integrator leaves a point it can't see (nor any other light that
+    //does this) lit only by what glows there, rather than by the background too
+    fn gates(&self) -> bool {
+        true
+    }
+}
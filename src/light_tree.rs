@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::light::{Falloff, Light, LightGroups};
+use super::random::random_f64;
+use super::vec3::{Point3, Vec3};
+
+
+
+//Light tree (after Conty and Kulla 2018) for scenes with thousands of small lights:
+//a binary tree of lights grouped by where they are, each node knowing its bounds and
+//how bright its lights are in all. A shading point walks down it, at each node
+//choosing a side at random in proportion to how much light that side could bring it
+//(brighter, nearer and above the surface counting for more), and ends at one light,
+//along with the chance of ending there. A few such picks stand in for looping over
+//every light, with each one's light divided by its chance.
+pub struct LightTree {
+    nodes: Vec<Node>,
+    //Lights picked per shading point
+    pub samples: usize,
+}
+
+struct Node {
+    bounds: Aabb,
+    //Summed luminance of the lights' diffuse and specular intensities
+    power: f64,
+    //At most as much falloff as any light under the node has, so it never
+    //underestimates what they could give
+    falloff: Falloff,
+    //Every group any light under the node is in
+    groups: LightGroups,
+    kind: Kind,
+}
+
+enum Kind {
+    //Index into the lights the tree was built from
+    Leaf(usize),
+    //Indices of the two halves in nodes
+    Inner(usize, usize),
+}
+
+impl LightTree {
+    //Tree over lights, taking samples picks per shading point
+    pub fn new(lights: &[Box<dyn Light>], samples: usize) -> LightTree {
+        let mut tree = LightTree { nodes: Vec::with_capacity(2 * lights.len()), samples: samples.max(1) };
+        let mut order: Vec<usize> = (0..lights.len()).collect();
+        if !order.is_empty() {
+            tree.build(lights, &mut order);
+        }
+        tree
+    }
+
+    //Node over the lights in order (which it reorders), returning its index
+    fn build(&mut self, lights: &[Box<dyn Light>], order: &mut [usize]) -> usize {
+        let index = self.nodes.len();
+        if let [light] = *order {
+            let l = &lights[light];
+            let power = (l.diffuse() + l.specular()).luminance().max(0.0);
+            self.nodes.push(Node { bounds: l.bounds(), power, falloff: l.falloff(), groups: l.groups(), kind: Kind::Leaf(light) });
+            return index;
+        }
+
+        //Split at the middle light along whichever axis their centres spread furthest
+        let centres = Aabb::around(order.iter().map(|&i| lights[i].bounds().centroid())).unwrap();
+        let extent = centres.max - centres.min;
+        let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() { 0 } else if extent.y() >= extent.z() { 1 } else { 2 };
+        order.sort_by(|&a, &b| lights[a].bounds().centroid()[axis].total_cmp(&lights[b].bounds().centroid()[axis]));
+        let (left, right) = order.split_at_mut(order.len() / 2);
+
+        //Placeholder until the children are built
+        self.nodes.push(Node { bounds: centres, power: 0.0, falloff: Falloff::none(), groups: LightGroups::NONE, kind: Kind::Leaf(0) });
+        let (a, b) = (self.build(lights, left), self.build(lights, right));
+        let (na, nb) = (&self.nodes[a], &self.nodes[b]);
+        self.nodes[index] = Node {
+            bounds: na.bounds.surrounding(&nb.bounds),
+            power: na.power + nb.power,
+            falloff: Falloff::new(
+                na.falloff.constant.min(nb.falloff.constant),
+                na.falloff.linear.min(nb.falloff.linear),
+                na.falloff.quadratic.min(nb.falloff.quadratic),
+            ),
+            groups: na.groups.with(nb.groups),
+            kind: Kind::Inner(a, b),
+        };
+        index
+    }
+
+    //Pick a light for a point at p (on a surface facing normal, if it's on one) lit by
+    //the lights in groups, returning its index and the chance it was picked. None if no
+    //light could light the point.
+    pub fn sample(&self, p: Point3, normal: Option<Vec3>, groups: LightGroups) -> Option<(usize, f64)> {
+        let mut node = self.nodes.first()?;
+        let mut chance = 1.0;
+        if node.importance(p, normal, groups) <= 0.0 {
+            return None;
+        }
+        loop {
+            match node.kind {
+                Kind::Leaf(light) => return Some((light, chance)),
+                Kind::Inner(a, b) => {
+                    let (a, b) = (&self.nodes[a], &self.nodes[b]);
+                    let (ia, ib) = (a.importance(p, normal, groups), b.importance(p, normal, groups));
+                    if ia + ib <= 0.0 {
+                        return None;
+                    }
+                    let pa = ia / (ia + ib);
+                    (node, chance) = if random_f64() < pa { (a, chance * pa) } else { (b, chance * (1.0 - pa)) };
+                }
+            }
+        }
+    }
+
+    //samples picks for a point as for sample, each with what its light should be
+    //multiplied by to stand in for all of them
+    pub fn picks(&self, p: Point3, normal: Option<Vec3>, groups: LightGroups) -> impl Iterator<Item = (usize, f64)> + '_ {
+        let samples = self.samples as f64;
+        (0..self.samples).filter_map(move |_| self.sample(p, normal, groups)).map(move |(light, chance)| (light, 1.0 / (samples * chance)))
+    }
+}
+
+impl Node {
+    //How much light the node's lights could bring to p, roughly: their power, dimmed
+    //by the distance to them. Nothing if they're all behind the surface or in no
+    //group of groups.
+    fn importance(&self, p: Point3, normal: Option<Vec3>, groups: LightGroups) -> f64 {
+        if !self.groups.overlaps(groups) {
+            return 0.0;
+        }
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        if let Some(n) = normal {
+            //The corner furthest along the normal
+            let corner = Point3::new(
+                if n.x() > 0.0 { max.x() } else { min.x() },
+                if n.y() > 0.0 { max.y() } else { min.y() },
+                if n.z() > 0.0 { max.z() } else { min.z() },
+            );
+            if n.dot(corner - p) < 0.0 {
+                return 0.0;
+            }
+        }
+        //From the middle rather than the nearest point, which would make every node
+        //around p look alike, but never less than half across, so one p is inside
+        //doesn't swamp the rest
+        let distance = (self.bounds.centroid() - p).length().max(0.5 * (max - min).length());
+        self.power * self.falloff.attenuation(distance)
+    }
+}
+
+thread_local! {
+    //Tree for the render this thread is working on, see set_current
+    static CURRENT: RefCell<Option<Arc<LightTree>>> = const { RefCell::new(None) };
+}
+
+//Have lighting on this thread pick lights from tree (None to go back to every light),
+//so shading needn't be handed it
+pub fn set_current(tree: Option<&Arc<LightTree>>) {
+    CURRENT.with(|current| *current.borrow_mut() = tree.cloned());
+}
+
+pub fn current() -> Option<Arc<LightTree>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
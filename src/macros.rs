@@ -0,0 +1,118 @@
+//Declarative scenes. Materials are named once and then referred to by name, and
+//anything the shorthand doesn't cover can be written out in full with custom(...):
+//
+//    let scene = scene! {
+//        camera: { from: (0.0, 0.0, 0.0), at: (0.0, 0.0, -1.0), vfov: 90.0, aspect: 16.0 / 9.0 },
+//        materials: {
+//            ground = lambertian(0.8, 0.8, 0.0),
+//            glass = dielectric(1.5),
+//            chrome = metal(0.9, 0.9, 0.9, 0.0),
+//            lamp = diffuse_light(4.0, 4.0, 4.0),
+//            shiny = custom(PhongMat::new(1.0, 1.0, 0.0, 0.5, 4, albedo, 0.0, 1.0, 0.0)),
+//        },
+//        objects: {
+//            plane((0.0, -0.5, 0.0), (0.0, 1.0, 0.0), ground),
+//            sphere((0.0, 0.0, -1.0), 0.5, glass),
+//            custom(TriangleMesh::uv_sphere(centre, 0.5, 8, 16, chrome.clone())),
+//        },
+//        lights: {
+//            point((2.0, 0.0, -1.0)),
+//            point((0.0, 1.0, -1.0), diffuse: (1.0, 1.0, 1.0), specular: (1.0, 0.0, 0.0)),
+//            point((0.0, 3.0, 0.0), falloff: (1.0, 0.0, 0.25)),
+//        },
+//    };
+//
+//Optional: background: <expr> after the camera (any Background, defaults to the sky
+//gradient), aperture: / focus: at the end of the camera (defaults to a pinhole
+//focused on at), and emitters: { ... } after the objects, written the same way, for
+//glowing objects to be sampled directly as lights as well as being in the world.
+//Point lights default to white with no falloff (falloff is constant, linear, quadratic).
+//Sections can be empty but must be there.
+#[macro_export]
+macro_rules! scene {
+    //The given value if there is one, otherwise the default
+    (@or [$value:expr] $default:expr) => {
+        $value
+    };
+    (@or [] $default:expr) => {
+        $default
+    };
+
+    (@vec3 ($x:expr, $y:expr, $z:expr)) => {
+        $crate::vec3::Vec3::new($x, $y, $z)
+    };
+
+    (@material lambertian ($r:expr, $g:expr, $b:expr)) => {
+        ::std::sync::Arc::new($crate::material::Lambertian::new($crate::vec3::Color::new($r, $g, $b)))
+    };
+    (@material metal ($r:expr, $g:expr, $b:expr, $fuzz:expr)) => {
+        ::std::sync::Arc::new($crate::material::Metal::new($crate::vec3::Color::new($r, $g, $b), $fuzz))
+    };
+    (@material dielectric ($ir:expr)) => {
+        ::std::sync::Arc::new($crate::material::Dielectric::new($ir, 1.0))
+    };
+    (@material diffuse_light ($r:expr, $g:expr, $b:expr)) => {
+        ::std::sync::Arc::new($crate::material::DiffuseLight::new($crate::vec3::Color::new($r, $g, $b)))
+    };
+    (@material custom ($mat:expr)) => {
+        ::std::sync::Arc::new($mat)
+    };
+
+    (@object sphere ($centre:tt, $radius:expr, $mat:ident)) => {
+        ::std::boxed::Box::new($crate::sphere::Sphere::new(scene!(@vec3 $centre), $radius, $mat.clone()))
+    };
+    (@object plane ($point:tt, $normal:tt, $mat:ident)) => {
+        ::std::boxed::Box::new($crate::plane::Plane::new(scene!(@vec3 $point), scene!(@vec3 $normal), $mat.clone()))
+    };
+    (@object custom ($object:expr)) => {
+        ::std::boxed::Box::new($object)
+    };
+
+    (@light point ($origin:tt $(, diffuse: $diffuse:tt)? $(, specular: $specular:tt)? $(, falloff: ($c:expr, $l:expr, $q:expr))?)) => {{
+        let diffuse = scene!(@or [$(scene!(@vec3 $diffuse))?] $crate::vec3::Color::new(1.0, 1.0, 1.0));
+        let specular = scene!(@or [$(scene!(@vec3 $specular))?] $crate::vec3::Color::new(1.0, 1.0, 1.0));
+        let falloff = scene!(@or [$($crate::light::Falloff::new($c, $l, $q))?] $crate::light::Falloff::none());
+        ::std::boxed::Box::new($crate::light::SimpleLight::new(diffuse, specular, scene!(@vec3 $origin)).with_falloff(falloff))
+    }};
+
+    (
+        camera: {
+            from: $from:tt,
+            at: $at:tt,
+            vfov: $vfov:expr,
+            aspect: $aspect:expr
+            $(, aperture: $aperture:expr)?
+            $(, focus: $focus:expr)?
+            $(,)?
+        },
+        $(background: $background:expr,)?
+        materials: { $($name:ident = $mat_kind:ident ($($mat_args:tt)*)),* $(,)? },
+        objects: { $($obj_kind:ident ($($obj_args:tt)*)),* $(,)? },
+        $(emitters: { $($em_kind:ident ($($em_args:tt)*)),* $(,)? },)?
+        lights: { $($light_kind:ident ($($light_args:tt)*)),* $(,)? } $(,)?
+    ) => {{
+        let lookfrom = scene!(@vec3 $from);
+        let lookat = scene!(@vec3 $at);
+        let aperture = scene!(@or [$($aperture)?] 0.0);
+        let focus = scene!(@or [$($focus)?] (lookfrom - lookat).length());
+        let camera = $crate::camera::Camera::new(lookfrom, lookat, $crate::vec3::Vec3::new(0.0, 1.0, 0.0), $vfov, $aspect, aperture, focus);
+
+        let background: ::std::boxed::Box<dyn $crate::background::Background> =
+            ::std::boxed::Box::new(scene!(@or [$($background)?] $crate::background::SkyGradient));
+
+        $(let $name: ::std::sync::Arc<dyn $crate::material::Scatter> = scene!(@material $mat_kind ($($mat_args)*));)*
+
+        let mut world: $crate::hit::World = vec![
+            $(scene!(@object $obj_kind ($($obj_args)*)) as ::std::boxed::Box<dyn $crate::hit::Hit>),*
+        ];
+        let emitters: ::std::vec::Vec<::std::sync::Arc<dyn $crate::hit::Hit>> = vec![
+            $($(::std::sync::Arc::from(scene!(@object $em_kind ($($em_args)*)) as ::std::boxed::Box<dyn $crate::hit::Hit>)),*)?
+        ];
+        world.extend(emitters.iter().map(|e| ::std::boxed::Box::new(::std::sync::Arc::clone(e)) as ::std::boxed::Box<dyn $crate::hit::Hit>));
+        let lights: $crate::light::Lighting = vec![
+            $(scene!(@light $light_kind ($($light_args)*)) as ::std::boxed::Box<dyn $crate::light::Light>),*
+        ];
+
+        $crate::scene::Scene { world, graph: $crate::scene_graph::Node::group(), lights, emitters, camera, camera_path: None, cameras: std::collections::HashMap::new(), background, space: None, integrator: None, ambient: $crate::vec3::Color::new(0.0, 0.0, 0.0) }
+    }};
+}
@@ -1,298 +1,1197 @@
-use std::io::{stderr, Write};
-use std::sync::Arc;
-
-use rand::Rng;
-use rayon::prelude::*;
-
-
-mod camera;
-mod hit;
-mod light;
-mod material;
-mod ray;
-mod sphere;
-mod vec3;
-
-use camera::Camera;
-use light::{Light, Lighting, SimpleLight};
-use vec3::{Vec3, Point3, Color};
-use ray::Ray;
-use material::{Dielectric, Lambertian, Metal, PhongMat};
-use sphere::Sphere;
-use hit::{OccludingHit, Hit, HitRecord, World};
-
-
-fn lambertian_hardcoded(rec: &HitRecord, world: &World, lights: &Lighting, depth: u64) -> Color{
-    //Lambertian reflection: Produce random points on the surface of the unit ball 
-        //offset along the surface normal; has a distribution of cos(phi) where phi is the angle
-        //from the normal. Without normalizing the final term we get a cos^3(phi) dist corresponding 
-        //to picking directions on the hemisphere with high prob close to the normal, and a lower
-        //prob of scattering rays at grazing angles. Shallow angles contribute less to color so wouldn't care
-        //but we want the lambertian dist.
-        //lambertian dist is more uniform though still has higher prob closer to norm
-        //than further. Get less pronounced shadows, esp in gaps between objects, as less light 
-        //bounces straight up i.e. close to normal.
-        //Also means diffuse surfaces become brighter as more light bounces towards camera.
-        let target = rec.p + rec.normal + Vec3::random_in_unit_sphere().normalized();
-
-        //Alternate method - hemispherical scattering, older method that just randomly scatters
-        //rays off the hit point, uniform distribution
-        //Gets you lighter surfaces + less prominent shadows because dist uniform so more rays going 
-        //towards camera, also not bouncing straight up to other object as much.
-        //let target = rec.p + Vec3::random_in_hemisphere(rec.normal);
-
-        let r = Ray::new(rec.p, target-rec.p);
-        //Hit an object; return the face normal of the object
-        0.5 * ray_color(&r, &world, &lights, depth - 1)
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+
+
+mod batch;
+mod dashboard;
+mod fly;
+mod preview;
+mod watch;
+mod window;
+
+use raytracer::animation::Turntable;
+use raytracer::aov::Aov;
+use raytracer::background::EnvironmentMap;
+use raytracer::camera::Camera;
+use raytracer::checkpoint::Checkpoint;
+use raytracer::furnace;
+use raytracer::gallery::{self, SceneName};
+use raytracer::gpu::{self, GpuScene};
+use raytracer::gradient;
+use raytracer::filter::FilterKind;
+use raytracer::image::Image;
+use raytracer::integrator::{Integrator, IntegratorKind, Tracer};
+use raytracer::material::Lambertian;
+use raytracer::mesh::Shading;
+use raytracer::output::{self, Format, Snapshots};
+use raytracer::random::reseed;
+use raytracer::ray::RayLimits;
+use raytracer::render::path_radiance;
+use raytracer::renderer::{camera_ray, RenderSettings, Renderer};
+use raytracer::light_tree::LightTree;
+use raytracer::photon_map::PhotonMap;
+use raytracer::post::{Bloom, Exposure, LensEffects, PostProcess};
+use raytracer::restir::Resampling;
+use raytracer::sampler::SamplerKind;
+use raytracer::scheduler::{self, Adaptive, CancelToken, ProgressReport};
+use raytracer::stats;
+use raytracer::tonemap::Tonemap;
+use raytracer::transient::{self, TransientSettings};
+use raytracer::vec3::{Color, Point3};
+use raytracer::{bvh, cryptomatte, denoise, diff, distributed, obj, scene_file, scene_gen, Scene};
+
+use dashboard::Dashboard;
+use preview::{Preview, PreviewMode};
+use window::Window;
+
+
+#[derive(Parser)]
+#[command(name = "parhelia", about = "A rust raytracer; writes a PPM or PNG image to stdout or --output")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Built-in scene to render
+    #[arg(long, value_enum, default_value_t = SceneName::HollowSphere)]
+    scene: SceneName,
+
+    /// Render a TOML scene file instead of a built-in scene
+    #[arg(long, value_name = "FILE", conflicts_with = "scene")]
+    scene_file: Option<PathBuf>,
+
+    /// Render from one of the scene file's [cameras.NAME] rather than its [camera]
+    #[arg(long, value_name = "NAME", requires = "scene_file")]
+    camera: Option<String>,
+
+    /// Focus on whatever the camera sees at this point on the image, X across from the
+    /// left and Y up from the bottom, each 0 to 1, rather than at the focus distance the
+    /// scene gives. On its own, the middle of the image.
+    #[arg(long, value_name = "X,Y", num_args = 0..=1, default_missing_value = "0.5,0.5", value_parser = parse_image_point)]
+    autofocus: Option<(f64, f64)>,
+
+    /// Render, then render again to the same output every time the scene file (or one
+    /// it includes) is saved, at this many samples per pixel, until stopped with Ctrl-C
+    #[arg(long, value_name = "SAMPLES", num_args = 0..=1, default_missing_value = "8",
+          value_parser = clap::value_parser!(u64).range(1..), requires_all = ["scene_file", "output"],
+          conflicts_with_all = ["window", "fly", "tui", "frames", "turntable", "checkpoint", "resume", "workers"])]
+    watch: Option<u64>,
+
+    /// Image width in pixels
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u64).range(2..))]
+    width: u64,
+
+    /// Image height in pixels [default: 16:9 to the width]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(2..))]
+    height: Option<u64>,
+
+    /// Samples per pixel
+    #[arg(long, short, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    samples: u64,
+
+    /// How samples are spread over each pixel, the lens and the lights: random, or
+    /// stratified, halton or sobol for less noise at the same sample count, or
+    /// blue-noise for noise that's finer grained and denoises better at low counts
+    #[arg(long, value_enum, default_value_t = SamplerKind::Random)]
+    sampler: SamplerKind,
+
+    /// How samples are weighted over each pixel and its neighbours: box (each sample only
+    /// counts for its own pixel), tent or gaussian for smoother anti-aliasing, or mitchell
+    /// for crisper edges (its negative lobes add a little noise)
+    #[arg(long, value_enum, default_value_t = FilterKind::Box)]
+    filter: FilterKind,
+
+    /// Trace each pixel's camera rays four at a time, which is quicker for scenes of
+    /// many small objects (e.g. --scene spheres). Same image for the same --seed,
+    /// except in smoke and fog.
+    #[arg(long)]
+    packets: bool,
+
+    /// Render with wavelengths of light rather than red, green and blue, for smooth
+    /// rainbows from dispersive glass. Slower, and needs more samples.
+    #[arg(long, conflicts_with_all = ["gradient_domain", "transient"])]
+    spectral: bool,
+
+    /// How light is worked out: hybrid path tracing (the default, unless the scene file
+    /// says otherwise), pure path tracing, whitted ray tracing or direct light only,
+    /// from slowest and most faithful to quickest
+    #[arg(long, value_enum, conflicts_with = "transient")]
+    integrator: Option<IntegratorKind>,
+
+    /// Clamp indirect light to at most this much per channel at every bounce, to get rid
+    /// of fireflies (lone bright pixels) at the cost of darkening the image a little
+    #[arg(long, value_parser = positive_f64)]
+    clamp: Option<f64>,
+
+    /// Resample light from point and area lights (ReSTIR): each shading point weighs up
+    /// this many lights picked at random and sends one shadow ray, to the one it keeps,
+    /// and pixels take in their neighbours' and the last frame's picks. For scenes with
+    /// hundreds of lights; a little darker at shadow edges.
+    #[arg(long, value_name = "CANDIDATES", num_args = 0..=1, default_missing_value = "32",
+          value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["gradient_domain", "transient"])]
+    restir: Option<u64>,
+
+    /// Pick this many lights per shading point from a tree over the scene's lights,
+    /// rather than lighting it from all of them. For scenes with thousands of small lights.
+    #[arg(long, value_name = "SAMPLES", num_args = 0..=1, default_missing_value = "4",
+          value_parser = clap::value_parser!(u64).range(1..))]
+    light_tree: Option<u64>,
+
+    /// Shoot this many photons from the point and area lights before rendering and add
+    /// the caustics they make, e.g. light focused through glass onto the floor, which
+    /// path tracing can't find from such lights. Not with the path integrator.
+    #[arg(long, value_name = "COUNT", num_args = 0..=1, default_missing_value = "1000000",
+          value_parser = clap::value_parser!(u64).range(1..))]
+    photons: Option<u64>,
+
+    /// Gather photons from no further than this around each point (defaults to a
+    /// twentieth of the way across where they landed)
+    #[arg(long, requires = "photons")]
+    photon_radius: Option<f64>,
+
+    /// Most bounces a path can take before it's cut off
+    #[arg(long, default_value_t = 50)]
+    max_depth: u64,
+
+    /// Ignore hits closer than this to where a ray starts, so rays leaving a surface
+    /// don't hit it again [default: 0.001, scaled up for scenes reaching past 10^4
+    /// units from the origin and down for ones within a unit of it]
+    #[arg(long, value_parser = positive_f64)]
+    epsilon: Option<f64>,
+
+    /// Ignore anything further along a ray than this [default: no limit]
+    #[arg(long, value_parser = positive_f64)]
+    max_distance: Option<f64>,
+
+    /// Write the image to this file instead of stdout
+    #[arg(long, short, value_name = "FILE", conflicts_with = "transient")]
+    output: Option<PathBuf>,
+
+    /// Image format [default: from the --output extension, otherwise ppm]
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+
+    /// How radiance brighter than white is squeezed into the image before sRGB encoding
+    /// (EXR output is left linear)
+    #[arg(long, value_enum, default_value = "clamp")]
+    tonemap: Tonemap,
+
+    /// Brighten the image by this many stops (each doubling it) before tonemapping, or
+    /// darken it if negative. With --auto-exposure, exposure compensation.
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    exposure: f64,
+
+    /// Expose as a camera would at this film speed, with --shutter-speed and --f-stop.
+    /// Scene values are taken as daylight levels, which f/16 at 1/100s and ISO 100
+    /// leaves as they are; dim interiors want a longer shutter, a wider aperture or a
+    /// higher ISO.
+    #[arg(long, value_parser = positive_f64, requires_all = ["shutter_speed", "f_stop"], conflicts_with = "auto_exposure")]
+    iso: Option<f64>,
+
+    /// Seconds the shutter is open for, e.g. 1/125 or 0.5, see --iso
+    #[arg(long, value_parser = parse_shutter_speed, requires = "iso")]
+    shutter_speed: Option<f64>,
+
+    /// f-number the lens is set to, e.g. 8, see --iso
+    #[arg(long, value_parser = positive_f64, requires = "iso")]
+    f_stop: Option<f64>,
+
+    /// Expose the image so its log-average luminance comes out this (0.18, a middle
+    /// grey, if not given), as a camera's meter would, for scenes too bright or too
+    /// dark to look right as they are
+    #[arg(long, value_name = "KEY", num_args = 0..=1, default_missing_value = "0.18", value_parser = positive_f64)]
+    auto_exposure: Option<f64>,
+
+    /// Add light spilling round the brightest parts of the image, this many times what's
+    /// over --bloom-threshold, e.g. 0.2, so the sun and glowing objects glare
+    #[arg(long, value_parser = positive_f64)]
+    bloom: Option<f64>,
+
+    /// Luminance over which light blooms
+    #[arg(long, default_value_t = 1.0, value_parser = non_negative_f64, requires = "bloom")]
+    bloom_threshold: f64,
+
+    /// How far bloom spreads, as a fraction of the image width
+    #[arg(long, default_value_t = 0.02, value_parser = positive_f64, requires = "bloom")]
+    bloom_radius: f64,
+
+    /// Bend the finished image as a real lens does, keeping the corners in place:
+    /// positive for barrel distortion (straight lines bow outwards, as with a wide
+    /// angle), negative for pincushion. Around 0.1 is noticeable.
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    distortion: f64,
+
+    /// Make red's image this fraction bigger than green's and blue's smaller, giving
+    /// bright edges coloured fringes towards the corners, e.g. 0.005
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    chromatic_aberration: f64,
+
+    /// Darken the corners, as light through a lens falls off as cos^4 of the angle off
+    /// axis: 1 is as for a lens seeing 90 degrees corner to corner, leaving them a
+    /// quarter as bright
+    #[arg(long, default_value_t = 0.0, value_parser = non_negative_f64)]
+    vignetting: f64,
+
+    /// Rewrite --output after every pass over the image, so it can be looked at
+    /// while the render refines
+    #[arg(long, requires = "output", conflicts_with = "gradient_domain")]
+    progressive: bool,
+
+    /// Adaptive sampling: stop sampling a pixel once its 95% confidence interval is
+    /// within this fraction of its brightness (e.g. 0.05), so --samples is the most any
+    /// pixel gets
+    #[arg(long, value_name = "THRESHOLD", conflicts_with_all = ["gradient_domain", "transient"])]
+    adaptive: Option<f64>,
+
+    /// Samples every pixel gets before --adaptive can call it done
+    #[arg(long, default_value_t = 16, requires = "adaptive")]
+    min_samples: u64,
+
+    /// Samples per pixel in each pass over the image
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pass_samples: u64,
+
+    /// Number of render threads [default: one per core]
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Seed for every random choice, including the random scenes, so a render can be
+    /// repeated exactly
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Light the scene with an equirectangular environment map (.hdr, .exr, .png or
+    /// .ppm) in place of its own background
+    #[arg(long, value_name = "FILE")]
+    hdri: Option<PathBuf>,
+
+    /// Add a Wavefront OBJ model to the scene (can be given more than once)
+    #[arg(long, value_name = "FILE")]
+    obj: Vec<PathBuf>,
+
+    /// How --obj models are shaded: smooth if they have vertex normals (auto), always
+    /// smooth, making normals up where they're missing, or with flat faces
+    #[arg(long, value_enum, default_value = "auto")]
+    obj_shading: Shading,
+
+    /// Show a downscaled preview of the render in the terminal as it progresses
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "auto")]
+    preview: Option<PreviewMode>,
+
+    /// Full-screen dashboard with per-tile progress, throughput and ETA
+    #[arg(long, conflicts_with = "preview")]
+    tui: bool,
+
+    /// Show the render in a window as it progresses. Esc or Q stops it, S saves the
+    /// image so far (needs the window feature)
+    #[arg(long, conflicts_with_all = ["preview", "tui"])]
+    window: bool,
+
+    /// Fly the camera round the scene in a window instead of rendering an image: WASD,
+    /// R/F and the mouse (or arrow keys) move and look, the view refines when still, and
+    /// P saves the camera into --scene-file (or prints it). Needs the window feature.
+    #[arg(long, conflicts_with_all = ["preview", "tui", "window", "output", "frames", "turntable", "checkpoint", "resume", "workers"])]
+    fly: bool,
+
+    /// Gradient-domain path tracing: also estimate differences between neighbouring
+    /// pixels and reconstruct the image from both, for less noise at the same sample count
+    #[arg(long, conflicts_with_all = ["preview", "tui", "window"])]
+    gradient_domain: bool,
+
+    /// Transient rendering: write one frame per slice of path length to this
+    /// directory instead of an image to stdout
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["preview", "tui", "window", "gradient_domain"])]
+    transient: Option<PathBuf>,
+
+    /// Every --checkpoint-interval seconds, and when the render finishes or is
+    /// interrupted, save where it's got to in this file, so it can be carried on with
+    /// --resume if it's killed
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["window", "gradient_domain", "transient", "frames", "turntable"])]
+    checkpoint: Option<PathBuf>,
+
+    /// Seconds between checkpoints
+    #[arg(long, default_value_t = 60)]
+    checkpoint_interval: u64,
+
+    /// Carry on a render from a checkpoint, with the same scene and image settings (the
+    /// seed comes from the checkpoint). Checkpoints keep going to this file unless
+    /// --checkpoint says otherwise. Raise --samples to refine a finished render further.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["window", "gradient_domain", "transient", "frames", "turntable"])]
+    resume: Option<PathBuf>,
+
+    /// Only render these tiles (e.g. 0..40, counting along the rows of 16x16 tiles from
+    /// the top left), to share a render between machines: each saves its part with
+    /// --checkpoint, and the merge subcommand puts them together
+    #[arg(long, value_name = "FIRST..END", value_parser = parse_range, requires = "checkpoint")]
+    tiles: Option<Range<usize>>,
+
+    /// Hand the tiles out to workers at these addresses (host:port, comma separated),
+    /// each started with the worker subcommand and the same scene and image options
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["window", "preview", "tui", "progressive", "gradient_domain", "transient", "frames", "turntable", "checkpoint", "resume", "tiles"])]
+    workers: Vec<String>,
+
+    /// Give up on a worker that sends nothing back for this many seconds, handing its
+    /// tiles to the others (long enough for it to render 16 tiles). Workers give up on
+    /// a coordinator that goes quiet for as long.
+    #[arg(long, value_name = "SECONDS", default_value_t = 600, value_parser = clap::value_parser!(u64).range(1..))]
+    network_timeout: u64,
+
+    /// Render an animation of this many frames, following the scene file's camera
+    /// keyframes, to numbered images next to --output (e.g. image_0007.png). Time runs
+    /// one unit per frame, so moving objects carry on moving from one frame to the next.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), requires = "output",
+        conflicts_with_all = ["preview", "tui", "window", "gradient_domain", "progressive", "denoise"])]
+    frames: Option<u64>,
+
+    /// Render this many frames of the camera going once round --pivot, to numbered
+    /// images next to --output as for --frames
+    #[arg(long, value_name = "FRAMES", value_parser = clap::value_parser!(u64).range(1..), requires = "output",
+        conflicts_with_all = ["frames", "preview", "tui", "window", "gradient_domain", "progressive", "denoise"])]
+    turntable: Option<u64>,
+
+    /// Point the turntable circles and looks at [default: where the camera looks, at its
+    /// focus distance]
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_point, allow_hyphen_values = true, requires = "turntable")]
+    pivot: Option<Point3>,
+
+    /// Turntable camera's distance from the pivot [default: the camera's]
+    #[arg(long, value_parser = positive_f64, requires = "turntable")]
+    orbit_radius: Option<f64>,
+
+    /// Turntable camera's height, in degrees above level [default: the camera's]
+    #[arg(long, allow_hyphen_values = true, requires = "turntable")]
+    elevation: Option<f64>,
+
+    /// Also write these auxiliary passes of what the camera sees first, next to
+    /// --output as e.g. image.normal.png (normal, depth, albedo, visibility, alpha,
+    /// object-id)
+    #[arg(long, value_enum, value_delimiter = ',', requires = "output", conflicts_with_all = ["gradient_domain", "transient"])]
+    aov: Vec<Aov>,
+
+    /// Run the finished image through Intel Open Image Denoise, guided by albedo and
+    /// normal passes (needs the oidn feature)
+    #[arg(long, conflicts_with_all = ["gradient_domain", "transient"])]
+    denoise: bool,
+
+    /// After the render, print how many rays of each kind were cast, BVH nodes visited,
+    /// the mean path depth and how long tiles took, to stderr, as text or JSON
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "text", conflicts_with = "workers")]
+    stats: Option<StatsFormat>,
+
+    /// Leave the background out, writing alpha 0 where the camera sees nothing, for
+    /// compositing over other footage. PNG gets straight alpha, EXR premultiplied.
+    #[arg(long, conflicts_with_all = ["gradient_domain", "transient"])]
+    transparent: bool,
+
+    /// Also write a Cryptomatte of which objects each pixel covers, with a manifest of
+    /// their names, next to --output as image.cryptomatte.exr
+    #[arg(long, requires = "output", conflicts_with_all = ["gradient_domain", "transient", "frames", "turntable"])]
+    cryptomatte: bool,
+
+    /// Number of time slices for --transient
+    #[arg(long, default_value_t = 64, requires = "transient")]
+    time_bins: usize,
+
+    /// Path length covered by the last time slice for --transient, in scene units
+    #[arg(long, default_value_t = 20.0, requires = "transient")]
+    max_path_length: f64,
+
+    /// Path trace on the GPU, for scenes of plain spheres lit by point lights (needs the
+    /// gpu feature). Anything it can't do is rendered on the CPU as usual, saying why.
+    #[arg(long, conflicts_with_all = ["workers", "window", "preview", "tui", "progressive", "gradient_domain", "transient", "frames", "turntable", "checkpoint", "resume", "tiles", "fly"])]
+    gpu: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StatsFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// White furnace test: report how far each material is from conserving energy
+    Furnace {
+        /// Rays per material
+        #[arg(long, default_value_t = 100_000)]
+        samples: u64,
+        /// Largest allowed deviation from 1 before a material fails
+        #[arg(long, default_value_t = 0.01)]
+        tolerance: f64,
+    },
+    /// Compare two images (PPM or PNG): prints RMSE, PSNR and SSIM
+    Diff {
+        a: PathBuf,
+        b: PathBuf,
+        /// Also write a heatmap of the per-pixel error here
+        #[arg(long)]
+        heatmap: Option<PathBuf>,
+        /// Exit with status 1 if the RMSE is over this
+        #[arg(long)]
+        max_rmse: Option<f64>,
+        /// Exit with status 1 if the SSIM is under this
+        #[arg(long)]
+        min_ssim: Option<f64>,
+    },
+    /// Render tiles for a coordinator started with --workers, taking the scene and image
+    /// options from the rest of the command line, which should match the coordinator's
+    Worker {
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0:7878")]
+        listen: String,
+    },
+    /// Put checkpoints of different parts of one render (from --tiles) back together,
+    /// writing the image to --output and the whole checkpoint to --checkpoint if given
+    Merge {
+        #[arg(required = true)]
+        parts: Vec<PathBuf>,
+    },
+    /// Render every job in a TOML manifest of scenes, cameras and output files (see batch.rs)
+    Batch {
+        manifest: PathBuf,
+        /// Renders to run at once, sharing the cores between them
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        parallel: u64,
+    },
+    /// Print a scene file for one of the demo scenes, to edit and render with --scene-file
+    Gen {
+        #[command(subcommand)]
+        scene: GenScene,
+    },
 }
 
-fn is_lit(p: Point3, n: Vec3, world: &World, lights: &Lighting) -> Option<Color> {
-    for light in lights {
-        if n.dot(light.origin() - p) < 0.0 {
-            continue;
+#[derive(Subcommand)]
+enum GenScene {
+    /// Small random spheres around three big ones
+    RandomSpheres {
+        /// Small spheres
+        #[arg(long, default_value_t = 529)]
+        count: usize,
+        /// Place and colour them the same way every time
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Have the diffuse ones jump up while the shutter is open
+        #[arg(long)]
+        bouncing: bool,
+    },
+    /// The closed Cornell box with two boxes inside
+    CornellBox,
+    /// The original test scene: a Phong sphere on a yellow ground
+    HollowSphere,
+}
+
+
+fn main() {
+    let args = Args::parse();
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new().num_threads(threads).build_global()
+            .expect("couldn't set up the thread pool");
+    }
+
+    match &args.command {
+        Some(Command::Furnace { samples, tolerance }) => {
+            let results = furnace::run(*samples, args.max_depth);
+            if !furnace::report(&results, *tolerance) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Diff { a, b, heatmap, max_rmse, min_ssim }) => {
+            let threshold = diff::Threshold { max_rmse: *max_rmse, min_ssim: *min_ssim };
+            run_diff(a, b, heatmap.as_deref(), &threshold);
+            return;
+        }
+        Some(Command::Merge { parts }) => {
+            run_merge(parts, &args);
+            return;
         }
-        else{
-            //TODO don't need to normalize here?
-            let ray = Ray::new(p, (light.origin() - p).normalized());
-            if !world.occluding_hit(&ray, light.origin(), 0.001, f64::INFINITY){
-                return Some(light.diffuse());
+        Some(Command::Batch { manifest, parallel }) => {
+            match batch::run(manifest, *parallel as usize) {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("Couldn't run {}: {}", manifest.display(), e);
+                    std::process::exit(2);
+                }
             }
+            return;
         }
+        Some(Command::Gen { scene }) => {
+            print!("{}", match scene {
+                GenScene::RandomSpheres { count, seed, bouncing } => scene_gen::random_spheres(*count, *seed, *bouncing),
+                GenScene::CornellBox => scene_gen::cornell_box(),
+                GenScene::HollowSphere => scene_gen::hollow_sphere(),
+            });
+            return;
+        }
+        Some(Command::Worker { .. }) | None => {}
     }
-    return None
-}
 
-fn ray_color(r: &Ray, world: &World, lights: &Lighting, depth: u64) -> Color {
-    if depth <= 0{
-        //Exceeded ray bounce limit, no more light is generated
-        return Color::new(0.0, 0.0, 0.0);
+    if let (Some(samples), Some(path)) = (args.watch, &args.scene_file) {
+        watch::run(path, samples);
     }
 
+    if args.denoise && !denoise::AVAILABLE {
+        eprintln!("Can't denoise: this build doesn't include the oidn feature (rebuild with --features oidn)");
+        std::process::exit(2);
+    }
+    if args.gpu && !gpu::AVAILABLE {
+        eprintln!("Can't render on the GPU: this build doesn't include the gpu feature (rebuild with --features gpu)");
+        std::process::exit(2);
+    }
 
-    //t_min set to 0.001 because some rays will hit the object they're reflecting off 
-    //at -0.0000001 or 0.00000001 or whatever floating point approximation the sphere intersector
-    //gives us, rather than t = 0. Without the correction we get shadow acne where the 
-    //shapes have black spots because hitting v.near 0 and then get highly absorbed.
-    //i.e. ignore hits v. near 0
-    if let Some(rec) = world.hit(r, 0.001, f64::INFINITY){
-        //Check if the point is occluded from all light sources
-        let light_color =  match is_lit(rec.p, rec.normal, &world, &lights) {
-            Some(color) => color,
-            None => return Color::new(0.0, 0.0, 0.0)
-        };
+    let output_format = match &args.output {
+        Some(path) => args.format.unwrap_or_else(|| Format::from_path(path)),
+        None => args.format.unwrap_or(Format::Ppm),
+    };
+    if args.transparent && matches!(output_format, Format::Ppm) {
+        eprintln!("--transparent needs PNG or EXR output, PPM has no alpha channel");
+        std::process::exit(2);
+    }
 
+    let resume = args.resume.as_ref().map(|path| Checkpoint::load(path).unwrap_or_else(|e| {
+        eprintln!("Couldn't load {}: {}", path.display(), e);
+        std::process::exit(2);
+    }));
+    let checkpoint_path = args.checkpoint.clone().or_else(|| args.resume.clone());
 
-        //lambertian_hardcoded(&rec, world, depth)
-        if let Some((attenuation, scattered)) = rec.mat.scatter(r.origin(), &lights, &world, r, &rec) {
-            /*light_color * */ attenuation * ray_color(&scattered, &world, lights, depth-1)
-        } else{
-            Color::new(0.0, 0.0, 0.0)
+    let image_width = args.width;
+    let image_height = args.height.unwrap_or(((image_width as f64) / (16.0 / 9.0)) as u64).max(2);
+    let mut settings = RenderSettings {
+        width: image_width,
+        height: image_height,
+        samples_per_pixel: args.samples,
+        max_depth: args.max_depth,
+        seed: args.seed.or(resume.as_ref().and_then(|checkpoint| checkpoint.seed)),
+        pass_samples: args.pass_samples,
+        sampler: args.sampler.sampler(),
+        filter: args.filter.sampler(),
+        packets: args.packets,
+        spectral: args.spectral,
+        transparent: args.transparent,
+        adaptive: args.adaptive.map(|threshold| Adaptive { threshold, min_samples: args.min_samples }),
+        ..RenderSettings::default()
+    };
+    if let (Some(checkpoint), Some(path)) = (&resume, &args.resume) {
+        if let Err(e) = checkpoint.check(&settings) {
+            eprintln!("Can't resume from {}: {}", path.display(), e);
+            std::process::exit(2);
         }
+        let (fewest, most) = checkpoint.samples();
+        eprintln!("Resuming from {} samples per pixel ({} in the furthest tile)", fewest, most);
     }
-    else{
-        //Linearly blend white and blue depending on height of y coord after scaling ray 
-        //direction to get a unit length (so -1.0 < y < 1.0)
-        //Will be a horizontal gradient too because look at y component after normalizing
-        let unit_direction = r.direction().normalized();
-        let t = 0.5 * (unit_direction.y() + 1.0);
-        (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+    let (samples_per_pixel, max_depth, seed, tile_size) =
+        (settings.samples_per_pixel, settings.max_depth, settings.seed, settings.tile_size);
+    //How many tiles there are depends on the image size, so only now can --tiles be checked
+    if let Some(tiles) = &args.tiles {
+        let count = (settings.width.div_ceil(tile_size) * settings.height.div_ceil(tile_size)) as usize;
+        if tiles.end > count {
+            let message = format!("--tiles {}..{} runs past the end: a {}x{} image has {} tiles of {}x{} (0..{})",
+                tiles.start, tiles.end, settings.width, settings.height, count, tile_size, tile_size, count);
+            Args::command().error(ErrorKind::ValueValidation, message).exit();
+        }
     }
-}
 
-fn main() {
-    const ASPECT_RATIO: f64 = 16.0/9.0;
-    const IMAGE_WIDTH: u64 = 256;
-    const IMAGE_HEIGHT: u64 = ((IMAGE_WIDTH as f64) / ASPECT_RATIO) as u64;
-    const SAMPLES_PER_PIXEL: u64 = 100;
-    const MAX_DEPTH: u64 = 50;
-
-    //World
-    let r: f64 = (std::f64::consts::PI / 4.0).cos();
-    let mut world = World::new();
-    
-    //Lighting
-    let mut lights = Lighting::new();
-
-    //Hollow glass sphere:
-    setup_hollow_sphere(&mut world, &mut lights);
-
-    //Camera
-    let lookfrom = Point3::new(0.0, 0.0, 0.0);
-    let lookat = Point3::new(0.0, 0.0, -1.0);
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = (lookfrom - lookat).length();
-    let aperture = 0.0;
-
-
-    let cam = Camera::new(lookfrom,
-        lookat,
-        vup,
-        90.0,
-        ASPECT_RATIO,
-        aperture,
-        dist_to_focus,
-        );
-
-
-    // //Image
-    // const ASPECT_RATIO: f64 = 3.0/2.0;
-    // const IMAGE_WIDTH: u64 = 256;
-    // const IMAGE_HEIGHT: u64 = ((IMAGE_WIDTH as f64) / ASPECT_RATIO) as u64;
-    // const SAMPLES_PER_PIXEL: u64 = 500;
-    // const MAX_DEPTH: u64 = 50;
-
-    // //World
-    // let world = random_scene();
-
-    // //Camera
-    // let lookfrom = Point3::new(13.0, 2.0, 3.0);
-    // let lookat = Point3::new(0.0, 0.0, 0.0);
-    // let vup = Vec3::new(0.0, 1.0, 0.0);
-    // let dist_to_focus = 10.0;
-    // let aperture = 0.1;
-
-    // let cam = Camera::new(lookfrom,
-    //     lookat,
-    //     vup,
-    //     20.0,
-    //     ASPECT_RATIO,
-    //     aperture,
-    //     dist_to_focus);
-
-
-    
-    println!("P3");
-    println!("{} {}", IMAGE_WIDTH, IMAGE_HEIGHT);
-    println!("255");
-
-    
-
-    for j in (0..IMAGE_HEIGHT).rev() {
-        
-        eprintln!("Scanlines remaining: {}", j+1);
-        stderr().flush().unwrap();
-
-        let scanline: Vec<Color> =  (0..IMAGE_WIDTH).into_par_iter().map(|i| {
-            let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-
-            for _ in 0..SAMPLES_PER_PIXEL {
-                let mut rng = rand::thread_rng();
-                let random_u: f64 = rng.gen();
-                let random_v: f64 = rng.gen();
-
-                let u = ((i as f64) + random_u) / ((IMAGE_WIDTH-1) as f64);
-                let v = ((j as f64) + random_v) / ((IMAGE_HEIGHT-1) as f64);
-
-                let r = cam.get_ray(u, v);
-
-                pixel_color += ray_color(&r, &world, &lights, MAX_DEPTH);
+    //Anything random about building the scene happens on this thread
+    if let Some(seed) = seed {
+        reseed(seed);
+    }
 
+    let mut scene = match &args.scene_file {
+        Some(path) => scene_file::load_scene(path, settings.aspect_ratio()).unwrap_or_else(|e| {
+            eprintln!("Couldn't load {}: {}", path.display(), e);
+            std::process::exit(2);
+        }),
+        None => gallery::build(args.scene, settings.aspect_ratio()),
+    };
+    if let Some(name) = &args.camera {
+        if !scene.use_camera(name) {
+            let mut names: Vec<&str> = scene.cameras.keys().map(String::as_str).collect();
+            names.sort();
+            eprintln!("No camera named '{}' in the scene (it has: {})", name, names.join(", "));
+            std::process::exit(2);
+        }
+    }
+    for path in &args.obj {
+        let default = Arc::new(Lambertian::new(Color::new(0.7, 0.7, 0.7)));
+        match obj::load_obj(path, default) {
+            Ok(mesh) => scene.world.push(Box::new(mesh.with_shading(args.obj_shading))),
+            Err(e) => {
+                eprintln!("Couldn't load {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Some(path) = &args.hdri {
+        match EnvironmentMap::load(path) {
+            Ok(map) => scene.background = Box::new(map),
+            Err(e) => {
+                eprintln!("Couldn't load {}: {}", path.display(), e);
+                std::process::exit(2);
             }
+        }
+    }
+    //Objects only need IDs for the passes that show them
+    let names = if args.cryptomatte || args.aov.contains(&Aov::ObjectId) {
+        scene.flatten_identified()
+    } else {
+        scene.flatten();
+        Vec::new()
+    };
+    if let Some((s, t)) = args.autofocus {
+        scene.camera = scene.camera.with_autofocus(s, t);
+    }
+    let mut integrator = Tracer::new(args.integrator.or(scene.integrator).unwrap_or(IntegratorKind::Hybrid));
+    if let Some(max) = args.clamp {
+        integrator = integrator.with_clamp(max);
+    }
+    settings.integrator = Arc::new(integrator);
+    //Packed before the BVH hides the spheres behind it
+    let gpu_scene = if args.gpu {
+        GpuScene::new(&scene, integrator).map_err(|e| eprintln!("Can't render this on the GPU ({}), rendering on the CPU", e)).ok()
+    } else {
+        None
+    };
+    settings.resampling = args.restir.map(|candidates| Arc::new(Resampling::new(candidates as usize)));
+    settings.light_tree = args.light_tree.map(|samples| Arc::new(LightTree::new(&scene.lights, samples as usize)));
+    scene.world = bvh::accelerate(std::mem::take(&mut scene.world));
+    settings.epsilon = args.epsilon.unwrap_or_else(|| RayLimits::scaled_epsilon(scene.extent()));
+    if let Some(max) = args.max_distance {
+        settings.max_distance = max;
+    }
+    //For autofocusing, which doesn't go through Renderer
+    let limits = settings.limits();
+    if scene.camera.autofocus().is_some() {
+        match scene.autofocus(limits) {
+            Some(distance) => eprintln!("Focused {:.3} away", distance),
+            None => eprintln!("Nothing to focus on at the autofocus point, keeping the focus at {:.3}", scene.camera.focus_dist()),
+        }
+    }
+    if let Some(count) = args.photons {
+        let mut map = PhotonMap::new(&scene, &settings, count as usize);
+        if let Some(radius) = args.photon_radius {
+            map = map.with_max_radius(radius);
+        }
+        eprintln!("{} caustic photons", map.len());
+        settings.photons = Some(Arc::new(map));
+    }
 
-            pixel_color
-        }).collect();
+    if args.fly {
+        if let Err(e) = fly::run(scene, settings, args.tonemap, args.scene_file.as_deref()) {
+            eprintln!("Couldn't fly the camera: {}", e);
+            std::process::exit(2);
+        }
+        return;
+    }
 
-        for pixel_color in scanline {
-            println!("{}", pixel_color.format_color(SAMPLES_PER_PIXEL));
+    //Workers are stopped by killing them, so don't get the Ctrl-C handler
+    if let Some(Command::Worker { listen }) = &args.command {
+        if let Err(e) = distributed::serve(listen, &Renderer::new(settings), &scene, Duration::from_secs(args.network_timeout)) {
+            eprintln!("Couldn't listen on {}: {}", listen, e);
+            std::process::exit(2);
         }
+        return;
+    }
+
+    //First Ctrl-C stops the render and writes out what there is so far, a second one
+    //gives up straight away
+    let cancel = CancelToken::new();
+    let handler_token = cancel.clone();
+    ctrlc::set_handler(move || {
+        if handler_token.is_cancelled() {
+            std::process::exit(130);
+        }
+        handler_token.cancel();
+    }).expect("couldn't install Ctrl-C handler");
+
+    let renderer = Renderer::new(settings).with_cancel(cancel.clone());
+    let renderer = match resume {
+        Some(checkpoint) => renderer.with_resume(checkpoint),
+        None => renderer,
+    };
+    let renderer = match &args.tiles {
+        Some(tiles) => renderer.with_tiles(tiles.clone()),
+        None => renderer,
+    };
+
+    if let Some(dir) = &args.transient {
+        let transient_settings = TransientSettings { bins: args.time_bins, max_length: args.max_path_length };
+        let frames = transient::render(image_width, image_height, samples_per_pixel, &transient_settings, &cancel, |i, y, s| {
+            renderer.start_sample(i, y, s);
+            path_radiance(&camera_ray(&scene.camera, i, y, image_width, image_height), &scene, max_depth)
+        }, report_scanlines);
+        write_frames(dir, args.format.unwrap_or(Format::Ppm), args.tonemap, frames);
+        return;
+    }
+
+    let exposure = match (args.iso, args.shutter_speed, args.f_stop) {
+        (Some(iso), Some(shutter), Some(f_stop)) => Exposure::camera(iso, shutter, f_stop),
+        _ => Exposure::stops(0.0),
+    }.with_compensation(args.exposure);
+    let post = PostProcess {
+        exposure: match args.auto_exposure {
+            Some(key) => exposure.with_auto(key),
+            None => exposure,
+        },
+        bloom: args.bloom.map(|intensity| Bloom::new(intensity).with_threshold(args.bloom_threshold).with_radius(args.bloom_radius)),
+        lens: LensEffects::new().with_distortion(args.distortion)
+            .with_chromatic_aberration(args.chromatic_aberration)
+            .with_vignetting(args.vignetting),
+    };
+
+    if let (Some(frames), Some(path)) = (args.frames, &args.output) {
+        let (still, camera_path) = (scene.camera, scene.camera_path.take());
+        let camera = |frame: u64| camera_path.as_ref().map_or(still, |path| path.camera(frame as f64)).delayed(frame as f64);
+        render_animation(&renderer, scene, frames, camera, path, output_format, args.tonemap, post, &args.aov);
+        return;
+    }
+    if let (Some(frames), Some(path)) = (args.turntable, &args.output) {
+        let still = scene.camera;
+        let pivot = args.pivot.unwrap_or_else(|| still.lookfrom() + still.focus_dist() * still.forward());
+        let mut turntable = Turntable::new(&still, pivot, frames);
+        if let Some(radius) = args.orbit_radius {
+            turntable = turntable.with_radius(radius);
+        }
+        if let Some(elevation) = args.elevation {
+            turntable = turntable.with_elevation(elevation);
+        }
+        render_animation(&renderer, scene, frames, |frame| turntable.camera(&still, frame), path, output_format, args.tonemap, post, &args.aov);
+        return;
+    }
+    let scene = Arc::new(scene);
+
+    let snapshots = match &args.output {
+        Some(path) if args.progressive => Some(Snapshots::new(path, output_format, args.tonemap, args.pass_samples)),
+        _ => None,
+    };
+
+    let preview = args.preview.map(|mode| Preview::new(mode, args.tonemap));
+    let dashboard = args.tui.then(|| Dashboard::new(args.tonemap));
+    //Redraw the preview roughly this many times over the render
+    const PREVIEW_UPDATES: usize = 20;
+
+    stats::enable(args.stats.is_some());
+    let started = Instant::now();
+
+    let gpu_image = gpu_scene.and_then(|gpu_scene| {
+        gpu::render(&gpu_scene, renderer.settings(), &cancel, report_passes)
+            .map_err(|e| eprintln!("Couldn't render on the GPU ({}), rendering on the CPU", e)).ok()
+    });
+
+    //Mean of the samples for each pixel, row-major from the top
+    let framebuffer = if let Some(image) = gpu_image {
+        image.pixels
+    } else if args.gradient_domain {
+        gradient::render(image_width, image_height, samples_per_pixel, seed, &cancel, |i, y| {
+            //gradient seeds each trace itself, for the random replay
+            renderer.set_current();
+            let r = camera_ray(&scene.camera, i, y, image_width, image_height);
+            integrator.radiance(&r, &scene, max_depth)
+        }, report_scanlines)
+    } else if !args.workers.is_empty() {
+        match distributed::coordinate(&args.workers, renderer.settings(), &cancel, Duration::from_secs(args.network_timeout)) {
+            Ok(tiles) => renderer.scheduler().averaged(&tiles),
+            Err(e) => {
+                eprintln!("Couldn't finish the render: {}", e);
+                std::process::exit(2);
+            }
+        }
+    } else if preview.is_none() && dashboard.is_none() && checkpoint_path.is_none() {
+        //Nothing to draw, so build the image from the tiles as they finish
+        let mut framebuffer = vec![Color::new(0.0, 0.0, 0.0); (image_width * image_height) as usize];
+        let tiles_across = image_width.div_ceil(tile_size);
+        let mut remaining = tiles_across * image_height.div_ceil(tile_size);
+        let mut tile_samples = vec![0; remaining as usize];
+        let total_samples = image_width * image_height * samples_per_pixel;
+        let mut samples_done = 0;
+
+        let mut window = args.window.then(|| {
+            Window::new(image_width, image_height, args.tonemap, cancel.clone()).unwrap_or_else(|e| {
+                eprintln!("Couldn't open a window: {}", e);
+                std::process::exit(2);
+            })
+        });
+        //S in the window writes to --output, or render.png without one
+        let save_path = args.output.clone().unwrap_or_else(|| PathBuf::from("render.png"));
+        let save_format = if args.output.is_some() { output_format } else { Format::Png };
+        let save = |pixels: &[Color]| match output::save(&save_path, save_format, args.tonemap, image_width, image_height, pixels) {
+            Ok(()) => eprintln!("Saved to {}", save_path.display()),
+            Err(e) => eprintln!("Couldn't write {}: {}", save_path.display(), e),
+        };
+
+        renderer.prepare(&scene);
+        let (stream_renderer, stream_scene) = (renderer.clone(), Arc::clone(&scene));
+        let mut stream = renderer.scheduler().stream_batched(move |i, y, samples, out| stream_renderer.samples(&stream_scene, i, y, samples, out));
+        loop {
+            let updates = match &mut window {
+                //Keep the window responsive rather than blocking until the next tile
+                Some(window) => {
+                    window.refresh(&framebuffer, save);
+                    match stream.pending() {
+                        Some(updates) => updates,
+                        None => break,
+                    }
+                }
+                None => match stream.next() {
+                    Some(update) => vec![update],
+                    None => break,
+                },
+            };
+            for update in updates {
+                let tile = update.tile;
+                let index = ((tile.y0 / tile_size) * tiles_across + tile.x0 / tile_size) as usize;
+                //Anything not newer than what's already shown would only take the tile back
+                if update.samples <= tile_samples[index] {
+                    continue;
+                }
+                for (k, c) in update.pixels.into_iter().enumerate() {
+                    let (x, y) = (tile.x0 + k as u64 % tile.width, tile.y0 + k as u64 / tile.width);
+                    framebuffer[(y * image_width + x) as usize] = c;
+                }
+                samples_done += tile.width * tile.height * (update.samples - tile_samples[index]);
+                tile_samples[index] = update.samples;
+                if update.samples == samples_per_pixel {
+                    remaining -= 1;
+                    match scheduler::eta(samples_done, total_samples - samples_done, started.elapsed()) {
+                        Some(eta) if remaining > 0 => eprintln!("Tiles remaining: {} (about {:.0}s left)", remaining, eta.as_secs_f64().ceil()),
+                        _ => eprintln!("Tiles remaining: {}", remaining),
+                    }
+                }
+                if let Some(snapshots) = &snapshots {
+                    let min_samples = tile_samples.iter().copied().min().unwrap_or(0);
+                    snapshots.update(min_samples, image_width, image_height, || framebuffer.clone());
+                }
+            }
+        }
+        stream.finish();
+        if let Some(window) = window {
+            if !cancel.is_cancelled() {
+                window.hold(&framebuffer, save);
+            }
+        }
+        framebuffer
+    } else {
+        let save_checkpoint = |path: &Path, tiles| {
+            if let Err(e) = Checkpoint::new(renderer.settings(), tiles).save(path) {
+                eprintln!("Couldn't write checkpoint {}: {}", path.display(), e);
+            }
+        };
+        let last_checkpoint = Mutex::new(Instant::now());
+        let interval = Duration::from_secs(args.checkpoint_interval);
+
+        let (image, tiles) = renderer.render_resumable(&scene, |progress| {
+            if let Some(path) = &checkpoint_path {
+                //Whoever gets here first saves it, the other threads carry on
+                if let Ok(mut last) = last_checkpoint.try_lock() {
+                    if last.elapsed() >= interval {
+                        save_checkpoint(path, progress.snapshot.accumulated());
+                        *last = Instant::now();
+                    }
+                }
+            }
+            if let Some(snapshots) = &snapshots {
+                let snapshot = &progress.snapshot;
+                let min_samples = snapshot.tile_progress().iter().map(|&(_, samples)| samples).min().unwrap_or(0);
+                snapshots.update(min_samples, image_width, image_height, || snapshot.averaged());
+            }
+            if let Some(dashboard) = &dashboard {
+                dashboard.update(progress);
+            } else if let Some(preview) = &preview {
+                let every = progress.total.div_ceil(PREVIEW_UPDATES);
+                if progress.done % every == 0 || progress.done == progress.total {
+                    let snapshot = &progress.snapshot;
+                    preview.draw(&snapshot.averaged(), snapshot.width(), snapshot.height());
+                }
+            }
+        });
+        if let Some(path) = &checkpoint_path {
+            save_checkpoint(path, tiles);
+            eprintln!("Checkpoint saved to {}", path.display());
+        }
+        image.pixels
+    };
+    let elapsed = started.elapsed();
+    stats::enable(false);
+
+    if let Some(dashboard) = dashboard {
+        dashboard.finish();
+    }
+
+    //An interrupted render has no AOVs to guide the denoiser, so goes out as it is
+    let framebuffer = if args.denoise && !cancel.is_cancelled() {
+        let noisy = Image { width: image_width, height: image_height, pixels: framebuffer };
+        let albedo = renderer.render_aov(&scene, Aov::Albedo);
+        let normal = renderer.render_aov(&scene, Aov::Normal);
+        match denoise::denoise(&noisy, Some(&albedo), Some(&normal)) {
+            Ok(image) => image.pixels,
+            //The render itself is fine, so it's kept rather than thrown away
+            Err(e) => {
+                eprintln!("Couldn't denoise, writing the image as rendered: {}", e);
+                noisy.pixels
+            }
+        }
+    } else {
+        framebuffer
+    };
+    let framebuffer = post.apply(&Image { width: image_width, height: image_height, pixels: framebuffer }).pixels;
+
+    //Coverage from the same camera rays as the image, moved where the lens moves it. An
+    //interrupted render goes out without, as there'd be no time to work it out.
+    let alpha = (args.transparent && !cancel.is_cancelled()).then(|| post.distort(&renderer.render_aov(&scene, Aov::Alpha)).pixels.iter().map(|c| c[0]).collect::<Vec<f64>>());
+    let written = match (&args.output, &alpha) {
+        (Some(path), Some(alpha)) => output::save_rgba(path, output_format, args.tonemap, image_width, image_height, &framebuffer, alpha),
+        (None, Some(alpha)) => output::write_rgba(io::stdout().lock(), output_format, args.tonemap, image_width, image_height, &framebuffer, alpha),
+        (Some(path), None) => output::save(path, output_format, args.tonemap, image_width, image_height, &framebuffer),
+        (None, None) => output::write(io::stdout().lock(), output_format, args.tonemap, image_width, image_height, &framebuffer),
+    };
+    if let Err(e) = written {
+        eprintln!("Couldn't write the image: {}", e);
+        std::process::exit(2);
+    }
+
+    if cancel.is_cancelled() {
+        eprintln!("Interrupted, wrote partial image");
+        std::process::exit(130);
+    }
+    if let Some(path) = &args.output {
+        write_aovs(&renderer, &scene, &args.aov, path, output_format, post);
+        if args.cryptomatte {
+            let path = path.with_file_name(format!("{}.cryptomatte.exr", path.file_stem().and_then(|s| s.to_str()).unwrap_or("image")));
+            let coverage = cryptomatte::coverage(&renderer, &scene);
+            match cryptomatte::save(&path, image_width, image_height, &coverage, &names) {
+                Ok(()) => eprintln!("Wrote the cryptomatte to {}", path.display()),
+                Err(e) => {
+                    eprintln!("Couldn't write {}: {}", path.display(), e);
+                    std::process::exit(2);
+                }
+            }
+        }
+    }
+    match args.stats {
+        Some(StatsFormat::Text) => eprint!("{}", stats::report().summary(elapsed)),
+        Some(StatsFormat::Json) => eprint!("{}", stats::report().to_json(elapsed)),
+        None => {}
     }
     eprint!("Done!");
 
 }
 
+//For renders that go a row at a time, the way tile renders report theirs
+fn report_scanlines(report: &ProgressReport) {
+    let remaining = report.total - report.done;
+    match report.eta {
+        Some(eta) if remaining > 0 => eprintln!("Scanlines remaining: {} (about {:.0}s left)", remaining, eta.as_secs_f64().ceil()),
+        _ => eprintln!("Scanlines remaining: {}", remaining),
+    }
+}
+
+//For GPU renders, which go a pass at a time
+fn report_passes(report: &ProgressReport) {
+    let remaining = report.total - report.done;
+    match report.eta {
+        Some(eta) if remaining > 0 => eprintln!("Passes remaining: {} (about {:.0}s left)", remaining, eta.as_secs_f64().ceil()),
+        _ => eprintln!("Passes remaining: {}", remaining),
+    }
+}
+
+//Write the transient frames out numbered, tonemapped like the ordinary output
+fn write_frames(dir: &Path, format: Format, tonemap: Tonemap, frames: Vec<Image>) {
+    if let Err(e) = fs::create_dir_all(dir) {
+        eprintln!("Couldn't create {}: {}", dir.display(), e);
+        std::process::exit(2);
+    }
+
+    let count = frames.len();
+    for (k, frame) in frames.iter().enumerate() {
+        let path = dir.join(format!("frame_{:04}.{}", k, format.extension()));
+        if let Err(e) = output::save(&path, format, tonemap, frame.width, frame.height, &frame.pixels) {
+            eprintln!("Couldn't write {}: {}", path.display(), e);
+            std::process::exit(2);
+        }
+    }
+    eprintln!("Wrote {} frames to {}", count, dir.display());
+}
+
+//Each frame is rendered with the camera camera gives for it, then saved as it finishes
+#[allow(clippy::too_many_arguments)]
+fn render_animation(renderer: &Renderer, mut scene: Scene, frames: u64, camera: impl Fn(u64) -> Camera, image_path: &Path,
+    format: Format, tonemap: Tonemap, post: PostProcess, aovs: &[Aov]) {
+    let stem = image_path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    for frame in 0..frames {
+        scene.camera = camera(frame);
 
-fn setup_hollow_sphere(world: &mut World, lights: &mut Lighting) {
-    let mat_ground = Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
-    let mat_centre = Arc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
-    let mat_left = Arc::new(Dielectric::new(1.5, 1.0));//Metal::new(Color::new(0.8, 0.8, 0.8), 0.0));
-    let mat_left_inner = Arc::new(Dielectric::new(1.5, 1.0));
-    let mat_right = Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.0));
-
-    let mat_phong = Arc::new(PhongMat::new(
-        1.0,
-        1.0,
-        0.0,
-        0.5,
-        4,
-        Color::new(0.1, 0.2, 0.5),
-        0.0,
-        1.0,
-        0.0,
-    ));
-
-    let sphere_ground = Sphere::new(Point3::new(0.0, -100.5, -1.0), 100.0, mat_ground);
-    let sphere_centre = Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, mat_centre);
-    let sphere_left = Sphere::new(Point3::new(-1.0, 0.0, -1.0), 0.5, mat_left);
-    let sphere_left_inner = Sphere::new(Point3::new(-1.0, 0.0, -1.0), -0.4, mat_left_inner);
-    let sphere_right = Sphere::new(Point3::new(1.0, 0.0, -1.0), 0.5, mat_right);
-
-    let sphere_phong = Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, mat_phong);
-
-    let light_top = SimpleLight::new(Color::new(1.0, 1.0, 1.0), Color::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, -1.0));
-    let light_right = SimpleLight::new(Color::new(1.0, 1.0, 1.0), Color::new(1.0, 1.0, 1.0), Point3::new(2.0, 0.0, -1.0));
-
-    world.push(Box::new(sphere_ground));
-    world.push(Box::new(sphere_centre));
-    //world.push(Box::new(sphere_left));
-    //world.push(Box::new(sphere_left_inner));
-    //world.push(Box::new(sphere_right));
-    world.push(Box::new(sphere_phong));
-    lights.push(Box::new(light_right));
-    //lights.push(Box::new(light_top));
+        let image = post.apply(&renderer.render(&scene));
+        let path = image_path.with_file_name(format!("{}_{:04}.{}", stem, frame, format.extension()));
+        //As for a still, with alpha unless the frame was cut short
+        let alpha = (renderer.settings().transparent && !renderer.is_cancelled())
+            .then(|| post.distort(&renderer.render_aov(&scene, Aov::Alpha)).pixels.iter().map(|c| c[0]).collect::<Vec<f64>>());
+        let written = match &alpha {
+            Some(alpha) => output::save_rgba(&path, format, tonemap, image.width, image.height, &image.pixels, alpha),
+            None => output::save(&path, format, tonemap, image.width, image.height, &image.pixels),
+        };
+        if let Err(e) = written {
+            eprintln!("Couldn't write {}: {}", path.display(), e);
+            std::process::exit(2);
+        }
+        if renderer.is_cancelled() {
+            eprintln!("Interrupted, wrote partial frame {}", path.display());
+            std::process::exit(130);
+        }
+        eprintln!("Frame {}/{} written to {}", frame + 1, frames, path.display());
+        write_aovs(renderer, &scene, aovs, &path, format, post);
+    }
+    eprint!("Done!");
 }
 
-fn random_scene() -> World {
-    let mut rng = rand::thread_rng();
-    let mut world = World::new();
-
-    let ground_mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
-    let ground_sphere = Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_mat);
-
-    world.push(Box::new(ground_sphere));
-
-    for a in -11..=11 {
-        for b in -11..=11 {
-            let choose_mat: f64 = rng.gen();
-            let center = Point3::new((a as f64) + rng.gen_range(0.0..0.9),
-                                     0.2,
-                                     (b as f64) + rng.gen_range(0.0..0.9));
-
-            if choose_mat < 0.8 {
-                // Diffuse
-                let albedo = Color::random(0.0..1.0) * Color::random(0.0..1.0);
-                let sphere_mat = Arc::new(Lambertian::new(albedo));
-                let sphere = Sphere::new(center, 0.2, sphere_mat);
-
-                world.push(Box::new(sphere));
-            } else if choose_mat < 0.95 {
-                // Metal
-                let albedo = Color::random(0.4..1.0);
-                let fuzz = rng.gen_range(0.0..0.5);
-                let sphere_mat = Arc::new(Metal::new(albedo, fuzz));
-                let sphere = Sphere::new(center, 0.2, sphere_mat);
-
-                world.push(Box::new(sphere));
-            } else {
-                // Glass
-                let sphere_mat = Arc::new(Dielectric::new(1.5, 1.0));
-                let sphere = Sphere::new(center, 0.2, sphere_mat);
-
-                world.push(Box::new(sphere));
+//Each AOV goes next to the image, named after it, bent by the lens as the image was
+fn write_aovs(renderer: &Renderer, scene: &Scene, aovs: &[Aov], image_path: &Path, format: Format, post: PostProcess) {
+    let stem = image_path.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    for &aov in aovs {
+        let path = image_path.with_file_name(format!("{}.{}.{}", stem, aov.name(), format.extension()));
+        let buffer = post.distort(&renderer.render_aov(scene, aov));
+        let written = if format.is_float() {
+            //Float formats can hold the real values, normals in [-1, 1] and distances
+            output::save(&path, format, Tonemap::Clamp, buffer.width, buffer.height, &buffer.pixels)
+        } else if aov.is_data() {
+            output::save_linear(&path, format, buffer.width, buffer.height, &aov.encode(&buffer))
+        } else {
+            //Albedo is reflectance, already in [0, 1]
+            output::save(&path, format, Tonemap::Clamp, buffer.width, buffer.height, &aov.encode(&buffer))
+        };
+        match written {
+            Ok(()) => eprintln!("Wrote {} to {}", aov.name(), path.display()),
+            Err(e) => {
+                eprintln!("Couldn't write {}: {}", path.display(), e);
+                std::process::exit(2);
             }
         }
     }
+}
+
+fn run_merge(parts: &[PathBuf], args: &Args) {
+    let load = |path: &PathBuf| Checkpoint::load(path).unwrap_or_else(|e| {
+        eprintln!("Couldn't load {}: {}", path.display(), e);
+        std::process::exit(2);
+    });
+    let mut merged = load(&parts[0]);
+    for path in &parts[1..] {
+        if let Err(e) = merged.merge(load(path)) {
+            eprintln!("Can't merge {}: {}", path.display(), e);
+            std::process::exit(2);
+        }
+    }
+    let (fewest, most) = merged.samples();
+    if fewest < most {
+        eprintln!("Some tiles have only {} samples per pixel, others {}", fewest, most);
+    }
+
+    if let Some(path) = &args.checkpoint {
+        if let Err(e) = merged.save(path) {
+            eprintln!("Couldn't write {}: {}", path.display(), e);
+            std::process::exit(2);
+        }
+    }
+    let image = merged.image();
+    let written = match &args.output {
+        Some(path) => output::save(path, args.format.unwrap_or_else(|| Format::from_path(path)), args.tonemap, image.width, image.height, &image.pixels),
+        None => output::write(io::stdout().lock(), args.format.unwrap_or(Format::Ppm), args.tonemap, image.width, image.height, &image.pixels),
+    };
+    if let Err(e) = written {
+        eprintln!("Couldn't write the image: {}", e);
+        std::process::exit(2);
+    }
+}
+
+//FIRST..END
+fn parse_range(s: &str) -> Result<Range<usize>, String> {
+    let (first, end) = s.split_once("..").ok_or("expected FIRST..END")?;
+    let parse = |n: &str| n.trim().parse::<usize>().map_err(|e| e.to_string());
+    let (first, end) = (parse(first)?, parse(end)?);
+    if first >= end {
+        return Err(format!("{}..{} is empty, END has to be after FIRST", first, end));
+    }
+    Ok(first..end)
+}
+
+//X,Y,Z
+fn parse_point(s: &str) -> Result<Point3, String> {
+    let coords = s.split(',').map(|n| n.trim().parse::<f64>().map_err(|e| e.to_string())).collect::<Result<Vec<f64>, String>>()?;
+    match coords[..] {
+        [x, y, z] => Ok(Point3::new(x, y, z)),
+        _ => Err("expected X,Y,Z".to_string()),
+    }
+}
+
+fn parse_image_point(s: &str) -> Result<(f64, f64), String> {
+    match s.split(',').map(|part| part.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>() {
+        Ok(v) if v.len() == 2 && v.iter().all(|x| (0.0..=1.0).contains(x)) => Ok((v[0], v[1])),
+        Ok(_) => Err("expected X,Y, each 0 to 1".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+//Seconds, as a fraction like 1/125 or a number
+fn parse_shutter_speed(s: &str) -> Result<f64, String> {
+    let seconds = match s.split_once('/') {
+        Some((top, bottom)) => {
+            let (top, bottom) = (positive_f64(top.trim())?, positive_f64(bottom.trim())?);
+            top / bottom
+        }
+        None => positive_f64(s)?,
+    };
+    Ok(seconds)
+}
+
+fn positive_f64(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(v) if v > 0.0 => Ok(v),
+        Ok(_) => Err("must be more than 0".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-    let mat1 = Arc::new(Dielectric::new(1.5, 1.0));
-    let mat2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
-    let mat3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+fn non_negative_f64(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(v) if v >= 0.0 => Ok(v),
+        Ok(_) => Err("can't be less than 0".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn run_diff(a: &Path, b: &Path, heatmap: Option<&Path>, threshold: &diff::Threshold) {
+    let load = |path: &Path| Image::read(path).unwrap_or_else(|e| {
+        eprintln!("Couldn't read {}: {}", path.display(), e);
+        std::process::exit(2);
+    });
+    let (img_a, img_b) = (load(a), load(b));
 
-    let sphere1 = Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, mat1);
-    let sphere2 = Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, mat2);
-    let sphere3 = Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, mat3);
+    let stats = diff::compare(&img_a, &img_b).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    println!("RMSE: {:.6}", stats.rmse);
+    println!("PSNR: {:.2} dB", stats.psnr);
+    println!("SSIM: {:.6}", stats.ssim);
+    println!("Max pixel error: {:.6}", stats.max_error);
 
-    world.push(Box::new(sphere1));
-    world.push(Box::new(sphere2));
-    world.push(Box::new(sphere3));
+    if let Some(path) = heatmap {
+        let (map, scale) = diff::heatmap(&img_a, &img_b);
+        if let Err(e) = map.write_ppm(path) {
+            eprintln!("Couldn't write {}: {}", path.display(), e);
+            std::process::exit(2);
+        }
+        eprintln!("Wrote heatmap to {} (white = error of {:.4})", path.display(), scale);
+    }
 
-    world
-}
\ No newline at end of file
+    if let Err(e) = threshold.check(&stats) {
+        eprintln!("Images differ too much: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,68 +1,202 @@
-use rand::seq::index;
-use rand::Rng;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
 
 use super::vec3::{Color, Point3, Vec3};
-use super::ray::Ray;
+use super::ray::{self, Ray};
 use super::hit::{Hit, HitRecord, OccludingHit, World};
-use super::light::{Light, Lighting};
+use super::interior::{Interior, CHANNEL_WAVELENGTHS, REFERENCE_WAVELENGTH};
+use super::light::{self, Lighting};
+use super::random::random_f64;
+use super::light_tree;
+use super::sampler::{decision_2d, next_2d};
+use super::spectrum::upsample;
+use super::texture::{SolidColor, Texture};
+
+
+//A scattered ray along with what the integrator needs to weigh it: the attenuation
+//(BSDF times cosine over pdf, or just the colour for a specular bounce) and, for rays
+//drawn from a distribution, the pdf per unit solid angle they were drawn with. Specular
+//rays, the one direction a mirror or glass allows, have none, as a light sample could
+//never have found the same path.
+pub struct ScatterRecord {
+    pub attenuation: Color,
+    pub ray: Ray,
+    pub pdf: Option<f64>,
+}
 
+impl ScatterRecord {
+    pub fn is_specular(&self) -> bool {
+        self.pdf.is_none()
+    }
+}
+
+//The materials that come down to a few numbers, for renderers that can't call back into
+//Scatter (the GPU one)
+#[derive(Clone, Copy)]
+pub enum Plain {
+    Lambertian { albedo: Color },
+    Metal { albedo: Color, fuzz: f64 },
+    //Clear, not dispersive, and taken to be in air
+    Dielectric { ior: f64 },
+    Light { emit: Color },
+}
 
 pub trait Scatter: Send + Sync {
     fn scatter(&self, vpos: Point3, lights: &Lighting, world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+    //Fraction of light a shadow ray keeps going through the surface, 0 for opaque
     fn occlusion(&self) -> f64;
+    //What a shadow ray keeps, per channel, going through the surface at rec, having come
+    //travelled from the last surface it went through (or from where it set out). By
+    //default just occlusion, untinted.
+    fn transmittance(&self, _rec: &HitRecord, _travelled: f64) -> Color {
+        let through = self.occlusion().clamp(0.0, 1.0);
+        Color::new(through, through, through)
+    }
+    //Light given off at the hit, on top of whatever is scattered. Nothing glows by default.
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+    //Whether hits are points inside a participating medium rather than on a surface.
+    //Their normals are meaningless, so light can reach them from any side.
+    fn in_medium(&self) -> bool {
+        false
+    }
+    //Colour of the surface itself, unlit, for the albedo AOV. Things without a colour
+    //of their own, like clear glass, are white.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+    //For lighting the hit by sampling lights directly (next-event estimation): the BSDF
+    //times the cosine for light arriving from direction wi and leaving towards wo (a unit
+    //vector back along the ray that found the hit), and the pdf, per unit solid angle, of
+    //scatter picking wi. None for materials that can't be evaluated that way, such as
+    //mirrors and glass, which only ever see light by scattering.
+    fn eval(&self, _rec: &HitRecord, _wo: Vec3, _wi: Vec3) -> Option<(Color, f64)> {
+        None
+    }
+    //Just the pdf from eval, 0 for materials that can't be evaluated
+    fn scattering_pdf(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> f64 {
+        self.eval(rec, wo, wi).map_or(0.0, |(_, pdf)| pdf)
+    }
+    //scatter, with the pdf of the direction it picked (see ScatterRecord), for MIS.
+    //Materials that know it while scattering can give it here rather than have it
+    //worked out again through eval.
+    fn scatter_record(&self, vpos: Point3, lights: &Lighting, world: &World, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let (attenuation, ray) = self.scatter(vpos, lights, world, r_in, rec)?;
+        let wo = (-1.0) * r_in.direction().normalized();
+        let pdf = self.eval(rec, wo, ray.direction()).map(|(_, pdf)| pdf);
+        Some(ScatterRecord { attenuation, ray, pdf })
+    }
+    //Bend rec.normal before the hit is shaded, for detail finer than the geometry (see
+    //NormalMapped). Called once per hit, before any of the above.
+    fn perturb(&self, _r_in: &Ray, _rec: &mut HitRecord) {}
+    //What fills the object, for materials whose rays pass through the surface into the
+    //inside, like glass. The integrator uses it to keep track of what rays are inside.
+    fn interior(&self) -> Option<Interior> {
+        None
+    }
+    //Whether, when rendering spectrally, scatter's attenuation is already at the
+    //wavelengths r_in carries rather than an RGB colour to be turned into them
+    fn spectral_attenuation(&self) -> bool {
+        false
+    }
+    //What it is, if it's one of the Plain ones with a solid colour
+    fn plain(&self) -> Option<Plain> {
+        None
+    }
 }
 
 
 
 pub struct Lambertian {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
     occlusion: f64,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Lambertian {
+        Lambertian::with_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>) -> Lambertian {
         Lambertian { albedo, occlusion: 0.0 }
     }
 }
 
+//Cosine-distributed direction about the normal n, from the sampler's next point when
+//it has one for the decision (see decision_2d)
+fn diffuse_direction(n: Vec3) -> Vec3 {
+    if let Some((u1, u2)) = decision_2d() {
+        let (t, b) = (n.any_perpendicular(), n.cross(n.any_perpendicular()));
+        let (r, phi) = (u1.sqrt(), 2.0 * PI * u2);
+        return r * phi.cos() * t + r * phi.sin() * b + (1.0 - u1).max(0.0).sqrt() * n;
+    }
+    let scatter_direction = n + Vec3::random_in_unit_sphere().normalized();
+    //Catch degen scatter direction (exactly opposite normal, gets 0 length, will cause 
+    //zero and infinity errors
+    if scatter_direction.near_zero() {
+        n
+    } else {
+        scatter_direction
+    }
+}
+
 impl Scatter for Lambertian {
     //Calculate a new ray (the ray scattered off the object) and its color.
-    fn scatter(&self, vpos: Point3, lights: &Lighting, world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>{
-        let mut scatter_direction = rec.normal + Vec3::random_in_unit_sphere().normalized();
-        //Catch degen scatter direction (exactly opposite normal, gets 0 length, will cause 
-        //zero and infinity errors
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
-        }
+    fn scatter(&self, _vpos: Point3, _lights: &Lighting, _world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>{
+        let scatter_direction = diffuse_direction(rec.normal);
 
-        Some((self.albedo, Ray::new(rec.p, scatter_direction)))
+        Some((self.albedo.value_at(rec), Ray::new(rec.p, scatter_direction).with_time(r_in.time())))
     }
     fn occlusion(&self) -> f64 {
         self.occlusion
     }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value_at(rec)
+    }
+    //Without looking the albedo up twice
+    fn scatter_record(&self, _vpos: Point3, _lights: &Lighting, _world: &World, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let scatter_direction = diffuse_direction(rec.normal);
+        let pdf = rec.normal.dot(scatter_direction.normalized()).max(0.0) / PI;
+        let ray = Ray::new(rec.p, scatter_direction).with_time(r_in.time());
+        Some(ScatterRecord { attenuation: self.albedo.value_at(rec), ray, pdf: Some(pdf) })
+    }
+    //scatter's directions are cosine-distributed, so its pdf is cos / pi
+    fn eval(&self, rec: &HitRecord, _wo: Vec3, wi: Vec3) -> Option<(Color, f64)> {
+        let cosine = rec.normal.dot(wi.normalized()).max(0.0);
+        Some((cosine / PI * self.albedo.value_at(rec), cosine / PI))
+    }
+    fn plain(&self) -> Option<Plain> {
+        self.albedo.constant().map(|albedo| Plain::Lambertian { albedo })
+    }
 }
 
 
 //This is actually specular reflection
 pub struct Metal {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
     fuzz: f64,
     occlusion: f64,
 }
 
 impl Metal {
     pub fn new(a: Color, f: f64) -> Metal {
-        Metal {albedo: a, fuzz: f, occlusion: 0.0}
+        Metal::with_texture(Arc::new(SolidColor::new(a)), f)
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>, fuzz: f64) -> Metal {
+        Metal { albedo, fuzz, occlusion: 0.0 }
     }
 }
 
 impl Scatter for Metal {
-    fn scatter(&self, vpos: Point3, lights: &Lighting, world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, _vpos: Point3, _lights: &Lighting, _world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
         let scatter_direction = r_in.direction().reflect(rec.normal).normalized();
-        let scattered = Ray::new(rec.p, scatter_direction + self.fuzz * Vec3::random_in_unit_sphere());
+        let scattered = Ray::new(rec.p, scatter_direction + self.fuzz * Vec3::random_in_unit_sphere()).with_time(r_in.time());
 
         if scattered.direction().dot(rec.normal) > 0.0 {
-            Some((self.albedo, scattered))
+            Some((self.albedo.value_at(rec), scattered))
         }
         else {
             None
@@ -71,16 +205,137 @@ impl Scatter for Metal {
     fn occlusion(&self) -> f64 {
         self.occlusion
     }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value_at(rec)
+    }
+    fn plain(&self) -> Option<Plain> {
+        self.albedo.constant().map(|albedo| Plain::Metal { albedo, fuzz: self.fuzz })
+    }
+}
+
+//Glows with the texture's colour from the front face and absorbs everything that
+//hits it. Colours above 1 are fine, and usually needed for a small light to light
+//up much of a scene.
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> DiffuseLight {
+        DiffuseLight::with_texture(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub fn with_texture(emit: Arc<dyn Texture>) -> DiffuseLight {
+        DiffuseLight { emit }
+    }
+}
+
+impl Scatter for DiffuseLight {
+    fn scatter(&self, _vpos: Point3, _lights: &Lighting, _world: &World, _r_in: &Ray, _rec: &HitRecord) -> Option<(Color, Ray)> {
+        None
+    }
+    fn occlusion(&self) -> f64 {
+        0.0
+    }
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        if rec.front_face {
+            self.emit.value_at(rec)
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+    fn plain(&self) -> Option<Plain> {
+        self.emit.constant().map(|emit| Plain::Light { emit })
+    }
+}
+
+//Phase function for a participating medium: scatters the same amount in every
+//direction, whichever way the light came in
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Isotropic {
+        Isotropic::with_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>) -> Isotropic {
+        Isotropic { albedo }
+    }
 }
 
+impl Scatter for Isotropic {
+    fn scatter(&self, _vpos: Point3, _lights: &Lighting, _world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let direction = Vec3::random_in_unit_sphere().normalized();
+        Some((self.albedo.value_at(rec), Ray::new(rec.p, direction).with_time(r_in.time())))
+    }
+    fn occlusion(&self) -> f64 {
+        0.0
+    }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value_at(rec)
+    }
+    fn in_medium(&self) -> bool {
+        true
+    }
+    //No cosine inside a medium
+    fn eval(&self, rec: &HitRecord, _wo: Vec3, _wi: Vec3) -> Option<(Color, f64)> {
+        Some((self.albedo.value_at(rec) / (4.0 * PI), 1.0 / (4.0 * PI)))
+    }
+}
+
+//Glass, water and the like. Objects of it can nest or overlap: the refraction at each
+//boundary is worked out from what's on either side of it, and where two objects overlap
+//the one with the higher priority fills the overlap. For liquid in a glass, model the
+//liquid slightly bigger than the glass's inside, so they overlap, and give the glass
+//the higher priority.
 pub struct Dielectric {
     ir: f64,
     occlusion: f64,
+    //Per unit distance inside, per channel
+    absorption: Color,
+    //Cauchy's B, in square micrometres
+    dispersion: f64,
+    priority: u32,
+    id: usize,
 }
 
 impl Dielectric {
     pub fn new(index_of_refraction: f64, occlusion: f64) -> Dielectric {
-        Dielectric { ir: index_of_refraction, occlusion }
+        Dielectric {
+            ir: index_of_refraction,
+            occlusion,
+            absorption: Color::new(0.0, 0.0, 0.0),
+            dispersion: 0.0,
+            priority: 0,
+            id: Interior::next_id(),
+        }
+    }
+
+    //Light travelling a distance d inside is dimmed by exp(-absorption * d) (Beer-Lambert),
+    //so thick glass is darker than thin and absorbing more red than blue tints it blue
+    pub fn with_absorption(mut self, absorption: Color) -> Dielectric {
+        self.absorption = absorption;
+        self
+    }
+
+    //Makes the index of refraction depend on wavelength, splitting white light into
+    //colours: by Cauchy's equation, ior + dispersion * (1 / wavelength^2 - 1 / 0.55^2)
+    //with wavelengths in micrometres, so ior is the index for green. About 0.004 for
+    //window glass, 0.01 or more for flint glass and diamond. Each path refracts for only
+    //one of red, green and blue (or of its wavelengths, rendering spectrally), picked at
+    //random, so renders take more samples to settle. Rendering spectrally gives smooth
+    //rainbows rather than three separate images.
+    pub fn with_dispersion(mut self, dispersion: f64) -> Dielectric {
+        self.dispersion = dispersion;
+        self
+    }
+
+    //Higher wins where objects overlap, 0 by default
+    pub fn with_priority(mut self, priority: u32) -> Dielectric {
+        self.priority = priority;
+        self
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -91,20 +346,34 @@ impl Dielectric {
 }
 
 impl Scatter for Dielectric {
-    fn scatter(&self, vpos: Point3, lights: &Lighting, world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, _vpos: Point3, _lights: &Lighting, _world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        //Whatever's around the object on this side of the boundary, and what the
+        //inside is with that around it
+        let outside = r_in.interiors().without(self.id);
+        let inside = outside.with(self.interior()?);
+        //A path meeting its first dispersive surface is narrowed to one channel, at
+        //random, which the integrator then weights (see Ray::channel)
+        let channel = match r_in.channel() {
+            None if self.dispersion != 0.0 => Some(((3.0 * random_f64()) as usize).min(2)),
+            channel => channel,
+        };
+        let wavelength = match (channel, r_in.wavelengths()) {
+            (Some(c), Some(wavelengths)) => wavelengths[c],
+            (Some(c), None) => CHANNEL_WAVELENGTHS[c],
+            (None, _) => REFERENCE_WAVELENGTH,
+        };
         let refraction_ratio = if rec.front_face {
-            1.0/self.ir
+            outside.ior_at(wavelength) / inside.ior_at(wavelength)
         } else {
-            self.ir
+            inside.ior_at(wavelength) / outside.ior_at(wavelength)
         };
 
         let unit_direction = r_in.direction().normalized();
         let cos_theta = ((-1.0) * unit_direction).dot(rec.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
-        let mut rng = rand::thread_rng();
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let will_reflect = rng.gen::<f64>() < Self::reflectance(cos_theta, refraction_ratio);
+        let will_reflect = random_f64() < Self::reflectance(cos_theta, refraction_ratio);
 
         let direction = if cannot_refract || will_reflect {
             //Must reflect (no solution to refraction eqns)
@@ -114,23 +383,563 @@ impl Scatter for Dielectric {
             unit_direction.refract(rec.normal, refraction_ratio)
         };
 
-        let scattered = Ray::new(rec.p, direction);
+        let scattered = Ray::new(rec.p, direction).with_time(r_in.time()).with_channel(channel);
 
         Some((Color::new(1.0, 1.0, 1.0), scattered))
     }
     fn occlusion(&self) -> f64 {
         self.occlusion
     }
+    //occlusion going in, then on the way out whatever was absorbed inside, so coloured
+    //glass casts a shadow of its colour, deeper where it's thicker
+    fn transmittance(&self, rec: &HitRecord, travelled: f64) -> Color {
+        if rec.front_face {
+            let through = self.occlusion.clamp(0.0, 1.0);
+            return Color::new(through, through, through);
+        }
+        let a = self.absorption;
+        Color::new((-a[0] * travelled).exp(), (-a[1] * travelled).exp(), (-a[2] * travelled).exp())
+    }
+    fn interior(&self) -> Option<Interior> {
+        Some(Interior { id: self.id, ior: self.ir, dispersion: self.dispersion, priority: self.priority, absorption: self.absorption })
+    }
+    fn plain(&self) -> Option<Plain> {
+        (self.absorption.near_zero() && self.dispersion == 0.0).then_some(Plain::Dielectric { ior: self.ir })
+    }
 }
 
 
+//Physically based surface in the usual metallic/roughness terms: a GGX (Trowbridge-Reitz)
+//microfacet specular layer over a Lambertian base. Non-metals reflect about 4% head on
+//and show base_color diffusely underneath, like plastic or paint; metals have no diffuse
+//part and tint their reflections with base_color instead. Roughness 0 is a near mirror,
+//1 very rough. Anisotropy stretches the highlight along the surface's tangent (positive)
+//or bitangent (negative), like brushed metal.
+//
+//Reflection directions are picked by sampling the microfacet normals visible from the
+//ray (Heitz 2018), which wastes far fewer samples than sampling the whole distribution.
+//Light that would bounce again between microfacets is lost, so very rough surfaces come
+//out a little dark.
+pub struct Pbr {
+    base_color: Arc<dyn Texture>,
+    metallic: f64,
+    roughness: f64,
+    anisotropy: f64,
+}
+
+impl Pbr {
+    pub fn new(base_color: Color, metallic: f64, roughness: f64) -> Pbr {
+        Pbr::with_texture(Arc::new(SolidColor::new(base_color)), metallic, roughness)
+    }
+
+    pub fn with_texture(base_color: Arc<dyn Texture>, metallic: f64, roughness: f64) -> Pbr {
+        Pbr { base_color, metallic: metallic.clamp(0.0, 1.0), roughness: roughness.clamp(0.0, 1.0), anisotropy: 0.0 }
+    }
+
+    //In [-1, 1]
+    pub fn with_anisotropy(mut self, anisotropy: f64) -> Pbr {
+        self.anisotropy = anisotropy.clamp(-1.0, 1.0);
+        self
+    }
+
+    //GGX widths along the tangent and bitangent. Roughness is squared so it looks
+    //about linear, and kept off 0, which the maths can't take.
+    fn alphas(&self) -> (f64, f64) {
+        let alpha = (self.roughness * self.roughness).max(0.001);
+        let aspect = (1.0 - 0.9 * self.anisotropy.abs()).sqrt();
+        if self.anisotropy >= 0.0 {
+            (alpha / aspect, alpha * aspect)
+        } else {
+            (alpha * aspect, alpha / aspect)
+        }
+    }
+
+    //Chance of sampling the specular lobe rather than the diffuse one
+    fn specular_chance(&self) -> f64 {
+        0.5 + 0.5 * self.metallic
+    }
+
+    //Tangent, bitangent and normal at the hit, the normal on the side the ray came from
+    fn frame(rec: &HitRecord) -> (Vec3, Vec3, Vec3) {
+        let n = rec.normal;
+        let along = rec.tangent - rec.tangent.dot(n) * n;
+        let t = if along.near_zero() { n.any_perpendicular() } else { along.normalized() };
+        (t, n.cross(t), n)
+    }
+
+    //eval with wo and wi in the local frame, z along the normal
+    fn eval_local(&self, rec: &HitRecord, o: Vec3, i: Vec3) -> (Color, f64) {
+        if o.z() <= 0.0 || i.z() <= 0.0 {
+            return (Color::new(0.0, 0.0, 0.0), 0.0);
+        }
+        let (ax, ay) = self.alphas();
+        let h = (o + i).normalized();
+        let base = self.base_color.value_at(rec);
+        let f0 = (1.0 - self.metallic) * Color::new(0.04, 0.04, 0.04) + self.metallic * base;
+        let fresnel = f0 + (1.0 - i.dot(h)).max(0.0).powi(5) * (Color::new(1.0, 1.0, 1.0) - f0);
+
+        let d = ggx_d(h, ax, ay);
+        let (lambda_o, lambda_i) = (ggx_lambda(o, ax, ay), ggx_lambda(i, ax, ay));
+        //Height-correlated masking and shadowing, times the cosine, which cancels
+        let specular = d / (1.0 + lambda_o + lambda_i) / (4.0 * o.z()) * fresnel;
+        let diffuse = (1.0 - self.metallic) * i.z() / PI * (Color::new(1.0, 1.0, 1.0) - fresnel) * base;
+
+        let specular_pdf = d / (1.0 + lambda_o) / (4.0 * o.z());
+        let diffuse_pdf = i.z() / PI;
+        let chance = self.specular_chance();
+        (specular + diffuse, chance * specular_pdf + (1.0 - chance) * diffuse_pdf)
+    }
+}
+
+//GGX distribution of microfacet normals h (local frame)
+fn ggx_d(h: Vec3, ax: f64, ay: f64) -> f64 {
+    let e = (h.x() / ax).powi(2) + (h.y() / ay).powi(2) + h.z() * h.z();
+    1.0 / (PI * ax * ay * e * e)
+}
+
+//Smith's Lambda for GGX, from which masking of direction v is 1 / (1 + Lambda)
+fn ggx_lambda(v: Vec3, ax: f64, ay: f64) -> f64 {
+    let tan2 = ((ax * v.x()).powi(2) + (ay * v.y()).powi(2)) / (v.z() * v.z());
+    0.5 * ((1.0 + tan2).sqrt() - 1.0)
+}
+
+//A microfacet normal visible from o, with density G1(o) max(0, o.h) D(h) / o.z
+fn sample_visible_normal(o: Vec3, ax: f64, ay: f64, (u1, u2): (f64, f64)) -> Vec3 {
+    //Stretch to where the roughness is 1, pick a point on the projected hemisphere there,
+    //and unstretch
+    let v = Vec3::new(ax * o.x(), ay * o.y(), o.z()).normalized();
+    let len2 = v.x() * v.x() + v.y() * v.y();
+    let t1 = if len2 > 0.0 { Vec3::new(-v.y(), v.x(), 0.0) / len2.sqrt() } else { Vec3::new(1.0, 0.0, 0.0) };
+    let t2 = v.cross(t1);
+
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + v.z());
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+    Vec3::new(ax * n.x(), ay * n.y(), n.z().max(1e-6)).normalized()
+}
+
+impl Scatter for Pbr {
+    fn scatter(&self, _vpos: Point3, _lights: &Lighting, _world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let (t, b, n) = Self::frame(rec);
+        let to_local = |v: Vec3| Vec3::new(v.dot(t), v.dot(b), v.dot(n));
+        let o = to_local((-1.0) * r_in.direction().normalized());
+        if o.z() <= 0.0 {
+            return None;
+        }
+
+        let i = if random_f64() < self.specular_chance() {
+            let (ax, ay) = self.alphas();
+            let h = sample_visible_normal(o, ax, ay, next_2d());
+            2.0 * o.dot(h) * h - o
+        } else {
+            //Cosine-weighted
+            let (u1, u2) = next_2d();
+            let (r, phi) = (u1.sqrt(), 2.0 * PI * u2);
+            Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u1).max(0.0).sqrt())
+        };
+        let (f, pdf) = self.eval_local(rec, o, i);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        let direction = i.x() * t + i.y() * b + i.z() * n;
+        Some((f / pdf, Ray::new(rec.p, direction).with_time(r_in.time())))
+    }
+    fn occlusion(&self) -> f64 {
+        0.0
+    }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base_color.value_at(rec)
+    }
+    fn eval(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Option<(Color, f64)> {
+        let (t, b, n) = Self::frame(rec);
+        let to_local = |v: Vec3| Vec3::new(v.dot(t), v.dot(b), v.dot(n));
+        Some(self.eval_local(rec, to_local(wo), to_local(wi.normalized())))
+    }
+}
+
+//Translucent stuff like wax, skin, milk or marble, where light goes in, bounces around
+//inside and comes out somewhere else. Scattering walks a path through the inside until
+//it finds its way out again, finding the boundary by tracing the world, so the object
+//must be closed. Light that reflects off the surface instead goes the same way as for
+//glass of the same ior.
+//
+//color is roughly what colour the surface ends up (it sets how much of the light each
+//bounce inside keeps, by Chiang et al.'s fit for a random walk), and mean_free_path how
+//far light of each colour gets between bounces, in scene units: the longer it is
+//compared to the object, the more see-through it looks. Like glass, it's only lit by
+//what scattering finds, so it needs glowing objects or a background rather than point
+//lights.
+pub struct Subsurface {
+    color: Arc<dyn Texture>,
+    mean_free_path: Color,
+    ior: f64,
+    anisotropy: f64,
+}
+
+impl Subsurface {
+    //Give up on paths that have bounced this many times inside
+    const MAX_BOUNCES: usize = 256;
+
+    pub fn new(color: Color, mean_free_path: Color, ior: f64) -> Subsurface {
+        Subsurface::with_texture(Arc::new(SolidColor::new(color)), mean_free_path, ior)
+    }
+
+    pub fn with_texture(color: Arc<dyn Texture>, mean_free_path: Color, ior: f64) -> Subsurface {
+        Subsurface { color, mean_free_path, ior, anisotropy: 0.0 }
+    }
+
+    //Henyey-Greenstein g for each bounce inside, in (-1, 1): positive keeps light going
+    //the way it was (as in skin), negative sends it back, 0 is the same every way
+    pub fn with_anisotropy(mut self, g: f64) -> Subsurface {
+        self.anisotropy = g.clamp(-0.99, 0.99);
+        self
+    }
+
+    //Fraction of light kept at each bounce inside for the surface to come out about a
+    fn single_scattering_albedo(a: f64) -> f64 {
+        let a = a.clamp(0.0, 0.999);
+        1.0 - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2)
+    }
+
+    //New direction for light going along d that bounces inside
+    fn bounce(&self, d: Vec3) -> Vec3 {
+        let g = self.anisotropy;
+        if g.abs() < 1e-3 {
+            return Vec3::random_in_unit_sphere().normalized();
+        }
+        let k = (1.0 - g * g) / (1.0 - g + 2.0 * g * random_f64());
+        let cos = ((1.0 + g * g - k * k) / (2.0 * g)).clamp(-1.0, 1.0);
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_f64();
+        let u = d.any_perpendicular();
+        let v = d.cross(u);
+        cos * d + sin * (phi.cos() * u + phi.sin() * v)
+    }
+
+    //Follow light in from p along (unit) d until it leaves, returning how much of it gets
+    //out and the way it goes; None if it doesn't. Distances are picked using one colour
+    //channel at a time, chosen at random, and weighted by the chance of any of the three
+    //having picked them, so each colour can go as far as it should.
+    fn walk(&self, world: &World, mut p: Point3, mut d: Vec3, time: f64, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let color = self.color.value_at(rec);
+        let f = |c: usize| Self::single_scattering_albedo(color[c]);
+        let albedo = Color::new(f(0), f(1), f(2));
+        let g = |c: usize| 1.0 / self.mean_free_path[c].max(1e-9);
+        let sigma = Color::new(g(0), g(1), g(2));
+        let transmittance = |t: f64| Color::new((-sigma[0] * t).exp(), (-sigma[1] * t).exp(), (-sigma[2] * t).exp());
+        let mean = |c: Color| (c[0] + c[1] + c[2]) / 3.0;
+
+        let mut weight = Color::new(1.0, 1.0, 1.0);
+        for _ in 0..Self::MAX_BOUNCES {
+            let channel = ((random_f64() * 3.0) as usize).min(2);
+            let t = -(1.0 - random_f64()).ln() / sigma[channel];
+            let ray = Ray::new(p, d).with_time(time);
+
+            match world.hit(&ray, ray::limits().epsilon, t) {
+                //Got to the surface first
+                Some(hit) => {
+                    let through = transmittance(hit.t);
+                    weight = weight * through / mean(through);
+                    let ratio = if hit.front_face { 1.0 / self.ior } else { self.ior };
+                    let cos_theta = ((-1.0) * d).dot(hit.normal).min(1.0);
+                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                    p = hit.p;
+                    if ratio * sin_theta > 1.0 || random_f64() < Dielectric::reflectance(cos_theta, ratio) {
+                        d = d.reflect(hit.normal).normalized();
+                    } else {
+                        return Some((weight, Ray::new(p, d.refract(hit.normal, ratio)).with_time(time)));
+                    }
+                }
+                None => {
+                    let through = transmittance(t);
+                    weight = weight * albedo * sigma * through / mean(sigma * through);
+                    p = ray.at(t);
+                    d = self.bounce(d).normalized();
+                }
+            }
+
+            //Russian roulette, so dim paths don't go on for ever
+            let brightest = weight[0].max(weight[1]).max(weight[2]);
+            if brightest < 0.25 {
+                if random_f64() >= brightest {
+                    return None;
+                }
+                weight /= brightest;
+            }
+        }
+        None
+    }
+}
+
+impl Scatter for Subsurface {
+    fn scatter(&self, _vpos: Point3, _lights: &Lighting, world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let unit_direction = r_in.direction().normalized();
+        let inward = if rec.front_face {
+            let cos_theta = ((-1.0) * unit_direction).dot(rec.normal).min(1.0);
+            if random_f64() < Dielectric::reflectance(cos_theta, 1.0 / self.ior) {
+                let reflected = Ray::new(rec.p, unit_direction.reflect(rec.normal)).with_time(r_in.time());
+                return Some((Color::new(1.0, 1.0, 1.0), reflected));
+            }
+            unit_direction.refract(rec.normal, 1.0 / self.ior).normalized()
+        } else {
+            //Already inside
+            unit_direction
+        };
+        self.walk(world, rec.p, inward, r_in.time(), rec)
+    }
+    fn occlusion(&self) -> f64 {
+        0.0
+    }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.color.value_at(rec)
+    }
+}
+
+//Where the detail on a NormalMapped surface comes from
+pub enum Detail {
+    //Tangent-space normals packed into colours as 0.5 * (n + 1): red along the tangent
+    //(increasing u), green along the bitangent (increasing v), blue out of the surface.
+    //Image maps need mapping.color_space = "linear".
+    NormalMap(Arc<dyn Texture>),
+    //Heights, from the texture's brightness. The slope is taken by nudging both (u, v)
+    //and the hit point a little along the tangents, so it works for image and solid
+    //textures alike.
+    BumpMap(Arc<dyn Texture>),
+}
+
+//Another material with its shading normal bent by a normal or bump map, so a surface
+//can show fine detail without more geometry. Only the shading is changed: the surface
+//is still hit where it was. Needs a tangent frame that follows the texture's (u, v),
+//which every primitive but the infinite plane and the boxes provide.
+pub struct NormalMapped {
+    inner: Arc<dyn Scatter>,
+    detail: Detail,
+    strength: f64,
+}
+
+impl NormalMapped {
+    pub fn new(inner: Arc<dyn Scatter>, detail: Detail) -> NormalMapped {
+        NormalMapped { inner, detail, strength: 1.0 }
+    }
+
+    //Scales the tilt of a normal map's normals or the height of a bump map, 0 for flat
+    pub fn with_strength(mut self, strength: f64) -> NormalMapped {
+        self.strength = strength;
+        self
+    }
+
+    //Normal in the tangent frame (x along the tangent, y the bitangent, z outward)
+    fn local_normal(&self, rec: &HitRecord, t: Vec3, b: Vec3) -> Vec3 {
+        match &self.detail {
+            Detail::NormalMap(map) => {
+                let c = map.value_at(rec);
+                Vec3::new(self.strength * (2.0 * c[0] - 1.0), self.strength * (2.0 * c[1] - 1.0), (2.0 * c[2] - 1.0).max(0.0))
+            }
+            Detail::BumpMap(map) => {
+                const STEP: f64 = 1e-3;
+                let height = |du: f64, dv: f64| map.value(rec.u + du, rec.v + dv, rec.p + du * t + dv * b).luminance();
+                let h = height(0.0, 0.0);
+                let slope_u = (height(STEP, 0.0) - h) / STEP;
+                let slope_v = (height(0.0, STEP) - h) / STEP;
+                Vec3::new(-self.strength * slope_u, -self.strength * slope_v, 1.0)
+            }
+        }
+    }
+}
+
+impl Scatter for NormalMapped {
+    fn scatter(&self, vpos: Point3, lights: &Lighting, world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        self.inner.scatter(vpos, lights, world, r_in, rec)
+    }
+    fn occlusion(&self) -> f64 {
+        self.inner.occlusion()
+    }
+    fn transmittance(&self, rec: &HitRecord, travelled: f64) -> Color {
+        self.inner.transmittance(rec, travelled)
+    }
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.inner.emitted(rec)
+    }
+    fn in_medium(&self) -> bool {
+        self.inner.in_medium()
+    }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.inner.albedo(rec)
+    }
+    fn eval(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Option<(Color, f64)> {
+        self.inner.eval(rec, wo, wi)
+    }
+    fn interior(&self) -> Option<Interior> {
+        self.inner.interior()
+    }
+    fn spectral_attenuation(&self) -> bool {
+        self.inner.spectral_attenuation()
+    }
+    fn perturb(&self, r_in: &Ray, rec: &mut HitRecord) {
+        //The frame is built around the outward normal, which the map is relative to
+        let outward = if rec.front_face { rec.normal } else { (-1.0) * rec.normal };
+        let along = rec.tangent - rec.tangent.dot(outward) * outward;
+        let t = if along.near_zero() { outward.any_perpendicular() } else { along.normalized() };
+        //Keeping the bitangent's side, for mirrored UVs
+        let b = if outward.cross(t).dot(rec.bitangent) < 0.0 { t.cross(outward) } else { outward.cross(t) };
+
+        let local = self.local_normal(rec, t, b);
+        if local.near_zero() {
+            return;
+        }
+        let bent = (local[0] * t + local[1] * b + local[2] * outward).normalized();
+        let mut normal = if rec.front_face { bent } else { (-1.0) * bent };
+        //A normal tilted away from the viewer would light the back of the surface, so
+        //it's pulled back until it just faces the ray
+        let wo = (-1.0) * r_in.direction().normalized();
+        let cos = normal.dot(wo);
+        if cos < 0.01 {
+            normal = (normal + (0.01 - cos) * wo).normalized();
+        }
+        rec.normal = normal;
+        self.inner.perturb(r_in, rec);
+    }
+}
+
+//A thin transparent film over another material, like a soap bubble, oil on water or the
+//tint on heat-treated metal: light reflected off the top of the film and off what's
+//underneath interferes, so some wavelengths are reflected more than others depending on
+//the film's thickness and the angle, giving rainbow tints that shift across the surface.
+//Made for metals and dielectrics underneath. Over a dielectric the film takes over the
+//reflection and the light that isn't reflected refracts into it as usual; over anything
+//else the inner material picks the direction and the film only tints it, treating the
+//inner material's colour as its reflectance.
+//
+//Reflectance is worked out at one wavelength per channel, or at the path's wavelengths
+//rendering spectrally, which gives truer colours.
+pub struct ThinFilm {
+    inner: Arc<dyn Scatter>,
+    //In nanometres
+    thickness: f64,
+    ior: f64,
+    variation: Option<Arc<dyn Texture>>,
+}
+
+impl ThinFilm {
+    //thickness in nanometres: a few hundred give the strongest colours
+    pub fn new(inner: Arc<dyn Scatter>, thickness: f64, ior: f64) -> ThinFilm {
+        ThinFilm { inner, thickness, ior, variation: None }
+    }
+
+    //Scales the thickness across the surface by the texture's brightness, for the
+    //swirls of a soap bubble
+    pub fn with_variation(mut self, variation: Arc<dyn Texture>) -> ThinFilm {
+        self.variation = Some(variation);
+        self
+    }
+
+    //Fraction of light reflected at each of wavelengths (micrometres) arriving at cos_i
+    //to the normal from a medium of index outside. Underneath is either a dielectric of
+    //index below, or an opaque surface reflecting amplitude (per channel) with a phase
+    //flip. Airy's formula for a single layer, averaged over the two polarizations.
+    fn reflectance(&self, rec: &HitRecord, cos_i: f64, outside: f64, below: Result<f64, Color>, wavelengths: [f64; 3]) -> Color {
+        let thickness = self.thickness * self.variation.as_ref().map_or(1.0, |t| t.value_at(rec).luminance()) / 1000.0;
+        let (n1, n2) = (outside, self.ior);
+        let sin2_i = (1.0 - cos_i * cos_i).max(0.0);
+        let cos_f = (1.0 - sin2_i * (n1 / n2).powi(2)).max(0.0).sqrt();
+        let r12 = ((n1 * cos_i - n2 * cos_f) / (n1 * cos_i + n2 * cos_f), (n2 * cos_i - n1 * cos_f) / (n2 * cos_i + n1 * cos_f));
+
+        let mut reflected = Color::new(0.0, 0.0, 0.0);
+        for c in 0..3 {
+            let r23 = match below {
+                Ok(n3) => {
+                    let sin2_t = sin2_i * (n1 / n3).powi(2);
+                    if sin2_t >= 1.0 {
+                        //Totally reflected underneath
+                        (1.0, 1.0)
+                    } else {
+                        let cos_t = (1.0 - sin2_t).sqrt();
+                        ((n2 * cos_f - n3 * cos_t) / (n2 * cos_f + n3 * cos_t), (n3 * cos_f - n2 * cos_t) / (n3 * cos_f + n2 * cos_t))
+                    }
+                }
+                Err(amplitude) => (-amplitude[c], -amplitude[c]),
+            };
+            let phase = 4.0 * PI * n2 * thickness * cos_f / wavelengths[c];
+            let airy = |a: f64, b: f64| {
+                let cross = 2.0 * a * b * phase.cos();
+                (a * a + b * b + cross) / (1.0 + a * a * b * b + cross)
+            };
+            reflected[c] = 0.5 * (airy(r12.0, r23.0) + airy(r12.1, r23.1));
+        }
+        reflected
+    }
+}
+
+impl Scatter for ThinFilm {
+    fn scatter(&self, vpos: Point3, lights: &Lighting, world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let wavelengths = r_in.wavelengths().unwrap_or(CHANNEL_WAVELENGTHS);
+        let unit_direction = r_in.direction().normalized();
+        let cos_i = ((-1.0) * unit_direction).dot(rec.normal).min(1.0);
+
+        let Some(interior) = self.inner.interior() else {
+            let (_, scattered) = self.inner.scatter(vpos, lights, world, r_in, rec)?;
+            let amplitude = match r_in.wavelengths() {
+                Some(wavelengths) => upsample(self.inner.albedo(rec), wavelengths),
+                None => self.inner.albedo(rec),
+            };
+            let amplitude = Color::new(amplitude[0].sqrt(), amplitude[1].sqrt(), amplitude[2].sqrt());
+            return Some((self.reflectance(rec, cos_i, 1.0, Err(amplitude), wavelengths), scattered));
+        };
+
+        //As Dielectric, but without dispersion
+        let outside = r_in.interiors().without(interior.id);
+        let inside = outside.with(interior);
+        let (n1, n3) = if rec.front_face { (outside.ior_at(REFERENCE_WAVELENGTH), inside.ior_at(REFERENCE_WAVELENGTH)) } else { (inside.ior_at(REFERENCE_WAVELENGTH), outside.ior_at(REFERENCE_WAVELENGTH)) };
+        let reflected = self.reflectance(rec, cos_i, n1, Ok(n3), wavelengths);
+
+        //Reflect or refract in proportion to how much is reflected overall, weighting
+        //each channel by how far it is from that
+        let chance = (reflected[0] + reflected[1] + reflected[2]) / 3.0;
+        let cannot_refract = n1 / n3 * (1.0 - cos_i * cos_i).sqrt() > 1.0;
+        let (attenuation, direction) = if cannot_refract {
+            (Color::new(1.0, 1.0, 1.0), unit_direction.reflect(rec.normal))
+        } else if random_f64() < chance {
+            (reflected / chance, unit_direction.reflect(rec.normal))
+        } else {
+            ((Color::new(1.0, 1.0, 1.0) - reflected) / (1.0 - chance), unit_direction.refract(rec.normal, n1 / n3))
+        };
+        Some((attenuation, Ray::new(rec.p, direction).with_time(r_in.time())))
+    }
+    fn occlusion(&self) -> f64 {
+        self.inner.occlusion()
+    }
+    fn transmittance(&self, rec: &HitRecord, travelled: f64) -> Color {
+        self.inner.transmittance(rec, travelled)
+    }
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.inner.emitted(rec)
+    }
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.inner.albedo(rec)
+    }
+    fn interior(&self) -> Option<Interior> {
+        self.inner.interior()
+    }
+    fn spectral_attenuation(&self) -> bool {
+        true
+    }
+    fn perturb(&self, r_in: &Ray, rec: &mut HitRecord) {
+        self.inner.perturb(r_in, rec);
+    }
+}
+
 pub struct PhongMat {
     a: f64,
     d: f64,
     s: f64,
+    #[allow(dead_code)]
     shine: f64,
-    //B = shine/gamma
-    B: f64,
+    //b = shine/gamma
+    b: f64,
     //Ideally want gamma to be a power of 2 for power efficiency; 4 or 8 should suffice
     //gamma can be a float but locked it to int for now so remember power of 2
     g: i32,
@@ -138,24 +947,47 @@ pub struct PhongMat {
     fuzz: f64,
     d_s: f64,
     occlusion: f64,
-
+    //Rays to test the hemisphere with for ambient occlusion, and how far they look
+    ambient_occlusion: Option<(u32, f64)>,
 }
 
 impl PhongMat {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(a: f64, d: f64, s: f64, shine: f64, g: i32, albedo: Color, fuzz: f64, d_s: f64, occlusion :f64) -> PhongMat{
         PhongMat {
             a,
             d,
             s,
             shine,
-            B: shine/(g as f64),
+            b: shine/(g as f64),
             g,
             albedo,
             fuzz,
             d_s,
             occlusion,
+            ambient_occlusion: None,
          }
     }
+
+    //Dim the ambient term by how much of the hemisphere above a hit is blocked within
+    //distance, tested with samples rays. Without it, ambient light reaches everywhere.
+    pub fn with_ambient_occlusion(mut self, samples: u32, distance: f64) -> PhongMat {
+        self.ambient_occlusion = Some((samples.max(1), distance));
+        self
+    }
+
+    //Fraction of the rays from rec into the hemisphere above it (cosine-distributed)
+    //that get away
+    fn unoccluded(&self, rec: &HitRecord, world: &World, time: f64) -> f64 {
+        let Some((samples, distance)) = self.ambient_occlusion else { return 1.0 };
+        let limits = ray::limits();
+        let open = (0..samples).filter(|_| {
+            let direction = (rec.normal + Vec3::random_in_unit_sphere().normalized()).normalized();
+            let ray = Ray::new(rec.p, direction).with_time(time);
+            !world.occluding_hit(&ray, rec.p + distance * direction, limits.epsilon, distance)
+        }).count();
+        open as f64 / samples as f64
+    }
 }
 
 impl Scatter for PhongMat{
@@ -165,6 +997,9 @@ impl Scatter for PhongMat{
     fn occlusion(&self) -> f64 {
         self.occlusion
     }
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
 impl Phongian for PhongMat {
@@ -174,15 +1009,29 @@ impl Phongian for PhongMat {
         
         let viewer_direction = (vpos - rec.p).normalized();
         
-        for light in lights {
-            if Self::is_lit(rec.p, rec.normal, &world, light.origin()) {
-                let L = (light.origin()-rec.p).normalized();
-                let diffuse = (L.dot(rec.normal));
+        //With a light tree, a few lights it picks stand in for the lot
+        let tree = light_tree::current();
+        let picked = tree.iter().flat_map(|tree| tree.picks(rec.p, Some(rec.normal), rec.light_groups)).map(|(light, weight)| (&lights[light], weight));
+        let every = lights.iter().take(if tree.is_some() { 0 } else { lights.len() }).filter(|light| light.lights(rec)).map(|light| (light, 1.0));
+        for (light, weight) in picked.chain(every) {
+            //Area lights are averaged over points on them, so one that's partly hidden
+            //gives partial illumination
+            let samples = light.shadow_samples();
+            for _ in 0..samples {
+                let lpos = light.sample_point();
+                let shadow = Self::light_through(rec.p, rec.normal, world, lpos, r_in.time());
+                if shadow.near_zero() {
+                    continue;
+                }
+                let to_light = lpos - rec.p;
+                let falloff = weight * light.attenuation(to_light.length()) / samples as f64 * shadow;
+                let l = to_light.normalized();
+                let diffuse = l.dot(rec.normal);
                 
-                let R = L.reflect(rec.normal).normalized();
-                let lambda = 1.0 - R.dot(viewer_direction);
+                let r = l.reflect(rec.normal).normalized();
+                let lambda = 1.0 - r.dot(viewer_direction);
                 
-                let tmp = 1.0-self.B*lambda;
+                let tmp = 1.0-self.b*lambda;
 
                 let specular = if 0.0 < tmp {
                     tmp.powi(self.g)
@@ -190,44 +1039,48 @@ impl Phongian for PhongMat {
                     0.0
                 };
 
-                //TODO: ambient term
-                
-                illumination += (self.d * diffuse * light.diffuse()) 
-                    + (self.s * specular * light.specular());
+                illumination += falloff * ((self.d * diffuse * light.diffuse())
+                    + (self.s * specular * light.specular()));
             }
         }
         //TODO: divide illumination by number of lights in scene?
 
+        let ambient = light::ambient();
+        if self.a != 0.0 && !ambient.near_zero() {
+            illumination += self.a * self.unoccluded(rec, world, r_in.time()) * ambient;
+        }
+
         //Calculate scatter direction
-        if rand::thread_rng().gen_range(0.0..1.0) < self.d_s {
-            if let Some((attenuation, scattered)) = self.lambertian(&r_in, &rec){
+        if random_f64() < self.d_s {
+            if let Some((attenuation, scattered)) = self.lambertian(r_in, rec){
                 return Some((illumination * attenuation, scattered));
             }
         }
         else{
-            if let Some((attenuation, scattered)) = self.specular(&r_in, &rec) {
+            if let Some((attenuation, scattered)) = self.specular(r_in, rec) {
                 return Some((illumination * attenuation, scattered));
             }
         }
         None
     }
 
-    fn is_lit(p: Point3, n: Vec3, world: &World, lpos: Point3) -> bool {
+    fn light_through(p: Point3, n: Vec3, world: &World, lpos: Point3, time: f64) -> Color {
         //TODO: perhaps make this 0.001; only supposed to calc illumination if this
         //term is positive
         if n.dot(lpos - p) < 0.0 {
-            return false
+            return Color::new(0.0, 0.0, 0.0)
         }
 
-        let ray = Ray::new(p, (lpos - p).normalized());
-        return !world.occluding_hit(&ray, lpos, 0.001, f64::INFINITY)
+        let ray = Ray::new(p, (lpos - p).normalized()).with_time(time);
+        let limits = ray::limits();
+        world.transmittance(&ray, lpos, limits.epsilon, limits.max_distance)
     }
 }
 
 impl Specular for PhongMat {
     fn specular(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
         let scatter_direction = r_in.direction().reflect(rec.normal).normalized();
-        let scattered = Ray::new(rec.p, scatter_direction + self.fuzz * Vec3::random_in_unit_sphere());
+        let scattered = Ray::new(rec.p, scatter_direction + self.fuzz * Vec3::random_in_unit_sphere()).with_time(r_in.time());
 
         if scattered.direction().dot(rec.normal) > 0.0 {
             Some((self.albedo, scattered))
@@ -240,21 +1093,17 @@ impl Specular for PhongMat {
 
 impl Lamb for PhongMat {
     fn lambertian(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let mut scatter_direction = rec.normal + Vec3::random_in_unit_sphere().normalized();
-        //Catch degen scatter direction (exactly opposite normal, gets 0 length, will cause 
-        //zero and infinity errors
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
-        }
+        let scatter_direction = diffuse_direction(rec.normal);
 
-        Some((self.albedo, Ray::new(rec.p, scatter_direction)))
+        Some((self.albedo, Ray::new(rec.p, scatter_direction).with_time(r_in.time())))
     }
 }
 
 
 pub trait Phongian: Lamb + Specular {
     fn illumination(&self, vpos: Point3, lights: &Lighting, world: &World, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
-    fn is_lit(p: Point3, n: Vec3, world: &World, lpos: Point3) -> bool;
+    //How much of the light from lpos reaches p, on a surface facing n
+    fn light_through(p: Point3, n: Vec3, world: &World, lpos: Point3, time: f64) -> Color;
 }
 
 pub trait Lamb {
@@ -0,0 +1,133 @@
+use std::ops::Mul;
+
+use super::vec3::{Point3, Vec3};
+
+
+
+//4x4 matrix for affine transforms of points and vectors, row-major. The bottom row
+//is always 0 0 0 1; inverse relies on it.
+#[derive(Clone, Copy)]
+pub struct Mat4 {
+    m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        Mat4::from_rows([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn from_rows(m: [[f64; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        let mut t = Mat4::identity();
+        for i in 0..3 {
+            t.m[i][3] = offset[i];
+        }
+        t
+    }
+
+    pub fn scaling(factors: Vec3) -> Mat4 {
+        let mut s = Mat4::identity();
+        for i in 0..3 {
+            s.m[i][i] = factors[i];
+        }
+        s
+    }
+
+    //Rotations are anticlockwise looking down the axis towards the origin, in degrees
+    pub fn rotation_x(degrees: f64) -> Mat4 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Mat4::from_rows([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, cos, -sin, 0.0],
+            [0.0, sin, cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotation_y(degrees: f64) -> Mat4 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Mat4::from_rows([
+            [cos, 0.0, sin, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [-sin, 0.0, cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotation_z(degrees: f64) -> Mat4 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Mat4::from_rows([
+            [cos, -sin, 0.0, 0.0],
+            [sin, cos, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        self.transform_vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    //Directions ignore the translation
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let row = |i: usize| self.m[i][0] * v[0] + self.m[i][1] * v[1] + self.m[i][2] * v[2];
+        Vec3::new(row(0), row(1), row(2))
+    }
+
+    //Normals go through the transpose of the inverse, so they stay perpendicular to
+    //the surface under non-uniform scaling. Call this on the inverse.
+    pub fn transform_normal(&self, n: Vec3) -> Vec3 {
+        let column = |j: usize| self.m[0][j] * n[0] + self.m[1][j] * n[1] + self.m[2][j] * n[2];
+        Vec3::new(column(0), column(1), column(2))
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.m == Mat4::identity().m
+    }
+
+    //None if the matrix squashes space flat (e.g. a zero scale)
+    pub fn inverse(&self) -> Option<Mat4> {
+        let a = &self.m;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| a[r0][c0] * a[r1][c1] - a[r0][c1] * a[r1][c0];
+        let det = a[0][0] * cofactor(1, 2, 1, 2) - a[0][1] * cofactor(1, 2, 0, 2) + a[0][2] * cofactor(1, 2, 0, 1);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        //Inverse of the 3x3 part from its adjugate, then undo the translation with it
+        let inv = [
+            [cofactor(1, 2, 1, 2) / det, -cofactor(0, 2, 1, 2) / det, cofactor(0, 1, 1, 2) / det],
+            [-cofactor(1, 2, 0, 2) / det, cofactor(0, 2, 0, 2) / det, -cofactor(0, 1, 0, 2) / det],
+            [cofactor(1, 2, 0, 1) / det, -cofactor(0, 2, 0, 1) / det, cofactor(0, 1, 0, 1) / det],
+        ];
+        let mut result = Mat4::identity();
+        for (row, inv_row) in result.m.iter_mut().zip(inv) {
+            row[..3].copy_from_slice(&inv_row);
+            row[3] = -(0..3).map(|k| inv_row[k] * a[k][3]).sum::<f64>();
+        }
+        Some(result)
+    }
+}
+
+//a * b applies b first, then a
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord};
+use super::material::{Isotropic, Scatter};
+use super::random::random_f64;
+use super::ray::Ray;
+use super::texture::Texture;
+use super::vdb::VdbGrid;
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+//Smoke, fog or mist filling a closed boundary with the same density throughout. A ray
+//going in has a chance of scattering off the medium in every bit of distance it
+//travels, so it's hit somewhere random inside, or passes straight through. The
+//boundary is shared so the same shape can also be put in the world as, say, a glass
+//surface round the medium.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hit>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Scatter>,
+}
+
+impl ConstantMedium {
+    //boundary must be closed and convex, the ray is taken to be inside between the
+    //first two hits
+    pub fn new(boundary: Arc<dyn Hit>, density: f64, albedo: Color) -> ConstantMedium {
+        ConstantMedium::with_phase_function(boundary, density, Arc::new(Isotropic::new(albedo)))
+    }
+
+    pub fn with_texture(boundary: Arc<dyn Hit>, density: f64, albedo: Arc<dyn Texture>) -> ConstantMedium {
+        ConstantMedium::with_phase_function(boundary, density, Arc::new(Isotropic::with_texture(albedo)))
+    }
+
+    pub fn with_phase_function(boundary: Arc<dyn Hit>, density: f64, phase_function: Arc<dyn Scatter>) -> ConstantMedium {
+        ConstantMedium { boundary, neg_inv_density: -1.0 / density, phase_function }
+    }
+}
+
+impl Hit for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        //Where the ray's line goes in and out of the boundary, wherever the ray starts
+        let entry = self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY)?;
+        let exit = self.boundary.hit(r, entry.t + 0.0001, f64::INFINITY)?;
+
+        //Only the part of the ray inside counts
+        let t_in = entry.t.max(t_min).max(0.0);
+        let t_out = exit.t.min(t_max);
+        if t_in >= t_out {
+            return None;
+        }
+
+        //Directions aren't always unit length, but density is per unit of distance
+        let ray_length = r.direction().length();
+        let distance_inside = (t_out - t_in) * ray_length;
+        let hit_distance = self.neg_inv_density * random_f64().ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        //The normal doesn't mean anything inside a medium, Isotropic ignores it
+        let t = t_in + hit_distance / ray_length;
+        Some(HitRecord::new(r, t, Vec3::new(1.0, 0.0, 0.0), self.phase_function.clone(), 0.0, 0.0))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+
+
+//Smoke, fog or cloud whose density changes from place to place: density times the
+//brightness of a texture (fbm or turbulence, say) at each point. Rays are marched through
+//it step units at a time, adding up how much medium they've been through, and scatter
+//where that passes a random amount, so thin wisps let more through than thick ones. The
+//first step is a random part of a whole one, so the steps don't show as bands. Shading a
+//hit samples the lights as on a surface, with shadow rays that go through the medium and
+//stop at walls, so light through a window shows as a shaft.
+pub struct HeterogeneousMedium {
+    boundary: Arc<dyn Hit>,
+    density: f64,
+    variation: Arc<dyn Texture>,
+    step: f64,
+    phase_function: Arc<dyn Scatter>,
+}
+
+impl HeterogeneousMedium {
+    //As ConstantMedium::new, with density scaled by variation's brightness. Steps a 64th
+    //of the way across the boundary.
+    pub fn new(boundary: Arc<dyn Hit>, density: f64, variation: Arc<dyn Texture>, albedo: Color) -> HeterogeneousMedium {
+        let step = boundary.bounding_box().map_or(0.1, |bounds| (bounds.max - bounds.min).length() / 64.0);
+        HeterogeneousMedium { boundary, density, variation, step, phase_function: Arc::new(Isotropic::new(albedo)) }
+    }
+
+    //March step units at a time rather than a 64th of the way across. Shorter steps
+    //follow finer detail in the texture, at a texture lookup each.
+    pub fn with_step(mut self, step: f64) -> HeterogeneousMedium {
+        self.step = step;
+        self
+    }
+
+    pub fn with_phase_function(mut self, phase_function: Arc<dyn Scatter>) -> HeterogeneousMedium {
+        self.phase_function = phase_function;
+        self
+    }
+
+    //Chance of scattering per unit distance at p
+    fn density_at(&self, p: Point3) -> f64 {
+        self.density * self.variation.value(0.0, 0.0, p).luminance().max(0.0)
+    }
+}
+
+impl Hit for HeterogeneousMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let entry = self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY)?;
+        let exit = self.boundary.hit(r, entry.t + 0.0001, f64::INFINITY)?;
+        let t_in = entry.t.max(t_min).max(0.0);
+        let t_out = exit.t.min(t_max);
+        if t_in >= t_out || self.step <= 0.0 {
+            return None;
+        }
+
+        //Optical depth it scatters at, and how much it's been through so far, with each
+        //step's density taken from its middle
+        let ray_length = r.direction().length();
+        let dt = self.step / ray_length;
+        let threshold = -random_f64().ln();
+        let mut depth = 0.0;
+        let (mut t, mut end) = (t_in, t_in + random_f64() * dt);
+        while t < t_out {
+            end = end.min(t_out);
+            let density = self.density_at(r.at(0.5 * (t + end)));
+            let added = density * (end - t) * ray_length;
+            if depth + added >= threshold {
+                let t = t + (threshold - depth) / (density * ray_length);
+                return Some(HitRecord::new(r, t, Vec3::new(1.0, 0.0, 0.0), self.phase_function.clone(), 0.0, 0.0));
+            }
+            depth += added;
+            (t, end) = (end, end + dt);
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+
+
+//A density grid from a VDB file (see VdbGrid), as smoke or cloud: density times the
+//grid's value is the chance of scattering per unit distance. Found by delta tracking:
+//the ray takes steps as if the whole grid were as thick as its thickest voxel, and at
+//each stops with the chance of how thick it really is there, so there's no step size to
+//pick and nothing's missed between steps.
+pub struct VdbMedium {
+    grid: VdbGrid,
+    density: f64,
+    bounds: Option<Aabb>,
+    phase_function: Arc<dyn Scatter>,
+}
+
+impl VdbMedium {
+    pub fn new(grid: VdbGrid, density: f64, albedo: Color) -> VdbMedium {
+        let bounds = grid.bounds();
+        VdbMedium { grid, density, bounds, phase_function: Arc::new(Isotropic::new(albedo)) }
+    }
+
+    pub fn with_phase_function(mut self, phase_function: Arc<dyn Scatter>) -> VdbMedium {
+        self.phase_function = phase_function;
+        self
+    }
+}
+
+impl Hit for VdbMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let bounds = self.bounds?;
+        let majorant = self.density * self.grid.max_value();
+        if majorant <= 0.0 {
+            return None;
+        }
+        //Where the ray is inside the grid's box
+        let (mut t_in, mut t_out) = (t_min.max(0.0), t_max);
+        for axis in 0..3 {
+            let inv = 1.0 / r.direction()[axis];
+            let a = (bounds.min[axis] - r.origin()[axis]) * inv;
+            let b = (bounds.max[axis] - r.origin()[axis]) * inv;
+            t_in = t_in.max(a.min(b));
+            t_out = t_out.min(a.max(b));
+        }
+
+        let step = 1.0 / (majorant * r.direction().length());
+        let mut t = t_in;
+        loop {
+            t -= (1.0 - random_f64()).ln() * step;
+            if t >= t_out {
+                return None;
+            }
+            if random_f64() * majorant < self.density * self.grid.value(r.at(t)) {
+                return Some(HitRecord::new(r, t, Vec3::new(1.0, 0.0, 0.0), self.phase_function.clone(), 0.0, 0.0));
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bounds
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use clap::ValueEnum;
+
+use super::aabb::Aabb;
+use super::bvh::Bvh;
+use super::hit::{Hit, HitRecord};
+use super::material::Scatter;
+use super::ray::Ray;
+use super::texture::Texture;
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+//Indices into the mesh's vertex buffers, plus the material slot the face uses
+#[derive(Clone, Copy)]
+pub struct Triangle {
+    pub vertices: [usize; 3],
+    pub material: usize,
+}
+
+//How a mesh's faces are shaded
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shading {
+    //Smooth if the mesh came with vertex normals, flat if it didn't (the default)
+    Auto,
+    //Vertex normals interpolated across each face, worked out from the faces around
+    //each vertex if the mesh has none, so low-poly models don't look faceted
+    Smooth,
+    //Every face flat, ignoring any vertex normals
+    Flat,
+}
+
+//Indexed triangle mesh. Vertex attributes live in shared buffers so neighbouring
+//faces don't duplicate them; normals, uvs and colors are optional and either empty or
+//one per vertex. Each face picks its material from a list of slots, the same way OBJ
+//groups/usemtl do. Tangents are generated from the uvs when they're supplied.
+pub struct TriangleMesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    colors: Vec<Color>,
+    //Per-vertex tangent and handedness (+-1), bitangent = sign * (n x t)
+    tangents: Vec<(Vec3, f64)>,
+    triangles: Vec<Triangle>,
+    materials: Vec<Arc<dyn Scatter>>,
+    //Over the triangles, so big meshes don't test every face
+    bvh: Bvh,
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Point3>, triangles: Vec<Triangle>, materials: Vec<Arc<dyn Scatter>>) -> TriangleMesh {
+        for tri in &triangles {
+            assert!(tri.vertices.iter().all(|&i| i < positions.len()), "triangle references a missing vertex");
+            assert!(tri.material < materials.len(), "triangle references a missing material slot");
+        }
+
+        let boxes: Vec<Aabb> = triangles.iter()
+            .map(|tri| Aabb::around(tri.vertices.iter().map(|&i| positions[i])).unwrap())
+            .collect();
+        let bvh = Bvh::new(&boxes);
+
+        TriangleMesh {
+            positions,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            tangents: Vec::new(),
+            triangles,
+            materials,
+            bvh,
+        }
+    }
+
+    //Smooth shading normals, interpolated across each face
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> TriangleMesh {
+        assert_eq!(normals.len(), self.positions.len(), "need one normal per vertex");
+        self.normals = normals.into_iter().map(|n| n.normalized()).collect();
+        if !self.uvs.is_empty() {
+            self.generate_tangents();
+        }
+        self
+    }
+
+    //Shade the mesh as shading says, see Shading
+    pub fn with_shading(mut self, shading: Shading) -> TriangleMesh {
+        match shading {
+            Shading::Smooth if self.normals.is_empty() => {
+                let normals = self.vertex_normals();
+                self.with_normals(normals)
+            }
+            Shading::Flat => {
+                self.normals.clear();
+                if !self.uvs.is_empty() {
+                    self.generate_tangents();
+                }
+                self
+            }
+            Shading::Auto | Shading::Smooth => self,
+        }
+    }
+
+    //Normal at each vertex from the faces around it, weighted by their angles there.
+    //Vertices in the same place count as one, so the normals still match across seams
+    //where a mesh splits vertices for different uvs.
+    fn vertex_normals(&self) -> Vec<Vec3> {
+        let key = |p: Point3| [p.x().to_bits(), p.y().to_bits(), p.z().to_bits()];
+        let mut sums: HashMap<[u64; 3], Vec3> = HashMap::new();
+        for tri in &self.triangles {
+            let face_n = self.face_normal(tri);
+            if face_n.near_zero() {
+                continue;
+            }
+            for (c, &i) in tri.vertices.iter().enumerate() {
+                *sums.entry(key(self.positions[i])).or_insert(Vec3::new(0.0, 0.0, 0.0)) += self.corner_angle(tri, c) * face_n.normalized();
+            }
+        }
+        self.positions.iter().map(|&p| match sums.get(&key(p)) {
+            Some(n) if !n.near_zero() => n.normalized(),
+            _ => Vec3::new(0.0, 1.0, 0.0),
+        }).collect()
+    }
+
+    pub fn with_uvs(mut self, uvs: Vec<(f64, f64)>) -> TriangleMesh {
+        assert_eq!(uvs.len(), self.positions.len(), "need one uv per vertex");
+        self.uvs = uvs;
+        self.generate_tangents();
+        self
+    }
+
+    //Latitude/longitude tessellated sphere with smooth normals and spherical uvs,
+    //mostly useful for checking how low-poly smooth-shaded geometry behaves.
+    pub fn uv_sphere(centre: Point3, radius: f64, stacks: usize, sectors: usize, mat: Arc<dyn Scatter>) -> TriangleMesh {
+        let stacks = stacks.max(2);
+        let sectors = sectors.max(3);
+        let pi = std::f64::consts::PI;
+
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        for i in 0..=stacks {
+            let theta = pi * i as f64 / stacks as f64;
+            for j in 0..=sectors {
+                let phi = 2.0 * pi * j as f64 / sectors as f64;
+                //Same parameterization as sphere_uv
+                let n = Vec3::new(-phi.cos() * theta.sin(), -theta.cos(), phi.sin() * theta.sin());
+                positions.push(centre + radius * n);
+                normals.push(n);
+                uvs.push((j as f64 / sectors as f64, i as f64 / stacks as f64));
+            }
+        }
+
+        let row = sectors + 1;
+        let mut triangles = Vec::new();
+        for i in 0..stacks {
+            for j in 0..sectors {
+                let (a, b) = (i * row + j, i * row + j + 1);
+                let (c, d) = (a + row, b + row);
+                //The triangles touching the poles would be degenerate
+                if i != 0 {
+                    triangles.push(Triangle { vertices: [a, d, c], material: 0 });
+                }
+                if i != stacks - 1 {
+                    triangles.push(Triangle { vertices: [a, b, d], material: 0 });
+                }
+            }
+        }
+
+        TriangleMesh::new(positions, triangles, vec![mat])
+            .with_normals(normals)
+            .with_uvs(uvs)
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Color>) -> TriangleMesh {
+        assert_eq!(colors.len(), self.positions.len(), "need one colour per vertex");
+        self.colors = colors;
+        self
+    }
+
+    //Every triangle split into four at the middles of its edges, levels times over, with
+    //normals, uvs and colours blended for the new vertices. Triangles sharing an edge
+    //share its new vertex, so the mesh stays in one piece. The shape doesn't change (the
+    //new vertices are on the old faces), but there's more of it for displaced to move.
+    pub fn subdivided(self, levels: u32) -> TriangleMesh {
+        (0..levels).fold(self, |mesh, _| mesh.split())
+    }
+
+    fn split(self) -> TriangleMesh {
+        let (mut positions, mut normals, mut uvs, mut colors) = (self.positions, self.normals, self.uvs, self.colors);
+        let mut middles: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut middle = |i: usize, j: usize| {
+            let key = (i.min(j), i.max(j));
+            if let Some(&m) = middles.get(&key) {
+                return m;
+            }
+            positions.push(0.5 * (positions[i] + positions[j]));
+            if !normals.is_empty() {
+                let n = normals[i] + normals[j];
+                normals.push(if n.near_zero() { normals[i] } else { n.normalized() });
+            }
+            if !uvs.is_empty() {
+                uvs.push((0.5 * (uvs[i].0 + uvs[j].0), 0.5 * (uvs[i].1 + uvs[j].1)));
+            }
+            if !colors.is_empty() {
+                colors.push(0.5 * (colors[i] + colors[j]));
+            }
+            middles.insert(key, positions.len() - 1);
+            positions.len() - 1
+        };
+
+        let mut triangles = Vec::with_capacity(4 * self.triangles.len());
+        for tri in &self.triangles {
+            let [a, b, c] = tri.vertices;
+            let (ab, bc, ca) = (middle(a, b), middle(b, c), middle(c, a));
+            for vertices in [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]] {
+                triangles.push(Triangle { vertices, material: tri.material });
+            }
+        }
+
+        let mut mesh = TriangleMesh::new(positions, triangles, self.materials);
+        mesh.normals = normals;
+        mesh.colors = colors;
+        if uvs.is_empty() { mesh } else { mesh.with_uvs(uvs) }
+    }
+
+    //Every vertex pushed out along its normal (made up from the faces if there are none)
+    //by height times the brightness of texture there, looked up at the vertex's uv and
+    //position, for real bumps that show in the silhouette. Normals are then worked out
+    //afresh for the new shape. Detail finer than the triangles is lost, so subdivide
+    //first. Vertices split along a seam only stay together if their normals and the
+    //texture agree there.
+    pub fn displaced(self, texture: &dyn Texture, height: f64) -> TriangleMesh {
+        let normals = if self.normals.is_empty() { self.vertex_normals() } else { self.normals.clone() };
+        let positions = self.positions.iter().zip(&normals).enumerate().map(|(i, (&p, &n))| {
+            let (u, v) = self.uvs.get(i).copied().unwrap_or((0.0, 0.0));
+            p + height * texture.value(u, v, p).luminance() * n
+        }).collect();
+
+        let mut mesh = TriangleMesh::new(positions, self.triangles, self.materials);
+        mesh.uvs = self.uvs;
+        mesh.colors = self.colors;
+        let normals = mesh.vertex_normals();
+        mesh.with_normals(normals)
+    }
+
+    fn face_normal(&self, tri: &Triangle) -> Vec3 {
+        let [i0, i1, i2] = tri.vertices;
+        let p0 = self.positions[i0];
+        (self.positions[i1] - p0).cross(self.positions[i2] - p0)
+    }
+
+    //Interior angle of the triangle at corner c (0, 1 or 2)
+    fn corner_angle(&self, tri: &Triangle, c: usize) -> f64 {
+        let p = self.positions[tri.vertices[c]];
+        let a = (self.positions[tri.vertices[(c + 1) % 3]] - p).normalized();
+        let b = (self.positions[tri.vertices[(c + 2) % 3]] - p).normalized();
+        a.dot(b).clamp(-1.0, 1.0).acos()
+    }
+
+    //Per-vertex tangent space in the same spirit as MikkTSpace: each face's dp/du and
+    //dp/dv are accumulated onto its corners weighted by corner angle, then the tangent
+    //is Gram-Schmidt orthogonalized against the (angle-weighted) vertex normal and the
+    //bitangent is reduced to a handedness sign so mirrored UVs still work.
+    fn generate_tangents(&mut self) {
+        let n = self.positions.len();
+        let zero = Vec3::new(0.0, 0.0, 0.0);
+        let mut normals = vec![zero; n];
+        let mut tan = vec![zero; n];
+        let mut bitan = vec![zero; n];
+
+        for tri in &self.triangles {
+            let [i0, i1, i2] = tri.vertices;
+            let e1 = self.positions[i1] - self.positions[i0];
+            let e2 = self.positions[i2] - self.positions[i0];
+            let (du1, dv1) = (self.uvs[i1].0 - self.uvs[i0].0, self.uvs[i1].1 - self.uvs[i0].1);
+            let (du2, dv2) = (self.uvs[i2].0 - self.uvs[i0].0, self.uvs[i2].1 - self.uvs[i0].1);
+
+            let face_n = self.face_normal(tri);
+            let face_n = if face_n.near_zero() { zero } else { face_n.normalized() };
+
+            //Zero area in UV space: the face adds to the normal but has no usable tangent
+            let det = du1 * dv2 - du2 * dv1;
+            let (sdir, tdir) = if det.abs() < 1.0e-20 {
+                (zero, zero)
+            } else {
+                let r = 1.0 / det;
+                ((e1 * dv2 - e2 * dv1) * r, (e2 * du1 - e1 * du2) * r)
+            };
+
+            for (c, &i) in tri.vertices.iter().enumerate() {
+                let w = self.corner_angle(tri, c);
+                normals[i] += w * face_n;
+                tan[i] += w * sdir;
+                bitan[i] += w * tdir;
+            }
+        }
+
+        self.tangents = (0..n).map(|i| {
+            let nrm = if !self.normals.is_empty() {
+                self.normals[i]
+            } else if normals[i].near_zero() {
+                Vec3::new(0.0, 1.0, 0.0)
+            } else {
+                normals[i].normalized()
+            };
+            let t = tan[i] - nrm.dot(tan[i]) * nrm;
+            let t = if t.near_zero() { nrm.any_perpendicular() } else { t.normalized() };
+            let sign = if nrm.cross(t).dot(bitan[i]) < 0.0 { -1.0 } else { 1.0 };
+            (t, sign)
+        }).collect();
+    }
+
+    //Moller-Trumbore: solve o + t*d = (1-b1-b2)*p0 + b1*p1 + b2*p2 for (t, b1, b2)
+    //via Cramer's rule. Returns the hit distance and barycentrics.
+    fn intersect(&self, tri: &Triangle, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        const EPS: f64 = 1.0e-12;
+
+        let p0 = self.positions[tri.vertices[0]];
+        let e1 = self.positions[tri.vertices[1]] - p0;
+        let e2 = self.positions[tri.vertices[2]] - p0;
+
+        let pvec = r.direction().cross(e2);
+        let det = e1.dot(pvec);
+        //Ray parallel to the triangle's plane
+        if det.abs() < EPS {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin() - p0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+
+        let qvec = tvec.cross(e1);
+        let b2 = r.direction().dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+
+        let t = e2.dot(qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        Some((t, b1, b2))
+    }
+}
+
+impl Hit for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut closest = t_max;
+        let mut best = None;
+
+        self.bvh.traverse(r, t_min, t_max, |i, t_max| {
+            let (t, b1, b2) = self.intersect(&self.triangles[i], r, t_min, t_max)?;
+            closest = t;
+            best = Some((i, b1, b2));
+            Some(t)
+        });
+
+        let (i, b1, b2) = best?;
+        let tri = &self.triangles[i];
+        let [i0, i1, i2] = tri.vertices;
+        let b0 = 1.0 - b1 - b2;
+
+        //Without texture coords fall back to the barycentrics themselves
+        //Along with twice the triangle's area in (u, v)
+        let (u, v, uv_area) = if self.uvs.is_empty() {
+            (b1, b2, 1.0)
+        } else {
+            let (uv0, uv1, uv2) = (self.uvs[i0], self.uvs[i1], self.uvs[i2]);
+            let area = ((uv1.0 - uv0.0) * (uv2.1 - uv0.1) - (uv2.0 - uv0.0) * (uv1.1 - uv0.1)).abs();
+            (b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0, b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1, area)
+        };
+
+        //Counter-clockwise winding faces outwards
+        let face_normal = self.face_normal(tri);
+        let outward_normal = face_normal.normalized();
+        let mut rec = HitRecord::new(r, closest, outward_normal, Arc::clone(&self.materials[tri.material]), u, v);
+        if uv_area > 0.0 {
+            rec.uv_size = (face_normal.length() / uv_area).sqrt();
+        }
+
+        if !self.colors.is_empty() {
+            rec.vertex_color = Some(b0 * self.colors[i0] + b1 * self.colors[i1] + b2 * self.colors[i2]);
+        }
+
+        //Outward-facing normal the rest of the shading frame is built around
+        let mut shading_normal = outward_normal;
+
+        if !self.normals.is_empty() {
+            let (n0, n1, n2) = (self.normals[i0], self.normals[i1], self.normals[i2]);
+            shading_normal = (b0 * n0 + b1 * n1 + b2 * n2).normalized();
+            rec.normal = if rec.front_face { shading_normal } else { -1.0 * shading_normal };
+
+            //Shadow terminator fix (Hanika 2021, "Hacking the shadow terminator").
+            //Smooth normals make coarse faces look curved, but shadow rays still leave from
+            //the flat face and get blocked by the neighbouring faces, which gives blocky
+            //shadow edges. Push the hit point out onto the curved surface implied by the
+            //vertex normals: for each vertex, if p is below that vertex's tangent plane
+            //lift it up to the plane, and blend the lifts with the barycentrics.
+            //Points already above all three planes aren't moved at all.
+            let sign = if rec.front_face { 1.0 } else { -1.0 };
+            let mut offset = Vec3::new(0.0, 0.0, 0.0);
+            for (b, &i) in [b0, b1, b2].iter().zip(&tri.vertices) {
+                let n = sign * self.normals[i];
+                let below = (rec.p - self.positions[i]).dot(n).min(0.0);
+                offset -= b * below * n;
+            }
+            rec.p += offset;
+        }
+
+        if !self.tangents.is_empty() {
+            let (t0, s0) = self.tangents[i0];
+            let (t1, s1) = self.tangents[i1];
+            let (t2, s2) = self.tangents[i2];
+
+            let t = b0 * t0 + b1 * t1 + b2 * t2;
+            let t = t - shading_normal.dot(t) * shading_normal;
+            if !t.near_zero() {
+                let sign = if b0 * s0 + b1 * s1 + b2 * s2 < 0.0 { -1.0 } else { 1.0 };
+                rec.tangent = t.normalized();
+                rec.bitangent = sign * shading_normal.cross(rec.tangent);
+            }
+        }
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounding_box()
+    }
+}
@@ -0,0 +1,186 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use super::vec3::{Point3, Vec3};
+
+
+
+//Procedural noise, as the raw numbers behind the noise textures and displaced surfaces.
+//Everything is a pure function of the point and the seed, so it's the same on every
+//thread and every run.
+
+//Classic Perlin gradient noise over a 256 lattice. Output is roughly in [-1, 1].
+pub struct Perlin {
+    ranvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    const POINT_COUNT: usize = 256;
+
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let ranvec = (0..Self::POINT_COUNT)
+            .map(|_| Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalized())
+            .collect();
+
+        Perlin {
+            ranvec,
+            perm_x: Self::generate_perm(&mut rng),
+            perm_y: Self::generate_perm(&mut rng),
+            perm_z: Self::generate_perm(&mut rng),
+        }
+    }
+
+    fn generate_perm(rng: &mut StdRng) -> Vec<usize> {
+        let mut p: Vec<usize> = (0..Self::POINT_COUNT).collect();
+        for i in (1..Self::POINT_COUNT).rev() {
+            let target = rng.gen_range(0..=i);
+            p.swap(i, target);
+        }
+        p
+    }
+
+    pub fn noise(&self, p: Point3) -> f64 {
+        let u = p.x() - p.x().floor();
+        let v = p.y() - p.y().floor();
+        let w = p.z() - p.z().floor();
+
+        let i = p.x().floor() as i64;
+        let j = p.y().floor() as i64;
+        let k = p.z().floor() as i64;
+
+        //Hermite smoothing of the interpolation weights to hide the lattice
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let idx = self.perm_x[((i + di) & 255) as usize]
+                        ^ self.perm_y[((j + dj) & 255) as usize]
+                        ^ self.perm_z[((k + dk) & 255) as usize];
+                    let weight = Vec3::new(u - di as f64, v - dj as f64, w - dk as f64);
+
+                    let (fi, fj, fk) = (di as f64, dj as f64, dk as f64);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * self.ranvec[idx].dot(weight);
+                }
+            }
+        }
+        accum
+    }
+
+    //Fractal Brownian motion: octaves of noise, each at lacunarity times the frequency
+    //and gain times the amplitude of the last, divided by the total amplitude so it
+    //stays roughly in [-1, 1]
+    pub fn fbm(&self, p: Point3, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        let mut sum = 0.0;
+        let mut norm = 0.0;
+        let mut amplitude = 1.0;
+        let mut q = p;
+
+        for _ in 0..octaves.max(1) {
+            sum += amplitude * self.noise(q);
+            norm += amplitude;
+            amplitude *= gain;
+            q *= lacunarity;
+        }
+        sum / norm
+    }
+
+    //Sum of |noise| over depth octaves, which has creases where the noise crosses zero
+    pub fn turbulence(&self, p: Point3, depth: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut q = p;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            accum += weight * self.noise(q);
+            weight *= 0.5;
+            q *= 2.0;
+        }
+        accum.abs()
+    }
+}
+
+
+
+//Which distance Worley noise gives: to the nearest feature point (F1), which makes
+//blobs, or the gap to the second nearest (F2 - F1), which makes cell borders
+#[derive(Clone, Copy)]
+pub enum WorleyMode {
+    F1,
+    F2MinusF1,
+}
+
+//Worley / cellular noise. Space is divided into unit cells with one pseudo-random
+//feature point per cell, and the value depends on how far the nearest ones are.
+pub struct Worley {
+    seed: u64,
+}
+
+impl Worley {
+    pub fn new(seed: u64) -> Worley {
+        Worley { seed }
+    }
+
+    //Distance at p as mode says, mostly in [0, 1]
+    pub fn value(&self, p: Point3, mode: WorleyMode) -> f64 {
+        let (f1, f2) = self.distances(p);
+        match mode {
+            WorleyMode::F1 => f1,
+            WorleyMode::F2MinusF1 => f2 - f1,
+        }
+    }
+
+    //Distances from p to the nearest and second nearest feature points
+    pub fn distances(&self, p: Point3) -> (f64, f64) {
+        let ci = p.x().floor() as i64;
+        let cj = p.y().floor() as i64;
+        let ck = p.z().floor() as i64;
+
+        let mut f1 = f64::INFINITY;
+        let mut f2 = f64::INFINITY;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let (i, j, k) = (ci + di, cj + dj, ck + dk);
+                    let feature = Vec3::new(i as f64, j as f64, k as f64) + self.feature_point(i, j, k);
+                    let d = (feature - p).length();
+                    if d < f1 {
+                        f2 = f1;
+                        f1 = d;
+                    } else if d < f2 {
+                        f2 = d;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+
+    //Cheap integer hash (splitmix64 finaliser) of a cell, turned into a point in [0, 1)^3
+    fn feature_point(&self, i: i64, j: i64, k: i64) -> Vec3 {
+        let mut h = self.seed
+            ^ (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (j as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+            ^ (k as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+
+        let mut next = || {
+            h = h.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = h;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^= z >> 31;
+            (z >> 11) as f64 / (1u64 << 53) as f64
+        };
+
+        Vec3::new(next(), next(), next())
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::material::{Lambertian, Scatter};
+use super::mesh::{Triangle, TriangleMesh};
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+//Wavefront OBJ importer. Handles v/vt/vn and f in all four index styles (including
+//negative, relative indices), triangulates polygons as fans, and maps usemtl groups
+//to material slots. Materials named in an mtllib become Lambertians with their Kd
+//colour; faces without a usemtl, or naming a material that isn't found, get default.
+//Everything else (groups, smoothing groups, lines, curves) is ignored.
+pub fn load_obj(path: &Path, default: Arc<dyn Scatter>) -> io::Result<TriangleMesh> {
+    let text = fs::read_to_string(path)?;
+
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut tex_coords: Vec<(f64, f64)> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+
+    let mut library: HashMap<String, Color> = HashMap::new();
+    let mut materials: Vec<Arc<dyn Scatter>> = vec![default];
+    let mut slots: HashMap<String, usize> = HashMap::new();
+    let mut current_slot = 0;
+
+    //OBJ indexes positions, uvs and normals separately, the mesh shares one index
+    //between them, so every distinct combination becomes a vertex of its own
+    let mut corners: HashMap<(usize, Option<usize>, Option<usize>), usize> = HashMap::new();
+    let mut corner_list: Vec<(usize, Option<usize>, Option<usize>)> = Vec::new();
+    let mut triangles: Vec<Triangle> = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let fail = |msg: &str| invalid(format!("{}:{}: {}", path.display(), line_number + 1, msg));
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("v") => {
+                let [x, y, z] = parse_floats(&mut tokens).ok_or_else(|| fail("bad vertex"))?;
+                positions.push(Point3::new(x, y, z));
+            }
+            Some("vt") => {
+                let u = tokens.next().and_then(|t| t.parse().ok()).ok_or_else(|| fail("bad texture coordinate"))?;
+                let v = tokens.next().and_then(|t| t.parse().ok()).unwrap_or(0.0);
+                tex_coords.push((u, v));
+            }
+            Some("vn") => {
+                let [x, y, z] = parse_floats(&mut tokens).ok_or_else(|| fail("bad normal"))?;
+                normals.push(Vec3::new(x, y, z));
+            }
+            Some("f") => {
+                let mut face = Vec::new();
+                for token in tokens {
+                    let corner = parse_corner(token, positions.len(), tex_coords.len(), normals.len())
+                        .ok_or_else(|| fail(&format!("bad face vertex '{}'", token)))?;
+                    let index = *corners.entry(corner).or_insert_with(|| {
+                        corner_list.push(corner);
+                        corner_list.len() - 1
+                    });
+                    face.push(index);
+                }
+                if face.len() < 3 {
+                    return Err(fail("face with fewer than three vertices"));
+                }
+                for k in 1..face.len() - 1 {
+                    triangles.push(Triangle { vertices: [face[0], face[k], face[k + 1]], material: current_slot });
+                }
+            }
+            Some("usemtl") => {
+                let name = tokens.next().unwrap_or("").to_string();
+                current_slot = match slots.get(&name) {
+                    Some(&slot) => slot,
+                    None => match library.get(&name) {
+                        Some(&kd) => {
+                            materials.push(Arc::new(Lambertian::new(kd)));
+                            slots.insert(name, materials.len() - 1);
+                            materials.len() - 1
+                        }
+                        None => 0,
+                    },
+                };
+            }
+            Some("mtllib") => {
+                //Relative to the OBJ file; a missing library just means default materials
+                for name in tokens {
+                    let mtl_path = path.parent().unwrap_or(Path::new("")).join(name);
+                    if let Ok(mtl) = fs::read_to_string(&mtl_path) {
+                        library.extend(parse_mtl(&mtl));
+                    } else {
+                        eprintln!("Couldn't read material library {}, using the default material", mtl_path.display());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mesh_positions = corner_list.iter().map(|&(p, _, _)| positions[p]).collect();
+    let mut mesh = TriangleMesh::new(mesh_positions, triangles, materials);
+
+    //Only use normals and uvs if every vertex has them
+    if !corner_list.is_empty() && corner_list.iter().all(|c| c.2.is_some()) {
+        mesh = mesh.with_normals(corner_list.iter().map(|c| normals[c.2.unwrap()]).collect());
+    }
+    if !corner_list.is_empty() && corner_list.iter().all(|c| c.1.is_some()) {
+        mesh = mesh.with_uvs(corner_list.iter().map(|c| tex_coords[c.1.unwrap()]).collect());
+    }
+
+    Ok(mesh)
+}
+
+//Diffuse colour of each material in an MTL file
+fn parse_mtl(text: &str) -> HashMap<String, Color> {
+    let mut library = HashMap::new();
+    let mut current: Option<String> = None;
+
+    for line in text.lines() {
+        let mut tokens = line.split('#').next().unwrap_or("").split_whitespace();
+        match tokens.next() {
+            Some("newmtl") => {
+                current = tokens.next().map(|s| s.to_string());
+                if let Some(name) = &current {
+                    //Kd defaults to white-ish grey if the material never sets it
+                    library.insert(name.clone(), Color::new(0.8, 0.8, 0.8));
+                }
+            }
+            Some("Kd") => {
+                if let (Some(name), Some([r, g, b])) = (&current, parse_floats(&mut tokens)) {
+                    library.insert(name.clone(), Color::new(r, g, b));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    library
+}
+
+fn parse_floats<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Option<[f64; 3]> {
+    let mut values = [0.0; 3];
+    for v in values.iter_mut() {
+        *v = tokens.next()?.parse().ok()?;
+    }
+    Some(values)
+}
+
+//"v", "v/vt", "v//vn" or "v/vt/vn", 1-based or negative (counting back from the
+//latest), as zero-based indices
+fn parse_corner(token: &str, positions: usize, tex_coords: usize, normals: usize) -> Option<(usize, Option<usize>, Option<usize>)> {
+    let mut parts = token.split('/');
+    let position = resolve(parts.next()?, positions)?;
+    let tex_coord = match parts.next() {
+        Some("") | None => None,
+        Some(s) => Some(resolve(s, tex_coords)?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(s) => Some(resolve(s, normals)?),
+    };
+    Some((position, tex_coord, normal))
+}
+
+fn resolve(index: &str, count: usize) -> Option<usize> {
+    let i: i64 = index.parse().ok()?;
+    let resolved = if i < 0 { count as i64 + i } else { i - 1 };
+    if resolved >= 0 && (resolved as usize) < count {
+        Some(resolved as usize)
+    } else {
+        None
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+
+use super::exr;
+use super::tonemap::Tonemap;
+use super::vec3::Color;
+
+
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    //Plain (ASCII) PPM
+    Ppm,
+    Png,
+    //OpenEXR, half floats. The linear radiance as it is, for grading and compositing
+    //elsewhere: no tone mapping, no clamping.
+    Exr,
+    //OpenEXR, 32-bit floats
+    ExrFloat,
+}
+
+impl Format {
+    //From the file extension, PPM if it isn't one we know. .exr gets half floats.
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("png") => Format::Png,
+            Some(ext) if ext.eq_ignore_ascii_case("exr") => Format::Exr,
+            _ => Format::Ppm,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Ppm => "ppm",
+            Format::Png => "png",
+            Format::Exr | Format::ExrFloat => "exr",
+        }
+    }
+
+    //Whether the format keeps the linear values, so there's no tone mapping to do
+    pub fn is_float(self) -> bool {
+        matches!(self, Format::Exr | Format::ExrFloat)
+    }
+}
+
+//Tonemap linear pixels (row-major from the top) and encode them to out. Float
+//formats ignore tonemap.
+pub fn write(out: impl Write, format: Format, tonemap: Tonemap, width: u64, height: u64, pixels: &[Color]) -> io::Result<()> {
+    let encoded = || -> Vec<[u8; 3]> { pixels.iter().map(|&c| tonemap.to_display(c)).collect() };
+    match format {
+        Format::Ppm => write_ppm(out, width, height, &encoded()),
+        Format::Png => write_png(out, width, height, &encoded(), true),
+        Format::Exr => exr::write(out, width, height, pixels, None, true),
+        Format::ExrFloat => exr::write(out, width, height, pixels, None, false),
+    }
+}
+
+pub fn save(path: &Path, format: Format, tonemap: Tonemap, width: u64, height: u64, pixels: &[Color]) -> io::Result<()> {
+    write(BufWriter::new(fs::File::create(path)?), format, tonemap, width, height, pixels)
+}
+
+//write with an alpha channel, for pixels rendered over nothing (see
+//RenderSettings::transparent) and so already multiplied by their alpha. PNG stores
+//colours unpremultiplied, so they're divided back out before tone mapping; EXR keeps
+//them premultiplied. PPM has no alpha channel, so is an error.
+pub fn write_rgba(out: impl Write, format: Format, tonemap: Tonemap, width: u64, height: u64, pixels: &[Color], alpha: &[f64]) -> io::Result<()> {
+    match format {
+        Format::Ppm => Err(io::Error::new(io::ErrorKind::InvalidInput, "PPM has no alpha channel")),
+        Format::Png => {
+            let encoded: Vec<[u8; 4]> = pixels.iter().zip(alpha).map(|(&c, &a)| {
+                let [r, g, b] = tonemap.to_display(if a > 0.0 { c / a } else { c });
+                [r, g, b, (256.0 * a.clamp(0.0, 0.999)) as u8]
+            }).collect();
+            write_png_rgba(out, width, height, &encoded)
+        }
+        Format::Exr => exr::write(out, width, height, pixels, Some(alpha), true),
+        Format::ExrFloat => exr::write(out, width, height, pixels, Some(alpha), false),
+    }
+}
+
+pub fn save_rgba(path: &Path, format: Format, tonemap: Tonemap, width: u64, height: u64, pixels: &[Color], alpha: &[f64]) -> io::Result<()> {
+    write_rgba(BufWriter::new(fs::File::create(path)?), format, tonemap, width, height, pixels, alpha)
+}
+
+//Like save but with no tone mapping or sRGB encoding: values in [0, 1] go straight to bytes, for data such as
+//normals that should read back the way they went in
+pub fn save_linear(path: &Path, format: Format, width: u64, height: u64, pixels: &[Color]) -> io::Result<()> {
+    let f = |x: f64| (256.0 * x.clamp(0.0, 0.999)) as u8;
+    let encoded = || -> Vec<[u8; 3]> { pixels.iter().map(|c| [f(c[0]), f(c[1]), f(c[2])]).collect() };
+    let out = BufWriter::new(fs::File::create(path)?);
+    match format {
+        Format::Ppm => write_ppm(out, width, height, &encoded()),
+        Format::Png => write_png(out, width, height, &encoded(), false),
+        Format::Exr => exr::write(out, width, height, pixels, None, true),
+        Format::ExrFloat => exr::write(out, width, height, pixels, None, false),
+    }
+}
+
+//Rewrites an image file each time every pixel has had another pass of samples, so a
+//long render can be looked at early and then watched as it refines
+pub struct Snapshots {
+    path: PathBuf,
+    format: Format,
+    tonemap: Tonemap,
+    pass_samples: u64,
+    //Passes written so far
+    written: Mutex<u64>,
+}
+
+impl Snapshots {
+    pub fn new(path: &Path, format: Format, tonemap: Tonemap, pass_samples: u64) -> Snapshots {
+        Snapshots { path: path.to_path_buf(), format, tonemap, pass_samples: pass_samples.max(1), written: Mutex::new(0) }
+    }
+
+    //min_samples is the fewest samples any pixel has had so far. pixels (the means,
+    //row-major from the top) is only worked out if there's a new pass to write.
+    //Can be called from several threads at once.
+    pub fn update(&self, min_samples: u64, width: u64, height: u64, pixels: impl FnOnce() -> Vec<Color>) {
+        let passes = min_samples / self.pass_samples;
+        let mut written = self.written.lock().unwrap();
+        if passes <= *written {
+            return;
+        }
+        *written = passes;
+
+        //Written to the side and renamed over the old one, so anything watching the
+        //file never sees half an image
+        let partial = self.path.with_extension(format!("partial.{}", self.format.extension()));
+        let result = save(&partial, self.format, self.tonemap, width, height, &pixels())
+            .and_then(|_| fs::rename(&partial, &self.path));
+        match result {
+            Ok(()) => eprintln!("Pass {} ({} spp) written to {}", passes, passes * self.pass_samples, self.path.display()),
+            Err(e) => eprintln!("Couldn't write snapshot {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+fn write_ppm(mut out: impl Write, width: u64, height: u64, pixels: &[[u8; 3]]) -> io::Result<()> {
+    writeln!(out, "P3")?;
+    writeln!(out, "{} {}", width, height)?;
+    writeln!(out, "255")?;
+    for [r, g, b] in pixels {
+        writeln!(out, "{} {} {}", r, g, b)?;
+    }
+    out.flush()
+}
+
+//srgb marks the pixels as sRGB encoded, otherwise they're linear
+fn write_png(out: impl Write, width: u64, height: u64, pixels: &[[u8; 3]], srgb: bool) -> io::Result<()> {
+    write_png_bytes(out, width, height, pixels.as_flattened(), png::ColorType::Rgb, srgb)
+}
+
+//sRGB colours with straight (not premultiplied) alpha
+fn write_png_rgba(out: impl Write, width: u64, height: u64, pixels: &[[u8; 4]]) -> io::Result<()> {
+    write_png_bytes(out, width, height, pixels.as_flattened(), png::ColorType::Rgba, true)
+}
+
+fn write_png_bytes(out: impl Write, width: u64, height: u64, bytes: &[u8], color: png::ColorType, srgb: bool) -> io::Result<()> {
+    let too_big = |_| io::Error::new(io::ErrorKind::InvalidInput, "image too large for PNG");
+    let mut encoder = png::Encoder::new(out, width.try_into().map_err(too_big)?, height.try_into().map_err(too_big)?);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    if srgb {
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    } else {
+        encoder.set_source_gamma(png::ScaledFloat::new(1.0));
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(bytes)?;
+    writer.finish()?;
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use super::ray::Ray;
+use super::vec3::Vec3x4;
+
+
+
+//Number of rays traced together, one to a lane of a Vec3x4
+pub const PACKET: usize = 4;
+
+//A few rays that go much the same way, like the camera rays for one pixel, traced
+//through the scene together. The BVH visits a node once for all of them instead of
+//once each, slab tests take all of them in one go, and sphere batches test each sphere
+//against every ray at once.
+pub struct RayPacket {
+    pub rays: [Ray; PACKET],
+    pub origin: Vec3x4,
+    pub direction: Vec3x4,
+    //1 / direction, for slab tests
+    pub inv_direction: Vec3x4,
+}
+
+impl RayPacket {
+    pub fn new(rays: [Ray; PACKET]) -> RayPacket {
+        let direction = Vec3x4::new(rays.map(|r| r.direction()));
+        RayPacket { rays, origin: Vec3x4::new(rays.map(|r| r.origin())), direction, inv_direction: direction.recip() }
+    }
+}
@@ -0,0 +1,261 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord};
+use super::light::LightGroups;
+use super::random::{hash, reseed};
+use super::ray::{self, Ray};
+use super::renderer::RenderSettings;
+use super::scene::Scene;
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+//Caustic photon map (after Jensen 1996): photons are shot from the point and area lights,
+//followed through glass, mirrors and anything else that can't be evaluated for a
+//direction, and kept where they land on the first surface that can. Shading then adds
+//the light of the photons nearest each point. Path tracing can't find these paths at
+//all, since point and area lights can't be hit, so light focused through a glass sphere
+//onto the floor otherwise never shows up.
+//
+//Lights here needn't fall off physically (see Falloff), so a photon's power is scaled as
+//the light's attenuation says over the whole length of its path. A surface the light
+//reaches straight on would get just what direct lighting gives it.
+pub struct PhotonMap {
+    //Kept as a kd-tree: each range's middle photon splits the rest along axes of it
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+    //Photons gathered per shading point, and furthest out they're looked for
+    nearest: usize,
+    max_radius: f64,
+}
+
+struct Photon {
+    p: Point3,
+    //Unit vector back the way it came
+    wi: Vec3,
+    power: Color,
+    //Those of the light it came from, see LightGroups
+    groups: LightGroups,
+}
+
+//Photons shot per chunk, each chunk seeded on its own so a seeded map comes out the same
+//whatever the number of threads
+const CHUNK: usize = 4096;
+
+impl PhotonMap {
+    //Shoot count photons into scene, shared evenly between its lights, following each
+    //for at most the settings' max_depth bounces. Gathers 50 photons per point, from at
+    //most a twentieth of the way across where they landed.
+    pub fn new(scene: &Scene, settings: &RenderSettings, count: usize) -> PhotonMap {
+        let mut photons: Vec<Photon> = if scene.lights.is_empty() || count == 0 {
+            Vec::new()
+        } else {
+            (0..count.div_ceil(CHUNK)).into_par_iter().flat_map_iter(|chunk| {
+                ray::set_limits(settings.limits());
+                if let Some(seed) = settings.seed {
+                    reseed(hash(seed ^ hash(chunk as u64)));
+                }
+                let shot = chunk * CHUNK..(chunk * CHUNK + CHUNK).min(count);
+                shot.filter_map(|i| shoot(scene, settings.max_depth, i, count)).collect::<Vec<_>>()
+            }).collect()
+        };
+
+        let max_radius = Aabb::around(photons.iter().map(|photon| photon.p))
+            .map_or(0.0, |bounds| (bounds.max - bounds.min).length() / 20.0);
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap { photons, axes, nearest: 50, max_radius }
+    }
+
+    //Gather nearest photons per shading point rather than 50
+    pub fn with_nearest(mut self, nearest: usize) -> PhotonMap {
+        self.nearest = nearest.max(1);
+        self
+    }
+
+    //Look no further than radius for them, rather than a twentieth of the way across
+    //where the photons landed
+    pub fn with_max_radius(mut self, radius: f64) -> PhotonMap {
+        self.max_radius = radius;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    //Caustic light leaving rec towards wo: the nearest photons' power through the BSDF,
+    //spread over the disc they were found in. Photons on the other side of the surface
+    //or from lights not linked to it don't count.
+    pub fn caustics(&self, rec: &HitRecord, wo: Vec3) -> Color {
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        if self.photons.is_empty() || rec.mat.in_medium() {
+            return total;
+        }
+        let mut found = BinaryHeap::with_capacity(self.nearest + 1);
+        let mut radius2 = self.max_radius * self.max_radius;
+        self.gather(rec.p, 0..self.photons.len(), &mut radius2, &mut found);
+        for Near { index, .. } in found {
+            let photon = &self.photons[index];
+            let cosine = rec.normal.dot(photon.wi);
+            if cosine <= 0.0 || !photon.groups.overlaps(rec.light_groups) {
+                continue;
+            }
+            //eval has the cosine in it, which the photon's power already allows for
+            if let Some((f, _)) = rec.mat.eval(rec, wo, photon.wi) {
+                total += f / cosine * photon.power;
+            }
+        }
+        total / (PI * radius2)
+    }
+
+    //Up to nearest photons in range closer to p than radius2 squared, into found,
+    //shrinking radius2 to the furthest of them once there are enough
+    fn gather(&self, p: Point3, range: std::ops::Range<usize>, radius2: &mut f64, found: &mut BinaryHeap<Near>) {
+        if range.is_empty() {
+            return;
+        }
+        let middle = range.start + range.len() / 2;
+        let (photon, axis) = (&self.photons[middle], self.axes[middle]);
+        let offset = p[axis] - photon.p[axis];
+        let (near, far) = if offset < 0.0 { (range.start..middle, middle + 1..range.end) } else { (middle + 1..range.end, range.start..middle) };
+
+        self.gather(p, near, radius2, found);
+        let distance2 = (photon.p - p).dot(photon.p - p);
+        if distance2 < *radius2 {
+            found.push(Near { distance2, index: middle });
+            if found.len() > self.nearest {
+                found.pop();
+            }
+            if found.len() == self.nearest {
+                *radius2 = found.peek().map_or(*radius2, |furthest| furthest.distance2);
+            }
+        }
+        if offset * offset < *radius2 {
+            self.gather(p, far, radius2, found);
+        }
+    }
+}
+
+//Photon number i of count, from light i % lights, if it lands somewhere after going
+//through something specular
+fn shoot(scene: &Scene, max_depth: u64, i: usize, count: usize) -> Option<Photon> {
+    let light = &scene.lights[i % scene.lights.len()];
+    let share = 4.0 * PI * scene.lights.len() as f64 / count as f64;
+    let mut power = share * light.diffuse();
+    let mut ray = Ray::new(light.sample_point(), Vec3::random_in_unit_sphere().normalized());
+    let mut travelled = 0.0;
+    let mut specular = false;
+    let limits = ray::limits();
+
+    for _ in 0..max_depth {
+        let mut rec = scene.world.hit(&ray, limits.epsilon, limits.max_distance)?;
+        Arc::clone(&rec.mat).perturb(&ray, &mut rec);
+        let length = (rec.p - ray.origin()).length();
+        travelled += length;
+        power *= ray.interiors().transmittance(length);
+        if rec.mat.in_medium() {
+            return None;
+        }
+
+        //As in shading, boundaries inside something of higher priority aren't there
+        let interior = rec.mat.interior();
+        if let Some(interior) = interior.filter(|i| ray.interiors().without(i.id).outranks(i.priority)) {
+            ray = Ray::new(rec.p, ray.direction()).with_time(ray.time())
+                .with_interiors(ray.interiors().crossing(interior, rec.front_face))
+                .with_channel(ray.channel());
+            continue;
+        }
+
+        let wi = (-1.0) * ray.direction().normalized();
+        if rec.mat.eval(&rec, wi, rec.normal).is_some() {
+            let scale = light.attenuation(travelled) * travelled * travelled;
+            return specular.then(|| Photon { p: rec.p, wi, power: scale * power, groups: light.groups() });
+        }
+
+        let (attenuation, scattered) = rec.mat.scatter(ray.origin(), &scene.lights, &scene.world, &ray, &rec)?;
+        let interiors = match interior {
+            Some(interior) if scattered.direction().dot(rec.normal) < 0.0 => ray.interiors().crossing(interior, rec.front_face),
+            _ => ray.interiors(),
+        };
+        power *= attenuation;
+        //Dispersion sends each colour its own way, so the photon goes on as one of them
+        if let (None, Some(c)) = (ray.channel(), scattered.channel()) {
+            let kept = 3.0 * power[c];
+            power = Color::new(0.0, 0.0, 0.0);
+            power[c] = kept;
+        }
+        ray = scattered.with_interiors(interiors).with_channel(scattered.channel().or(ray.channel()));
+        specular = true;
+    }
+    None
+}
+
+//Make photons a kd-tree, splitting each range at its middle along the axis it's widest
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let Some(bounds) = Aabb::around(photons.iter().map(|photon| photon.p)) else { return };
+    let extent = bounds.max - bounds.min;
+    let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() { 0 } else if extent.y() >= extent.z() { 1 } else { 2 };
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+    axes[middle] = axis;
+    let (left, right) = photons.split_at_mut(middle);
+    let (left_axes, right_axes) = axes.split_at_mut(middle);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+//A photon found near a point, ordered by distance so the heap's top is the furthest
+struct Near {
+    distance2: f64,
+    index: usize,
+}
+
+impl PartialEq for Near {
+    fn eq(&self, other: &Near) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Near {}
+
+impl PartialOrd for Near {
+    fn partial_cmp(&self, other: &Near) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Near {
+    fn cmp(&self, other: &Near) -> Ordering {
+        self.distance2.total_cmp(&other.distance2)
+    }
+}
+
+thread_local! {
+    //Map for the render this thread is working on, see set_current
+    static CURRENT: RefCell<Option<Arc<PhotonMap>>> = const { RefCell::new(None) };
+}
+
+//Have shading on this thread add caustics from map (None for none), so it needn't be
+//handed it
+pub fn set_current(map: Option<&Arc<PhotonMap>>) {
+    CURRENT.with(|current| *current.borrow_mut() = map.cloned());
+}
+
+pub fn current() -> Option<Arc<PhotonMap>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord};
+use super::material::Scatter;
+use super::ray::{self, Ray};
+use super::sampler::next_2d;
+use super::vec3::{Point3, Vec3};
+
+
+
+//Flat surface through point facing normal, going on for ever unless given an extent.
+//A ground plane, without the curvature and precision trouble of a huge sphere.
+//Infinite planes have no bounding box, so the BVH keeps them in its unbounded list.
+pub struct Plane {
+    point: Point3,
+    normal: Vec3,
+    //In-plane axes, u runs along tangent and v along bitangent
+    tangent: Vec3,
+    bitangent: Vec3,
+    //Half the width and height, centred on point
+    half_extent: Option<(f64, f64)>,
+    //World units per repeat of the texture on an infinite plane
+    uv_scale: f64,
+    mat: Arc<dyn Scatter>,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, mat: Arc<dyn Scatter>) -> Plane {
+        let normal = normal.normalized();
+        let tangent = normal.any_perpendicular();
+        Plane {
+            point,
+            normal,
+            tangent,
+            bitangent: normal.cross(tangent),
+            half_extent: None,
+            uv_scale: 1.0,
+            mat,
+        }
+    }
+
+    //Cut down to a width x height rectangle centred on point, width along the u
+    //direction. The texture is stretched over it once.
+    pub fn with_extent(mut self, width: f64, height: f64) -> Plane {
+        self.half_extent = Some((0.5 * width.abs(), 0.5 * height.abs()));
+        self
+    }
+
+    //Repeat the texture every size units along the plane (infinite planes only)
+    pub fn with_uv_scale(mut self, size: f64) -> Plane {
+        self.uv_scale = size;
+        self
+    }
+
+    //Point the u direction along the part of u lying in the plane, e.g. +x for a ground
+    //plane to have textures lined up with the world axes. u mustn't be along the normal.
+    pub fn with_u_axis(mut self, u: Vec3) -> Plane {
+        self.tangent = (u - u.dot(self.normal) * self.normal).normalized();
+        self.bitangent = self.normal.cross(self.tangent);
+        self
+    }
+}
+
+impl Hit for Plane {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        //Parallel rays never cross (and give t = inf or NaN, both rejected)
+        let t = self.normal.dot(self.point - r.origin()) / self.normal.dot(r.direction());
+        if !(t >= t_min && t <= t_max) {
+            return None;
+        }
+
+        let offset = r.at(t) - self.point;
+        let a = offset.dot(self.tangent);
+        let b = offset.dot(self.bitangent);
+        let (u, v, uv_size) = match self.half_extent {
+            Some((half_w, half_h)) => {
+                if a.abs() > half_w || b.abs() > half_h {
+                    return None;
+                }
+                (0.5 + 0.5 * a / half_w, 0.5 + 0.5 * b / half_h, 2.0 * (half_w * half_h).sqrt())
+            }
+            None => ((a / self.uv_scale).rem_euclid(1.0), (b / self.uv_scale).rem_euclid(1.0), self.uv_scale.abs()),
+        };
+
+        let mut rec = HitRecord::new(r, t, self.normal, Arc::clone(&self.mat), u, v);
+        rec.tangent = self.tangent;
+        rec.bitangent = self.bitangent;
+        rec.uv_size = uv_size;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let (half_w, half_h) = self.half_extent?;
+        let corners = [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)].map(|(sa, sb)| {
+            self.point + sa * half_w * self.tangent + sb * half_h * self.bitangent
+        });
+        //Padded so a plane lined up with an axis doesn't get a flat box
+        let pad = 0.0001 * Vec3::new(1.0, 1.0, 1.0);
+        Aabb::around(corners.into_iter().flat_map(|c| [c - pad, c + pad]))
+    }
+
+    //Only bounded planes can be sampled, uniformly over their area
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let Some((half_w, half_h)) = self.half_extent else {
+            return 0.0;
+        };
+        let Some(rec) = self.hit(&Ray::new(origin, direction), ray::limits().epsilon, f64::INFINITY) else {
+            return 0.0;
+        };
+        let distance_squared = rec.t * rec.t * direction.dot(direction);
+        let cosine = (direction.dot(self.normal) / direction.length()).abs();
+        distance_squared / (cosine * 4.0 * half_w * half_h)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        let (half_w, half_h) = self.half_extent.unwrap_or((0.0, 0.0));
+        let (u, v) = next_2d();
+        let a = (2.0 * u - 1.0) * half_w;
+        let b = (2.0 * v - 1.0) * half_h;
+        self.point + a * self.tangent + b * self.bitangent - origin
+    }
+}
@@ -0,0 +1,298 @@
+use rayon::prelude::*;
+
+use super::image::Image;
+use super::vec3::Color;
+
+
+
+//What's done to the finished image, still linear, before it's tonemapped: exposed,
+//then light blooming round the brightest parts, then the lens bending and darkening it
+#[derive(Clone, Copy, Default)]
+pub struct PostProcess {
+    pub exposure: Exposure,
+    pub bloom: Option<Bloom>,
+    pub lens: LensEffects,
+}
+
+impl PostProcess {
+    pub fn apply(&self, image: &Image) -> Image {
+        let exposed = self.exposure.apply(image);
+        match &self.bloom {
+            Some(bloom) => self.lens.apply(&bloom.apply(&exposed)),
+            None => self.lens.apply(&exposed),
+        }
+    }
+
+    //Only moved as the image's pixels are, see LensEffects::distort
+    pub fn distort(&self, image: &Image) -> Image {
+        self.lens.distort(image)
+    }
+}
+
+//How much the image is brightened or darkened, in stops (each doubling it), as by a
+//camera's exposure. Scene values are taken to be daylight levels, so a camera set for
+//a sunny day leaves them as they are.
+#[derive(Clone, Copy, Default)]
+pub struct Exposure {
+    stops: f64,
+    //Log-average luminance to scale the image to first, see with_auto
+    auto: Option<f64>,
+}
+
+//Exposure value (at ISO 100) of the "sunny 16" rule, f/16 at 1/100s, which the scene's
+//values are taken to be right for
+const DAYLIGHT_EV: f64 = 14.643856189774725;
+
+impl Exposure {
+    //Brighter by stops, or darker if negative
+    pub fn stops(stops: f64) -> Exposure {
+        Exposure { stops, auto: None }
+    }
+
+    //As a camera with these settings would take the scene: film speed iso, the shutter
+    //open for shutter seconds, the lens at f-number f_stop. f/16 at 1/100s and ISO 100
+    //changes nothing; each halving of the shutter time or doubling of the f-number
+    //darkens it a stop.
+    pub fn camera(iso: f64, shutter: f64, f_stop: f64) -> Exposure {
+        let ev = (f_stop * f_stop / shutter).log2() - (iso / 100.0).log2();
+        Exposure::stops(DAYLIGHT_EV - ev)
+    }
+
+    //stops more (or fewer, if negative) on top of what it already has
+    pub fn with_compensation(mut self, stops: f64) -> Exposure {
+        self.stops += stops;
+        self
+    }
+
+    //Scale each image first so its log-average luminance (Reinhard et al 2002), much
+    //what a camera's meter reads, comes out key: 0.18, a middle grey, looks natural.
+    //The stops then go on top.
+    pub fn with_auto(mut self, key: f64) -> Exposure {
+        self.auto = Some(key);
+        self
+    }
+
+    //What image is multiplied by
+    pub fn scale(&self, image: &Image) -> f64 {
+        let metered = match self.auto {
+            Some(key) => {
+                //Nothing at all (like a black background) isn't metered, or it would
+                //drag the average down without end
+                let logs: Vec<f64> = image.pixels.iter().map(|c| c.luminance()).filter(|l| l.is_finite() && *l > 0.0)
+                    .map(|l| (l + 1.0e-4).ln()).collect();
+                if logs.is_empty() { 1.0 } else { key / (logs.iter().sum::<f64>() / logs.len() as f64).exp() }
+            }
+            None => 1.0,
+        };
+        metered * self.stops.exp2()
+    }
+
+    pub fn apply(&self, image: &Image) -> Image {
+        let scale = self.scale(image);
+        let pixels = if scale == 1.0 { image.pixels.clone() } else { image.pixels.iter().map(|&c| scale * c).collect() };
+        Image { width: image.width, height: image.height, pixels }
+    }
+}
+
+//Light from the brightest parts of the image spilling onto what's round them, as it
+//scatters in a real lens or eye. Whatever is brighter than the threshold is blurred and
+//added back on, so it only shows around values over 1 unless the threshold is lower.
+#[derive(Clone, Copy)]
+pub struct Bloom {
+    threshold: f64,
+    intensity: f64,
+    //Standard deviation of the glow, as a fraction of the image width
+    radius: f64,
+}
+
+impl Bloom {
+    //Adds intensity times the light over 1, spread over about a fiftieth of the width,
+    //with a fainter glow four times as wide round that, as real glare tails off slowly
+    pub fn new(intensity: f64) -> Bloom {
+        Bloom { threshold: 1.0, intensity, radius: 0.02 }
+    }
+
+    //Bloom light over threshold (by luminance) rather than 1
+    pub fn with_threshold(mut self, threshold: f64) -> Bloom {
+        self.threshold = threshold.max(0.0);
+        self
+    }
+
+    pub fn with_radius(mut self, radius: f64) -> Bloom {
+        self.radius = radius.max(0.0);
+        self
+    }
+
+    pub fn apply(&self, image: &Image) -> Image {
+        let (w, h) = (image.width as usize, image.height as usize);
+        let mut pixels = image.pixels.clone();
+        if self.intensity == 0.0 || pixels.is_empty() {
+            return Image { width: image.width, height: image.height, pixels };
+        }
+        //Only the part over the threshold, keeping its colour
+        let bright: Vec<Color> = image.pixels.iter().map(|&c| {
+            let luminance = c.luminance();
+            if luminance > self.threshold { (luminance - self.threshold) / luminance * c } else { Color::new(0.0, 0.0, 0.0) }
+        }).collect();
+        let sigma = self.radius * w as f64;
+        for (scale, weight) in [(1.0, 0.75), (4.0, 0.25)] {
+            let glow = gaussian_blur(&bright, w, h, scale * sigma);
+            for (pixel, glow) in pixels.iter_mut().zip(glow) {
+                *pixel += self.intensity * weight * glow;
+            }
+        }
+        Image { width: image.width, height: image.height, pixels }
+    }
+}
+
+//Imperfections of real lenses, put on the finished image (still linear, before
+//tonemapping) to make it look more like a photo. Each is off at 0. Positions are taken
+//from the middle of the image, out to 1 at the corners.
+#[derive(Clone, Copy, Default)]
+pub struct LensEffects {
+    //k in r' = r (1 + k r^2), scaled so the corners stay put: positive bows straight lines
+    //out (barrel), negative pulls them in (pincushion)
+    distortion: f64,
+    //How much bigger the red image is than the green, and the green than the blue, as a
+    //fraction, so bright edges get coloured fringes further out
+    chromatic_aberration: f64,
+    //tan^2 of the angle from the middle of the view to its corners: light falls off as
+    //cos^4 of the angle off axis, as it does through a simple lens
+    vignetting: f64,
+}
+
+impl LensEffects {
+    pub fn new() -> LensEffects {
+        LensEffects::default()
+    }
+
+    pub fn with_distortion(mut self, k: f64) -> LensEffects {
+        self.distortion = k.max(-0.9);
+        self
+    }
+
+    pub fn with_chromatic_aberration(mut self, amount: f64) -> LensEffects {
+        self.chromatic_aberration = amount;
+        self
+    }
+
+    //Darken the corners; 1 is what a lens seeing 90 degrees corner to corner would do,
+    //leaving them a quarter as bright
+    pub fn with_vignetting(mut self, strength: f64) -> LensEffects {
+        self.vignetting = strength.max(0.0);
+        self
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.distortion == 0.0 && self.chromatic_aberration == 0.0 && self.vignetting == 0.0
+    }
+
+    //image as seen through the lens
+    pub fn apply(&self, image: &Image) -> Image {
+        self.resample(image, true)
+    }
+
+    //Only where the lens moves each pixel to, for passes (like alpha) that have to line
+    //up with the image but aren't light
+    pub fn distort(&self, image: &Image) -> Image {
+        self.resample(image, false)
+    }
+
+    fn resample(&self, image: &Image, optical: bool) -> Image {
+        if self.is_none() || image.pixels.is_empty() {
+            return Image { width: image.width, height: image.height, pixels: image.pixels.clone() };
+        }
+        let (w, h) = (image.width as usize, image.height as usize);
+        let (cx, cy) = (0.5 * w as f64, 0.5 * h as f64);
+        let reach = (cx * cx + cy * cy).sqrt();
+        let scales = if optical {
+            let a = self.chromatic_aberration;
+            [1.0 / (1.0 + a), 1.0, 1.0 + a]
+        } else {
+            [1.0; 3]
+        };
+
+        let mut pixels = vec![Color::new(0.0, 0.0, 0.0); w * h];
+        pixels.par_chunks_mut(w).enumerate().for_each(|(y, row)| {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let (u, v) = ((x as f64 + 0.5 - cx) / reach, (y as f64 + 0.5 - cy) / reach);
+                let r2 = u * u + v * v;
+                let warp = (1.0 + self.distortion * r2) / (1.0 + self.distortion);
+                for (c, scale) in scales.into_iter().enumerate() {
+                    let (sx, sy) = (cx + u * warp * scale * reach - 0.5, cy + v * warp * scale * reach - 0.5);
+                    pixel[c] = bilinear(image, sx, sy)[c];
+                }
+                if optical && self.vignetting > 0.0 {
+                    let cos2 = 1.0 / (1.0 + self.vignetting * r2);
+                    *pixel = cos2 * cos2 * *pixel;
+                }
+            }
+        });
+        Image { width: image.width, height: image.height, pixels }
+    }
+}
+
+//image at (x, y) in pixels from the centre of the top left one, held at the edges
+fn bilinear(image: &Image, x: f64, y: f64) -> Color {
+    let (w, h) = (image.width as i64, image.height as i64);
+    let texel = |x: i64, y: i64| image.pixels[(y.clamp(0, h - 1) * w + x.clamp(0, w - 1)) as usize];
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let top = (1.0 - fx) * texel(x0, y0) + fx * texel(x0 + 1, y0);
+    let bottom = (1.0 - fx) * texel(x0, y0 + 1) + fx * texel(x0 + 1, y0 + 1);
+    (1.0 - fy) * top + fy * bottom
+}
+
+//pixels (w by h) blurred with a Gaussian of standard deviation sigma pixels, taken as
+//three box blurs one after another (close enough, and as quick for any sigma), with
+//the edges held
+fn gaussian_blur(pixels: &[Color], w: usize, h: usize, sigma: f64) -> Vec<Color> {
+    let mut blurred = pixels.to_vec();
+    if sigma < 0.5 {
+        return blurred;
+    }
+    //Box widths whose variances add up to sigma^2 (Kovesi 2010): odd ones, m of them
+    //the narrower
+    let ideal = (4.0 * sigma * sigma + 1.0).sqrt();
+    let mut narrow = ideal.floor() as usize;
+    if narrow.is_multiple_of(2) {
+        narrow -= 1;
+    }
+    let n = narrow as f64;
+    let m = ((12.0 * sigma * sigma - 3.0 * n * n - 12.0 * n - 9.0) / (-4.0 * n - 4.0)).round().clamp(0.0, 3.0) as usize;
+    let mut scratch = vec![Color::new(0.0, 0.0, 0.0); pixels.len()];
+    for pass in 0..3 {
+        let radius = (if pass < m { narrow } else { narrow + 2 }) / 2;
+        //Along the rows, then down the columns by way of the image on its side
+        box_blur_rows(&blurred, &mut scratch, w, radius);
+        transpose(&scratch, &mut blurred, w, h);
+        box_blur_rows(&blurred, &mut scratch, h, radius);
+        transpose(&scratch, &mut blurred, h, w);
+    }
+    blurred
+}
+
+//Each row of from (w wide) averaged over the pixels up to radius either side, into to
+fn box_blur_rows(from: &[Color], to: &mut [Color], w: usize, radius: usize) {
+    let r = radius as i64;
+    to.par_chunks_mut(w).zip(from.par_chunks(w)).for_each(|(out, row)| {
+        let at = |x: i64| row[x.clamp(0, w as i64 - 1) as usize];
+        let mut sum = (-r..=r).fold(Color::new(0.0, 0.0, 0.0), |sum, x| sum + at(x));
+        let width = (2 * r + 1) as f64;
+        for (x, pixel) in out.iter_mut().enumerate() {
+            *pixel = sum / width;
+            let x = x as i64;
+            sum += at(x + r + 1) - at(x - r);
+        }
+    });
+}
+
+//from (w by h) turned on its side into to (h by w)
+fn transpose(from: &[Color], to: &mut [Color], w: usize, h: usize) {
+    to.par_chunks_mut(h).enumerate().for_each(|(x, column)| {
+        for (y, pixel) in column.iter_mut().enumerate() {
+            *pixel = from[y * w + x];
+        }
+    });
+}
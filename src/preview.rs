@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+
+use raytracer::tonemap::Tonemap;
+use raytracer::vec3::Color;
+
+
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum PreviewMode {
+    //Sixel if the terminal looks like it supports it, half-blocks otherwise
+    Auto,
+    Sixel,
+    //Truecolor upper half-blocks, two pixels per character cell
+    Blocks,
+}
+
+impl PreviewMode {
+    //Pick a concrete mode for Auto. There's no portable way to ask a terminal about sixel
+    //support without putting it into raw mode, so go by what it says it is.
+    fn resolve(self) -> PreviewMode {
+        if self != PreviewMode::Auto {
+            return self;
+        }
+
+        let term = env::var("TERM").unwrap_or_default();
+        let program = env::var("TERM_PROGRAM").unwrap_or_default();
+        let sixel_terms = ["mlterm", "foot", "contour", "yaft"];
+        let sixel_programs = ["WezTerm", "iTerm.app", "mintty"];
+
+        if term.contains("sixel")
+            || sixel_terms.iter().any(|t| term.starts_with(t))
+            || sixel_programs.contains(&program.as_str())
+        {
+            PreviewMode::Sixel
+        } else {
+            PreviewMode::Blocks
+        }
+    }
+}
+
+//Draws a downscaled copy of the framebuffer to stderr, over the top of the last copy
+//it drew, so it can be called repeatedly while a render is in progress.
+pub struct Preview {
+    mode: PreviewMode,
+    tonemap: Tonemap,
+    //Largest preview in pixels; blocks use one column and half a row per pixel
+    max_width: u64,
+    max_height: u64,
+    //Text lines the last half-block preview took up, None before the first draw
+    drawn: Mutex<Option<u64>>,
+}
+
+impl Preview {
+    pub fn new(mode: PreviewMode, tonemap: Tonemap) -> Preview {
+        let mode = mode.resolve();
+        let columns = env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).unwrap_or(80u64);
+        let (max_width, max_height) = match mode {
+            PreviewMode::Sixel => (480, 270),
+            _ => (columns.min(120), 60),
+        };
+
+        Preview { mode, tonemap, max_width, max_height, drawn: Mutex::new(None) }
+    }
+
+    //pixels are linear, averaged colours, row-major from the top
+    pub fn draw(&self, pixels: &[Color], width: u64, height: u64) {
+        let (small, w, h) = downscale(pixels, width, height, self.max_width, self.max_height);
+        let encoded: Vec<[u8; 3]> = small.iter().map(|&c| self.tonemap.to_display(c)).collect();
+
+        //Only one thread draws at a time, the rest would just interleave escape codes
+        let mut drawn = self.drawn.lock().unwrap();
+        let mut out = String::new();
+        match self.mode {
+            PreviewMode::Sixel => {
+                //Save the cursor before the first image and go back to it for each redraw
+                out.push_str(if drawn.is_some() { "\x1b8" } else { "\x1b7" });
+                out.push_str(&sixel(&encoded, w, h));
+                out.push('\n');
+                *drawn = Some(0);
+            }
+            _ => {
+                if let Some(lines) = *drawn {
+                    let _ = write!(out, "\x1b[{}A", lines);
+                }
+                out.push_str(&half_blocks(&encoded, w, h));
+                *drawn = Some(h.div_ceil(2));
+            }
+        }
+
+        let mut stderr = io::stderr().lock();
+        let _ = stderr.write_all(out.as_bytes());
+        let _ = stderr.flush();
+    }
+}
+
+//Box filter down to fit within max_width x max_height, keeping the aspect ratio.
+//Never scales up.
+pub fn downscale(pixels: &[Color], width: u64, height: u64, max_width: u64, max_height: u64) -> (Vec<Color>, u64, u64) {
+    let factor = (width as f64 / max_width as f64).max(height as f64 / max_height as f64).max(1.0);
+    let w = ((width as f64 / factor) as u64).max(1);
+    let h = ((height as f64 / factor) as u64).max(1);
+
+    let mut out = Vec::with_capacity((w * h) as usize);
+    for y in 0..h {
+        let (y0, y1) = (y * height / h, ((y + 1) * height / h).max(y * height / h + 1));
+        for x in 0..w {
+            let (x0, x1) = (x * width / w, ((x + 1) * width / w).max(x * width / w + 1));
+            let mut sum = Color::new(0.0, 0.0, 0.0);
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    sum += pixels[(sy * width + sx) as usize];
+                }
+            }
+            out.push(sum / ((x1 - x0) * (y1 - y0)) as f64);
+        }
+    }
+
+    (out, w, h)
+}
+
+//Each cell is an upper half-block with the top pixel as foreground and the bottom as background
+fn half_blocks(pixels: &[[u8; 3]], width: u64, height: u64) -> String {
+    let mut out = String::new();
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let top = pixels[(y * width + x) as usize];
+            let _ = write!(out, "\x1b[38;2;{};{};{}m", top[0], top[1], top[2]);
+            if y + 1 < height {
+                let bottom = pixels[((y + 1) * width + x) as usize];
+                let _ = write!(out, "\x1b[48;2;{};{};{}m", bottom[0], bottom[1], bottom[2]);
+            } else {
+                out.push_str("\x1b[49m");
+            }
+            out.push('▀');
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+//Sixel image quantized to a 6x6x6 colour cube. Each band is six pixel rows; every colour
+//used in a band gets one pass over it, with runs of the same sixel compressed.
+fn sixel(pixels: &[[u8; 3]], width: u64, height: u64) -> String {
+    let level = |v: u8| (v as u32 * 5 + 127) / 255;
+    let index: Vec<u32> = pixels.iter().map(|p| level(p[0]) * 36 + level(p[1]) * 6 + level(p[2])).collect();
+
+    //DCS with pixel aspect 1:1, then raster attributes
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    for i in 0..216 {
+        let pct = |l: u32| l * 100 / 5;
+        let _ = write!(out, "#{};2;{};{};{}", i, pct(i / 36), pct(i / 6 % 6), pct(i % 6));
+    }
+
+    for band in (0..height).step_by(6) {
+        let rows = (height - band).min(6);
+
+        //Which colours appear in this band and where
+        let mut masks: HashMap<u32, Vec<u8>> = HashMap::new();
+        for dy in 0..rows {
+            for x in 0..width {
+                let c = index[((band + dy) * width + x) as usize];
+                masks.entry(c).or_insert_with(|| vec![0; width as usize])[x as usize] |= 1 << dy;
+            }
+        }
+
+        let mut colours: Vec<_> = masks.into_iter().collect();
+        colours.sort_by_key(|(c, _)| *c);
+        for (k, (c, mask)) in colours.iter().enumerate() {
+            if k > 0 {
+                //Back to the start of the band for the next colour
+                out.push('$');
+            }
+            let _ = write!(out, "#{}", c);
+            push_runs(&mut out, mask);
+        }
+        out.push('-');
+    }
+
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_runs(out: &mut String, mask: &[u8]) {
+    let mut x = 0;
+    while x < mask.len() {
+        let run = mask[x..].iter().take_while(|&&m| m == mask[x]).count();
+        let ch = (63 + mask[x]) as char;
+        if run > 3 {
+            let _ = write!(out, "!{}{}", run, ch);
+        } else {
+            for _ in 0..run {
+                out.push(ch);
+            }
+        }
+        x += run;
+    }
+}
@@ -0,0 +1,212 @@
+use super::hit::{Hit, HitRecord, World};
+use super::ray::{self, Ray};
+use super::vec3::{Point3, Vec3};
+
+
+
+//Where a curved ray ended up
+pub enum Propagated {
+    //Hit something; the ray is the straight segment it was on at the time, so
+    //materials see the direction it was actually travelling in
+    Hit(Ray, HitRecord),
+    //Left the curved region (or ran out of steps) and carries on in a straight line
+    Escaped(Ray),
+    //Fell into something that doesn't let light out
+    Absorbed,
+}
+
+//A region of space in which rays follow curves instead of straight lines. Inside a
+//sphere around centre, rays are marched in short straight segments, bending between
+//segments as the Deflection says; outside it they're ordinary rays. Shadow rays to
+//point lights are still straight.
+pub struct CurvedSpace {
+    deflection: Box<dyn Deflection>,
+    centre: Point3,
+    radius: f64,
+    //Longest segment to march in one go
+    step: f64,
+    max_steps: usize,
+}
+
+impl CurvedSpace {
+    pub fn new(deflection: Box<dyn Deflection>, centre: Point3, radius: f64, step: f64) -> CurvedSpace {
+        //Enough to cross the region a few times over at the longest step, for rays
+        //that wind around before escaping
+        let max_steps = (8.0 * radius / step).ceil() as usize;
+        CurvedSpace { deflection, centre, radius, step, max_steps }
+    }
+
+    pub fn propagate(&self, r: &Ray, world: &World) -> Propagated {
+        let mut p = r.origin();
+        let mut d = r.direction().normalized();
+        let mut t_min = ray::limits().epsilon;
+
+        //Straight up to where the ray enters the region
+        if (p - self.centre).length() > self.radius {
+            let straight = Ray::new(p, d).with_time(r.time());
+            let Some(t_enter) = self.entry_distance(p, d) else {
+                return Propagated::Escaped(straight);
+            };
+            if let Some(rec) = world.hit(&straight, t_min, t_enter) {
+                return Propagated::Hit(straight, rec);
+            }
+            p = straight.at(t_enter);
+            t_min = 0.0;
+        }
+
+        for _ in 0..self.max_steps {
+            if self.deflection.absorbs(p) {
+                return Propagated::Absorbed;
+            }
+            if (p - self.centre).length() > self.radius {
+                return Propagated::Escaped(Ray::new(p, d).with_time(r.time()));
+            }
+
+            let h = self.step.min(self.deflection.step_limit(p));
+            let (p_next, d_next) = self.rk4(p, d, h);
+
+            //Treat the step as a straight chord for intersection
+            let chord = p_next - p;
+            let length = chord.length();
+            let segment = Ray::new(p, chord / length).with_time(r.time());
+            if let Some(rec) = world.hit(&segment, t_min, length) {
+                return Propagated::Hit(segment, rec);
+            }
+
+            p = p_next;
+            d = d_next;
+            t_min = 0.0;
+        }
+
+        Propagated::Escaped(Ray::new(p, d).with_time(r.time()))
+    }
+
+    //Distance along a unit direction d from p (outside the region) to where it enters, if it does
+    fn entry_distance(&self, p: Point3, d: Vec3) -> Option<f64> {
+        let oc = p - self.centre;
+        let half_b = oc.dot(d);
+        let c = oc.dot(oc) - self.radius * self.radius;
+        let discriminant = half_b * half_b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let t = -half_b - discriminant.sqrt();
+        if t > 0.0 { Some(t) } else { None }
+    }
+
+    //One Runge-Kutta step of dp/ds = d, dd/ds = curvature(p, d) over distance h.
+    //The direction is renormalized afterwards; only its direction means anything.
+    fn rk4(&self, p: Point3, d: Vec3, h: f64) -> (Point3, Vec3) {
+        let f = |p: Point3, d: Vec3| self.deflection.curvature(p, d);
+
+        let (k1p, k1d) = (d, f(p, d));
+        let (k2p, k2d) = (d + 0.5 * h * k1d, f(p + 0.5 * h * k1p, d + 0.5 * h * k1d));
+        let (k3p, k3d) = (d + 0.5 * h * k2d, f(p + 0.5 * h * k2p, d + 0.5 * h * k2d));
+        let (k4p, k4d) = (d + h * k3d, f(p + h * k3p, d + h * k3d));
+
+        let p_next = p + h / 6.0 * (k1p + 2.0 * k2p + 2.0 * k3p + k4p);
+        let d_next = d + h / 6.0 * (k1d + 2.0 * k2d + 2.0 * k3d + k4d);
+        (p_next, d_next.normalized())
+    }
+}
+
+//Gradient-index medium: light bends towards higher refractive index, following
+//d/ds (n dr/ds) = grad n. For a unit direction d that's a turn of
+//(grad n - (grad n . d) d) / n per unit distance.
+pub struct GradientIndex {
+    field: Box<dyn IndexField>,
+}
+
+impl GradientIndex {
+    pub fn new(field: Box<dyn IndexField>) -> GradientIndex {
+        GradientIndex { field }
+    }
+}
+
+impl Deflection for GradientIndex {
+    fn curvature(&self, p: Point3, d: Vec3) -> Vec3 {
+        let d = d.normalized();
+        let grad = self.field.gradient(p);
+        (grad - grad.dot(d) * d) / self.field.index(p)
+    }
+
+    //A small fraction of the radius of curvature, so rays can stride through nearly
+    //uniform stretches and only slow down where the index changes quickly
+    fn step_limit(&self, p: Point3) -> f64 {
+        0.02 * self.field.index(p) / self.field.gradient(p).length()
+    }
+}
+
+//Any function of position works as an index field
+impl<F: Fn(Point3) -> f64 + Send + Sync> IndexField for F {
+    fn index(&self, p: Point3) -> f64 {
+        self(p)
+    }
+}
+
+//Light passing a non-rotating black hole. Rather than integrate geodesics, use the
+//Newtonian-looking form of the photon orbit equation, x'' = -3/2 rs h^2 x / r^5 with
+//h = |x cross x'|, which traces the same paths in flat coordinates.
+pub struct Schwarzschild {
+    centre: Point3,
+    //Schwarzschild radius; nothing gets out from inside it
+    rs: f64,
+}
+
+impl Schwarzschild {
+    pub fn new(centre: Point3, rs: f64) -> Schwarzschild {
+        Schwarzschild { centre, rs }
+    }
+}
+
+impl Deflection for Schwarzschild {
+    fn curvature(&self, p: Point3, d: Vec3) -> Vec3 {
+        let x = p - self.centre;
+        let r = x.length();
+        let h2 = x.cross(d).length().powi(2);
+        -1.5 * self.rs * h2 / r.powi(5) * x
+    }
+
+    fn absorbs(&self, p: Point3) -> bool {
+        (p - self.centre).length() < self.rs
+    }
+
+    //The bending is all close in, so take small steps there and big ones far out
+    fn step_limit(&self, p: Point3) -> f64 {
+        0.1 * (p - self.centre).length()
+    }
+}
+
+
+pub trait Deflection: Send + Sync {
+    //Rate at which a ray at p travelling in direction d turns, per unit distance
+    fn curvature(&self, p: Point3, d: Vec3) -> Vec3;
+
+    //Whether a ray that reaches p is gone for good
+    fn absorbs(&self, _p: Point3) -> bool {
+        false
+    }
+
+    //Longest step that keeps the march accurate around p
+    fn step_limit(&self, _p: Point3) -> f64 {
+        f64::INFINITY
+    }
+}
+
+//A scalar refractive index field
+pub trait IndexField: Send + Sync {
+    fn index(&self, p: Point3) -> f64;
+
+    //Central differences unless the field knows better
+    fn gradient(&self, p: Point3) -> Vec3 {
+        const H: f64 = 1e-4;
+        let dx = Vec3::new(H, 0.0, 0.0);
+        let dy = Vec3::new(0.0, H, 0.0);
+        let dz = Vec3::new(0.0, 0.0, H);
+        Vec3::new(
+            self.index(p + dx) - self.index(p - dx),
+            self.index(p + dy) - self.index(p - dy),
+            self.index(p + dz) - self.index(p - dz),
+        ) / (2.0 * H)
+    }
+}
@@ -0,0 +1,119 @@
+use std::cell::RefCell;
+use std::ops::Range;
+
+use rand::{Rng, RngCore, SeedableRng};
+
+
+
+//PCG-XSH-RR (O'Neill 2014): 64 bits of state, 32 bits out. Seeded renders start a
+//fresh sequence for every sample, so what matters most is that seeding is next to
+//free, which isn't true of the ChaCha behind StdRng. The numbers are plenty good
+//enough for Monte Carlo, but not for anything that needs to be unpredictable.
+#[derive(Clone)]
+pub struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    //stream picks one of 2^63 independent sequences
+    pub fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0, increment: (stream << 1) | 1 };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+
+    fn step(&mut self) {
+        self.state = self.state.wrapping_mul(Self::MULTIPLIER).wrapping_add(self.increment);
+    }
+}
+
+impl RngCore for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) << 32 | self.next_u32() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for Pcg32 {
+    //State then stream, little-endian
+    type Seed = [u8; 16];
+
+    fn from_seed(seed: [u8; 16]) -> Pcg32 {
+        let (state, stream) = seed.split_at(8);
+        Pcg32::new(u64::from_le_bytes(state.try_into().unwrap()), u64::from_le_bytes(stream.try_into().unwrap()))
+    }
+
+    fn seed_from_u64(seed: u64) -> Pcg32 {
+        Pcg32::new(seed, hash(seed))
+    }
+}
+
+//Per-thread generator behind every random decision made while tracing a path.
+//Unlike rand::thread_rng it can be reseeded, so a path can be traced again with
+//exactly the same random numbers (see gradient.rs), and a seeded render gives every
+//sample its own sequence (see sample_seed).
+thread_local! {
+    static RNG: RefCell<Pcg32> = RefCell::new(Pcg32::from_entropy());
+}
+
+//Uniform in [0, 1)
+pub fn random_f64() -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+pub fn random_range(r: Range<f64>) -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen_range(r))
+}
+
+//Restart this thread's sequence from seed
+pub fn reseed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = Pcg32::seed_from_u64(seed));
+}
+
+//This thread's generator as it stands, to carry on from later with set_state. Lets
+//work be done out of order without changing the numbers anything gets.
+pub fn state() -> Pcg32 {
+    RNG.with(|rng| rng.borrow().clone())
+}
+
+pub fn set_state(state: Pcg32) {
+    RNG.with(|rng| *rng.borrow_mut() = state);
+}
+
+//splitmix64 finalizer, to turn sample coordinates into well spread seeds
+pub fn hash(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+//Seed for sample s of pixel (x, y), so a seeded render comes out the same whichever
+//thread happens to trace each sample
+pub fn sample_seed(seed: u64, x: u64, y: u64, s: u64) -> u64 {
+    hash(seed ^ hash(x ^ hash(y ^ hash(s))))
+}
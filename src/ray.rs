@@ -1,8 +1,29 @@
+use std::cell::Cell;
+
+use super::interior::InteriorStack;
 use super::vec3::{Vec3, Point3};
 
+#[derive(Clone, Copy)]
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
+    //When the ray was sent, within the camera's shutter interval. Rays scattered off
+    //a surface keep the time of the ray that hit it.
+    time: f64,
+    //Refractive objects the ray is inside. Rays scattered off a surface are given them
+    //by the integrator, so materials needn't pass them on.
+    interiors: InteriorStack,
+    //Colour channel (0, 1, 2 for red, green, blue, or the path's wavelengths when
+    //rendering spectrally) a dispersive material has narrowed the path down to. The
+    //integrator drops the others and triples this one when it happens, and passes it on
+    //like interiors.
+    channel: Option<usize>,
+    //The wavelengths the path carries, when rendering spectrally (see spectrum)
+    wavelengths: Option<[f64; 3]>,
+    //Cone round the ray standing for the pixel it came from, as its width at the origin
+    //and how much wider it gets per unit of distance, so textures know how much of
+    //themselves a hit covers. Camera rays have one; (0, 0) is a ray without.
+    cone: (f64, f64),
 }
 
 impl Ray {
@@ -10,9 +31,39 @@ impl Ray {
         Ray {
             orig: origin,
             dir: direction,
+            time: 0.0,
+            interiors: InteriorStack::new(),
+            channel: None,
+            wavelengths: None,
+            cone: (0.0, 0.0),
         }
     }
 
+    pub fn with_time(mut self, time: f64) -> Ray {
+        self.time = time;
+        self
+    }
+
+    pub fn with_interiors(mut self, interiors: InteriorStack) -> Ray {
+        self.interiors = interiors;
+        self
+    }
+
+    pub fn with_channel(mut self, channel: Option<usize>) -> Ray {
+        self.channel = channel;
+        self
+    }
+
+    pub fn with_wavelengths(mut self, wavelengths: Option<[f64; 3]>) -> Ray {
+        self.wavelengths = wavelengths;
+        self
+    }
+
+    pub fn with_cone(mut self, cone: (f64, f64)) -> Ray {
+        self.cone = cone;
+        self
+    }
+
     pub fn origin(&self) -> Point3 {
         self.orig
     }
@@ -21,7 +72,79 @@ impl Ray {
         self.dir
     }
 
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn interiors(&self) -> InteriorStack {
+        self.interiors
+    }
+
+    pub fn channel(&self) -> Option<usize> {
+        self.channel
+    }
+
+    pub fn wavelengths(&self) -> Option<[f64; 3]> {
+        self.wavelengths
+    }
+
+    pub fn cone(&self) -> (f64, f64) {
+        self.cone
+    }
+
+    //Width of the ray's cone at at(t), across the ray
+    pub fn cone_width(&self, t: f64) -> f64 {
+        let (width, spread) = self.cone;
+        if spread == 0.0 { width } else { width + spread * t * self.dir.length() }
+    }
+
     pub fn at(&self, t: f64) -> Point3 {
         self.orig + t * self.dir
     }
-}
\ No newline at end of file
+}
+
+//How far along a ray hits count: from epsilon, so a ray leaving a surface doesn't find
+//that same surface again through rounding error (shadow acne), out to max_distance
+#[derive(Clone, Copy, PartialEq)]
+pub struct RayLimits {
+    pub epsilon: f64,
+    pub max_distance: f64,
+}
+
+impl Default for RayLimits {
+    fn default() -> RayLimits {
+        RayLimits { epsilon: 0.001, max_distance: f64::INFINITY }
+    }
+}
+
+impl RayLimits {
+    //Epsilon for a scene reaching extent units from the origin. Rounding error grows
+    //with the size of the coordinates, so scenes past 10^4 units get a proportionally
+    //bigger epsilon, and ones within a unit of the origin a smaller one, so contact
+    //shadows don't go missing. Anything in between keeps 0.001.
+    pub fn scaled_epsilon(extent: f64) -> f64 {
+        let scale = if extent > 1.0e4 {
+            extent / 1.0e4
+        } else if extent > 0.0 && extent < 1.0 {
+            extent
+        } else {
+            1.0
+        };
+        RayLimits::default().epsilon * scale
+    }
+}
+
+thread_local! {
+    static LIMITS: Cell<RayLimits> = const { Cell::new(RayLimits { epsilon: 0.001, max_distance: f64::INFINITY }) };
+}
+
+//Use limits for rays traced on this thread from now on. The renderer sets them at the
+//start of every sample, so they needn't be passed all the way down.
+pub fn set_limits(limits: RayLimits) {
+    LIMITS.with(|l| l.set(limits));
+}
+
+//The limits rays traced on this thread should keep to
+pub fn limits() -> RayLimits {
+    LIMITS.with(|l| l.get())
+}
@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord};
+use super::material::Scatter;
+use super::ray::{self, Ray};
+use super::sampler::next_2d;
+use super::vec3::{Point3, Vec3};
+
+
+
+//Half the thickness given to rects' bounding boxes, which would be flat otherwise
+const PAD: f64 = 0.0001;
+
+//Rectangle in the plane z = k, spanning [x0, x1] x [y0, y1]. Faces +z unless flipped.
+pub struct XyRect {
+    rect: AxisRect,
+}
+
+//Rectangle in the plane y = k, spanning [x0, x1] x [z0, z1]. Faces +y unless flipped.
+pub struct XzRect {
+    rect: AxisRect,
+}
+
+//Rectangle in the plane x = k, spanning [y0, y1] x [z0, z1]. Faces +x unless flipped.
+pub struct YzRect {
+    rect: AxisRect,
+}
+
+impl XyRect {
+    pub fn new(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, mat: Arc<dyn Scatter>) -> XyRect {
+        XyRect { rect: AxisRect::new([0, 1, 2], (x0, x1), (y0, y1), k, mat) }
+    }
+
+    //Facing -z instead
+    pub fn flipped(mut self) -> XyRect {
+        self.rect.flip = true;
+        self
+    }
+}
+
+impl XzRect {
+    pub fn new(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, mat: Arc<dyn Scatter>) -> XzRect {
+        XzRect { rect: AxisRect::new([0, 2, 1], (x0, x1), (z0, z1), k, mat) }
+    }
+
+    //Facing -y instead
+    pub fn flipped(mut self) -> XzRect {
+        self.rect.flip = true;
+        self
+    }
+}
+
+impl YzRect {
+    pub fn new(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, mat: Arc<dyn Scatter>) -> YzRect {
+        YzRect { rect: AxisRect::new([1, 2, 0], (y0, y1), (z0, z1), k, mat) }
+    }
+
+    //Facing -x instead
+    pub fn flipped(mut self) -> YzRect {
+        self.rect.flip = true;
+        self
+    }
+}
+
+//What the three rects have in common: a rectangle spanning a along axes[0] and b
+//along axes[1], at axes[2] = k. u runs along a and v along b.
+struct AxisRect {
+    axes: [usize; 3],
+    a: (f64, f64),
+    b: (f64, f64),
+    k: f64,
+    flip: bool,
+    mat: Arc<dyn Scatter>,
+}
+
+impl AxisRect {
+    fn new(axes: [usize; 3], a: (f64, f64), b: (f64, f64), k: f64, mat: Arc<dyn Scatter>) -> AxisRect {
+        AxisRect {
+            axes,
+            a: (a.0.min(a.1), a.0.max(a.1)),
+            b: (b.0.min(b.1), b.0.max(b.1)),
+            k,
+            flip: false,
+            mat,
+        }
+    }
+
+    fn unit(axis: usize) -> Vec3 {
+        let mut e = Vec3::new(0.0, 0.0, 0.0);
+        e[axis] = 1.0;
+        e
+    }
+
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let [ia, ib, ik] = self.axes;
+        let (origin, direction) = (r.origin(), r.direction());
+
+        //Parallel rays never cross the plane (and give t = inf or NaN, both rejected)
+        let t = (self.k - origin[ik]) / direction[ik];
+        if !(t >= t_min && t <= t_max) {
+            return None;
+        }
+
+        let a = origin[ia] + t * direction[ia];
+        let b = origin[ib] + t * direction[ib];
+        if a < self.a.0 || a > self.a.1 || b < self.b.0 || b > self.b.1 {
+            return None;
+        }
+
+        let u = (a - self.a.0) / (self.a.1 - self.a.0);
+        let v = (b - self.b.0) / (self.b.1 - self.b.0);
+        let sign = if self.flip { -1.0 } else { 1.0 };
+        let outward_normal = sign * Self::unit(ik);
+
+        let mut rec = HitRecord::new(r, t, outward_normal, Arc::clone(&self.mat), u, v);
+        rec.tangent = Self::unit(ia);
+        rec.bitangent = Self::unit(ib);
+        rec.uv_size = ((self.a.1 - self.a.0) * (self.b.1 - self.b.0)).abs().sqrt();
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let [ia, ib, ik] = self.axes;
+        let mut min = Point3::new(0.0, 0.0, 0.0);
+        let mut max = Point3::new(0.0, 0.0, 0.0);
+        (min[ia], max[ia]) = self.a;
+        (min[ib], max[ib]) = self.b;
+        (min[ik], max[ik]) = (self.k - PAD, self.k + PAD);
+        Some(Aabb::new(min, max))
+    }
+
+    //Uniform over the area, so per unit solid angle it's distance^2 / (cos * area)
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        let Some(rec) = self.hit(&Ray::new(origin, direction), ray::limits().epsilon, f64::INFINITY) else {
+            return 0.0;
+        };
+        let area = (self.a.1 - self.a.0) * (self.b.1 - self.b.0);
+        let distance_squared = rec.t * rec.t * direction.dot(direction);
+        let cosine = (direction.dot(rec.normal) / direction.length()).abs();
+        distance_squared / (cosine * area)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        let [ia, ib, ik] = self.axes;
+        let mut p = Point3::new(0.0, 0.0, 0.0);
+        let (u, v) = next_2d();
+        p[ia] = self.a.0 + u * (self.a.1 - self.a.0);
+        p[ib] = self.b.0 + v * (self.b.1 - self.b.0);
+        p[ik] = self.k;
+        p - origin
+    }
+}
+
+impl Hit for XyRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.rect.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.rect.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.rect.pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        self.rect.random_direction(origin)
+    }
+}
+
+impl Hit for XzRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.rect.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.rect.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.rect.pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        self.rect.random_direction(origin)
+    }
+}
+
+impl Hit for YzRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.rect.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.rect.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.rect.pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        self.rect.random_direction(origin)
+    }
+}
@@ -0,0 +1,361 @@
+use std::sync::Arc;
+
+use super::bdpt;
+use super::hit::{OccludingHit, Hit, HitRecord, World};
+use super::integrator::{IntegratorKind, Tracer};
+use super::propagation::Propagated;
+use super::light::{self, Light, LightGroups};
+use super::light_tree;
+use super::photon_map;
+use super::random::random_f64;
+use super::ray::{self, Ray};
+use super::restir;
+use super::sampler::decision_2d;
+use super::scene::Scene;
+use super::spectrum::upsample;
+use super::stats::{self, Counter};
+use super::vec3::{Vec3, Point3, Color};
+
+
+#[allow(dead_code)]
+fn lambertian_hardcoded(rec: &HitRecord, scene: &Scene, depth: u64) -> Color{
+    //Lambertian reflection: Produce random points on the surface of the unit ball 
+        //offset along the surface normal; has a distribution of cos(phi) where phi is the angle
+        //from the normal. Without normalizing the final term we get a cos^3(phi) dist corresponding 
+        //to picking directions on the hemisphere with high prob close to the normal, and a lower
+        //prob of scattering rays at grazing angles. Shallow angles contribute less to color so wouldn't care
+        //but we want the lambertian dist.
+        //lambertian dist is more uniform though still has higher prob closer to norm
+        //than further. Get less pronounced shadows, esp in gaps between objects, as less light 
+        //bounces straight up i.e. close to normal.
+        //Also means diffuse surfaces become brighter as more light bounces towards camera.
+        let target = rec.p + rec.normal + Vec3::random_in_unit_sphere().normalized();
+
+        //Alternate method - hemispherical scattering, older method that just randomly scatters
+        //rays off the hit point, uniform distribution
+        //Gets you lighter surfaces + less prominent shadows because dist uniform so more rays going 
+        //towards camera, also not bouncing straight up to other object as much.
+        //let target = rec.p + Vec3::random_in_hemisphere(rec.normal);
+
+        let r = Ray::new(rec.p, target-rec.p);
+        //Hit an object; return the face normal of the object
+        0.5 * ray_color(&r, scene, depth - 1)
+}
+
+//Light reaching p from the first light that can see it. Area lights get one shadow
+//ray to a random point on them, so over a pixel's samples a point in a penumbra is
+//lit in proportion to how much of the light it can see.
+//n is None inside a medium, where light can come from any direction. Only lights
+//linked to groups count.
+fn is_lit<'a>(p: Point3, n: Option<Vec3>, groups: LightGroups, world: &World, lights: impl IntoIterator<Item = &'a Box<dyn Light>>, time: f64) -> Option<Color> {
+    for light in lights.into_iter().filter(|light| light.groups().overlaps(groups)) {
+        let lpos = light.sample_point();
+        if n.is_some_and(|n| n.dot(lpos - p) < 0.0) {
+            continue;
+        }
+        else{
+            //TODO don't need to normalize here?
+            let ray = Ray::new(p, (lpos - p).normalized()).with_time(time);
+            stats::count(Counter::ShadowRays);
+            let limits = ray::limits();
+            if !world.occluding_hit(&ray, lpos, limits.epsilon, limits.max_distance){
+                return Some(light.attenuation((lpos - p).length()) * light.diffuse());
+            }
+        }
+    }
+    None
+}
+
+//Light arriving at rec straight from the scene's lights, one sample from each, plus one
+//from an emitter picked at random, through the material's BSDF towards wo. Nothing for
+//materials that can't be evaluated for a given direction. With mis the emitter sample is
+//weighted against the BSDF finding the same light, for paths that go on to scatter.
+//Caustics from the photon map, if there is one, come in with the rest.
+fn direct_light(rec: &HitRecord, scene: &Scene, wo: Vec3, time: f64, mis: bool) -> Color {
+    let mut total = Color::new(0.0, 0.0, 0.0);
+    if rec.mat.eval(rec, wo, rec.normal).is_none() {
+        return total;
+    }
+    let limits = ray::limits();
+
+    //Point and area lights can't be hit by scattered rays, so this is all the light
+    //they give. Lights not linked to the surface give none. With resampling, one of
+    //them picked for the lot stands in for them all; with a light tree, a few do.
+    let caustics = photon_map::current();
+    let mut light_from = |light: &dyn Light, weight: f64| {
+        let lpos = light.sample_point();
+        let to_light = lpos - rec.p;
+        let Some((f, _)) = rec.mat.eval(rec, wo, to_light) else { return };
+        if f.near_zero() {
+            return;
+        }
+        let ray = Ray::new(rec.p, to_light.normalized()).with_time(time);
+        stats::count(Counter::ShadowRays);
+        //Dimmed and tinted by anything translucent in the way. With a photon map, light
+        //through glass comes from its photons instead, so anything at all blocks it.
+        let shadow = match caustics {
+            Some(_) if scene.world.hit(&ray, limits.epsilon, limits.max_distance).is_some_and(|hit| ray.direction().dot(lpos - hit.p) > 0.0) => {
+                Color::new(0.0, 0.0, 0.0)
+            }
+            Some(_) => Color::new(1.0, 1.0, 1.0),
+            None => scene.world.transmittance(&ray, lpos, limits.epsilon, limits.max_distance),
+        };
+        if !shadow.near_zero() {
+            total += weight * light.attenuation(to_light.length()) * shadow * f * light.diffuse();
+        }
+    };
+    match (restir::current(), light_tree::current()) {
+        (Some(resampling), _) if !scene.lights.is_empty() => total += resampling.direct_light(rec, scene, wo, time),
+        (_, Some(tree)) => {
+            let normal = if rec.mat.in_medium() { None } else { Some(rec.normal) };
+            for (light, weight) in tree.picks(rec.p, normal, rec.light_groups) {
+                light_from(scene.lights[light].as_ref(), weight);
+            }
+        }
+        _ => {
+            for light in scene.lights.iter().filter(|light| light.lights(rec)) {
+                light_from(light.as_ref(), 1.0);
+            }
+        }
+    }
+
+    if !scene.emitters.is_empty() {
+        let count = scene.emitters.len();
+        let pick = decision_2d().map_or_else(random_f64, |(u, _)| u);
+        let emitter = &scene.emitters[((pick * count as f64) as usize).min(count - 1)];
+        let wi = emitter.random_direction(rec.p);
+        //Emitters that can't be sampled from here are left to be found by scattering
+        if emitter.pdf_value(rec.p, wi) > 0.0 {
+            if let Some((f, bsdf_pdf)) = rec.mat.eval(rec, wo, wi) {
+                let ray = Ray::new(rec.p, wi).with_time(time);
+                stats::count(Counter::Rays);
+                if let Some(light_rec) = scene.world.hit(&ray, limits.epsilon, limits.max_distance) {
+                    let light_pdf = emitter_pdf(scene, rec.p, wi);
+                    let weight = if mis { power_heuristic(light_pdf, bsdf_pdf) / light_pdf } else { 1.0 / light_pdf };
+                    total += weight * f * light_rec.mat.emitted(&light_rec);
+                }
+            }
+        }
+    }
+    if let Some(map) = caustics {
+        total += map.caustics(rec, wo);
+    }
+    total
+}
+
+//Density of direct_light's emitter sample picking direction from origin
+fn emitter_pdf(scene: &Scene, origin: Point3, direction: Vec3) -> f64 {
+    if scene.emitters.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = scene.emitters.iter().map(|e| e.pdf_value(origin, direction)).sum();
+    sum / scene.emitters.len() as f64
+}
+
+//MIS weight for a sample taken with density pdf when other could have found it too
+fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    if pdf <= 0.0 {
+        return 0.0;
+    }
+    pdf * pdf / (pdf * pdf + other * other)
+}
+
+pub fn ray_color(r: &Ray, scene: &Scene, depth: u64) -> Color {
+    path_radiance(r, scene, depth).0
+}
+
+//ray_color for a camera ray whose first hit has already been found, e.g. traced in a
+//packet. Straight space only.
+pub fn ray_color_from(r: &Ray, hit: Option<HitRecord>, scene: &Scene, depth: u64) -> Color {
+    radiance_from(r, hit, scene, depth, Tracer::new(IntegratorKind::Hybrid))
+}
+
+//ray_color along with the length of the path, from r's origin to the last surface
+//it hit before escaping to the background. Lengths are straight-line distances
+//between bounces, so they're only approximate in curved space.
+pub fn path_radiance(r: &Ray, scene: &Scene, depth: u64) -> (Color, f64) {
+    radiance(r, scene, depth, Tracer::new(IntegratorKind::Hybrid))
+}
+
+//path_radiance, shading each hit the way tracer does
+pub fn radiance(r: &Ray, scene: &Scene, depth: u64, tracer: Tracer) -> (Color, f64) {
+    light::set_ambient(scene.ambient);
+    if bidirectional(r, scene, tracer) {
+        if depth == 0 {
+            return (Color::new(0.0, 0.0, 0.0), 0.0);
+        }
+        stats::count(Counter::Rays);
+        let limits = ray::limits();
+        return bdpt::radiance(r, scene.world.hit(r, limits.epsilon, limits.max_distance), scene, depth);
+    }
+    trace(r, scene, depth, None, tracer)
+}
+
+//ray_color_from, shading each hit the way tracer does
+pub fn radiance_from(r: &Ray, hit: Option<HitRecord>, scene: &Scene, depth: u64, tracer: Tracer) -> Color {
+    light::set_ambient(scene.ambient);
+    if depth == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    if bidirectional(r, scene, tracer) {
+        return bdpt::radiance(r, hit, scene, depth).0;
+    }
+    shade(r, r.origin(), hit, scene, depth, None, tracer).0
+}
+
+//Whether r is to be traced bidirectionally, which takes straight space and RGB; the
+//rest of the time the bidirectional integrator is hybrid
+fn bidirectional(r: &Ray, scene: &Scene, tracer: Tracer) -> bool {
+    tracer.kind == IntegratorKind::Bidirectional && scene.space.is_none() && r.wavelengths().is_none()
+}
+
+//bsdf_pdf is the pdf the last bounce picked r with, if that bounce also sampled the
+//emitters directly. Any emitter r finds is then weighted against the chance of the
+//direct sample having found it, so its light isn't counted twice.
+fn trace(r: &Ray, scene: &Scene, depth: u64, bsdf_pdf: Option<f64>, tracer: Tracer) -> (Color, f64) {
+    if depth == 0{
+        //Exceeded ray bounce limit, no more light is generated
+        return (Color::new(0.0, 0.0, 0.0), 0.0);
+    }
+    let origin = r.origin();
+    stats::count(Counter::Rays);
+
+    //t_min set to epsilon (0.001 unless the settings say) because some rays will hit the
+    //object they're reflecting off at -0.0000001 or 0.00000001 or whatever floating point
+    //approximation the sphere intersector gives us, rather than t = 0. Without the correction
+    //we get shadow acne where the shapes have black spots because hitting v.near 0 and then
+    //get highly absorbed. i.e. ignore hits v. near 0
+    //In curved space the ray that reaches the surface isn't the one that set out
+    let limits = ray::limits();
+    let (r, hit) = match &scene.space {
+        None => (*r, scene.world.hit(r, limits.epsilon, limits.max_distance)),
+        Some(space) => match space.propagate(r, &scene.world) {
+            Propagated::Hit(segment, rec) => (segment.with_interiors(r.interiors()), Some(rec)),
+            Propagated::Escaped(out) => (out.with_interiors(r.interiors()), scene.world.hit(&out, limits.epsilon, limits.max_distance)),
+            Propagated::Absorbed => return (Color::new(0.0, 0.0, 0.0), 0.0),
+        },
+    };
+    shade(&r, origin, hit, scene, depth, bsdf_pdf, tracer)
+}
+
+//An RGB colour met along r as what r carries: a spectrum at r's wavelengths when
+//rendering spectrally, otherwise just the colour
+fn carried(c: Color, r: &Ray) -> Color {
+    match r.wavelengths() {
+        Some(wavelengths) => upsample(c, wavelengths),
+        None => c,
+    }
+}
+
+//The rest of trace, once r has found hit (or not). origin is where the ray set out
+//from, which in curved space isn't r's origin.
+fn shade(r: &Ray, origin: Point3, hit: Option<HitRecord>, scene: &Scene, depth: u64, bsdf_pdf: Option<f64>, tracer: Tracer) -> (Color, f64) {
+    match hit {
+        Some(rec) => {
+            //Light coming back along r is dimmed by whatever r is travelling through
+            let transmittance = r.interiors().transmittance((rec.p - r.origin()).length());
+            let (color, length) = shade_hit(r, origin, rec, scene, depth, bsdf_pdf, tracer);
+            (carried(transmittance, r) * color, length)
+        }
+        None => (carried(scene.background.color(r), r), 0.0),
+    }
+}
+
+fn shade_hit(r: &Ray, origin: Point3, mut rec: HitRecord, scene: &Scene, depth: u64, bsdf_pdf: Option<f64>, tracer: Tracer) -> (Color, f64) {
+    Arc::clone(&rec.mat).perturb(r, &mut rec);
+    let length = (rec.p - origin).length();
+
+    //The boundary of an object inside something with a higher priority isn't really
+    //there: the ray carries on as it was, but is now inside (or outside) the object too
+    let interior = rec.mat.interior();
+    if let Some(interior) = interior.filter(|i| r.interiors().without(i.id).outranks(i.priority)) {
+        let through = Ray::new(rec.p, r.direction()).with_time(r.time())
+            .with_interiors(r.interiors().crossing(interior, rec.front_face))
+            .with_channel(r.channel())
+            .with_wavelengths(r.wavelengths())
+            .with_cone(r.cone());
+        let (color, rest) = trace(&through, scene, depth, bsdf_pdf, tracer);
+        return (color, length + rest);
+    }
+
+    //Glowing surfaces show up whether or not a point light can see them
+    let mut emitted = carried(rec.mat.emitted(&rec), r);
+    if let Some(bsdf_pdf) = bsdf_pdf.filter(|_| !emitted.near_zero()) {
+        emitted = power_heuristic(bsdf_pdf, emitter_pdf(scene, r.origin(), r.direction())) * emitted;
+    }
+
+    //Check if the point is occluded from all light sources.
+    //A scene with no lights at all is lit only by the background and emitters.
+    //Resampling leaves this out, as it would mean a shadow ray per light at worst.
+    let gated = matches!(tracer.kind, IntegratorKind::Hybrid | IntegratorKind::Bidirectional) && restir::current().is_none();
+    if gated && scene.lights.iter().any(|light| light.gates() && light.lights(&rec)) {
+        let normal = if rec.mat.in_medium() { None } else { Some(rec.normal) };
+        //With a light tree, only the lights it picks are tried, and none of those that
+        //gate leaves the point alone
+        let lit = match light_tree::current() {
+            Some(tree) => {
+                let picked: Vec<_> = tree.picks(rec.p, normal, rec.light_groups).map(|(light, _)| &scene.lights[light])
+                    .filter(|light| light.gates()).collect();
+                if picked.is_empty() { Some(Color::new(0.0, 0.0, 0.0)) } else { is_lit(rec.p, normal, rec.light_groups, &scene.world, picked, r.time()) }
+            }
+            None => is_lit(rec.p, normal, rec.light_groups, &scene.world, scene.lights.iter().filter(|light| light.gates()), r.time()),
+        };
+        if lit.is_none() {
+            return (emitted, length);
+        }
+    }
+
+
+    //Next-event estimation needs straight shadow rays, so not in curved space. Only the
+    //hybrid integrator both samples lights and scatters towards them, so needs MIS.
+    let sampled_directly = scene.space.is_none() && tracer.kind != IntegratorKind::Path;
+    let mis = sampled_directly && matches!(tracer.kind, IntegratorKind::Hybrid | IntegratorKind::Bidirectional);
+    let wo = (-1.0) * r.direction().normalized();
+    let direct = if sampled_directly { carried(direct_light(&rec, scene, wo, r.time(), mis), r) } else { Color::new(0.0, 0.0, 0.0) };
+
+    //Whitted only follows perfect (or at least unevaluable) reflection and refraction,
+    //direct lighting doesn't follow anything
+    let follow = match tracer.kind {
+        IntegratorKind::Bidirectional | IntegratorKind::Hybrid | IntegratorKind::Path => true,
+        IntegratorKind::Whitted => rec.mat.eval(&rec, wo, rec.normal).is_none(),
+        IntegratorKind::Direct => false,
+    };
+    if !follow {
+        return (emitted + direct, length);
+    }
+
+    //lambertian_hardcoded(&rec, scene, depth)
+    if let Some(record) = rec.mat.scatter_record(r.origin(), &scene.lights, &scene.world, r, &rec) {
+        let next_pdf = record.pdf.filter(|_| mis && !scene.emitters.is_empty());
+        let (attenuation, scattered) = (record.attenuation, record.ray);
+        //Going through the surface takes the ray into or out of what it encloses
+        let interiors = match interior {
+            Some(interior) if scattered.direction().dot(rec.normal) < 0.0 => r.interiors().crossing(interior, rec.front_face),
+            _ => r.interiors(),
+        };
+        let mut attenuation = if rec.mat.spectral_attenuation() { attenuation } else { carried(attenuation, r) };
+        //Narrowed to one channel: the path carries on with only that one's light,
+        //tripled as it was picked one time in three
+        if let (None, Some(c)) = (r.channel(), scattered.channel()) {
+            let kept = 3.0 * attenuation[c];
+            attenuation = Color::new(0.0, 0.0, 0.0);
+            attenuation[c] = kept;
+        }
+        let scattered = scattered.with_interiors(interiors)
+            .with_channel(scattered.channel().or(r.channel()))
+            .with_wavelengths(r.wavelengths());
+        stats::count(Counter::Bounces);
+        let (mut color, rest) = trace(&scattered, scene, depth-1, next_pdf, tracer);
+        //Light from further along the path is held down, if asked, to keep the rare very
+        //bright path from leaving a lone white pixel. Biased: the image gets darker.
+        if let Some(clamp) = tracer.clamp {
+            let brightest = color[0].max(color[1]).max(color[2]);
+            if brightest > clamp {
+                color = (clamp / brightest) * color;
+            }
+        }
+        (emitted + direct + /*light_color * */ attenuation * color, length + rest)
+    } else{
+        (emitted + direct, length)
+    }
+}
@@ -0,0 +1,376 @@
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+
+use super::aov::Aov;
+use super::camera::Camera;
+use super::checkpoint::Checkpoint;
+use super::filter::FilterSampler;
+use super::hit::Hit;
+use super::image::Image;
+use super::integrator::{Integrator, IntegratorKind, Tracer};
+use super::light_tree::{self, LightTree};
+use super::packet::{RayPacket, PACKET};
+use super::photon_map::{self, PhotonMap};
+use super::random::{self, random_f64, reseed, sample_seed};
+use super::ray::{self, Ray, RayLimits};
+use super::restir::{self, Resampling};
+use super::sampler::{self, next_2d, start_sample, Independent, Sampler};
+use super::scene::Scene;
+use super::spectrum::{self, sample_wavelengths};
+use super::stats::{self, Counter};
+use super::scheduler::{Adaptive, CancelToken, Progress, ProgressReport, Scheduler, TileAccum};
+use super::vec3::Color;
+
+
+
+//How to render a scene, as opposed to what's in it
+#[derive(Clone)]
+pub struct RenderSettings {
+    pub width: u64,
+    pub height: u64,
+    pub samples_per_pixel: u64,
+    //Bounces before a path is given up on
+    pub max_depth: u64,
+    //Seed every sample from this, so the image comes out the same every time whatever
+    //the number of threads. None picks fresh random numbers each render.
+    pub seed: Option<u64>,
+    //Square tiles of this many pixels are the unit of work
+    pub tile_size: u64,
+    //Samples per pixel each tile gets at a time, so the whole image fills in together
+    pub pass_samples: u64,
+    //Stop sampling pixels once they've converged, making samples_per_pixel a maximum
+    pub adaptive: Option<Adaptive>,
+    //Where samples go in the pixel, on the lens and on the lights
+    pub sampler: Arc<dyn Sampler>,
+    //Find where a pixel's camera rays first hit in packets of PACKET, which is quicker
+    //in scenes of many small objects. The image is the same either way, except that
+    //random choices made in finding hits (in participating media) come out differently.
+    pub packets: bool,
+    //Follow wavelengths of light rather than red, green and blue (see spectrum): slower
+    //and noisier, but dispersion comes out as smooth rainbows
+    pub spectral: bool,
+    //How the light along each camera ray is worked out
+    pub integrator: Arc<dyn Integrator>,
+    //How samples are weighted by where they are in and around the pixel, None for a box
+    //filter (see FilterKind::sampler)
+    pub filter: Option<Arc<FilterSampler>>,
+    //Camera rays that miss everything bring back nothing instead of the background, for
+    //compositing over something else with Aov::Alpha as the alpha. The background still
+    //lights the scene. Straight space only.
+    pub transparent: bool,
+    //Hits closer than this to where a ray starts are ignored, so rays leaving a surface
+    //don't hit it again (see RayLimits::scaled_epsilon for one to suit the scene)
+    pub epsilon: f64,
+    //Nothing further along a ray than this is hit
+    pub max_distance: f64,
+    //Light from point and area lights found by resampling (see restir) rather than
+    //with a shadow ray to each one, for scenes with hundreds of them. Hybrid, Whitted
+    //and direct integrators only.
+    pub resampling: Option<Arc<Resampling>>,
+    //Light from a few point and area lights picked by this tree (built over the
+    //scene's lights) at each shading point, rather than from every one
+    pub light_tree: Option<Arc<LightTree>>,
+    //Caustics from point and area lights, added wherever they're sampled directly (so
+    //not with the path integrator)
+    pub photons: Option<Arc<PhotonMap>>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 256,
+            height: 144,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            seed: None,
+            tile_size: 16,
+            pass_samples: 10,
+            adaptive: None,
+            sampler: Arc::new(Independent),
+            packets: false,
+            spectral: false,
+            integrator: Arc::new(Tracer::new(IntegratorKind::Hybrid)),
+            filter: None,
+            transparent: false,
+            epsilon: RayLimits::default().epsilon,
+            max_distance: RayLimits::default().max_distance,
+            resampling: None,
+            light_tree: None,
+            photons: None,
+        }
+    }
+}
+
+impl RenderSettings {
+    //What the camera should be built with to fill the image
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 / self.height as f64
+    }
+
+    pub fn limits(&self) -> RayLimits {
+        RayLimits { epsilon: self.epsilon, max_distance: self.max_distance }
+    }
+}
+
+//Renders scenes with the tile scheduler on rayon's thread pool:
+//
+//    let settings = RenderSettings { width: 400, height: 225, ..RenderSettings::default() };
+//    let scene = gallery::build(SceneName::Spheres, settings.aspect_ratio());
+//    let image = Renderer::new(settings).render(&scene);
+//
+//Set rayon's global pool up first to control the number of threads.
+#[derive(Clone)]
+pub struct Renderer {
+    settings: RenderSettings,
+    cancel: CancelToken,
+    resume: Option<Arc<Checkpoint>>,
+    tiles: Option<Range<usize>>,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Renderer {
+        Renderer { settings, cancel: CancelToken::new(), resume: None, tiles: None }
+    }
+
+    //Stop early once token is cancelled, returning what there is so far
+    pub fn with_cancel(mut self, token: CancelToken) -> Renderer {
+        self.cancel = token;
+        self
+    }
+
+    //Have render and render_with_progress carry on from where checkpoint got to, which
+    //should have passed Checkpoint::check with these settings
+    pub fn with_resume(mut self, checkpoint: Checkpoint) -> Renderer {
+        self.resume = Some(Arc::new(checkpoint));
+        self
+    }
+
+    //Only render these tiles, see Scheduler::with_tiles
+    pub fn with_tiles(mut self, tiles: Range<usize>) -> Renderer {
+        self.tiles = Some(tiles);
+        self
+    }
+
+    //Whether the render was stopped early
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    //Scheduler for the image these settings describe, for callers that want to drive
+    //the render themselves (e.g. with Scheduler::stream) using sample
+    pub fn scheduler(&self) -> Scheduler {
+        let s = &self.settings;
+        let mut scheduler = Scheduler::new(s.width, s.height, s.tile_size, s.samples_per_pixel, s.pass_samples)
+            .with_cancel(self.cancel.clone());
+        if let Some(adaptive) = s.adaptive {
+            scheduler = scheduler.with_adaptive(adaptive);
+        }
+        if let Some(tiles) = &self.tiles {
+            scheduler = scheduler.with_tiles(tiles.clone());
+        }
+        scheduler
+    }
+
+    //Sample number s of the pixel at image coords (x, y), y counted from the top
+    pub fn sample(&self, scene: &Scene, x: u64, y: u64, s: u64) -> Color {
+        self.start_sample(x, y, s);
+        stats::count(Counter::CameraRays);
+        let (r, weight) = self.camera_ray(scene, x, y);
+        let r = self.with_wavelengths(r);
+        let integrator = &self.settings.integrator;
+        let radiance = if self.settings.transparent && scene.space.is_none() {
+            stats::count(Counter::Rays);
+            match scene.world.hit(&r, self.settings.epsilon, self.settings.max_distance) {
+                Some(hit) => integrator.radiance_from(&r, Some(hit), scene, self.settings.max_depth),
+                None => Color::new(0.0, 0.0, 0.0),
+            }
+        } else {
+            integrator.radiance(&r, scene, self.settings.max_depth)
+        };
+        weight * self.to_rgb(&r, radiance)
+    }
+
+    pub fn render(&self, scene: &Scene) -> Image {
+        self.render_with_progress(scene, |_| {})
+    }
+
+    //progress is called from the worker threads each time a tile gets another pass
+    pub fn render_with_progress<P>(&self, scene: &Scene, progress: P) -> Image
+    where
+        P: Fn(&Progress) + Sync,
+    {
+        self.render_resumable(scene, progress).0
+    }
+
+    //render_with_progress, sending a report down reports each time (to another thread
+    //showing progress, say). Cancelling the renderer's CancelToken stops it early.
+    pub fn render_with_reports(&self, scene: &Scene, reports: Sender<ProgressReport>) -> Image {
+        self.render_with_progress(scene, |progress| {
+            //Nobody listening is no reason to stop
+            let _ = reports.send(progress.report());
+        })
+    }
+
+    //render_with_progress, also handing back where the render got to, for a Checkpoint
+    //to carry on from later (with more samples, or after being cancelled)
+    pub fn render_resumable<P>(&self, scene: &Scene, progress: P) -> (Image, Vec<TileAccum>)
+    where
+        P: Fn(&Progress) + Sync,
+    {
+        let scheduler = match &self.resume {
+            Some(checkpoint) => self.scheduler().with_resume(checkpoint.tiles.clone()),
+            None => self.scheduler(),
+        };
+        self.prepare(scene);
+        let tiles = scheduler.accumulate(|x, y, samples, out| self.samples(scene, x, y, samples, out), progress);
+        let image = Image { width: self.settings.width, height: self.settings.height, pixels: scheduler.averaged(&tiles) };
+        (image, tiles)
+    }
+
+    //An auxiliary buffer for scene, with the same camera rays as render. Values are raw
+    //(e.g. depth in scene units), see Aov::encode for 8-bit formats.
+    pub fn render_aov(&self, scene: &Scene, aov: Aov) -> Image {
+        let (width, height) = (self.settings.width, self.settings.height);
+        let pixels = self.scheduler().run(|x, y, s| {
+            //Negative filter weights would make no sense for e.g. depth, so AOVs only
+            //take where the filter puts the rays
+            aov.sample(&self.camera_sample(scene, x, y, s).0, scene)
+        }, |_| {});
+        Image { width, height, pixels }
+    }
+
+    //Work out anything the samples need from the whole image before any are taken (for
+    //now, resampling's picks). render does this itself; call it before samples otherwise.
+    pub fn prepare(&self, scene: &Scene) {
+        if let Some(resampling) = &self.settings.resampling {
+            resampling.prepare(self, scene);
+        }
+    }
+
+    //The samples in range for the pixel at (x, y), handed to out in order, for use with
+    //Scheduler::run_batched. With packets on they're traced a packet at a time (and any
+    //left over one by one). Each sample's random numbers are put back as they were
+    //after its camera ray before it's shaded, so it's as if it had been traced alone.
+    pub fn samples(&self, scene: &Scene, x: u64, y: u64, samples: Range<u64>, out: &mut dyn FnMut(Color)) {
+        let mut s = samples.start;
+        //Curved space bends camera rays, so they can't be traced as straight packets
+        if self.settings.packets && scene.space.is_none() {
+            while s + PACKET as u64 <= samples.end {
+                let mut states = Vec::with_capacity(PACKET);
+                let rays = std::array::from_fn(|l| {
+                    self.start_sample(x, y, s + l as u64);
+                    let (r, weight) = self.camera_ray(scene, x, y);
+                    states.push((random::state(), sampler::suspend(), weight));
+                    self.with_wavelengths(r)
+                });
+                stats::add(Counter::CameraRays, PACKET as u64);
+                stats::add(Counter::Rays, PACKET as u64);
+                let hits = scene.world.hit_packet(&RayPacket::new(rays), self.settings.epsilon, [self.settings.max_distance; PACKET]);
+                for ((r, hit), (rng, suspended, weight)) in rays.iter().zip(hits).zip(states) {
+                    random::set_state(rng);
+                    sampler::resume(suspended);
+                    let radiance = match hit {
+                        None if self.settings.transparent => Color::new(0.0, 0.0, 0.0),
+                        hit => self.settings.integrator.radiance_from(r, hit, scene, self.settings.max_depth),
+                    };
+                    out(weight * self.to_rgb(r, radiance));
+                }
+                s += PACKET as u64;
+            }
+        }
+        for s in s..samples.end {
+            out(self.sample(scene, x, y, s));
+        }
+    }
+
+    //Camera ray for sample number s of the pixel at (x, y), the same one render traces,
+    //with its filter weight
+    pub fn camera_sample(&self, scene: &Scene, x: u64, y: u64, s: u64) -> (Ray, f64) {
+        self.start_sample(x, y, s);
+        self.camera_ray(scene, x, y)
+    }
+
+    //Camera ray for a sample of the pixel at (x, y), with its filter weight
+    fn camera_ray(&self, scene: &Scene, x: u64, y: u64) -> (Ray, f64) {
+        let s = &self.settings;
+        filtered_camera_ray(&scene.camera, x, y, s.width, s.height, s.filter.as_deref())
+    }
+
+    //A camera ray given its wavelengths, if rendering spectrally
+    fn with_wavelengths(&self, r: Ray) -> Ray {
+        if self.settings.spectral {
+            r.with_wavelengths(Some(sample_wavelengths(random_f64())))
+        } else {
+            r
+        }
+    }
+
+    //What a camera ray brought back as a colour
+    fn to_rgb(&self, r: &Ray, radiance: Color) -> Color {
+        match r.wavelengths() {
+            Some(wavelengths) => spectrum::to_rgb(radiance, wavelengths),
+            None => radiance,
+        }
+    }
+
+    //Set this thread up to trace sample s of the pixel at (x, y), as samples does, for
+    //callers tracing it some other way
+    pub fn start_sample(&self, x: u64, y: u64, s: u64) {
+        let settings = &self.settings;
+        seed_sample(settings.seed, x, y, s);
+        self.set_current();
+        start_sample(&settings.sampler, settings.seed.unwrap_or(0), x, y, s, settings.samples_per_pixel);
+    }
+
+    //Set this thread up to trace with the settings' ray limits, resampling, light tree
+    //and photons, for samples traced some other way than through the renderer, which
+    //seed themselves
+    pub fn set_current(&self) {
+        let settings = &self.settings;
+        ray::set_limits(settings.limits());
+        restir::set_current(settings.resampling.as_ref());
+        light_tree::set_current(settings.light_tree.as_ref());
+        photon_map::set_current(settings.photons.as_ref());
+    }
+}
+
+//Render scene with settings in one go
+pub fn render(scene: &Scene, settings: &RenderSettings) -> Image {
+    Renderer::new(settings.clone()).render(scene)
+}
+
+//With a seed, every sample starts from a seed of its own, so the image doesn't depend
+//on which thread traced what
+pub fn seed_sample(seed: Option<u64>, i: u64, y: u64, s: u64) {
+    if let Some(seed) = seed {
+        reseed(sample_seed(seed, i, y, s));
+    }
+}
+
+//Jittered camera ray through pixel (i, y) of a width x height image, y counted from the top
+pub fn camera_ray(camera: &Camera, i: u64, y: u64, width: u64, height: u64) -> Ray {
+    filtered_camera_ray(camera, i, y, width, height, None).0
+}
+
+//camera_ray with the ray placed in and around the pixel by filter (None for a box
+//filter), along with the weight its radiance should carry
+pub fn filtered_camera_ray(camera: &Camera, i: u64, y: u64, width: u64, height: u64, filter: Option<&FilterSampler>) -> (Ray, f64) {
+    //Rows count from the top of the image, camera v goes up from the bottom
+    let j = height - 1 - y;
+
+    let (random_u, random_v) = next_2d();
+    let (random_u, random_v, weight) = match filter {
+        Some(filter) => filter.sample(random_u, random_v),
+        None => (random_u, random_v, 1.0),
+    };
+
+    let u = ((i as f64) + random_u) / ((width-1) as f64);
+    let v = ((j as f64) + random_v) / ((height-1) as f64);
+
+    (camera.get_ray(u, v).with_cone(camera.pixel_cone(height)), weight)
+}
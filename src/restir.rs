@@ -0,0 +1,310 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use rayon::prelude::*;
+
+use super::hit::{Hit, HitRecord, OccludingHit};
+use super::random::{random_f64, reseed, sample_seed};
+use super::ray::{self, Ray};
+use super::renderer::Renderer;
+use super::sampler;
+use super::scene::Scene;
+use super::stats::{self, Counter};
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+//Resampled direct lighting (ReSTIR, Bitterli et al. 2020), for scenes with many point
+//and area lights. Rather than a shadow ray to every light, a shading point looks at a
+//few lights picked at random, keeps one in proportion to how much light it would give
+//if nothing were in the way, and sends a single shadow ray to that one. Before the
+//render, every pixel does this at the first thing it sees and then takes in what its
+//neighbours (and, for animation frames, the same pixel last frame) kept, so a pixel
+//benefits from many more lights than it looked at itself. Each sample's first bounce
+//starts from its pixel's pick. Emitters are sampled as before.
+//
+//Neighbours are only taken from when they see much the same surface, but what they
+//kept isn't checked for shadows again, so like the paper's biased variant it's a little
+//dark at the edges of shadows.
+pub struct Resampling {
+    //Lights looked at per shading point
+    pub candidates: usize,
+    //Neighbouring pixels each pixel takes from before the render
+    pub neighbours: usize,
+    //How far away (in pixels) neighbours can be
+    pub radius: f64,
+    //Take from the same pixel in the last image rendered, for animation frames, where
+    //the camera and scene only move a little from one to the next
+    pub temporal: bool,
+    //Each pixel's pick from the last prepare
+    grid: RwLock<Option<Arc<Grid>>>,
+    //Images prepared so far, so each one's random numbers are its own
+    frames: AtomicU64,
+}
+
+impl Resampling {
+    pub fn new(candidates: usize) -> Resampling {
+        Resampling {
+            candidates: candidates.max(1),
+            neighbours: 4,
+            radius: 5.0,
+            temporal: true,
+            grid: RwLock::new(None),
+            frames: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_neighbours(mut self, neighbours: usize, radius: f64) -> Resampling {
+        self.neighbours = neighbours;
+        self.radius = radius.max(1.0);
+        self
+    }
+
+    pub fn with_temporal(mut self, temporal: bool) -> Resampling {
+        self.temporal = temporal;
+        self
+    }
+
+    //Work out every pixel's pick for renderer's image of scene, taking from the last
+    //image's picks where they fit. Renderer::render does this itself; anything driving
+    //the render with Renderer::samples should call it first. Straight space only.
+    pub fn prepare(&self, renderer: &Renderer, scene: &Scene) {
+        let settings = renderer.settings();
+        let (width, height) = (settings.width, settings.height);
+        let previous = self.grid.read().unwrap().clone()
+            .filter(|grid| self.temporal && grid.width == width && grid.height == height);
+        if scene.space.is_some() || scene.lights.is_empty() {
+            *self.grid.write().unwrap() = None;
+            return;
+        }
+        let frame = self.frames.fetch_add(1, Ordering::Relaxed);
+        let seed = sample_seed(settings.seed.unwrap_or(0), frame, u64::MAX, u64::MAX);
+        let pixels = |f: &(dyn Fn(u64, u64) -> Option<Stored> + Sync)| -> Vec<Option<Stored>> {
+            (0..height).into_par_iter().flat_map_iter(|y| (0..width).map(move |x| f(x, y)).collect::<Vec<_>>()).collect()
+        };
+
+        //Each pixel's own pick, checked for shadow, with last frame's added in
+        let initial = pixels(&|x, y| {
+            let (rec, r) = first_hit(renderer, scene, seed, x, y)?;
+            let wo = (-1.0) * r.direction().normalized();
+            let mut reservoir = self.candidates(&rec, wo, scene);
+            if reservoir.weight_sum > 0.0 && !visible(&rec, reservoir.point, scene, r.time()) {
+                reservoir.weight_sum = 0.0;
+            }
+            let stored = Stored { reservoir, p: rec.p, normal: rec.normal, distance: (rec.p - r.origin()).length() };
+            if let Some(last) = previous.as_ref().and_then(|grid| grid.get(x, y)).filter(|last| last.fits(&stored)) {
+                //Capped so the past doesn't drown out what's there now
+                let last = last.reservoir.capped(20.0 * self.candidates as f64);
+                reservoir.merge(&last, target(&rec, wo, scene, last.light, last.point), random_f64());
+            }
+            Some(Stored { reservoir, ..stored })
+        });
+
+        //Then what the neighbours seeing the same surface picked
+        let spread = pixels(&|x, y| {
+            let own = initial[(y * width + x) as usize]?;
+            let (rec, r) = first_hit(renderer, scene, seed, x, y)?;
+            let wo = (-1.0) * r.direction().normalized();
+            let mut reservoir = own.reservoir;
+            for _ in 0..self.neighbours {
+                let (dx, dy) = sampler::to_unit_disk((random_f64(), random_f64()));
+                let (nx, ny) = (x as f64 + self.radius * dx, y as f64 + self.radius * dy);
+                if nx < 0.0 || ny < 0.0 || nx >= width as f64 || ny >= height as f64 {
+                    continue;
+                }
+                if let Some(other) = initial[ny as usize * width as usize + nx as usize].filter(|other| other.fits(&own)) {
+                    let other = other.reservoir;
+                    reservoir.merge(&other, target(&rec, wo, scene, other.light, other.point), random_f64());
+                }
+            }
+            Some(Stored { reservoir, ..own })
+        });
+
+        *self.grid.write().unwrap() = Some(Arc::new(Grid { width, height, pixels: spread }));
+    }
+
+    //Light reaching rec straight from the scene's point and area lights, towards wo,
+    //from one shadow ray to a light picked by resampling
+    pub fn direct_light(&self, rec: &HitRecord, scene: &Scene, wo: Vec3, time: f64) -> Color {
+        let mut reservoir = self.candidates(rec, wo, scene);
+        if let Some((stored, index)) = self.pixel_pick(rec) {
+            //Every sample of the pixel starts from the same pick, so it counts for less
+            //and less as the pixel gets more samples, or the image would never settle
+            let stored = stored.capped(stored.count / (index + 1) as f64);
+            reservoir.merge(&stored, target(rec, wo, scene, stored.light, stored.point), random_f64());
+        }
+        let weight = reservoir.weight();
+        if weight <= 0.0 || !visible(rec, reservoir.point, scene, time) {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        weight * unshadowed(rec, wo, scene, reservoir.light, reservoir.point)
+    }
+
+    //Pick among candidates lights at random, each kept in proportion to its light
+    fn candidates(&self, rec: &HitRecord, wo: Vec3, scene: &Scene) -> Reservoir {
+        let count = scene.lights.len();
+        let mut reservoir = Reservoir::new();
+        for _ in 0..self.candidates {
+            let light = ((random_f64() * count as f64) as usize).min(count - 1);
+            let point = scene.lights[light].sample_point();
+            let target = target(rec, wo, scene, light, point);
+            reservoir.update(light, point, target, target * count as f64, random_f64());
+        }
+        reservoir
+    }
+
+    //The pick of the pixel this sample is for, and the sample's index, the first time
+    //it's asked for in the sample (so at its first bounce) if it's for the surface the
+    //pixel saw
+    fn pixel_pick(&self, rec: &HitRecord) -> Option<(Reservoir, u64)> {
+        let (pixel, index) = sampler::position()?;
+        let first = LAST.with(|last| last.borrow_mut().replace((pixel, index)) != Some((pixel, index)));
+        if !first {
+            return None;
+        }
+        let grid = self.grid.read().unwrap().clone()?;
+        let stored = grid.get(pixel.0, pixel.1)?;
+        let here = Stored { reservoir: stored.reservoir, p: rec.p, normal: rec.normal, distance: stored.distance };
+        stored.fits(&here).then_some((stored.reservoir, index))
+    }
+}
+
+thread_local! {
+    //Resampling for the render this thread is working on, see set_current
+    static CURRENT: RefCell<Option<Arc<Resampling>>> = const { RefCell::new(None) };
+    //Sample whose pixel pick was last handed out, see Resampling::pixel_pick
+    static LAST: RefCell<Option<((u64, u64), u64)>> = const { RefCell::new(None) };
+}
+
+//Have direct light on this thread resampled with resampling (None to go back to a
+//shadow ray per light), so render's path tracer needn't be handed it
+pub fn set_current(resampling: Option<&Arc<Resampling>>) {
+    CURRENT.with(|current| *current.borrow_mut() = resampling.cloned());
+    LAST.with(|last| *last.borrow_mut() = None);
+}
+
+pub fn current() -> Option<Arc<Resampling>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+//One light sample kept out of a stream of candidates, each kept with chance in
+//proportion to its weight (weighted reservoir sampling)
+#[derive(Clone, Copy)]
+pub struct Reservoir {
+    //Index into Scene::lights, and the point on it
+    pub light: usize,
+    pub point: Point3,
+    //How much light the kept sample gives where the reservoir is for, ignoring shadows
+    pub target: f64,
+    pub weight_sum: f64,
+    //Candidates seen, including those behind reservoirs merged in
+    pub count: f64,
+}
+
+impl Reservoir {
+    pub fn new() -> Reservoir {
+        Reservoir { light: 0, point: Point3::new(0.0, 0.0, 0.0), target: 0.0, weight_sum: 0.0, count: 0.0 }
+    }
+
+    //Offer a candidate with the given resampling weight, u uniform in [0, 1)
+    pub fn update(&mut self, light: usize, point: Point3, target: f64, weight: f64, u: f64) {
+        self.weight_sum += weight;
+        self.count += 1.0;
+        if weight > 0.0 && u * self.weight_sum < weight {
+            (self.light, self.point, self.target) = (light, point, target);
+        }
+    }
+
+    //Take other in, whose kept sample gives target here
+    pub fn merge(&mut self, other: &Reservoir, target: f64, u: f64) {
+        let count = self.count;
+        self.update(other.light, other.point, target, target * other.weight() * other.count, u);
+        self.count = count + other.count;
+    }
+
+    //The same pick counting for at most count candidates
+    pub fn capped(mut self, count: f64) -> Reservoir {
+        if self.count > count {
+            self.weight_sum *= count / self.count;
+            self.count = count;
+        }
+        self
+    }
+
+    //What the kept sample's light should be multiplied by for an unbiased estimate
+    pub fn weight(&self) -> f64 {
+        if self.target <= 0.0 || self.count <= 0.0 { 0.0 } else { self.weight_sum / (self.count * self.target) }
+    }
+}
+
+impl Default for Reservoir {
+    fn default() -> Reservoir {
+        Reservoir::new()
+    }
+}
+
+//A pixel's pick, with the surface it was made for
+#[derive(Clone, Copy)]
+struct Stored {
+    reservoir: Reservoir,
+    p: Point3,
+    normal: Vec3,
+    //From the camera
+    distance: f64,
+}
+
+impl Stored {
+    //Whether other is for much the same surface, so this pick suits it too
+    fn fits(&self, other: &Stored) -> bool {
+        self.normal.dot(other.normal) > 0.9 && (self.p - other.p).length() < 0.1 * self.distance
+    }
+}
+
+struct Grid {
+    width: u64,
+    height: u64,
+    pixels: Vec<Option<Stored>>,
+}
+
+impl Grid {
+    fn get(&self, x: u64, y: u64) -> Option<Stored> {
+        if x < self.width && y < self.height { self.pixels[(y * self.width + x) as usize] } else { None }
+    }
+}
+
+//What the camera sees first through the pixel at (x, y), if it's a surface that can
+//be lit, and the camera ray that saw it
+fn first_hit(renderer: &Renderer, scene: &Scene, seed: u64, x: u64, y: u64) -> Option<(HitRecord, Ray)> {
+    //The same ray each time it's asked for, whether or not the render has a seed
+    reseed(sample_seed(seed, x, y, 0));
+    let (r, _) = renderer.camera_sample(scene, x, y, 0);
+    let limits = ray::limits();
+    let mut rec = scene.world.hit(&r, limits.epsilon, limits.max_distance)?;
+    Arc::clone(&rec.mat).perturb(&r, &mut rec);
+    rec.mat.eval(&rec, (-1.0) * r.direction(), rec.normal)?;
+    Some((rec, r))
+}
+
+//Light the light numbered light would bring to rec from point, towards wo, if nothing
+//were in the way. Nothing from lights not linked to rec.
+fn unshadowed(rec: &HitRecord, wo: Vec3, scene: &Scene, light: usize, point: Point3) -> Color {
+    let light = &scene.lights[light];
+    let to_light = point - rec.p;
+    match rec.mat.eval(rec, wo, to_light) {
+        Some((f, _)) if light.lights(rec) => light.attenuation(to_light.length()) * f * light.diffuse(),
+        _ => Color::new(0.0, 0.0, 0.0),
+    }
+}
+
+//What resampling aims for: unshadowed light, as one number
+fn target(rec: &HitRecord, wo: Vec3, scene: &Scene, light: usize, point: Point3) -> f64 {
+    unshadowed(rec, wo, scene, light, point).luminance().max(0.0)
+}
+
+fn visible(rec: &HitRecord, point: Point3, scene: &Scene, time: f64) -> bool {
+    let ray = Ray::new(rec.p, (point - rec.p).normalized()).with_time(time);
+    stats::count(Counter::ShadowRays);
+    let limits = ray::limits();
+    !scene.world.occluding_hit(&ray, point, limits.epsilon, limits.max_distance)
+}
@@ -0,0 +1,314 @@
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use clap::ValueEnum;
+
+use super::blue_noise;
+use super::random::{hash, random_f64};
+
+
+
+//Where a pixel's samples go in the sample space. Sample s of n for a pixel asks for
+//2D points dimension by dimension: the position in the pixel first, then the lens, then
+//one for each light it samples along the path. Spreading each dimension's n points
+//evenly, rather than independently at random, makes the noise drop faster.
+pub trait Sampler: Send + Sync {
+    //Point number index (of count) for the pixel at (x, y) in the given dimension,
+    //in [0, 1)^2. scramble is a hash of the pixel and dimension, to decorrelate them.
+    fn sample_2d(&self, pixel: (u64, u64), index: u64, count: u64, dimension: u32, scramble: u64) -> (f64, f64);
+
+    //Whether the points are only random numbers, so decisions with random numbers of
+    //their own needn't take them (see decision_2d)
+    fn is_random(&self) -> bool {
+        false
+    }
+}
+
+//Plain random numbers, as if there were no sampler
+pub struct Independent;
+
+impl Sampler for Independent {
+    fn sample_2d(&self, _pixel: (u64, u64), _index: u64, _count: u64, _dimension: u32, _scramble: u64) -> (f64, f64) {
+        (random_f64(), random_f64())
+    }
+
+    fn is_random(&self) -> bool {
+        true
+    }
+}
+
+//Correlated multi-jittered sampling (Kensler 2013): one point in each cell of a grid
+//as near square as count allows, and also one in each row and column of a finer
+//grid. Works for any count.
+pub struct Stratified;
+
+impl Sampler for Stratified {
+    fn sample_2d(&self, _pixel: (u64, u64), index: u64, count: u64, _dimension: u32, scramble: u64) -> (f64, f64) {
+        let n = count.clamp(1, u32::MAX as u64) as u32;
+        let p = scramble as u32;
+        let s = permute(index as u32 % n, n, p.wrapping_mul(0x51633e2d));
+        let m = (n as f64).sqrt() as u32;
+        let rows = n.div_ceil(m);
+        let sx = permute(s % m, m, p.wrapping_mul(0x68bc21eb));
+        let sy = permute(s / m, rows, p.wrapping_mul(0x02e5be93));
+        let (jx, jy) = (random_f64(), random_f64());
+        ((sx as f64 + (sy as f64 + jx) / rows as f64) / m as f64, (s as f64 + jy) / n as f64)
+    }
+}
+
+//Halton sequence, a prime base per axis, with each pixel's points shifted by a random
+//amount (wrapping round) so neighbouring pixels don't share a pattern
+pub struct Halton;
+
+impl Halton {
+    const PRIMES: [u64; 16] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+}
+
+impl Sampler for Halton {
+    fn sample_2d(&self, _pixel: (u64, u64), index: u64, count: u64, dimension: u32, scramble: u64) -> (f64, f64) {
+        let i = shuffle(index, count, scramble);
+        let pair = 2 * dimension as usize % Halton::PRIMES.len();
+        let shift = |salt: u64| (hash(scramble ^ salt) >> 11) as f64 / (1u64 << 53) as f64;
+        (
+            (radical_inverse(i, Halton::PRIMES[pair]) + shift(1)).fract(),
+            (radical_inverse(i, Halton::PRIMES[pair + 1]) + shift(2)).fract(),
+        )
+    }
+}
+
+//The first two dimensions of the Sobol sequence, a (0, 2)-sequence: every power of two
+//points is stratified in every way a 2D grid of that many cells can be. Each pixel
+//gets its own random digit scramble.
+pub struct Sobol;
+
+impl Sampler for Sobol {
+    fn sample_2d(&self, _pixel: (u64, u64), index: u64, count: u64, _dimension: u32, scramble: u64) -> (f64, f64) {
+        let i = shuffle(index, count, scramble) as u32;
+        let x = i.reverse_bits() ^ scramble as u32;
+        let y = sobol_second(i) ^ (scramble >> 32) as u32;
+        let to_unit = |v: u32| v as f64 / (1u64 << 32) as f64;
+        (to_unit(x), to_unit(y))
+    }
+}
+
+//Blue-noise dithered sampling (Georgiev and Fajardo 2016): every pixel takes the same
+//Sobol points, shifted round the unit square by an amount from a blue-noise mask (see
+//blue_noise). Each pixel's error still drops as fast as with Sobol, but neighbouring
+//pixels' errors differ as much as they can, so what noise is left at low sample counts
+//is fine-grained and even, which is easier on the eye and easier to denoise than
+//clumps. Each dimension reads the mask at a different offset.
+pub struct BlueNoise;
+
+impl Sampler for BlueNoise {
+    fn sample_2d(&self, (x, y): (u64, u64), index: u64, count: u64, dimension: u32, _scramble: u64) -> (f64, f64) {
+        //The same order in every pixel, or the shifts wouldn't line up
+        let order = hash(dimension as u64);
+        let i = shuffle(index, count, order) as u32;
+        let to_unit = |v: u32| v as f64 / (1u64 << 32) as f64;
+        let shift = |salt: u64| {
+            let offset = hash(order ^ salt);
+            blue_noise::value(x + (offset & 0xffff), y + (offset >> 16 & 0xffff))
+        };
+        ((to_unit(i.reverse_bits()) + shift(1)).fract(), (to_unit(sobol_second(i)) + shift(2)).fract())
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SamplerKind {
+    Random,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub fn sampler(self) -> Arc<dyn Sampler> {
+        match self {
+            SamplerKind::Random => Arc::new(Independent),
+            SamplerKind::Stratified => Arc::new(Stratified),
+            SamplerKind::Halton => Arc::new(Halton),
+            SamplerKind::Sobol => Arc::new(Sobol),
+            SamplerKind::BlueNoise => Arc::new(BlueNoise),
+        }
+    }
+}
+
+//Dimensions past this many come from plain random numbers. Deep into a path the
+//sample is decorrelated enough that stratifying helps little.
+const DIMENSIONS: u32 = 8;
+
+//The sample this thread is tracing, so whatever needs a 2D point can ask for the next
+//one without the sampler being passed all the way down
+#[derive(Clone)]
+struct Current {
+    sampler: Option<Arc<dyn Sampler>>,
+    pixel: (u64, u64),
+    pixel_hash: u64,
+    index: u64,
+    count: u64,
+    dimension: u32,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Current> = const {
+        RefCell::new(Current { sampler: None, pixel: (0, 0), pixel_hash: 0, index: 0, count: 1, dimension: 0 })
+    };
+}
+
+//Start sample index (of count) for the pixel at (x, y) on this thread. seed varies the
+//scrambling from render to render.
+pub fn start_sample(sampler: &Arc<dyn Sampler>, seed: u64, x: u64, y: u64, index: u64, count: u64) {
+    CURRENT.with(|current| {
+        *current.borrow_mut() = Current {
+            sampler: Some(Arc::clone(sampler)),
+            pixel: (x, y),
+            pixel_hash: hash(seed ^ hash(x ^ hash(y))),
+            index,
+            count,
+            dimension: 0,
+        };
+    });
+}
+
+//Where the current sample has got to, to pick it up again with resume after tracing
+//some other samples
+pub struct Suspended(Current);
+
+pub fn suspend() -> Suspended {
+    Suspended(CURRENT.with(|current| current.borrow().clone()))
+}
+
+pub fn resume(suspended: Suspended) {
+    CURRENT.with(|current| *current.borrow_mut() = suspended.0);
+}
+
+//Pixel and index of the sample this thread is tracing, if it's tracing one for a
+//sampler
+pub fn position() -> Option<((u64, u64), u64)> {
+    CURRENT.with(|current| {
+        let current = current.borrow();
+        current.sampler.as_ref().map(|_| (current.pixel, current.index))
+    })
+}
+
+//Next 2D point of the current sample, or two random numbers outside of one
+pub fn next_2d() -> (f64, f64) {
+    next(true).unwrap_or_else(|| (random_f64(), random_f64()))
+}
+
+//Next 2D point of the current sample for a decision that otherwise uses random numbers
+//its own way (scattering off a diffuse surface, picking an emitter), so at the first
+//bounce or so it's spread out like the rest. None with the random sampler, outside a
+//sample and past the dimensions samplers are asked for, for the caller to do as it
+//always did, so renders with the random sampler don't change.
+pub fn decision_2d() -> Option<(f64, f64)> {
+    next(false)
+}
+
+//Next point of the current sample from its sampler, if it has one, the sample is still
+//within DIMENSIONS and (unless random is) the sampler isn't random. The dimension moves
+//on either way.
+fn next(random: bool) -> Option<(f64, f64)> {
+    let next = CURRENT.with(|current| {
+        let mut current = current.borrow_mut();
+        let dimension = current.dimension;
+        current.dimension += 1;
+        match &current.sampler {
+            Some(sampler) if dimension < DIMENSIONS && (random || !sampler.is_random()) => {
+                let scramble = hash(current.pixel_hash ^ dimension as u64);
+                Some((Arc::clone(sampler), current.pixel, current.index, current.count, dimension, scramble))
+            }
+            _ => None,
+        }
+    });
+    //Called outside the borrow, samplers are free to use the random numbers
+    next.map(|(sampler, pixel, index, count, dimension, scramble)| sampler.sample_2d(pixel, index, count, dimension, scramble))
+}
+
+//Map a point in the unit square to the unit disk, keeping it evenly spread (Shirley
+//and Chiu's concentric mapping)
+pub fn to_unit_disk((u, v): (f64, f64)) -> (f64, f64) {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+//Reorder the points within each run of count, differently per dimension, so the same
+//sample isn't at the same spot in every dimension
+fn shuffle(index: u64, count: u64, scramble: u64) -> u64 {
+    let n = count.clamp(1, u32::MAX as u64);
+    let base = index / n * n;
+    base + permute((index % n) as u32, n as u32, scramble as u32) as u64
+}
+
+//Pseudo-random permutation of [0, len), chosen by seed (Kensler 2013)
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    if len <= 1 {
+        return 0;
+    }
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    //Permutes the next power of two up, retrying until it lands inside len
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % len
+}
+
+//i's digits in base, mirrored about the point
+fn radical_inverse(mut i: u64, base: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let (mut result, mut scale) = (0.0, inv_base);
+    while i > 0 {
+        result += (i % base) as f64 * scale;
+        i /= base;
+        scale *= inv_base;
+    }
+    result
+}
+
+//Second Sobol dimension, from its generator matrix
+fn sobol_second(mut i: u32) -> u32 {
+    let (mut v, mut result) = (1u32 << 31, 0);
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
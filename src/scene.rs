@@ -0,0 +1,114 @@
+use super::animation::CameraPath;
+use super::background::Background;
+use super::camera::Camera;
+use super::cryptomatte::{object_id, Identified};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::hit::{Hit, World};
+use super::integrator::IntegratorKind;
+use super::light::Lighting;
+use super::propagation::CurvedSpace;
+use super::ray::RayLimits;
+use super::scene_graph::Node;
+use super::vec3::Color;
+
+
+
+//Everything needed to render a frame apart from the image settings
+pub struct Scene {
+    pub world: World,
+    //Objects placed by a hierarchy of named nodes, put into the world by flatten. Scenes
+    //built straight into the world leave it as an empty group.
+    pub graph: Node,
+    pub lights: Lighting,
+    //Glowing objects to sample directly at every bounce, as well as being found by
+    //chance. Each should be in the world too; add_emitter puts it in both.
+    pub emitters: Vec<Arc<dyn Hit>>,
+    pub camera: Camera,
+    //Where the camera goes over an animation, if it moves
+    pub camera_path: Option<CameraPath>,
+    //Other cameras to render from by name (see use_camera), each with its own path
+    pub cameras: HashMap<String, (Camera, Option<CameraPath>)>,
+    pub background: Box<dyn Background>,
+    //Region where rays bend, if any
+    pub space: Option<CurvedSpace>,
+    //How the scene is meant to be rendered, if it says; the renderer's settings decide
+    pub integrator: Option<IntegratorKind>,
+    //Light reaching everything from all around, for the ambient term of Phong materials.
+    //Black for none.
+    pub ambient: Color,
+}
+
+impl Scene {
+    pub fn add_emitter(&mut self, object: Arc<dyn Hit>) {
+        self.world.push(Box::new(Arc::clone(&object)));
+        self.emitters.push(object);
+    }
+
+    //Render from the camera called name, and its path, instead. False if there's no
+    //camera by that name.
+    pub fn use_camera(&mut self, name: &str) -> bool {
+        match self.cameras.get(name) {
+            Some((camera, path)) => {
+                self.camera = *camera;
+                self.camera_path = path.clone();
+                true
+            }
+            None => false,
+        }
+    }
+
+    //Focus the camera on what it sees at its autofocus point (see Camera::with_autofocus),
+    //if it has one, once the world is filled in. Returns how far away that is along the
+    //way the camera looks, or None if the camera has no autofocus point or sees nothing
+    //there, leaving the focus as it was.
+    pub fn autofocus(&mut self, limits: RayLimits) -> Option<f64> {
+        let (s, t) = self.camera.autofocus()?;
+        let ray = self.camera.central_ray(s, t);
+        let rec = self.world.hit(&ray, limits.epsilon, limits.max_distance)?;
+        let distance = (rec.p - self.camera.lookfrom()).dot(self.camera.forward());
+        if distance <= 0.0 {
+            return None;
+        }
+        self.camera = self.camera.focused(distance);
+        Some(distance)
+    }
+
+    //Move everything in the graph into the world, leaving it empty, ready to be rendered
+    //(or accelerated first). Changes to the graph after this don't show.
+    pub fn flatten(&mut self) {
+        let graph = std::mem::replace(&mut self.graph, Node::group());
+        graph.flatten_into(&mut self.world, &mut self.emitters);
+    }
+
+    //How far from the origin the scene reaches along any axis, going by the boxes round
+    //the bounded objects in the world (0 if there aren't any), for
+    //RayLimits::scaled_epsilon
+    pub fn extent(&self) -> f64 {
+        self.world.iter().filter_map(|o| o.bounding_box()).map(|b| {
+            (0..3).map(|i| b.min[i].abs().max(b.max[i].abs())).fold(0.0, f64::max)
+        }).fold(0.0, f64::max)
+    }
+
+    //flatten, giving every object in the world an ID for ID passes (see cryptomatte).
+    //Objects from the graph are named by their path through it and ones put straight
+    //into the world "object" and their place in it. Returns the names, for the manifest.
+    pub fn flatten_identified(&mut self) -> Vec<String> {
+        let mut names = Vec::new();
+        for (i, object) in std::mem::take(&mut self.world).into_iter().enumerate() {
+            let name = format!("object{}", i);
+            self.world.push(Box::new(Identified::new(Arc::from(object), object_id(&name))));
+            names.push(name);
+        }
+        let graph = std::mem::replace(&mut self.graph, Node::group());
+        for (name, object, emitter) in graph.flatten_named() {
+            if emitter {
+                self.emitters.push(Arc::clone(&object));
+            }
+            self.world.push(Box::new(Identified::new(object, object_id(&name))));
+            names.push(name);
+        }
+        names
+    }
+}
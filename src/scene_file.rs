@@ -0,0 +1,1624 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::de::{self, value::MapAccessDeserializer, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use super::animation::{CameraKey, CameraPath, Easing};
+use super::background::{Background, EnvironmentMap, PreethamSky, SkyGradient, Solid};
+use super::box_obj::BoxObj;
+use super::camera::{Camera, Lens, Projection, LENS_PRESETS};
+use super::cylinder::{Cone, Cylinder};
+use super::heightfield::Heightfield;
+use super::hit::{Hit, World};
+use super::integrator::IntegratorKind;
+use super::light::{AreaLight, DirectionalLight, Falloff, Light, LightGroups, Lighting, SimpleLight};
+use super::material::{Detail, Dielectric, DiffuseLight, Lambertian, Metal, NormalMapped, Pbr, PhongMat, Scatter, Subsurface, ThinFilm};
+use super::matrix::Mat4;
+use super::medium::{ConstantMedium, HeterogeneousMedium, VdbMedium};
+use super::mesh::{Shading, TriangleMesh};
+use super::noise::{Perlin, WorleyMode};
+use super::obj::load_obj;
+use super::plane::Plane;
+use super::rect::{XyRect, XzRect, YzRect};
+use super::scene::Scene;
+use super::scene_graph::Node;
+use super::solar::{day_of_year, Site};
+use super::sphere::{DisplacedSphere, MovingSphere, Sphere};
+use super::texture::{Brick, Checker, ColorSpace, Fbm, Gradient, GradientAxis, ImageTexture, MappedTexture, Marble, SolidColor, Texture, Turbulence, UvTransform, VertexColor, Worley};
+use super::torus::Torus;
+use super::vdb::VdbGrid;
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+//TOML scene files, for rendering scenes without recompiling:
+//
+//    background = [0.1, 0.1, 0.1]    #optional, defaults to the sky gradient
+//    #or an environment map: background = { type = "hdri", path = "sky.hdr", rotation = 90.0 }
+//    #or a daylight sky: background = { type = "sky", sun = [1.0, 0.5, -0.3], turbidity = 3.0 }
+//    integrator = "whitted"          #optional: bidirectional, hybrid, path, whitted or direct, see IntegratorKind
+//    ambient = [0.2, 0.2, 0.25]      #optional light from all around, for phong materials
+//
+//    [camera]
+//    from = [0.0, 0.0, 0.0]
+//    at = [0.0, 0.0, -1.0]
+//    vfov = 90.0
+//    #optional: up (defaults to +y), aperture (0), focus (distance to at),
+//    #shutter ([open, close], defaults to [0, 0] for no motion blur),
+//    #projection = { type = "orthographic", height = 4.0 } or { type = "fisheye", fov = 180.0 }
+//    #or { type = "equirectangular" } (defaults to perspective, for which vfov is needed)
+//    #or, instead of vfov and aperture, a real lens: lens = "50mm f/1.8" (or a preset:
+//    #normal, wide, portrait, telephoto), or lens = { focal_length = 35.0, f_stop = 2.8,
+//    #sensor = [23.5, 15.6] } in millimetres (full frame unless given), with unit the
+//    #metres per scene unit (1)
+//    #autofocus = true, in place of focus, focuses on whatever is in the middle of the image,
+//    #or autofocus = [0.3, 0.6] on what's at that point on it ([0, 0] bottom left, [1, 1]
+//    #top right)
+//
+//    #optional keyframes for --frames, each overriding any of from, at, aperture and
+//    #focus, with easing = "linear" (the default) or "ease" into the next key
+//    [[camera.keys]]
+//    frame = 0
+//    at = [0.0, 0.0, -1.0]
+//
+//    #optional more cameras, each like [camera], to render from with --camera overhead
+//    [cameras.overhead]
+//    from = [0.0, 5.0, -1.0]
+//    at = [0.0, 0.0, -1.0]
+//    vfov = 60.0
+//
+//    [materials.ground]
+//    type = "lambertian"
+//    albedo = [0.8, 0.8, 0.0]
+//
+//    [materials.bricks]
+//    type = "lambertian"
+//    texture = { type = "brick", brick = [0.6, 0.2, 0.1], mortar = [0.9, 0.9, 0.9] }
+//
+//    [[objects]]
+//    type = "sphere"
+//    centre = [0.0, -100.5, -1.0]
+//    radius = 100.0
+//    material = "ground"
+//
+//    [[lights]]
+//    type = "point"
+//    position = [2.0, 0.0, -1.0]
+//    falloff = [1.0, 0.0, 0.25]      #optional [constant, linear, quadratic], defaults to none
+//
+//Lights: point, rect (centre, edges u and v), disk (centre, normal, radius) and directional
+//(direction towards it, like the sun, with an optional angle across in degrees). The
+//area lights take an optional samples (shadow rays per shading point, 16) for soft shadows.
+//The sky background (sun the direction towards the sun, turbidity from 2 for clear air to
+//10 for haze, intensity) also adds the sun as a directional light of its colour unless
+//sun_light = false, with sun_intensity scaling it. Instead of sun it can take where and
+//when: latitude, longitude, date = "2024-06-21", time = "17:30", optionally utc_offset
+//(hours the clock is ahead of UTC, otherwise it's solar time) and north (degrees it's
+//turned from -z, anticlockwise from above; +x is east).
+//A light with a group = "rim" only lights objects (or groups of them) with that group in
+//their lights = ["default", "rim"]; everything else, and any light without a group, is
+//in "default".
+//
+//Materials: lambertian (albedo or texture), metal (albedo or texture), dielectric
+//(optional absorption per unit length inside, per channel, for coloured glass and shadows,
+//dispersion as Cauchy's B in square micrometres, and priority, higher winning where
+//dielectrics overlap),
+//diffuse_light (emit or texture), phong (optionally with ambient_occlusion = { samples =
+//8, distance = 1.0 } dimming its ambient term), pbr (base_color or texture, metallic 0 to 1,
+//roughness 0 to 1 defaulting to 0.5, anisotropy -1 to 1 for brushed metal), subsurface
+//(color or texture, mean_free_path per channel, ior, optional anisotropy), for closed
+//objects only. Any material can take a normal_map (a texture, with mapping.color_space
+//= "linear" for images) or a bump_map (heights as brightness), scaled by detail_strength,
+//and metals and dielectrics a thin_film = { thickness = 400.0, ior = 1.33 } (nanometres),
+//optionally with a variation texture scaling the thickness by its brightness.
+//Textures: solid, checker, brick, fbm, turbulence, marble, worley, gradient, image,
+//vertex_color, each with an optional mapping = { scale, offset, rotation, color_space }.
+//Objects: sphere, moving_sphere (centre0 at time0 to centre1 at time1, default 0 and 1),
+//displaced_sphere (radius pushed out by amplitude * fBm noise at frequency, optional
+//octaves and seed), heightfield (corner, size = [x, z], and heights as rows along x, an
+//image whose brightness times height gives them, or noise = { resolution, frequency,
+//octaves, seed } for fBm hills of that height), uv_sphere, obj (path relative to the scene file; the material is
+//used for faces the OBJ's own materials don't cover), both with shading = "auto", "smooth"
+//(making up vertex normals if there are none) or "flat", and like sphere an optional
+//displacement = { texture, height, subdivisions = 2 } making real bumps: the surface is cut
+//into finer triangles and moved out along its normals by height times the texture's
+//brightness, xy_rect / xz_rect / yz_rect (e.g. x = [x0, x1],
+//z = [z0, z1], k = y, optional flip), box (min, max) and plane (point, normal, optional
+//size = [width, height], uv_scale for infinite planes, u_axis), cylinder (base, top, radius)
+//and cone (base, apex, radius, optional top_radius to cut it off short of the apex), both
+//closed unless given caps = false, and torus (centre, major_radius, minor_radius, optional
+//axis the ring goes round, +y by default). A medium is fog or smoke filling a boundary =
+//{ type = "box", min, max } or { type = "sphere", centre, radius }, with a density (chance of
+//scattering per unit distance), optional albedo (white) and optional texture whose
+//brightness scales the density from place to place, marched through step units at a time
+//(a 64th of the way across by default). A vdb is smoke or cloud from a float grid in an
+//OpenVDB file (path, optional grid name, defaulting to "density" or the first float
+//grid), with the grid's values times density (1) the chance of scattering per unit
+//distance, and an optional albedo. Any object can be moved with
+//transform = { scale = 2.0, rotate = [0.0, 30.0, 0.0], translate = [1.0, 0.0, 0.0] }
+//(scale is a number or [x, y, z], rotate is degrees about x, y then z).
+//
+//Objects can be gathered into a group, with the group's transform moving them all:
+//
+//    [[objects]]
+//    type = "group"
+//    name = "table"
+//    transform = { translate = [0.0, 0.0, -2.0] }
+//
+//    [[objects.children]]
+//    type = "box"
+//    ...
+//
+//and anything with a name can be put down again by an instance, which copies it with
+//a transform of its own in place of the original's:
+//
+//    [[objects]]
+//    type = "instance"
+//    of = "table"
+//    transform = { translate = [3.0, 0.0, -2.0] }
+//
+//Names must be unique, and an instance has to come after what it copies. The objects
+//end up in Scene::graph, to be found by name and flattened before rendering.
+//
+//Textures can be named too, and used by name anywhere a texture goes (a checker's
+//even and odd included), so several materials can share one:
+//
+//    [textures.wood]
+//    type = "image"
+//    path = "wood.png"
+//
+//    [materials.table]
+//    type = "pbr"
+//    texture = "wood"
+//
+//A scene can be split across files, or take its materials from a shared library, with
+//include = ["library/materials.toml", "room.toml"] (relative to the file, as are the
+//included files' own paths). Their materials, textures, objects and lights are added
+//in that order, then the file's own; a material or texture defined again replaces the
+//earlier one, and camera, background, integrator and ambient come from the last file to give
+//them, so only the scene as a whole needs a camera. A file included twice adds its
+//objects twice.
+pub fn load_scene(path: &Path, aspect_ratio: f64) -> io::Result<Scene> {
+    let mut files = Vec::new();
+    read_files(path, &mut Vec::new(), &mut files)?;
+
+    //Later definitions of a name replace earlier ones, and settings come from the
+    //last file to give them
+    let (mut camera, mut background, mut integrator, mut ambient) = (None, None, None, None);
+    let mut cameras = HashMap::new();
+    let mut material_entries: HashMap<String, (&Path, MaterialEntry)> = HashMap::new();
+    let mut texture_entries = HashMap::new();
+    let mut objects = Vec::new();
+    let mut light_entries = Vec::new();
+    for (path, file) in &mut files {
+        let base = path.parent().unwrap_or(Path::new(""));
+        camera = file.camera.take().or(camera);
+        cameras.extend(file.cameras.drain());
+        background = file.background.take().map(|b| (base, b)).or(background);
+        integrator = file.integrator.take().or(integrator);
+        ambient = file.ambient.or(ambient);
+        material_entries.extend(file.materials.drain().map(|(name, entry)| (name, (base, entry))));
+        texture_entries.extend(file.textures.drain().map(|(name, entry)| (name, (base.to_path_buf(), entry))));
+        objects.extend(file.objects.drain(..).map(|entry| (base, entry)));
+        light_entries.append(&mut file.lights);
+    }
+    let textures = Textures::new(texture_entries);
+
+    //Objects made of these are also sampled directly as lights
+    let glowing: HashSet<String> = material_entries.iter()
+        .filter(|(_, (_, entry))| matches!(entry.kind, MaterialDesc::DiffuseLight { .. }))
+        .map(|(name, _)| name.clone())
+        .collect();
+    let mut materials: HashMap<String, Arc<dyn Scatter>> = HashMap::new();
+    for (name, (base, entry)) in material_entries {
+        let mat = entry.build(&Context { base, textures: &textures }).map_err(|e| invalid(format!("material '{}': {}", name, e)))?;
+        materials.insert(name, mat);
+    }
+    let material = |name: &str| {
+        materials.get(name).cloned()
+            .ok_or_else(|| invalid(format!("no material named '{}'", name)))
+    };
+
+    //Light groups by name, numbered as the lights bring them up
+    let mut group_numbers: HashMap<String, u32> = HashMap::from([("default".to_string(), 0)]);
+    for name in light_entries.iter().filter_map(|light| light.group.as_ref()) {
+        let next = group_numbers.len() as u32;
+        if !group_numbers.contains_key(name) {
+            if next == 64 {
+                return Err(invalid("there can only be 64 light groups".to_string()));
+            }
+            group_numbers.insert(name.clone(), next);
+        }
+    }
+    let light_groups = |names: &[String]| {
+        names.iter().try_fold(LightGroups::NONE, |groups, name| {
+            let number = group_numbers.get(name).ok_or_else(|| invalid(format!("no light in group '{}'", name)))?;
+            Ok(groups.with(LightGroups::group(*number)))
+        })
+    };
+
+    let mut graph = Node::group();
+    let mut named = HashMap::new();
+    for (base, entry) in objects {
+        graph.push(build_node(entry, &Context { base, textures: &textures }, &material, &light_groups, &glowing, &mut named)?);
+    }
+
+    let falloff = |f: Option<[f64; 3]>| f.map_or(Falloff::none(), |[c, l, q]| Falloff::new(c, l, q));
+    let mut lights: Lighting = light_entries.into_iter().map(|LightEntry { kind, group }| -> Box<dyn Light> {
+        let groups = group.map_or(LightGroups::DEFAULT, |name| LightGroups::group(group_numbers[&name]));
+        match kind {
+            LightDesc::Point { position, diffuse, specular, falloff: f } => {
+                Box::new(SimpleLight::new(point(diffuse), point(specular), point(position)).with_falloff(falloff(f))
+                    .with_groups(groups))
+            }
+            LightDesc::Rect { centre, u, v, diffuse, specular, samples, falloff: f } => {
+                Box::new(AreaLight::rect(point(diffuse), point(specular), point(centre), point(u), point(v))
+                    .with_samples(samples).with_falloff(falloff(f)).with_groups(groups))
+            }
+            LightDesc::Disk { centre, normal, radius, diffuse, specular, samples, falloff: f } => {
+                Box::new(AreaLight::disk(point(diffuse), point(specular), point(centre), point(normal), radius)
+                    .with_samples(samples).with_falloff(falloff(f)).with_groups(groups))
+            }
+            LightDesc::Directional { direction, diffuse, specular, angle } => {
+                Box::new(DirectionalLight::new(point(diffuse), point(specular), point(direction)).with_angle(angle)
+                    .with_groups(groups))
+            }
+        }
+    }).collect();
+
+    let c = camera.ok_or_else(|| invalid("no [camera] in the scene or anything it includes".to_string()))?;
+    let (camera, camera_path) = c.build(aspect_ratio)?;
+    let cameras = cameras.into_iter()
+        .map(|(name, c): (String, CameraDesc)| {
+            let camera = c.build(aspect_ratio).map_err(|e| invalid(format!("camera '{}': {}", name, e)))?;
+            Ok((name, camera))
+        })
+        .collect::<io::Result<HashMap<_, _>>>()?;
+
+    let background: Box<dyn Background> = match background {
+        None | Some((_, BackgroundDesc::Kind(BackgroundKind::Gradient))) => Box::new(SkyGradient),
+        Some((_, BackgroundDesc::Color(c))) | Some((_, BackgroundDesc::Kind(BackgroundKind::Solid { color: c }))) => {
+            Box::new(Solid(point(c)))
+        }
+        Some((base, BackgroundDesc::Kind(BackgroundKind::Hdri { path: map_path, rotation, intensity }))) => {
+            Box::new(EnvironmentMap::load(&base.join(map_path))?.with_rotation(rotation).with_intensity(intensity))
+        }
+        Some((_, BackgroundDesc::Kind(BackgroundKind::Sky { sun, site, turbidity, intensity, sun_light, sun_intensity }))) => {
+            let sun = match (sun, site) {
+                (Some(sun), None) => point(sun),
+                (None, Some(site)) => site.sun_direction()?,
+                _ => return Err(invalid("the sky needs either a sun direction or a latitude, date and time".to_string())),
+            };
+            let sky = PreethamSky::new(sun, turbidity).with_intensity(intensity);
+            if sun_light {
+                lights.push(Box::new(sky.sun_light(sun_intensity)));
+                Box::new(sky.without_sun_disc())
+            } else {
+                Box::new(sky)
+            }
+        }
+    };
+
+    Ok(Scene { world: World::new(), graph, lights, emitters: Vec::new(), camera, camera_path, cameras, background, space: None,
+        integrator: integrator.map(IntegratorDesc::kind), ambient: point(ambient.unwrap_or([0.0; 3])) })
+}
+
+//The scene file at path and every file it includes, directly or not, for watching
+//for changes
+pub fn scene_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    read_files(path, &mut Vec::new(), &mut files)?;
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+}
+
+//The file at path, after everything it includes (and they include), in turn, each
+//with its path. within is the chain of files including this one, so a file can't
+//include itself.
+fn read_files(path: &Path, within: &mut Vec<PathBuf>, files: &mut Vec<(PathBuf, SceneFile)>) -> io::Result<()> {
+    let text = fs::read_to_string(path)?;
+    let mut file: SceneFile = toml::from_str(&text)
+        .map_err(|e| invalid(e.to_string()))?;
+    let base = path.parent().unwrap_or(Path::new("")).to_path_buf();
+
+    within.push(fs::canonicalize(path)?);
+    for include in std::mem::take(&mut file.include) {
+        let included = base.join(&include);
+        if fs::canonicalize(&included).is_ok_and(|p| within.contains(&p)) {
+            return Err(invalid(format!("{} includes itself", included.display())));
+        }
+        read_files(&included, within, files)
+            .map_err(|e| io::Error::new(e.kind(), format!("in {}: {}", included.display(), e)))?;
+    }
+    within.pop();
+    files.push((path.to_path_buf(), file));
+    Ok(())
+}
+
+//The node for one entry in objects (or a group's children). Named nodes are kept in
+//named as they're made, for later instances to copy, and light_groups looks light
+//groups up by name.
+fn build_node(entry: ObjectEntry, cx: &Context, material: &dyn Fn(&str) -> io::Result<Arc<dyn Scatter>>,
+    light_groups: &dyn Fn(&[String]) -> io::Result<LightGroups>, glowing: &HashSet<String>, named: &mut HashMap<String, Node>) -> io::Result<Node> {
+    let ObjectEntry { kind, name, transform, lights } = entry;
+    let mut node = match kind {
+        ObjectDesc::Group { children } => {
+            let mut group = Node::group();
+            for child in children {
+                group.push(build_node(child, cx, material, light_groups, glowing, named)?);
+            }
+            group
+        }
+        ObjectDesc::Instance { of } => {
+            named.get(&of).cloned().ok_or_else(|| invalid(format!("no object named '{}' (it must come first)", of)))?
+        }
+        kind => {
+            let emitter = kind.material().is_some_and(|name| glowing.contains(name));
+            let leaf = Node::leaf(Arc::from(build_object(kind, cx, material)?));
+            if emitter { leaf.with_emitter() } else { leaf }
+        }
+    };
+    node.transform = transform.map_or(Mat4::identity(), |t| t.matrix());
+    if let Some(lights) = lights {
+        node.light_groups = Some(light_groups(&lights)?);
+    }
+    node.name = None;
+    if let Some(name) = name {
+        if named.contains_key(&name) {
+            return Err(invalid(format!("more than one object named '{}'", name)));
+        }
+        node.name = Some(name.clone());
+        named.insert(name, node.clone());
+    }
+    Ok(node)
+}
+
+//material looks materials up by name
+fn build_object(kind: ObjectDesc, cx: &Context, material: &dyn Fn(&str) -> io::Result<Arc<dyn Scatter>>) -> io::Result<Box<dyn Hit>> {
+    let object: Box<dyn Hit> = match kind {
+        ObjectDesc::Sphere { centre, radius, material: name, displacement: None } => {
+            Box::new(Sphere::new(point(centre), radius, material(&name)?))
+        }
+        //Tessellated finely enough for the detail asked for, straight onto the sphere
+        ObjectDesc::Sphere { centre, radius, material: name, displacement: Some(d) } => {
+            let (stacks, sectors) = (16 << d.subdivisions, 32 << d.subdivisions);
+            let mesh = TriangleMesh::uv_sphere(point(centre), radius, stacks, sectors, material(&name)?);
+            Box::new(d.displace(mesh, cx)?)
+        }
+        ObjectDesc::MovingSphere { centre0, centre1, time0, time1, radius, material: name } => {
+            Box::new(MovingSphere::new(point(centre0), point(centre1), time0, time1, radius, material(&name)?))
+        }
+        ObjectDesc::DisplacedSphere { centre, radius, amplitude, frequency, octaves, seed, material: name } => {
+            Box::new(DisplacedSphere::new(point(centre), radius, amplitude, frequency, material(&name)?)
+                .with_octaves(octaves).with_seed(seed))
+        }
+        ObjectDesc::Heightfield { corner, size: [x, z], heights, image, noise, height, material: name } => {
+            let (corner, mat) = (point(corner), material(&name)?);
+            match (heights, image, noise) {
+                (Some(heights), None, None) => Box::new(Heightfield::new(corner, x, z, heights, mat)),
+                (None, Some(image), None) => Box::new(Heightfield::load(&cx.base.join(image), corner, x, z, height, mat)?),
+                (None, None, Some(n)) => {
+                    let noise = Perlin::new(n.seed);
+                    let heights = (0..n.resolution).map(|j| (0..n.resolution).map(|i| {
+                        let along = |k: usize, size: f64| k as f64 / (n.resolution - 1) as f64 * size * n.frequency;
+                        height * noise.fbm(Point3::new(along(i, x), 0.0, along(j, z)), n.octaves, 2.0, 0.5)
+                    }).collect()).collect();
+                    Box::new(Heightfield::new(corner, x, z, heights, mat))
+                }
+                _ => return Err(invalid("heightfield needs one of heights, image or noise".to_string())),
+            }
+        }
+        ObjectDesc::UvSphere { centre, radius, stacks, sectors, material: name, shading, displacement } => {
+            let mesh = TriangleMesh::uv_sphere(point(centre), radius, stacks, sectors, material(&name)?);
+            let mesh = match displacement {
+                Some(d) => d.apply(mesh, cx)?,
+                None => mesh,
+            };
+            Box::new(mesh.with_shading(shading.shading()))
+        }
+        ObjectDesc::Obj { path: obj_path, material: name, shading, displacement } => {
+            let default = match name {
+                Some(name) => material(&name)?,
+                None => Arc::new(Lambertian::new(Color::new(0.7, 0.7, 0.7))),
+            };
+            let mesh = load_obj(&cx.base.join(obj_path), default)?;
+            let mesh = match displacement {
+                Some(d) => d.apply(mesh, cx)?,
+                None => mesh,
+            };
+            Box::new(mesh.with_shading(shading.shading()))
+        }
+        ObjectDesc::XyRect { x, y, k, flip, material: name } => {
+            let rect = XyRect::new(x[0], x[1], y[0], y[1], k, material(&name)?);
+            if flip { Box::new(rect.flipped()) } else { Box::new(rect) }
+        }
+        ObjectDesc::XzRect { x, z, k, flip, material: name } => {
+            let rect = XzRect::new(x[0], x[1], z[0], z[1], k, material(&name)?);
+            if flip { Box::new(rect.flipped()) } else { Box::new(rect) }
+        }
+        ObjectDesc::YzRect { y, z, k, flip, material: name } => {
+            let rect = YzRect::new(y[0], y[1], z[0], z[1], k, material(&name)?);
+            if flip { Box::new(rect.flipped()) } else { Box::new(rect) }
+        }
+        ObjectDesc::Box { min, max, material: name } => {
+            Box::new(BoxObj::new(point(min), point(max), material(&name)?))
+        }
+        ObjectDesc::Plane { point: p, normal, size, uv_scale, u_axis, material: name } => {
+            let mut plane = Plane::new(point(p), point(normal), material(&name)?).with_uv_scale(uv_scale);
+            if let Some(u) = u_axis {
+                plane = plane.with_u_axis(point(u));
+            }
+            match size {
+                Some([w, h]) => Box::new(plane.with_extent(w, h)),
+                None => Box::new(plane),
+            }
+        }
+        ObjectDesc::Cylinder { base, top, radius, caps, material: name } => {
+            let cylinder = Cylinder::new(point(base), point(top), radius, material(&name)?);
+            if caps { Box::new(cylinder) } else { Box::new(cylinder.without_caps()) }
+        }
+        ObjectDesc::Cone { base, apex, radius, top_radius, caps, material: name } => {
+            let mut cone = Cone::new(point(base), point(apex), radius, material(&name)?);
+            if let Some(r) = top_radius {
+                cone = cone.with_top_radius(r);
+            }
+            if caps { Box::new(cone) } else { Box::new(cone.without_caps()) }
+        }
+        ObjectDesc::Torus { centre, major_radius, minor_radius, axis, material: name } => {
+            let torus = Torus::new(point(centre), major_radius, minor_radius, material(&name)?);
+            match axis {
+                Some(axis) => Box::new(torus.with_axis(point(axis))),
+                None => Box::new(torus),
+            }
+        }
+        ObjectDesc::Medium { boundary, density, albedo, texture: None, step: _ } => {
+            Box::new(ConstantMedium::new(boundary.build(), density, point(albedo)))
+        }
+        ObjectDesc::Medium { boundary, density, albedo, texture: Some(texture), step } => {
+            let variation = texture.build(cx).map_err(invalid)?;
+            let medium = HeterogeneousMedium::new(boundary.build(), density, variation, point(albedo));
+            match step {
+                Some(step) => Box::new(medium.with_step(step)),
+                None => Box::new(medium),
+            }
+        }
+        ObjectDesc::Vdb { path: vdb_path, grid, density, albedo } => {
+            let grid = VdbGrid::load(&cx.base.join(vdb_path), grid.as_deref())?;
+            Box::new(VdbMedium::new(grid, density, point(albedo)))
+        }
+        ObjectDesc::Group { .. } | ObjectDesc::Instance { .. } => unreachable!("not a single object"),
+    };
+    Ok(object)
+}
+
+//text of a scene file with the camera moved to from and looking at at. Only those two
+//lines of the [camera] table change (or are added to the end of it), so comments and
+//layout are kept.
+pub fn with_camera(text: &str, from: Point3, at: Point3) -> String {
+    let line = |key: &str, p: Point3| format!("{} = [{:.4}, {:.4}, {:.4}]", key, p.x(), p.y(), p.z());
+    let mut pending = vec![("from", from), ("at", at)];
+    let mut out: Vec<String> = Vec::new();
+    let mut in_camera = false;
+    for original in text.lines() {
+        let trimmed = original.trim_start();
+        if trimmed.starts_with('[') {
+            if in_camera {
+                //Before the blank lines separating the tables
+                let gap = out.iter().rev().take_while(|l| l.trim().is_empty()).count();
+                let at_end = out.len() - gap;
+                out.splice(at_end..at_end, pending.drain(..).map(|(key, p)| line(key, p)));
+            }
+            in_camera = trimmed.starts_with("[camera]");
+        } else if in_camera {
+            let key = trimmed.split('=').next().unwrap_or("").trim();
+            if let Some(k) = pending.iter().position(|&(name, _)| name == key) {
+                let (name, p) = pending.remove(k);
+                out.push(line(name, p));
+                continue;
+            }
+        }
+        out.push(original.to_string());
+    }
+    if in_camera {
+        out.extend(pending.drain(..).map(|(key, p)| line(key, p)));
+    }
+    out.join("\n") + "\n"
+}
+
+#[derive(Deserialize)]
+struct SceneFile {
+    //Other scene files, relative to this one, whose definitions come before its own
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    //Optional in files meant to be included
+    camera: Option<CameraDesc>,
+    //More cameras to render from instead, by name
+    #[serde(default)]
+    cameras: HashMap<String, CameraDesc>,
+    background: Option<BackgroundDesc>,
+    integrator: Option<IntegratorDesc>,
+    ambient: Option<[f64; 3]>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialEntry>,
+    #[serde(default)]
+    textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectEntry>,
+    #[serde(default)]
+    lights: Vec<LightEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum IntegratorDesc {
+    #[serde(alias = "bdpt")]
+    Bidirectional,
+    Hybrid,
+    Path,
+    Whitted,
+    Direct,
+}
+
+impl IntegratorDesc {
+    fn kind(self) -> IntegratorKind {
+        match self {
+            IntegratorDesc::Bidirectional => IntegratorKind::Bidirectional,
+            IntegratorDesc::Hybrid => IntegratorKind::Hybrid,
+            IntegratorDesc::Path => IntegratorKind::Path,
+            IntegratorDesc::Whitted => IntegratorKind::Whitted,
+            IntegratorDesc::Direct => IntegratorKind::Direct,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum ShadingDesc {
+    #[default]
+    Auto,
+    Smooth,
+    Flat,
+}
+
+impl ShadingDesc {
+    fn shading(self) -> Shading {
+        match self {
+            ShadingDesc::Auto => Shading::Auto,
+            ShadingDesc::Smooth => Shading::Smooth,
+            ShadingDesc::Flat => Shading::Flat,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CameraDesc {
+    from: [f64; 3],
+    at: [f64; 3],
+    #[serde(default = "y_up")]
+    up: [f64; 3],
+    vfov: Option<f64>,
+    #[serde(default)]
+    aperture: f64,
+    focus: Option<f64>,
+    //In place of vfov and aperture
+    lens: Option<LensDesc>,
+    //Metres per scene unit, for the lens's aperture
+    #[serde(default = "one")]
+    unit: f64,
+    //In place of focus
+    autofocus: Option<AutofocusDesc>,
+    //[open, close]
+    #[serde(default)]
+    shutter: [f64; 2],
+    #[serde(default)]
+    keys: Vec<CameraKeyDesc>,
+    #[serde(default)]
+    projection: ProjectionDesc,
+}
+
+impl CameraDesc {
+    //The camera, and the path it takes if it has keys
+    fn build(self, aspect_ratio: f64) -> io::Result<(Camera, Option<CameraPath>)> {
+        let (lookfrom, lookat) = (point(self.from), point(self.at));
+        let focus = self.focus.unwrap_or_else(|| (lookfrom - lookat).length());
+        let projection = match self.projection {
+            ProjectionDesc::Perspective => Projection::Perspective,
+            ProjectionDesc::Orthographic { height } => Projection::Orthographic { height },
+            ProjectionDesc::Fisheye { fov } => Projection::Fisheye { fov },
+            ProjectionDesc::Equirectangular => Projection::Equirectangular,
+        };
+        let lens = self.lens.map(LensDesc::build).transpose()?;
+        let vfov = match (self.vfov, lens, projection) {
+            (Some(_), Some(_), _) => return Err(invalid("camera can't have both a vfov and a lens".to_string())),
+            (Some(vfov), None, _) => vfov,
+            (None, Some(lens), _) => lens.vfov(aspect_ratio),
+            (None, None, Projection::Perspective) => return Err(invalid("camera needs a vfov or a lens".to_string())),
+            //Not used
+            (None, None, _) => 90.0,
+        };
+        if self.unit <= 0.0 {
+            return Err(invalid("camera unit must be more than 0".to_string()));
+        }
+        let aperture = lens.map_or(self.aperture, |lens| lens.aperture(self.unit));
+        let mut camera = Camera::new(lookfrom, lookat, point(self.up), vfov, aspect_ratio, aperture, focus)
+            .with_shutter(self.shutter[0], self.shutter[1])
+            .with_projection(projection);
+        match self.autofocus {
+            Some(_) if self.focus.is_some() => return Err(invalid("camera can't have both a focus and autofocus".to_string())),
+            Some(AutofocusDesc::On(true)) => camera = camera.with_autofocus(0.5, 0.5),
+            Some(AutofocusDesc::At([s, t])) => camera = camera.with_autofocus(s, t),
+            Some(AutofocusDesc::On(false)) | None => {}
+        }
+        let path = (!self.keys.is_empty()).then(|| {
+            let keys = self.keys.iter().map(|k| CameraKey {
+                frame: k.frame,
+                from: point(k.from.unwrap_or(self.from)),
+                at: point(k.at.unwrap_or(self.at)),
+                aperture: k.aperture.unwrap_or(aperture),
+                focus: k.focus.or(self.focus),
+                easing: match k.easing {
+                    EasingDesc::Linear => Easing::Linear,
+                    EasingDesc::Ease => Easing::Ease,
+                },
+            }).collect();
+            CameraPath::new(keys, point(self.up), vfov, aspect_ratio).with_shutter(self.shutter[0], self.shutter[1])
+                .with_projection(projection)
+        });
+        Ok((camera, path))
+    }
+}
+
+//autofocus = true for the middle of the image, or a point on it, [0, 0] the bottom left
+//and [1, 1] the top right
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AutofocusDesc {
+    On(bool),
+    At([f64; 2]),
+}
+
+//A real lens, see Lens: "50mm f/1.8", a preset name, or the numbers in millimetres
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LensDesc {
+    Named(String),
+    Numbers {
+        focal_length: f64,
+        f_stop: f64,
+        //Full frame unless given
+        sensor: Option<[f64; 2]>,
+    },
+}
+
+impl LensDesc {
+    fn build(self) -> io::Result<Lens> {
+        match self {
+            LensDesc::Named(text) => Lens::parse(&text).ok_or_else(|| {
+                let names: Vec<_> = LENS_PRESETS.iter().map(|(name, _)| *name).collect();
+                invalid(format!("bad lens '{}', should be like \"50mm f/1.8\" or one of {}", text, names.join(", ")))
+            }),
+            LensDesc::Numbers { focal_length, f_stop, sensor } => {
+                if focal_length <= 0.0 || f_stop <= 0.0 || sensor.is_some_and(|[w, h]| w <= 0.0 || h <= 0.0) {
+                    return Err(invalid("lens focal_length, f_stop and sensor must be more than 0".to_string()));
+                }
+                let [width, height] = sensor.unwrap_or([36.0, 24.0]);
+                Ok(Lens::new(focal_length, f_stop).with_sensor(width, height))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ProjectionDesc {
+    #[default]
+    Perspective,
+    Orthographic {
+        height: f64,
+    },
+    Fisheye {
+        #[serde(default = "one_eighty")]
+        fov: f64,
+    },
+    Equirectangular,
+}
+
+//Anything left out is taken from the camera
+#[derive(Deserialize)]
+struct CameraKeyDesc {
+    frame: f64,
+    from: Option<[f64; 3]>,
+    at: Option<[f64; 3]>,
+    aperture: Option<f64>,
+    focus: Option<f64>,
+    #[serde(default)]
+    easing: EasingDesc,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum EasingDesc {
+    #[default]
+    Linear,
+    Ease,
+}
+
+#[derive(Deserialize)]
+struct MaterialEntry {
+    #[serde(flatten)]
+    kind: MaterialDesc,
+    thin_film: Option<ThinFilmDesc>,
+    normal_map: Option<TextureRef>,
+    bump_map: Option<TextureRef>,
+    #[serde(default = "one")]
+    detail_strength: f64,
+}
+
+//Thickness in nanometres
+#[derive(Deserialize)]
+struct ThinFilmDesc {
+    thickness: f64,
+    ior: f64,
+    variation: Option<TextureRef>,
+}
+
+#[derive(Deserialize)]
+struct AmbientOcclusionDesc {
+    #[serde(default = "eight")]
+    samples: u32,
+    distance: f64,
+}
+
+impl MaterialEntry {
+    fn build(self, cx: &Context) -> Result<Arc<dyn Scatter>, String> {
+        let mut mat = self.kind.build(cx)?;
+        if let Some(film) = self.thin_film {
+            let mut thin_film = ThinFilm::new(mat, film.thickness, film.ior);
+            if let Some(variation) = film.variation {
+                thin_film = thin_film.with_variation(variation.build(cx)?);
+            }
+            mat = Arc::new(thin_film);
+        }
+        let detail = match (self.normal_map, self.bump_map) {
+            (None, None) => return Ok(mat),
+            (Some(map), None) => Detail::NormalMap(map.build(cx)?),
+            (None, Some(map)) => Detail::BumpMap(map.build(cx)?),
+            _ => return Err("can't have both a normal_map and a bump_map".to_string()),
+        };
+        Ok(Arc::new(NormalMapped::new(mat, detail).with_strength(self.detail_strength)))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MaterialDesc {
+    Lambertian {
+        albedo: Option<[f64; 3]>,
+        texture: Option<TextureRef>,
+    },
+    Metal {
+        albedo: Option<[f64; 3]>,
+        texture: Option<TextureRef>,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        ior: f64,
+        #[serde(default = "one")]
+        occlusion: f64,
+        #[serde(default)]
+        absorption: [f64; 3],
+        #[serde(default)]
+        dispersion: f64,
+        #[serde(default)]
+        priority: u32,
+    },
+    //Colour or texture, as for lambertian. Above 1 is fine
+    DiffuseLight {
+        emit: Option<[f64; 3]>,
+        texture: Option<TextureRef>,
+    },
+    //Same parameters as PhongMat::new
+    Phong {
+        ambient: f64,
+        diffuse: f64,
+        specular: f64,
+        shininess: f64,
+        exponent: i32,
+        albedo: [f64; 3],
+        #[serde(default)]
+        fuzz: f64,
+        //Chance of scattering diffusely rather than specularly
+        diffuse_fraction: f64,
+        #[serde(default)]
+        occlusion: f64,
+        ambient_occlusion: Option<AmbientOcclusionDesc>,
+    },
+    //base_color or texture, as for lambertian
+    Pbr {
+        base_color: Option<[f64; 3]>,
+        texture: Option<TextureRef>,
+        #[serde(default)]
+        metallic: f64,
+        #[serde(default = "half")]
+        roughness: f64,
+        #[serde(default)]
+        anisotropy: f64,
+    },
+    //color or texture, as for lambertian
+    Subsurface {
+        color: Option<[f64; 3]>,
+        texture: Option<TextureRef>,
+        mean_free_path: [f64; 3],
+        ior: f64,
+        #[serde(default)]
+        anisotropy: f64,
+    },
+}
+
+impl MaterialDesc {
+    fn build(self, cx: &Context) -> Result<Arc<dyn Scatter>, String> {
+        Ok(match self {
+            MaterialDesc::Lambertian { albedo, texture } => {
+                Arc::new(Lambertian::with_texture(color_or_texture("albedo", albedo, texture, cx)?))
+            }
+            MaterialDesc::Metal { albedo, texture, fuzz } => {
+                Arc::new(Metal::with_texture(color_or_texture("albedo", albedo, texture, cx)?, fuzz))
+            }
+            MaterialDesc::Dielectric { ior, occlusion, absorption, dispersion, priority } => {
+                Arc::new(Dielectric::new(ior, occlusion).with_absorption(point(absorption))
+                    .with_dispersion(dispersion).with_priority(priority))
+            }
+            MaterialDesc::DiffuseLight { emit, texture } => {
+                Arc::new(DiffuseLight::with_texture(color_or_texture("emit", emit, texture, cx)?))
+            }
+            MaterialDesc::Phong { ambient, diffuse, specular, shininess, exponent, albedo, fuzz, diffuse_fraction, occlusion, ambient_occlusion } => {
+                let phong = PhongMat::new(ambient, diffuse, specular, shininess, exponent, point(albedo), fuzz, diffuse_fraction, occlusion);
+                match ambient_occlusion {
+                    Some(AmbientOcclusionDesc { samples, distance }) => Arc::new(phong.with_ambient_occlusion(samples, distance)),
+                    None => Arc::new(phong),
+                }
+            }
+            MaterialDesc::Pbr { base_color, texture, metallic, roughness, anisotropy } => {
+                Arc::new(Pbr::with_texture(color_or_texture("base_color", base_color, texture, cx)?, metallic, roughness)
+                    .with_anisotropy(anisotropy))
+            }
+            MaterialDesc::Subsurface { color, texture, mean_free_path, ior, anisotropy } => {
+                Arc::new(Subsurface::with_texture(color_or_texture("color", color, texture, cx)?, point(mean_free_path), ior)
+                    .with_anisotropy(anisotropy))
+            }
+        })
+    }
+}
+
+//Surface pushed out along its normals by height times a texture's brightness, after
+//splitting its triangles into four subdivisions times over (see TriangleMesh::displaced)
+#[derive(Deserialize)]
+struct DisplacementDesc {
+    texture: TextureRef,
+    height: f64,
+    #[serde(default = "two_levels")]
+    subdivisions: u32,
+}
+
+impl DisplacementDesc {
+    //mesh subdivided, then displaced
+    fn apply(self, mesh: TriangleMesh, cx: &Context) -> io::Result<TriangleMesh> {
+        let levels = self.subdivisions;
+        self.displace(mesh.subdivided(levels), cx)
+    }
+
+    //mesh displaced as it is, for meshes made fine enough to begin with
+    fn displace(self, mesh: TriangleMesh, cx: &Context) -> io::Result<TriangleMesh> {
+        let texture = self.texture.build(cx).map_err(invalid)?;
+        Ok(mesh.displaced(texture.as_ref(), self.height))
+    }
+}
+
+//A texture given in place, or one of the scene's [textures] by name
+enum TextureRef {
+    Named(String),
+    Desc(TextureDesc),
+}
+
+impl<'de> Deserialize<'de> for TextureRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<TextureRef, D::Error> {
+        struct RefVisitor;
+        impl<'de> Visitor<'de> for RefVisitor {
+            type Value = TextureRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a texture or the name of one")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<TextureRef, E> {
+                Ok(TextureRef::Named(name.to_string()))
+            }
+
+            //Deserialized as a TextureDesc would be, so its errors are as helpful
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<TextureRef, A::Error> {
+                TextureDesc::deserialize(MapAccessDeserializer::new(map)).map(TextureRef::Desc)
+            }
+        }
+        deserializer.deserialize_any(RefVisitor)
+    }
+}
+
+impl TextureRef {
+    fn build(self, cx: &Context) -> Result<Arc<dyn Texture>, String> {
+        match self {
+            TextureRef::Named(name) => cx.textures.get(&name),
+            TextureRef::Desc(desc) => desc.build(cx),
+        }
+    }
+}
+
+//The [textures] of a scene and everything it includes, each built the first time
+//something uses it and shared from then on
+struct Textures {
+    names: HashSet<String>,
+    //Not built yet, with the directory of the file each is from
+    pending: RefCell<HashMap<String, (PathBuf, TextureDesc)>>,
+    built: RefCell<HashMap<String, Arc<dyn Texture>>>,
+}
+
+impl Textures {
+    fn new(entries: HashMap<String, (PathBuf, TextureDesc)>) -> Textures {
+        Textures { names: entries.keys().cloned().collect(), pending: RefCell::new(entries), built: RefCell::new(HashMap::new()) }
+    }
+
+    fn get(&self, name: &str) -> Result<Arc<dyn Texture>, String> {
+        if let Some(texture) = self.built.borrow().get(name) {
+            return Ok(texture.clone());
+        }
+        //Taken out while it's built, so one made from itself is caught rather than
+        //built forever
+        let (base, desc) = self.pending.borrow_mut().remove(name).ok_or_else(|| match self.names.contains(name) {
+            true => format!("texture '{}' is made from itself", name),
+            false => format!("no texture named '{}'", name),
+        })?;
+        let texture = desc.build(&Context { base: &base, textures: self }).map_err(|e| format!("texture '{}': {}", name, e))?;
+        self.built.borrow_mut().insert(name.to_string(), texture.clone());
+        Ok(texture)
+    }
+}
+
+//What building things from one file needs: the directory its relative paths start
+//from, and the scene's named textures
+struct Context<'a> {
+    base: &'a Path,
+    textures: &'a Textures,
+}
+
+#[derive(Deserialize)]
+struct TextureDesc {
+    #[serde(flatten)]
+    kind: TextureKind,
+    mapping: Option<MappingDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TextureKind {
+    Solid {
+        color: [f64; 3],
+    },
+    Brick {
+        brick: [f64; 3],
+        mortar: [f64; 3],
+        #[serde(default = "one")]
+        width: f64,
+        #[serde(default = "half")]
+        height: f64,
+        #[serde(default = "tenth")]
+        mortar_width: f64,
+    },
+    Fbm {
+        scale: f64,
+        #[serde(default = "six")]
+        octaves: u32,
+        #[serde(default = "two")]
+        lacunarity: f64,
+        #[serde(default = "half")]
+        gain: f64,
+        low: [f64; 3],
+        high: [f64; 3],
+        #[serde(default)]
+        seed: u64,
+    },
+    Worley {
+        scale: f64,
+        #[serde(default = "worley_f1")]
+        mode: WorleyModeDesc,
+        low: [f64; 3],
+        high: [f64; 3],
+        #[serde(default)]
+        seed: u64,
+    },
+    //Stops are [position, r, g, b]
+    Gradient {
+        stops: Vec<[f64; 4]>,
+        axis: AxisDesc,
+    },
+    VertexColor {
+        fallback: [f64; 3],
+    },
+    //3D checkerboard in cubes of side 1 / scale
+    Checker {
+        #[serde(default = "one")]
+        scale: f64,
+        even: Box<TextureRef>,
+        odd: Box<TextureRef>,
+    },
+    //Billowing |noise| summed over octaves
+    Turbulence {
+        #[serde(default = "one")]
+        scale: f64,
+        #[serde(default = "seven")]
+        octaves: u32,
+        #[serde(default = "black")]
+        low: [f64; 3],
+        #[serde(default = "white")]
+        high: [f64; 3],
+        #[serde(default)]
+        seed: u64,
+    },
+    Marble {
+        #[serde(default = "one")]
+        scale: f64,
+        #[serde(default = "black")]
+        low: [f64; 3],
+        #[serde(default = "white")]
+        high: [f64; 3],
+        #[serde(default)]
+        seed: u64,
+    },
+    //PNG or PPM, relative to the scene file. Decoded as sRGB unless a mapping says otherwise.
+    Image {
+        path: String,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum WorleyModeDesc {
+    F1,
+    F2MinusF1,
+}
+
+//"u", "v", or a world space direction
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AxisDesc {
+    Uv(UvAxis),
+    Along([f64; 3]),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum UvAxis {
+    U,
+    V,
+}
+
+#[derive(Deserialize)]
+struct MappingDesc {
+    #[serde(default = "unit_scale")]
+    scale: (f64, f64),
+    #[serde(default)]
+    offset: (f64, f64),
+    //Degrees
+    #[serde(default)]
+    rotation: f64,
+    #[serde(default = "srgb")]
+    color_space: ColorSpaceDesc,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ColorSpaceDesc {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpaceDesc {
+    fn space(self) -> ColorSpace {
+        match self {
+            ColorSpaceDesc::Srgb => ColorSpace::Srgb,
+            ColorSpaceDesc::Linear => ColorSpace::Linear,
+        }
+    }
+}
+
+impl TextureDesc {
+    fn build(self, cx: &Context) -> Result<Arc<dyn Texture>, String> {
+        let mut mapping = self.mapping;
+        let texture: Arc<dyn Texture> = match self.kind {
+            TextureKind::Solid { color } => Arc::new(SolidColor::new(point(color))),
+            TextureKind::Brick { brick, mortar, width, height, mortar_width } => {
+                Arc::new(Brick::new(point(brick), point(mortar), width, height, mortar_width))
+            }
+            TextureKind::Fbm { scale, octaves, lacunarity, gain, low, high, seed } => {
+                Arc::new(Fbm::new(scale, octaves, lacunarity, gain, point(low), point(high), seed))
+            }
+            TextureKind::Worley { scale, mode, low, high, seed } => {
+                let mode = match mode {
+                    WorleyModeDesc::F1 => WorleyMode::F1,
+                    WorleyModeDesc::F2MinusF1 => WorleyMode::F2MinusF1,
+                };
+                Arc::new(Worley::new(scale, mode, point(low), point(high), seed))
+            }
+            TextureKind::Gradient { stops, axis } => {
+                let axis = match axis {
+                    AxisDesc::Uv(UvAxis::U) => GradientAxis::U,
+                    AxisDesc::Uv(UvAxis::V) => GradientAxis::V,
+                    AxisDesc::Along(dir) => GradientAxis::Along(point(dir)),
+                };
+                let stops = stops.iter().map(|s| (s[0], Color::new(s[1], s[2], s[3]))).collect();
+                Arc::new(Gradient::new(stops, axis))
+            }
+            TextureKind::VertexColor { fallback } => Arc::new(VertexColor::new(point(fallback))),
+            TextureKind::Checker { scale, even, odd } => Arc::new(Checker::new(scale, even.build(cx)?, odd.build(cx)?)),
+            TextureKind::Turbulence { scale, octaves, low, high, seed } => {
+                Arc::new(Turbulence::new(scale, octaves, point(low), point(high), seed))
+            }
+            TextureKind::Marble { scale, low, high, seed } => Arc::new(Marble::new(scale, point(low), point(high), seed)),
+            TextureKind::Image { path } => {
+                let path = cx.base.join(path);
+                //Decoded by the image itself, before it's mipmapped, so the levels are
+                //averaged in linear light; the mapping is left with nothing to decode
+                let m = mapping.get_or_insert(MappingDesc {
+                    scale: unit_scale(),
+                    offset: (0.0, 0.0),
+                    rotation: 0.0,
+                    color_space: ColorSpaceDesc::Srgb,
+                });
+                let image = ImageTexture::load(&path, m.color_space.space())
+                    .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+                m.color_space = ColorSpaceDesc::Linear;
+                Arc::new(image)
+            }
+        };
+
+        Ok(match mapping {
+            Some(m) => Arc::new(MappedTexture::new(texture, UvTransform::new(m.scale, m.offset, m.rotation), m.color_space.space())),
+            None => texture,
+        })
+    }
+}
+
+//A flat colour or a texture, whichever was given
+fn color_or_texture(name: &str, color: Option<[f64; 3]>, texture: Option<TextureRef>, cx: &Context) -> Result<Arc<dyn Texture>, String> {
+    match (color, texture) {
+        (Some(color), None) => Ok(Arc::new(SolidColor::new(point(color)))),
+        (None, Some(texture)) => texture.build(cx),
+        _ => Err(format!("needs exactly one of {} and texture", name)),
+    }
+}
+
+#[derive(Deserialize)]
+struct ObjectEntry {
+    #[serde(flatten)]
+    kind: ObjectDesc,
+    name: Option<String>,
+    transform: Option<TransformDesc>,
+    //Light groups lighting it
+    lights: Option<Vec<String>>,
+}
+
+//Scaled, then rotated about x, y and z in turn (degrees), then translated
+#[derive(Deserialize)]
+struct TransformDesc {
+    #[serde(default)]
+    translate: [f64; 3],
+    #[serde(default)]
+    rotate: [f64; 3],
+    scale: Option<ScaleDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    Axes([f64; 3]),
+}
+
+impl TransformDesc {
+    fn matrix(&self) -> Mat4 {
+        let scale = match self.scale {
+            None => Vec3::new(1.0, 1.0, 1.0),
+            Some(ScaleDesc::Uniform(s)) => Vec3::new(s, s, s),
+            Some(ScaleDesc::Axes(s)) => point(s),
+        };
+        Mat4::translation(point(self.translate))
+            * Mat4::rotation_z(self.rotate[2])
+            * Mat4::rotation_y(self.rotate[1])
+            * Mat4::rotation_x(self.rotate[0])
+            * Mat4::scaling(scale)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ObjectDesc {
+    Sphere {
+        centre: [f64; 3],
+        radius: f64,
+        material: String,
+        displacement: Option<DisplacementDesc>,
+    },
+    MovingSphere {
+        centre0: [f64; 3],
+        centre1: [f64; 3],
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "one")]
+        time1: f64,
+        radius: f64,
+        material: String,
+    },
+    DisplacedSphere {
+        centre: [f64; 3],
+        radius: f64,
+        amplitude: f64,
+        frequency: f64,
+        #[serde(default = "four")]
+        octaves: u32,
+        #[serde(default)]
+        seed: u64,
+        material: String,
+    },
+    Heightfield {
+        corner: [f64; 3],
+        //[x, z]
+        size: [f64; 2],
+        heights: Option<Vec<Vec<f64>>>,
+        image: Option<String>,
+        noise: Option<HeightNoiseDesc>,
+        //Height of white in an image, or of the noise's peaks
+        #[serde(default = "one")]
+        height: f64,
+        material: String,
+    },
+    UvSphere {
+        centre: [f64; 3],
+        radius: f64,
+        stacks: usize,
+        sectors: usize,
+        material: String,
+        #[serde(default)]
+        shading: ShadingDesc,
+        displacement: Option<DisplacementDesc>,
+    },
+    Obj {
+        path: String,
+        material: Option<String>,
+        #[serde(default)]
+        shading: ShadingDesc,
+        displacement: Option<DisplacementDesc>,
+    },
+    //Ranges along the rect's two axes, its position k along the third, and whether
+    //it faces down that axis rather than up it
+    XyRect {
+        x: [f64; 2],
+        y: [f64; 2],
+        k: f64,
+        #[serde(default)]
+        flip: bool,
+        material: String,
+    },
+    XzRect {
+        x: [f64; 2],
+        z: [f64; 2],
+        k: f64,
+        #[serde(default)]
+        flip: bool,
+        material: String,
+    },
+    YzRect {
+        y: [f64; 2],
+        z: [f64; 2],
+        k: f64,
+        #[serde(default)]
+        flip: bool,
+        material: String,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: String,
+    },
+    //Infinite unless given a size (width along u, height along v). On an infinite
+    //plane the texture repeats every uv_scale units.
+    Plane {
+        point: [f64; 3],
+        normal: [f64; 3],
+        size: Option<[f64; 2]>,
+        #[serde(default = "one")]
+        uv_scale: f64,
+        u_axis: Option<[f64; 3]>,
+        material: String,
+    },
+    Cylinder {
+        base: [f64; 3],
+        top: [f64; 3],
+        radius: f64,
+        #[serde(default = "yes")]
+        caps: bool,
+        material: String,
+    },
+    Cone {
+        base: [f64; 3],
+        apex: [f64; 3],
+        radius: f64,
+        top_radius: Option<f64>,
+        #[serde(default = "yes")]
+        caps: bool,
+        material: String,
+    },
+    Torus {
+        centre: [f64; 3],
+        major_radius: f64,
+        minor_radius: f64,
+        axis: Option<[f64; 3]>,
+        material: String,
+    },
+    //Fog or smoke filling boundary, thickened and thinned by a texture if given one
+    Medium {
+        boundary: BoundaryDesc,
+        density: f64,
+        #[serde(default = "white")]
+        albedo: [f64; 3],
+        texture: Option<TextureRef>,
+        step: Option<f64>,
+    },
+    //A float grid from a VDB file, as smoke or cloud
+    Vdb {
+        path: String,
+        grid: Option<String>,
+        #[serde(default = "one")]
+        density: f64,
+        #[serde(default = "white")]
+        albedo: [f64; 3],
+    },
+    Group {
+        #[serde(default)]
+        children: Vec<ObjectEntry>,
+    },
+    //Another copy of the object or group with this name
+    Instance {
+        of: String,
+    },
+}
+
+impl ObjectDesc {
+    fn material(&self) -> Option<&str> {
+        match self {
+            ObjectDesc::Sphere { material, .. }
+            | ObjectDesc::MovingSphere { material, .. }
+            | ObjectDesc::DisplacedSphere { material, .. }
+            | ObjectDesc::Heightfield { material, .. }
+            | ObjectDesc::UvSphere { material, .. }
+            | ObjectDesc::XyRect { material, .. }
+            | ObjectDesc::XzRect { material, .. }
+            | ObjectDesc::YzRect { material, .. }
+            | ObjectDesc::Box { material, .. }
+            | ObjectDesc::Plane { material, .. }
+            | ObjectDesc::Cylinder { material, .. }
+            | ObjectDesc::Cone { material, .. }
+            | ObjectDesc::Torus { material, .. } => Some(material),
+            ObjectDesc::Obj { material, .. } => material.as_deref(),
+            ObjectDesc::Medium { .. } | ObjectDesc::Vdb { .. } | ObjectDesc::Group { .. } | ObjectDesc::Instance { .. } => None,
+        }
+    }
+}
+
+//Closed shape a medium fills
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BoundaryDesc {
+    Sphere {
+        centre: [f64; 3],
+        radius: f64,
+    },
+    Box {
+        min: [f64; 3],
+        max: [f64; 3],
+    },
+}
+
+impl BoundaryDesc {
+    //Its material is never seen, only where rays cross it
+    fn build(self) -> Arc<dyn Hit> {
+        let unseen = Arc::new(Lambertian::new(Color::new(0.0, 0.0, 0.0)));
+        match self {
+            BoundaryDesc::Sphere { centre, radius } => Arc::new(Sphere::new(point(centre), radius, unseen)),
+            BoundaryDesc::Box { min, max } => Arc::new(BoxObj::new(point(min), point(max), unseen)),
+        }
+    }
+}
+
+//fBm terrain on a resolution by resolution grid
+#[derive(Deserialize)]
+struct HeightNoiseDesc {
+    #[serde(default = "one_twenty_nine")]
+    resolution: usize,
+    #[serde(default = "one")]
+    frequency: f64,
+    #[serde(default = "six")]
+    octaves: u32,
+    #[serde(default)]
+    seed: u64,
+}
+
+//Either a plain colour or a table with a type
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BackgroundDesc {
+    Color([f64; 3]),
+    Kind(BackgroundKind),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BackgroundKind {
+    Gradient,
+    Solid {
+        color: [f64; 3],
+    },
+    Hdri {
+        path: String,
+        //Degrees about the vertical
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default = "one")]
+        intensity: f64,
+    },
+    Sky {
+        //Towards the sun, or worked out from where and when
+        sun: Option<[f64; 3]>,
+        #[serde(flatten)]
+        site: Option<SiteDesc>,
+        #[serde(default = "three")]
+        turbidity: f64,
+        #[serde(default = "one")]
+        intensity: f64,
+        #[serde(default = "yes")]
+        sun_light: bool,
+        #[serde(default = "one")]
+        sun_intensity: f64,
+    },
+}
+
+//Where and when a sky is seen, for where its sun is
+#[derive(Deserialize)]
+struct SiteDesc {
+    latitude: f64,
+    #[serde(default)]
+    longitude: f64,
+    //"2024-06-21" and "14:30" (or "14:30:15") on the local clock, which is local solar
+    //time unless utc_offset says how many hours it's ahead of UTC
+    date: String,
+    time: String,
+    utc_offset: Option<f64>,
+    //Degrees north is turned from -z, anticlockwise seen from above
+    #[serde(default)]
+    north: f64,
+}
+
+impl SiteDesc {
+    fn sun_direction(&self) -> io::Result<Vec3> {
+        let numbers = |text: &str, separator: char, what: &str| -> io::Result<Vec<f64>> {
+            text.split(separator).map(|part| part.trim().parse::<f64>()).collect::<Result<_, _>>()
+                .map_err(|_| invalid(format!("bad {} '{}'", what, text)))
+        };
+        let (year, month, day) = match numbers(&self.date, '-', "date")?[..] {
+            [year, month, day] if (1.0..=12.0).contains(&month) && (1.0..=31.0).contains(&day) => (year as i32, month as u32, day as u32),
+            _ => return Err(invalid(format!("bad date '{}', should be like \"2024-06-21\"", self.date))),
+        };
+        let hours = match numbers(&self.time, ':', "time")?[..] {
+            [hours, minutes] => hours + minutes / 60.0,
+            [hours, minutes, seconds] => hours + minutes / 60.0 + seconds / 3600.0,
+            _ => return Err(invalid(format!("bad time '{}', should be like \"14:30\"", self.time))),
+        };
+        let mut site = Site::new(self.latitude, self.longitude).with_north(self.north);
+        if let Some(offset) = self.utc_offset {
+            site = site.with_utc_offset(offset);
+        }
+        Ok(site.sun_direction(day_of_year(year, month, day), hours))
+    }
+}
+
+#[derive(Deserialize)]
+struct LightEntry {
+    #[serde(flatten)]
+    kind: LightDesc,
+    //Only light objects that list this group
+    group: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LightDesc {
+    Point {
+        position: [f64; 3],
+        #[serde(default = "white")]
+        diffuse: [f64; 3],
+        #[serde(default = "white")]
+        specular: [f64; 3],
+        //[constant, linear, quadratic]
+        falloff: Option<[f64; 3]>,
+    },
+    Rect {
+        centre: [f64; 3],
+        //The two edges
+        u: [f64; 3],
+        v: [f64; 3],
+        #[serde(default = "white")]
+        diffuse: [f64; 3],
+        #[serde(default = "white")]
+        specular: [f64; 3],
+        #[serde(default = "sixteen")]
+        samples: usize,
+        falloff: Option<[f64; 3]>,
+    },
+    Disk {
+        centre: [f64; 3],
+        normal: [f64; 3],
+        radius: f64,
+        #[serde(default = "white")]
+        diffuse: [f64; 3],
+        #[serde(default = "white")]
+        specular: [f64; 3],
+        #[serde(default = "sixteen")]
+        samples: usize,
+        falloff: Option<[f64; 3]>,
+    },
+    Directional {
+        //Towards the light
+        direction: [f64; 3],
+        #[serde(default = "white")]
+        diffuse: [f64; 3],
+        #[serde(default = "white")]
+        specular: [f64; 3],
+        //Degrees across
+        #[serde(default)]
+        angle: f64,
+    },
+}
+
+//Points, directions and colours are all written as [x, y, z]
+fn point(v: [f64; 3]) -> Vec3 {
+    Point3::new(v[0], v[1], v[2])
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+//Defaults for serde, which wants functions
+fn y_up() -> [f64; 3] { [0.0, 1.0, 0.0] }
+fn white() -> [f64; 3] { [1.0, 1.0, 1.0] }
+fn black() -> [f64; 3] { [0.0, 0.0, 0.0] }
+fn unit_scale() -> (f64, f64) { (1.0, 1.0) }
+fn yes() -> bool { true }
+fn one() -> f64 { 1.0 }
+fn two() -> f64 { 2.0 }
+fn three() -> f64 { 3.0 }
+fn one_eighty() -> f64 { 180.0 }
+fn half() -> f64 { 0.5 }
+fn eight() -> u32 { 8 }
+fn tenth() -> f64 { 0.1 }
+fn sixteen() -> usize { 16 }
+fn two_levels() -> u32 { 2 }
+fn four() -> u32 { 4 }
+fn six() -> u32 { 6 }
+fn seven() -> u32 { 7 }
+fn one_twenty_nine() -> usize { 129 }
+fn worley_f1() -> WorleyModeDesc { WorleyModeDesc::F1 }
+fn srgb() -> ColorSpaceDesc { ColorSpaceDesc::Srgb }
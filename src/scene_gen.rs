@@ -0,0 +1,164 @@
+use std::fmt::Write;
+
+use rand::{Rng, SeedableRng};
+
+use super::random::Pcg32;
+
+
+
+//Scene files for the demo scenes, written out as TOML (see scene_file) to be edited and
+//rendered with --scene-file, rather than only ever built in code like the gallery's.
+
+//The random spheres scene: count small spheres (a fifth of them metal or glass) on a
+//grid around three big ones, placed and coloured from seed (or at random without one).
+//With bouncing, the diffuse ones jump up while the shutter is open.
+pub fn random_spheres(count: usize, seed: Option<u64>, bouncing: bool) -> String {
+    let mut rng = seed.map_or_else(Pcg32::from_entropy, Pcg32::seed_from_u64);
+    let mut out = String::new();
+    let shutter = if bouncing { "\nshutter = [0.0, 1.0]" } else { "" };
+    let _ = writeln!(out, "[camera]
+from = [13.0, 2.0, 3.0]
+at = [0.0, 0.0, 0.0]
+vfov = 20.0
+aperture = 0.1
+focus = 10.0{}
+", shutter);
+    material(&mut out, "ground", "lambertian", &format!("albedo = {}", vec3([0.5, 0.5, 0.5])));
+    material(&mut out, "glass", "dielectric", "ior = 1.5");
+    material(&mut out, "brown", "lambertian", &format!("albedo = {}", vec3([0.4, 0.2, 0.1])));
+    material(&mut out, "steel", "metal", &format!("albedo = {}", vec3([0.7, 0.6, 0.5])));
+    sphere(&mut out, [0.0, -1000.0, 0.0], 1000.0, "ground");
+    sphere(&mut out, [0.0, 1.0, 0.0], 1.0, "glass");
+    sphere(&mut out, [-4.0, 1.0, 0.0], 1.0, "brown");
+    sphere(&mut out, [4.0, 1.0, 0.0], 1.0, "steel");
+
+    //On a square grid a unit apart, as many across as it takes
+    let side = (count as f64).sqrt().ceil() as usize;
+    for i in 0..count {
+        let (a, b) = ((i % side) as f64 - (side / 2) as f64, (i / side) as f64 - (side / 2) as f64);
+        let centre = [a + rng.gen_range(0.0..0.9), 0.2, b + rng.gen_range(0.0..0.9)];
+        let name = format!("small{}", i);
+        let choose_mat: f64 = rng.gen();
+        if choose_mat < 0.8 {
+            let albedo = [(); 3].map(|_| rng.gen::<f64>() * rng.gen::<f64>());
+            material(&mut out, &name, "lambertian", &format!("albedo = {}", vec3(albedo)));
+            if bouncing {
+                let lifted = [centre[0], centre[1] + rng.gen_range(0.0..0.5), centre[2]];
+                let _ = writeln!(out, "[[objects]]
+type = \"moving_sphere\"
+centre0 = {}
+centre1 = {}
+radius = 0.2
+material = \"{}\"
+", vec3(centre), vec3(lifted), name);
+                continue;
+            }
+        } else if choose_mat < 0.95 {
+            let albedo = [(); 3].map(|_| rng.gen_range(0.4..1.0));
+            let fuzz = number(rng.gen_range(0.0..0.5));
+            material(&mut out, &name, "metal", &format!("albedo = {}\nfuzz = {}", vec3(albedo), fuzz));
+        } else {
+            material(&mut out, &name, "dielectric", "ior = 1.5");
+        }
+        sphere(&mut out, centre, 0.2, &name);
+    }
+
+    light(&mut out, [10.0, 30.0, 10.0]);
+    out
+}
+
+//The closed Cornell box, lit only by the panel in its ceiling, with two boxes inside
+pub fn cornell_box() -> String {
+    let mut out = String::from("background = [0.0, 0.0, 0.0]
+
+[camera]
+from = [278.0, 278.0, -800.0]
+at = [278.0, 278.0, 0.0]
+vfov = 40.0
+
+");
+    material(&mut out, "red", "lambertian", &format!("albedo = {}", vec3([0.65, 0.05, 0.05])));
+    material(&mut out, "green", "lambertian", &format!("albedo = {}", vec3([0.12, 0.45, 0.15])));
+    material(&mut out, "white", "lambertian", &format!("albedo = {}", vec3([0.73, 0.73, 0.73])));
+    material(&mut out, "light", "diffuse_light", &format!("emit = {}", vec3([15.0, 15.0, 15.0])));
+    for (kind, axes, k, name) in [
+        ("yz_rect", "y = [0.0, 555.0]\nz = [0.0, 555.0]", 555.0, "green"),
+        ("yz_rect", "y = [0.0, 555.0]\nz = [0.0, 555.0]", 0.0, "red"),
+        ("xz_rect", "x = [0.0, 555.0]\nz = [0.0, 555.0]", 0.0, "white"),
+        ("xz_rect", "x = [0.0, 555.0]\nz = [0.0, 555.0]", 555.0, "white"),
+        ("xy_rect", "x = [0.0, 555.0]\ny = [0.0, 555.0]", 555.0, "white"),
+    ] {
+        let _ = writeln!(out, "[[objects]]\ntype = \"{}\"\n{}\nk = {}\nmaterial = \"{}\"\n", kind, axes, number(k), name);
+    }
+    //Facing down, since emitters only glow from the front
+    let _ = writeln!(out, "[[objects]]
+type = \"xz_rect\"
+x = [213.0, 343.0]
+z = [227.0, 332.0]
+k = 554.0
+flip = true
+material = \"light\"
+");
+    for (height, angle, at) in [(330.0, 15.0, [265.0, 0.0, 295.0]), (165.0, -18.0, [130.0, 0.0, 65.0])] {
+        let _ = writeln!(out, "[[objects]]
+type = \"box\"
+min = [0.0, 0.0, 0.0]
+max = {}
+transform = {{ rotate = {}, translate = {} }}
+material = \"white\"
+", vec3([165.0, height, 165.0]), vec3([0.0, angle, 0.0]), vec3(at));
+    }
+    out
+}
+
+//The original test scene: a Phong sphere on a yellow ground, lit from the right
+pub fn hollow_sphere() -> String {
+    let mut out = String::from("[camera]
+from = [0.0, 0.0, 0.0]
+at = [0.0, 0.0, -1.0]
+vfov = 90.0
+
+");
+    material(&mut out, "ground", "lambertian", &format!("albedo = {}", vec3([0.8, 0.8, 0.0])));
+    material(&mut out, "centre", "lambertian", &format!("albedo = {}", vec3([0.1, 0.2, 0.5])));
+    material(&mut out, "phong", "phong", &format!("ambient = 1.0
+diffuse = 1.0
+specular = 0.0
+shininess = 0.5
+exponent = 4
+albedo = {}
+diffuse_fraction = 1.0", vec3([0.1, 0.2, 0.5])));
+    let _ = writeln!(out, "[[objects]]
+type = \"plane\"
+point = [0.0, -0.5, 0.0]
+normal = [0.0, 1.0, 0.0]
+material = \"ground\"
+");
+    sphere(&mut out, [0.0, 0.0, -1.0], 0.5, "centre");
+    sphere(&mut out, [0.0, 0.0, -1.0], 0.5, "phong");
+    light(&mut out, [2.0, 0.0, -1.0]);
+    out
+}
+
+//A [materials.name] table of type kind, with the rest of its lines
+fn material(out: &mut String, name: &str, kind: &str, fields: &str) {
+    let _ = writeln!(out, "[materials.{}]\ntype = \"{}\"\n{}\n", name, kind, fields);
+}
+
+fn sphere(out: &mut String, centre: [f64; 3], radius: f64, material: &str) {
+    let _ = writeln!(out, "[[objects]]\ntype = \"sphere\"\ncentre = {}\nradius = {}\nmaterial = \"{}\"\n",
+        vec3(centre), number(radius), material);
+}
+
+fn light(out: &mut String, position: [f64; 3]) {
+    let _ = writeln!(out, "[[lights]]\ntype = \"point\"\nposition = {}", vec3(position));
+}
+
+//To four places, always with a decimal point so TOML reads it as a float
+fn number(x: f64) -> String {
+    format!("{:?}", (x * 1e4).round() / 1e4)
+}
+
+fn vec3(v: [f64; 3]) -> String {
+    format!("[{}, {}, {}]", number(v[0]), number(v[1]), number(v[2]))
+}
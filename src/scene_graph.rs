@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use super::hit::{Hit, World};
+use super::light::{LightGroups, LightLinked};
+use super::matrix::Mat4;
+use super::transform::Transform;
+
+
+
+//A scene as a tree rather than a flat list: each node is placed by a transform relative
+//to its parent, and holds an object, child nodes or both. Moving a node moves everything
+//under it, nodes can be looked up by name, and a subtree can be cloned to put the same
+//assembly down again (the objects themselves are shared, not copied). The tree is
+//flattened into the world, one transformed object per leaf, before rendering.
+#[derive(Clone)]
+pub struct Node {
+    pub name: Option<String>,
+    pub transform: Mat4,
+    pub object: Option<Arc<dyn Hit>>,
+    //Whether object glows, so is sampled directly as well (see Scene::emitters)
+    pub emitter: bool,
+    //Lights linked to everything under this node that doesn't say otherwise, None to
+    //leave it to the nodes above (see LightGroups)
+    pub light_groups: Option<LightGroups>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    //Empty node, for grouping others
+    pub fn group() -> Node {
+        Node { name: None, transform: Mat4::identity(), object: None, emitter: false, light_groups: None, children: Vec::new() }
+    }
+
+    pub fn leaf(object: Arc<dyn Hit>) -> Node {
+        Node { object: Some(object), ..Node::group() }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Node {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Mat4) -> Node {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_emitter(mut self) -> Node {
+        self.emitter = true;
+        self
+    }
+
+    pub fn with_light_groups(mut self, groups: LightGroups) -> Node {
+        self.light_groups = Some(groups);
+        self
+    }
+
+    pub fn with_child(mut self, child: Node) -> Node {
+        self.children.push(child);
+        self
+    }
+
+    pub fn push(&mut self, child: Node) {
+        self.children.push(child);
+    }
+
+    //First node called name, this one or any below it, depth first
+    pub fn find(&self, name: &str) -> Option<&Node> {
+        if self.name.as_deref() == Some(name) {
+            return Some(self);
+        }
+        self.children.iter().find_map(|c| c.find(name))
+    }
+
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut Node> {
+        if self.name.as_deref() == Some(name) {
+            return Some(self);
+        }
+        self.children.iter_mut().find_map(|c| c.find_mut(name))
+    }
+
+    //Every object under this node, in world space, with whether it's an emitter. Objects
+    //that end up where they started aren't wrapped in a Transform at all.
+    pub fn flatten(&self) -> Vec<(Arc<dyn Hit>, bool)> {
+        self.flatten_named().into_iter().map(|(_, object, emitter)| (object, emitter)).collect()
+    }
+
+    //flatten, with each object's path through the tree: the names of the nodes down to
+    //it joined by "/", with unnamed nodes standing as their place among their siblings
+    //(e.g. "table/0/leg")
+    pub fn flatten_named(&self) -> Vec<(String, Arc<dyn Hit>, bool)> {
+        let mut out = Vec::new();
+        self.flatten_under(None, LightGroups::DEFAULT, self.name.clone().unwrap_or_default(), &mut out);
+        out
+    }
+
+    //parent is the transform of everything above, None for the identity, groups the
+    //lights linked to it and path its path
+    fn flatten_under(&self, parent: Option<Mat4>, groups: LightGroups, path: String, out: &mut Vec<(String, Arc<dyn Hit>, bool)>) {
+        let groups = self.light_groups.unwrap_or(groups);
+        let placed = match (parent, self.transform.is_identity()) {
+            (parent, true) => parent,
+            (None, false) => Some(self.transform),
+            (Some(parent), false) => Some(parent * self.transform),
+        };
+        if let Some(object) = &self.object {
+            let object = match placed {
+                Some(m) => Arc::new(Transform::new(Arc::clone(object), m)) as Arc<dyn Hit>,
+                None => Arc::clone(object),
+            };
+            let object = if groups == LightGroups::DEFAULT { object } else { Arc::new(LightLinked::new(object, groups)) };
+            out.push((path.clone(), object, self.emitter));
+        }
+        for (i, child) in self.children.iter().enumerate() {
+            let step = child.name.clone().unwrap_or_else(|| i.to_string());
+            let path = if path.is_empty() { step } else { format!("{}/{}", path, step) };
+            child.flatten_under(placed, groups, path, out);
+        }
+    }
+
+    //Flattened objects added to world, and the emitters to emitters as well
+    pub fn flatten_into(&self, world: &mut World, emitters: &mut Vec<Arc<dyn Hit>>) {
+        for (object, emitter) in self.flatten() {
+            if emitter {
+                emitters.push(Arc::clone(&object));
+            }
+            world.push(Box::new(object));
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use super::stats;
+use super::vec3::Color;
+
+
+
+//A rectangular block of pixels. Coordinates are in image space, so y = 0 is the
+//top row of the output image (the first scanline written).
+#[derive(Clone, Copy)]
+pub struct Tile {
+    pub x0: u64,
+    pub y0: u64,
+    pub width: u64,
+    pub height: u64,
+}
+
+//The unit of work handed to the thread pool: one batch of samples for every pixel
+//in a single tile.
+#[derive(Clone, Copy)]
+pub struct WorkItem {
+    pub tile: usize,
+    pub first_sample: u64,
+    pub samples: u64,
+}
+
+//Shared flag for stopping a render early. Clones all refer to the same flag, so one
+//can be handed to the scheduler and another kept by whoever might want to stop it.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+//Running sums for one tile, and how many samples per pixel have been run over it.
+//With adaptive sampling pixels drop out once they've converged, so each keeps its own
+//count, and a sum of squared luminance for its variance.
+#[derive(Clone)]
+pub struct TileAccum {
+    pub sum: Vec<Color>,
+    pub sum_sq: Vec<f64>,
+    pub counts: Vec<u64>,
+    pub samples: u64,
+}
+
+impl TileAccum {
+    //Nothing yet, for tile
+    pub fn new(tile: &Tile) -> TileAccum {
+        let pixels = (tile.width * tile.height) as usize;
+        TileAccum {
+            sum: vec![Color::new(0.0, 0.0, 0.0); pixels],
+            sum_sq: vec![0.0; pixels],
+            counts: vec![0; pixels],
+            samples: 0,
+        }
+    }
+
+    fn means(&self) -> impl Iterator<Item = Color> + '_ {
+        self.sum.iter().zip(&self.counts).map(|(&c, &n)| c / n.max(1) as f64)
+    }
+}
+
+//When a pixel has had enough samples: once the 95% confidence interval of its mean
+//luminance is within threshold of the mean (relative), after at least min_samples
+#[derive(Clone, Copy)]
+pub struct Adaptive {
+    pub threshold: f64,
+    pub min_samples: u64,
+}
+
+impl Adaptive {
+    //Anything darker than this is judged as if it were this bright, or the near-black
+    //pixels would never be done
+    const DARK: f64 = 1.0 / 256.0;
+
+    fn converged(&self, sum: Color, sum_sq: f64, n: u64) -> bool {
+        if n < self.min_samples.max(2) {
+            return false;
+        }
+        let n = n as f64;
+        let mean = sum.luminance() / n;
+        let variance = (sum_sq / n - mean * mean).max(0.0) * n / (n - 1.0);
+        1.96 * (variance / n).sqrt() <= self.threshold * mean.max(Self::DARK)
+    }
+}
+
+//The state of one tile after a work item finished with it
+pub struct TileUpdate {
+    pub tile: Tile,
+    //Samples per pixel accumulated so far
+    pub samples: u64,
+    //Mean of those samples, row-major within the tile
+    pub pixels: Vec<Color>,
+}
+
+//Handed to the progress callback after each work item finishes
+pub struct Progress<'a> {
+    //Work items finished so far, this one included
+    pub done: usize,
+    pub total: usize,
+    //Index of the tile the item belonged to
+    pub tile: usize,
+    //Since the render started
+    pub elapsed: Duration,
+    //Samples there already were when it started, resuming from a checkpoint
+    pub resumed: u64,
+    pub snapshot: Snapshot<'a>,
+}
+
+impl Progress<'_> {
+    //The figures so far, owned, to keep or send elsewhere (down a channel, say)
+    pub fn report(&self) -> ProgressReport {
+        //Only the tiles being rendered, so a share of the image (see Scheduler::with_tiles)
+        //finishes at 100%
+        let scheduled = &self.snapshot.scheduler.scheduled;
+        let tiles: Vec<(Tile, u64)> = self.snapshot.tile_progress().into_iter().enumerate()
+            .filter(|(index, _)| scheduled.contains(index))
+            .map(|(_, progress)| progress)
+            .collect();
+        let samples: u64 = tiles.iter().map(|(t, s)| t.width * t.height * s).sum();
+        let total_samples: u64 = tiles.iter().map(|(t, _)| t.width * t.height * self.snapshot.samples_per_pixel()).sum();
+        ProgressReport {
+            eta: eta(samples - self.resumed.min(samples), total_samples.saturating_sub(samples), self.elapsed),
+            ..ProgressReport::new(self.done, self.total, samples, total_samples, self.elapsed)
+        }
+    }
+}
+
+//How far a render has got
+#[derive(Clone, Copy, Debug)]
+pub struct ProgressReport {
+    //Work items
+    pub done: usize,
+    pub total: usize,
+    //Samples over all the pixels being rendered
+    pub samples: u64,
+    pub total_samples: u64,
+    pub elapsed: Duration,
+    //Time left at the rate so far, None until there's a rate to go by
+    pub eta: Option<Duration>,
+}
+
+impl ProgressReport {
+    //With the time left going by the rate over all of elapsed
+    pub fn new(done: usize, total: usize, samples: u64, total_samples: u64, elapsed: Duration) -> ProgressReport {
+        ProgressReport { done, total, samples, total_samples, elapsed, eta: eta(samples, total_samples.saturating_sub(samples), elapsed) }
+    }
+
+    //0 to 1
+    pub fn fraction(&self) -> f64 {
+        self.samples as f64 / self.total_samples.max(1) as f64
+    }
+}
+
+//Time the samples left will take, if they go at the rate done samples took elapsed
+pub fn eta(done: u64, left: u64, elapsed: Duration) -> Option<Duration> {
+    (done > 0).then(|| elapsed.mul_f64(left as f64 / done as f64))
+}
+
+//Read-only view of a render in progress
+pub struct Snapshot<'a> {
+    scheduler: &'a Scheduler,
+    accumulators: &'a [Mutex<TileAccum>],
+}
+
+impl Snapshot<'_> {
+    pub fn width(&self) -> u64 {
+        self.scheduler.width
+    }
+
+    pub fn height(&self) -> u64 {
+        self.scheduler.height
+    }
+
+    pub fn samples_per_pixel(&self) -> u64 {
+        self.scheduler.samples_per_pixel
+    }
+
+    //Every tile with the number of samples per pixel it has had so far
+    pub fn tile_progress(&self) -> Vec<(Tile, u64)> {
+        self.scheduler.tiles.iter().zip(self.accumulators)
+            .map(|(tile, acc)| (*tile, acc.lock().unwrap().samples))
+            .collect()
+    }
+
+    //Current state of a single tile
+    pub fn tile(&self, index: usize) -> TileUpdate {
+        let acc = self.accumulators[index].lock().unwrap();
+        TileUpdate {
+            tile: self.scheduler.tiles[index],
+            samples: acc.samples,
+            pixels: acc.means().collect(),
+        }
+    }
+
+    //Samples each pixel has actually had, row-major from the top. Only differs
+    //between pixels with adaptive sampling.
+    pub fn sample_counts(&self) -> Vec<u64> {
+        let mut counts = vec![0; (self.width() * self.height()) as usize];
+        for (tile, acc) in self.scheduler.tiles.iter().zip(self.accumulators) {
+            let acc = acc.lock().unwrap();
+            for (k, &n) in acc.counts.iter().enumerate() {
+                let (x, y) = (k as u64 % tile.width, k as u64 / tile.width);
+                counts[((tile.y0 + y) * self.width() + tile.x0 + x) as usize] = n;
+            }
+        }
+        counts
+    }
+
+    //Copy of every tile's running sums, e.g. for a Checkpoint
+    pub fn accumulated(&self) -> Vec<TileAccum> {
+        self.accumulators.iter().map(|acc| acc.lock().unwrap().clone()).collect()
+    }
+
+    //Mean of the samples taken so far for each pixel, row-major from the top.
+    //Tiles that haven't been touched yet are black.
+    pub fn averaged(&self) -> Vec<Color> {
+        let mut framebuffer = vec![Color::new(0.0, 0.0, 0.0); (self.width() * self.height()) as usize];
+        for (tile, acc) in self.scheduler.tiles.iter().zip(self.accumulators) {
+            let acc = acc.lock().unwrap();
+            if acc.samples == 0 {
+                continue;
+            }
+            self.scheduler.scatter_tile(&mut framebuffer, tile, acc.means());
+        }
+        framebuffer
+    }
+}
+
+//Splits the image into (tile, sample-batch) work items and runs them on rayon's
+//work-stealing pool.
+//Parallelizing per scanline leaves cores idle at the end of every line, and small
+//images don't have enough scanlines to go round. Lots of small items means idle
+//threads can always steal something, and because the items are ordered batch by batch
+//the whole image fills in at roughly the same rate.
+pub struct Scheduler {
+    width: u64,
+    height: u64,
+    samples_per_pixel: u64,
+    tiles: Vec<Tile>,
+    //Indices of the tiles items are made for
+    scheduled: Range<usize>,
+    items: Vec<WorkItem>,
+    cancel: CancelToken,
+    adaptive: Option<Adaptive>,
+    //Where an earlier render of the same image got to
+    resume: Option<Vec<TileAccum>>,
+}
+
+impl Scheduler {
+    pub fn new(width: u64, height: u64, tile_size: u64, samples_per_pixel: u64, batch_size: u64) -> Scheduler {
+        let tile_size = tile_size.max(1);
+        let batch_size = batch_size.max(1);
+
+        let mut tiles = Vec::new();
+        for y0 in (0..height).step_by(tile_size as usize) {
+            for x0 in (0..width).step_by(tile_size as usize) {
+                tiles.push(Tile {
+                    x0,
+                    y0,
+                    width: tile_size.min(width - x0),
+                    height: tile_size.min(height - y0),
+                });
+            }
+        }
+
+        let mut items = Vec::new();
+        for first_sample in (0..samples_per_pixel).step_by(batch_size as usize) {
+            let samples = batch_size.min(samples_per_pixel - first_sample);
+            for tile in 0..tiles.len() {
+                items.push(WorkItem { tile, first_sample, samples });
+            }
+        }
+
+        Scheduler { width, height, samples_per_pixel, scheduled: 0..tiles.len(), tiles, items, cancel: CancelToken::new(), adaptive: None, resume: None }
+    }
+
+    //Stop sampling each pixel once it's converged, so samples_per_pixel is only the
+    //most any pixel gets. Pixels are checked between batches.
+    pub fn with_adaptive(mut self, adaptive: Adaptive) -> Scheduler {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    //Stop early once token is cancelled. Work items already running are abandoned
+    //part way and don't count, so every pixel of a tile always has the same number
+    //of samples.
+    pub fn with_cancel(mut self, token: CancelToken) -> Scheduler {
+        self.cancel = token;
+        self
+    }
+
+    //Only render the tiles with these indices (see tiles), for splitting a render up
+    //between machines. The rest are left black, with no samples.
+    pub fn with_tiles(mut self, tiles: Range<usize>) -> Scheduler {
+        self.items.retain(|item| tiles.contains(&item.tile));
+        self.scheduled = tiles.start.max(self.scheduled.start)..tiles.end.min(self.scheduled.end);
+        self
+    }
+
+    //The tiles the image is cut into, in the order Snapshot::accumulated gives them
+    pub fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+
+    //Carry on from the running sums an earlier render of the same image had got to
+    //(see Snapshot::accumulated), instead of starting from nothing. Each tile only
+    //gets the samples it hasn't had yet, so if samples_per_pixel has gone up since,
+    //it just gets more.
+    pub fn with_resume(mut self, tiles: Vec<TileAccum>) -> Scheduler {
+        assert!(tiles.len() == self.tiles.len() && tiles.iter().zip(&self.tiles).all(|(acc, tile)| acc.sum.len() == (tile.width * tile.height) as usize),
+            "resumed tiles don't match the image");
+        self.resume = Some(tiles);
+        self
+    }
+
+    //Copy a tile's pixels into their place in a full-image framebuffer
+    fn scatter_tile(&self, framebuffer: &mut [Color], tile: &Tile, pixels: impl Iterator<Item = Color>) {
+        for (k, c) in pixels.enumerate() {
+            let (x, y) = (k as u64 % tile.width, k as u64 / tile.width);
+            framebuffer[((tile.y0 + y) * self.width + tile.x0 + x) as usize] = c;
+        }
+    }
+
+    //Run every work item and return the mean of the samples for each pixel, row-major
+    //from the top of the image. If the render is cancelled the tiles have whatever
+    //samples they had got to, and tiles that were never started are black.
+    //sample(x, y, s) traces sample number s for the pixel at image coords (x, y).
+    //progress is called from the worker threads after each work item finishes.
+    pub fn run<F, P>(&self, sample: F, progress: P) -> Vec<Color>
+    where
+        F: Fn(u64, u64, u64) -> Color + Sync,
+        P: Fn(&Progress) + Sync,
+    {
+        self.run_batched(|x, y, samples, out| samples.for_each(|s| out(sample(x, y, s))), progress)
+    }
+
+    //run, but handing over all of a work item's samples for a pixel at once, for
+    //callers that trace several together: samples(x, y, range, out) traces the samples
+    //in range for the pixel at (x, y), passing each colour to out in order.
+    pub fn run_batched<F, P>(&self, samples: F, progress: P) -> Vec<Color>
+    where
+        F: Fn(u64, u64, Range<u64>, &mut dyn FnMut(Color)) + Sync,
+        P: Fn(&Progress) + Sync,
+    {
+        self.averaged(&self.accumulate(samples, progress))
+    }
+
+    //Mean of the samples for each pixel, row-major from the top, from the running sums
+    //accumulate returns
+    pub fn averaged(&self, tiles: &[TileAccum]) -> Vec<Color> {
+        let mut framebuffer = vec![Color::new(0.0, 0.0, 0.0); (self.width * self.height) as usize];
+        for (tile, acc) in self.tiles.iter().zip(tiles) {
+            self.scatter_tile(&mut framebuffer, tile, acc.means());
+        }
+        framebuffer
+    }
+
+    //run_batched, but returning each tile's running sums rather than the means
+    pub fn accumulate<F, P>(&self, samples: F, progress: P) -> Vec<TileAccum>
+    where
+        F: Fn(u64, u64, Range<u64>, &mut dyn FnMut(Color)) + Sync,
+        P: Fn(&Progress) + Sync,
+    {
+        let accumulators: Vec<Mutex<TileAccum>> = match &self.resume {
+            Some(tiles) => tiles.iter().cloned().map(Mutex::new).collect(),
+            None => self.tiles.iter().map(|tile| Mutex::new(TileAccum::new(tile))).collect(),
+        };
+        //Samples each tile already had, which items only need to make up the rest of
+        let resumed: Vec<u64> = accumulators.iter().map(|acc| acc.lock().unwrap().samples).collect();
+        let resumed_samples = self.tiles.iter().zip(&resumed).enumerate()
+            .filter(|(index, _)| self.scheduled.contains(index))
+            .map(|(_, (tile, s))| tile.width * tile.height * s)
+            .sum();
+        let begun = Instant::now();
+        let done = AtomicUsize::new(self.items.iter()
+            .filter(|item| item.first_sample + item.samples <= resumed[item.tile])
+            .count());
+
+        //max_len(1) stops rayon from handing out long runs of items to one thread
+        self.items.par_iter().with_max_len(1).for_each(|item| {
+            let end = item.first_sample + item.samples;
+            let first_sample = item.first_sample.max(resumed[item.tile]);
+            if self.cancel.is_cancelled() || first_sample >= end {
+                return;
+            }
+            let item_samples = end - first_sample;
+            let started = stats::is_enabled().then(Instant::now);
+
+            let tile = self.tiles[item.tile];
+            let pixels = (tile.width * tile.height) as usize;
+            //Pixels still wanting samples, going by the batches finished so far
+            let active: Vec<bool> = match &self.adaptive {
+                None => vec![true; pixels],
+                Some(adaptive) => {
+                    let acc = accumulators[item.tile].lock().unwrap();
+                    (0..pixels).map(|k| !adaptive.converged(acc.sum[k], acc.sum_sq[k], acc.counts[k])).collect()
+                }
+            };
+            let mut local = vec![Color::new(0.0, 0.0, 0.0); pixels];
+            let mut local_sq = vec![0.0; pixels];
+
+            for y in 0..tile.height {
+                //Checked once a row so cancelling doesn't have to wait for whole tiles
+                if self.cancel.is_cancelled() {
+                    return;
+                }
+                for x in 0..tile.width {
+                    let k = (y * tile.width + x) as usize;
+                    if !active[k] {
+                        continue;
+                    }
+                    samples(tile.x0 + x, tile.y0 + y, first_sample..end, &mut |c| {
+                        local[k] += c;
+                        local_sq[k] += c.luminance() * c.luminance();
+                    });
+                }
+            }
+
+            let mut acc = accumulators[item.tile].lock().unwrap();
+            for k in (0..pixels).filter(|&k| active[k]) {
+                acc.sum[k] += local[k];
+                acc.sum_sq[k] += local_sq[k];
+                acc.counts[k] += item_samples;
+            }
+            acc.samples += item_samples;
+            drop(acc);
+            if let Some(started) = started {
+                stats::tile_time(item.tile, started.elapsed());
+            }
+
+            let finished = done.fetch_add(1, Ordering::Relaxed) + 1;
+            progress(&Progress {
+                done: finished,
+                total: self.items.len(),
+                tile: item.tile,
+                elapsed: begun.elapsed(),
+                resumed: resumed_samples,
+                snapshot: Snapshot { scheduler: self, accumulators: &accumulators },
+            });
+        });
+
+        accumulators.into_iter().map(|acc| acc.into_inner().unwrap()).collect()
+    }
+
+    //Render on a background thread, yielding each tile's state as work items finish
+    //with it instead of blocking until the whole image is done. Every tile shows up
+    //at most once per sample batch, each time with more samples than the last; the last
+    //update for a tile has samples == samples_per_pixel, unless the render was cancelled
+    //first.
+    pub fn stream<F>(self, sample: F) -> TileStream
+    where
+        F: Fn(u64, u64, u64) -> Color + Send + Sync + 'static,
+    {
+        self.stream_batched(move |x, y, samples, out| samples.for_each(|s| out(sample(x, y, s))))
+    }
+
+    //stream with the samples handed over as in run_batched
+    pub fn stream_batched<F>(self, samples: F) -> TileStream
+    where
+        F: Fn(u64, u64, Range<u64>, &mut dyn FnMut(Color)) + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            //Samples in the last update sent for each tile. Held while a tile's update is
+            //taken and sent, so batches of one tile finishing together can't send theirs
+            //out of order, or the same one twice.
+            let sent: Vec<Mutex<u64>> = self.tiles.iter().map(|_| Mutex::new(0)).collect();
+            self.run_batched(samples, |progress| {
+                let mut last = sent[progress.tile].lock().unwrap();
+                let update = progress.snapshot.tile(progress.tile);
+                if update.samples > *last {
+                    *last = update.samples;
+                    //The receiver going away just means nobody is listening any more
+                    let _ = sender.send(update);
+                }
+            });
+        });
+
+        TileStream { receiver, handle }
+    }
+}
+
+//Iterator over the tile updates of a render started with Scheduler::stream.
+//Dropping it early doesn't stop the render, it just stops the updates.
+pub struct TileStream {
+    receiver: Receiver<TileUpdate>,
+    handle: JoinHandle<()>,
+}
+
+impl TileStream {
+    //Whatever updates have arrived since the last call, without waiting for more.
+    //None once the render has finished and every update has been handed out.
+    pub fn pending(&mut self) -> Option<Vec<TileUpdate>> {
+        let mut updates = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(update) => updates.push(update),
+                Err(TryRecvError::Empty) => return Some(updates),
+                Err(TryRecvError::Disconnected) if updates.is_empty() => return None,
+                Err(TryRecvError::Disconnected) => return Some(updates),
+            }
+        }
+    }
+
+    //Wait for the render thread to exit. If it panicked the stream ends early, so
+    //call this after the last update to pass the panic on rather than carry on with
+    //a partial image.
+    pub fn finish(self) {
+        if let Err(panic) = self.handle.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+impl Iterator for TileStream {
+    type Item = TileUpdate;
+
+    fn next(&mut self) -> Option<TileUpdate> {
+        self.receiver.recv().ok()
+    }
+}
@@ -0,0 +1,257 @@
+//Four f64 lanes, for sphere batches (a ray against four spheres) and ray packets (four
+//rays against one box or sphere). On x86_64 that's two SSE2 registers, which every
+//x86_64 has; elsewhere plain arrays, for the compiler to vectorize if it can. Either
+//way each lane gives exactly what the same f64 sum would.
+
+#[cfg(target_arch = "x86_64")]
+pub use self::sse2::{F64x4, Mask};
+#[cfg(not(target_arch = "x86_64"))]
+pub use self::portable::{F64x4, Mask};
+
+
+
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use std::arch::x86_64::*;
+    use std::ops::{Add, BitAnd, BitOr, Div, Mul, Neg, Sub};
+
+    //Safety, for every intrinsic here: SSE2 is part of x86_64, so always there, and the
+    //loads and stores only touch values inside the slices they're given
+
+    #[derive(Clone, Copy)]
+    pub struct F64x4(__m128d, __m128d);
+
+    //All bits set in the lanes where a comparison held
+    #[derive(Clone, Copy)]
+    pub struct Mask(__m128d, __m128d);
+
+    impl F64x4 {
+        pub fn splat(x: f64) -> F64x4 {
+            unsafe { F64x4(_mm_set1_pd(x), _mm_set1_pd(x)) }
+        }
+
+        //The first four values of s
+        pub fn load(s: &[f64]) -> F64x4 {
+            let s = &s[..4];
+            unsafe { F64x4(_mm_loadu_pd(s.as_ptr()), _mm_loadu_pd(s[2..].as_ptr())) }
+        }
+
+        pub fn to_array(self) -> [f64; 4] {
+            let mut out = [0.0; 4];
+            unsafe {
+                _mm_storeu_pd(out.as_mut_ptr(), self.0);
+                _mm_storeu_pd(out[2..].as_mut_ptr(), self.1);
+            }
+            out
+        }
+
+        pub fn sqrt(self) -> F64x4 {
+            unsafe { F64x4(_mm_sqrt_pd(self.0), _mm_sqrt_pd(self.1)) }
+        }
+
+        //other in lanes where either is NaN
+        pub fn max(self, other: F64x4) -> F64x4 {
+            unsafe { F64x4(_mm_max_pd(self.0, other.0), _mm_max_pd(self.1, other.1)) }
+        }
+
+        //other in lanes where either is NaN
+        pub fn min(self, other: F64x4) -> F64x4 {
+            unsafe { F64x4(_mm_min_pd(self.0, other.0), _mm_min_pd(self.1, other.1)) }
+        }
+
+        //As f64::max, NaN only where both are
+        pub fn max_num(self, other: F64x4) -> F64x4 {
+            other.is_nan().select(self, self.max(other))
+        }
+
+        //As f64::min, NaN only where both are
+        pub fn min_num(self, other: F64x4) -> F64x4 {
+            other.is_nan().select(self, self.min(other))
+        }
+
+        pub fn is_nan(self) -> Mask {
+            unsafe { Mask(_mm_cmpunord_pd(self.0, self.0), _mm_cmpunord_pd(self.1, self.1)) }
+        }
+
+        pub fn ge(self, other: F64x4) -> Mask {
+            unsafe { Mask(_mm_cmpge_pd(self.0, other.0), _mm_cmpge_pd(self.1, other.1)) }
+        }
+
+        pub fn le(self, other: F64x4) -> Mask {
+            unsafe { Mask(_mm_cmple_pd(self.0, other.0), _mm_cmple_pd(self.1, other.1)) }
+        }
+    }
+
+    impl Mask {
+        //yes in the lanes that are set, no in the rest
+        pub fn select(self, yes: F64x4, no: F64x4) -> F64x4 {
+            let pick = |mask, yes, no| unsafe { _mm_or_pd(_mm_and_pd(mask, yes), _mm_andnot_pd(mask, no)) };
+            F64x4(pick(self.0, yes.0, no.0), pick(self.1, yes.1, no.1))
+        }
+
+        //Whether any lane is set
+        pub fn any(self) -> bool {
+            unsafe { _mm_movemask_pd(_mm_or_pd(self.0, self.1)) != 0 }
+        }
+
+        pub fn to_array(self) -> [bool; 4] {
+            let bits = unsafe { _mm_movemask_pd(self.0) | _mm_movemask_pd(self.1) << 2 };
+            std::array::from_fn(|l| bits >> l & 1 == 1)
+        }
+    }
+
+    impl BitAnd for Mask {
+        type Output = Mask;
+        fn bitand(self, other: Mask) -> Mask {
+            unsafe { Mask(_mm_and_pd(self.0, other.0), _mm_and_pd(self.1, other.1)) }
+        }
+    }
+
+    impl BitOr for Mask {
+        type Output = Mask;
+        fn bitor(self, other: Mask) -> Mask {
+            unsafe { Mask(_mm_or_pd(self.0, other.0), _mm_or_pd(self.1, other.1)) }
+        }
+    }
+
+    impl Neg for F64x4 {
+        type Output = F64x4;
+        //Flipping the sign bit, as - does for a single f64
+        fn neg(self) -> F64x4 {
+            unsafe {
+                let sign = _mm_set1_pd(-0.0);
+                F64x4(_mm_xor_pd(self.0, sign), _mm_xor_pd(self.1, sign))
+            }
+        }
+    }
+
+    macro_rules! lanewise {
+        ($trait:ident, $method:ident, $intrinsic:ident) => {
+            impl $trait for F64x4 {
+                type Output = F64x4;
+                fn $method(self, other: F64x4) -> F64x4 {
+                    unsafe { F64x4($intrinsic(self.0, other.0), $intrinsic(self.1, other.1)) }
+                }
+            }
+        };
+    }
+    lanewise!(Add, add, _mm_add_pd);
+    lanewise!(Sub, sub, _mm_sub_pd);
+    lanewise!(Mul, mul, _mm_mul_pd);
+    lanewise!(Div, div, _mm_div_pd);
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod portable {
+    use std::array;
+    use std::ops::{Add, BitAnd, BitOr, Div, Mul, Neg, Sub};
+
+    #[derive(Clone, Copy)]
+    pub struct F64x4([f64; 4]);
+
+    #[derive(Clone, Copy)]
+    pub struct Mask([bool; 4]);
+
+    impl F64x4 {
+        pub fn splat(x: f64) -> F64x4 {
+            F64x4([x; 4])
+        }
+
+        //The first four values of s
+        pub fn load(s: &[f64]) -> F64x4 {
+            F64x4(array::from_fn(|l| s[l]))
+        }
+
+        pub fn to_array(self) -> [f64; 4] {
+            self.0
+        }
+
+        pub fn sqrt(self) -> F64x4 {
+            F64x4(self.0.map(f64::sqrt))
+        }
+
+        //other in lanes where either is NaN, as SSE2 has it
+        pub fn max(self, other: F64x4) -> F64x4 {
+            F64x4(array::from_fn(|l| if self.0[l] > other.0[l] { self.0[l] } else { other.0[l] }))
+        }
+
+        //other in lanes where either is NaN, as SSE2 has it
+        pub fn min(self, other: F64x4) -> F64x4 {
+            F64x4(array::from_fn(|l| if self.0[l] < other.0[l] { self.0[l] } else { other.0[l] }))
+        }
+
+        //As f64::max, NaN only where both are
+        pub fn max_num(self, other: F64x4) -> F64x4 {
+            F64x4(array::from_fn(|l| self.0[l].max(other.0[l])))
+        }
+
+        //As f64::min, NaN only where both are
+        pub fn min_num(self, other: F64x4) -> F64x4 {
+            F64x4(array::from_fn(|l| self.0[l].min(other.0[l])))
+        }
+
+        pub fn is_nan(self) -> Mask {
+            Mask(self.0.map(f64::is_nan))
+        }
+
+        pub fn ge(self, other: F64x4) -> Mask {
+            Mask(array::from_fn(|l| self.0[l] >= other.0[l]))
+        }
+
+        pub fn le(self, other: F64x4) -> Mask {
+            Mask(array::from_fn(|l| self.0[l] <= other.0[l]))
+        }
+    }
+
+    impl Mask {
+        //yes in the lanes that are set, no in the rest
+        pub fn select(self, yes: F64x4, no: F64x4) -> F64x4 {
+            F64x4(array::from_fn(|l| if self.0[l] { yes.0[l] } else { no.0[l] }))
+        }
+
+        //Whether any lane is set
+        pub fn any(self) -> bool {
+            self.0.contains(&true)
+        }
+
+        pub fn to_array(self) -> [bool; 4] {
+            self.0
+        }
+    }
+
+    impl BitAnd for Mask {
+        type Output = Mask;
+        fn bitand(self, other: Mask) -> Mask {
+            Mask(array::from_fn(|l| self.0[l] && other.0[l]))
+        }
+    }
+
+    impl BitOr for Mask {
+        type Output = Mask;
+        fn bitor(self, other: Mask) -> Mask {
+            Mask(array::from_fn(|l| self.0[l] || other.0[l]))
+        }
+    }
+
+    impl Neg for F64x4 {
+        type Output = F64x4;
+        fn neg(self) -> F64x4 {
+            F64x4(self.0.map(|x| -x))
+        }
+    }
+
+    macro_rules! lanewise {
+        ($trait:ident, $method:ident, $op:tt) => {
+            impl $trait for F64x4 {
+                type Output = F64x4;
+                fn $method(self, other: F64x4) -> F64x4 {
+                    F64x4(array::from_fn(|l| self.0[l] $op other.0[l]))
+                }
+            }
+        };
+    }
+    lanewise!(Add, add, +);
+    lanewise!(Sub, sub, -);
+    lanewise!(Mul, mul, *);
+    lanewise!(Div, div, /);
+}
@@ -0,0 +1,70 @@
+use std::f64::consts::PI;
+
+use super::vec3::Vec3;
+
+
+
+//A place on Earth, for working out where the sun is in its sky at a given time, to light
+//a scene as it would be lit there. The scene's +y is up and by default -z is north, so
+//+x is east.
+pub struct Site {
+    //Degrees, north and east positive
+    latitude: f64,
+    longitude: f64,
+    //Hours the local clock is ahead of UTC
+    utc_offset: f64,
+    //Turn of north away from -z about the vertical, in radians
+    north: f64,
+}
+
+impl Site {
+    //On local solar time, unless given a UTC offset
+    pub fn new(latitude: f64, longitude: f64) -> Site {
+        Site { latitude: latitude.clamp(-90.0, 90.0), longitude, utc_offset: longitude / 15.0, north: 0.0 }
+    }
+
+    //Read clock times as hours ahead of UTC, e.g. 1 for British Summer Time
+    pub fn with_utc_offset(mut self, hours: f64) -> Site {
+        self.utc_offset = hours;
+        self
+    }
+
+    //Have north turned degrees from -z, anticlockwise seen from above, to match a scene
+    //not built facing north
+    pub fn with_north(mut self, degrees: f64) -> Site {
+        self.north = degrees.to_radians();
+        self
+    }
+
+    //Unit vector towards the sun on day (1 for the 1st of January) at hours on the local
+    //clock, from NOAA's fits for the equation of time and the sun's declination (good to
+    //a few minutes of arc). Below the horizon at night.
+    pub fn sun_direction(&self, day: u32, hours: f64) -> Vec3 {
+        //Fraction of the way through the year, in radians
+        let g = 2.0 * PI / 365.0 * (day as f64 - 1.0 + (hours - self.utc_offset - 12.0) / 24.0);
+        let equation_of_time = 229.18 * (0.000075 + 0.001868 * g.cos() - 0.032077 * g.sin()
+            - 0.014615 * (2.0 * g).cos() - 0.040849 * (2.0 * g).sin());
+        let declination = 0.006918 - 0.399912 * g.cos() + 0.070257 * g.sin() - 0.006758 * (2.0 * g).cos()
+            + 0.000907 * (2.0 * g).sin() - 0.002697 * (3.0 * g).cos() + 0.00148 * (3.0 * g).sin();
+
+        //True solar time in minutes, and how far the sun has turned from noon
+        let solar = hours * 60.0 + equation_of_time + 4.0 * self.longitude - 60.0 * self.utc_offset;
+        let hour_angle = (solar / 4.0 - 180.0).to_radians();
+        let latitude = self.latitude.to_radians();
+        let east = -declination.cos() * hour_angle.sin();
+        let north = declination.sin() * latitude.cos() - declination.cos() * latitude.sin() * hour_angle.cos();
+        let up = declination.sin() * latitude.sin() + declination.cos() * latitude.cos() * hour_angle.cos();
+
+        let (sin, cos) = self.north.sin_cos();
+        let (x, z) = (east, -north);
+        Vec3::new(cos * x + sin * z, up, -sin * x + cos * z).normalized()
+    }
+}
+
+//Day of the year, 1 to 366, for a date in the Gregorian calendar
+pub fn day_of_year(year: i32, month: u32, day: u32) -> u32 {
+    const BEFORE: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let month = month.clamp(1, 12);
+    BEFORE[month as usize - 1] + day + u32::from(leap && month > 2)
+}
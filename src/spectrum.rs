@@ -0,0 +1,91 @@
+use super::vec3::Color;
+
+
+
+//Rendering with wavelengths of light instead of red, green and blue. Each camera path
+//follows three wavelengths, spread evenly across the visible range from one picked at
+//random (hero wavelength sampling), and carries its radiance at those wavelengths in
+//the three components of a Color. Materials, lights and the background keep their RGB
+//colours, which are turned into spectra (Smits' method) as the path meets them, and the
+//path's radiance is turned back into RGB through the CIE 1931 colour matching functions
+//once it's done. Wavelengths are in micrometres throughout, as for Cauchy's equation.
+
+//The visible range the wavelengths are picked from
+pub const MIN_WAVELENGTH: f64 = 0.38;
+pub const MAX_WAVELENGTH: f64 = 0.72;
+
+//Smits' spectra for the primaries and their mixes, in ten even bins across the range.
+//Any RGB colour is white plus one of the secondaries plus one of the primaries.
+const WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+//CIE XYZ to linear sRGB
+const XYZ_TO_RGB: [[f64; 3]; 3] = [
+    [3.2406, -1.5372, -0.4986],
+    [-0.9689, 1.8758, 0.0415],
+    [0.0557, -0.2040, 1.0570],
+];
+//XYZ_TO_RGB times the matching functions' average over the range: what a flat spectrum
+//of 1 comes to. Dividing by it makes flat spectra white, as white is rendering in RGB.
+const FLAT: [f64; 3] = [0.377532, 0.298641, 0.285444];
+
+//The path's three wavelengths, from u in [0, 1)
+pub fn sample_wavelengths(u: f64) -> [f64; 3] {
+    let range = MAX_WAVELENGTH - MIN_WAVELENGTH;
+    std::array::from_fn(|k| MIN_WAVELENGTH + ((u + k as f64 / 3.0) % 1.0) * range)
+}
+
+//An RGB colour as a spectrum, at each of wavelengths
+pub fn upsample(c: Color, wavelengths: [f64; 3]) -> Color {
+    let (r, g, b) = (c[0], c[1], c[2]);
+    //Whichever component is smallest is white, the next is a secondary and the rest
+    //a primary
+    let mix: [(f64, &[f64; 10]); 3] = if r <= g && r <= b {
+        if g <= b { [(r, &WHITE), (g - r, &CYAN), (b - g, &BLUE)] } else { [(r, &WHITE), (b - r, &CYAN), (g - b, &GREEN)] }
+    } else if g <= b {
+        if r <= b { [(g, &WHITE), (r - g, &MAGENTA), (b - r, &BLUE)] } else { [(g, &WHITE), (b - g, &MAGENTA), (r - b, &RED)] }
+    } else if r <= g {
+        [(b, &WHITE), (r - b, &YELLOW), (g - r, &GREEN)]
+    } else {
+        [(b, &WHITE), (g - b, &YELLOW), (r - g, &RED)]
+    };
+
+    let at = |wavelength: f64| {
+        let bin = ((wavelength - MIN_WAVELENGTH) / (MAX_WAVELENGTH - MIN_WAVELENGTH) * 10.0).clamp(0.0, 9.0) as usize;
+        mix.iter().map(|(amount, spectrum)| amount * spectrum[bin]).sum()
+    };
+    Color::new(at(wavelengths[0]), at(wavelengths[1]), at(wavelengths[2]))
+}
+
+//A path's radiance at its wavelengths as linear RGB: its estimate of the colour
+pub fn to_rgb(radiance: Color, wavelengths: [f64; 3]) -> Color {
+    let mut xyz = [0.0; 3];
+    for k in 0..3 {
+        let matched = matching(wavelengths[k]);
+        for (total, m) in xyz.iter_mut().zip(matched) {
+            *total += radiance[k] * m / 3.0;
+        }
+    }
+    let rgb = |row: usize| XYZ_TO_RGB[row].iter().zip(xyz).map(|(m, c)| m * c).sum::<f64>() / FLAT[row];
+    Color::new(rgb(0), rgb(1), rgb(2))
+}
+
+//CIE 1931 colour matching functions x, y and z at a wavelength, from the piecewise
+//Gaussian fit of Wyman, Sloan and Shirley (2013)
+fn matching(wavelength: f64) -> [f64; 3] {
+    let nm = wavelength * 1000.0;
+    let g = |mean: f64, below: f64, above: f64| {
+        let t = (nm - mean) / if nm < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    ]
+}
@@ -1,8 +1,12 @@
+use std::f64::consts::PI;
 use std::sync::Arc;
 
+use super::aabb::Aabb;
 use super::hit::{Hit, HitRecord};
 use super::material::Scatter;
-use super::ray::Ray;
+use super::noise::Perlin;
+use super::ray::{self, Ray};
+use super::sampler::next_2d;
 use super::vec3::{Point3, Vec3};
 
 
@@ -19,6 +23,36 @@ impl Sphere{
     }
 }
 
+//Spherical UV mapping for a point p on the unit sphere.
+//u: angle around the y axis from x = -1, v: angle from y = -1 up to y = +1,
+//both scaled to [0, 1].
+pub fn sphere_uv(p: Vec3) -> (f64, f64) {
+    let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z()).atan2(p.x()) + std::f64::consts::PI;
+
+    (phi / (2.0 * std::f64::consts::PI), theta / std::f64::consts::PI)
+}
+
+//Distance across a sphere of radius per unit of sphere_uv at n: u runs round the
+//circle of latitude, v from pole to pole
+pub fn sphere_uv_size(radius: f64, n: Vec3) -> f64 {
+    let sin_theta = (1.0 - n.y() * n.y()).max(0.0).sqrt();
+    PI * radius.abs() * (2.0 * sin_theta).sqrt()
+}
+
+//Analytic tangent frame matching sphere_uv for a point n on the unit sphere.
+//dp/du is proportional to (z, 0, -x); at the poles that vanishes so any
+//perpendicular will do.
+pub fn sphere_tangents(n: Vec3) -> (Vec3, Vec3) {
+    let t = Vec3::new(n.z(), 0.0, -n.x());
+    let tangent = if t.length() < 1.0e-8 {
+        n.any_perpendicular()
+    } else {
+        t.normalized()
+    };
+    (tangent, n.cross(tangent))
+}
+
 //Can solve for whether hit a sphere via (P(t) - C) (P(t) - C) = r^2 
 //Where P(t) = A + tb is the ray, A origin, b direction, t variable.
 //C is the centre (Cx, Cy, Cz) of the sphere, r radius.
@@ -35,45 +69,259 @@ impl Sphere{
 impl Hit for Sphere {
     
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        hit_sphere(self.centre, self.radius, &self.mat, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(self.centre, self.radius))
+    }
+
+    fn sphere(&self) -> Option<(Point3, f64, Arc<dyn Scatter>)> {
+        Some((self.centre, self.radius, Arc::clone(&self.mat)))
+    }
+
+    //Uniform over the cone of directions from origin that hit the sphere
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        match cone_cos_max(self.centre, self.radius, origin) {
+            Some(cos_max) if self.hit(&Ray::new(origin, direction), ray::limits().epsilon, f64::INFINITY).is_some() => {
+                1.0 / (2.0 * PI * (1.0 - cos_max))
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        let axis = self.centre - origin;
+        let Some(cos_max) = cone_cos_max(self.centre, self.radius, origin) else {
+            return axis;
+        };
+        let (r1, r2) = next_2d();
+        let z = 1.0 + r1 * (cos_max - 1.0);
+        let phi = 2.0 * PI * r2;
+        let sin = (1.0 - z * z).max(0.0).sqrt();
+
+        let w = axis.normalized();
+        let u = w.any_perpendicular();
+        let v = w.cross(u);
+        phi.cos() * sin * u + phi.sin() * sin * v + z * w
+    }
+}
+
+//Cosine of the half-angle of the cone that the sphere fills seen from origin, None
+//from inside it
+fn cone_cos_max(centre: Point3, radius: f64, origin: Point3) -> Option<f64> {
+    let distance_squared = (centre - origin).dot(centre - origin);
+    let ratio = radius * radius / distance_squared;
+    (ratio < 1.0).then(|| (1.0 - ratio).sqrt())
+}
+
+fn hit_sphere(centre: Point3, radius: f64, mat: &Arc<dyn Scatter>, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    let x = r.origin() - centre;
+    let a  = r.direction().length().powi(2);
+    let half_b = r.direction().dot(x);
+    let c = x.length().powi(2) - radius * radius;
+    let discrim = half_b * half_b - a * c;
+
+    //Doesn't hit
+    if discrim < 0.0 { return None }
+    
+    //Get nearest root in acceptable range (in front of camera)
+    let sqrtd = discrim.sqrt();
+    let mut root = (-half_b - sqrtd) / a;
+    if root < t_min || root > t_max {
+        root = (-half_b + sqrtd) / a;
+        if root < t_min || root > t_max {
+            return None
+        }
+    }
+
+    //Calc the outward surface norm and determine whether ray 
+    //is hitting from front or back
+    //Since p - centre gives vec from centre of sphere to p, 
+    //div by radius will normalize.
+    let outward_normal = (r.at(root) - centre) / radius;
+    let (u, v) = sphere_uv(outward_normal);
+    let mut rec = HitRecord::new(r, root, outward_normal, Arc::clone(mat), u, v);
+    (rec.tangent, rec.bitangent) = sphere_tangents(outward_normal);
+    rec.uv_size = sphere_uv_size(radius, outward_normal);
+
+    Some(rec)
+}
+
+fn sphere_box(centre: Point3, radius: f64) -> Aabb {
+    //Negative radii (inside-out spheres) are the same size
+    let r = radius.abs();
+    let extent = Vec3::new(r, r, r);
+    Aabb::new(centre - extent, centre + extent)
+}
+
+//Sphere whose centre moves in a straight line from centre0 at time0 to centre1 at
+//time1. Seen through a camera with its shutter open it comes out motion blurred. Its
+//bounding box only covers that interval, so the shutter should stay inside it.
+pub struct MovingSphere {
+    centre0: Point3,
+    centre1: Point3,
+    time0: f64,
+    time1: f64,
+    radius: f64,
+    mat: Arc<dyn Scatter>,
+}
+
+impl MovingSphere {
+    pub fn new(centre0: Point3, centre1: Point3, time0: f64, time1: f64, radius: f64, mat: Arc<dyn Scatter>) -> MovingSphere {
+        MovingSphere { centre0, centre1, time0, time1, radius, mat }
+    }
+
+    fn centre(&self, time: f64) -> Point3 {
+        if self.time1 == self.time0 {
+            return self.centre0;
+        }
+        self.centre0 + ((time - self.time0) / (self.time1 - self.time0)) * (self.centre1 - self.centre0)
+    }
+}
+
+impl Hit for MovingSphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        hit_sphere(self.centre(r.time()), self.radius, &self.mat, r, t_min, t_max)
+    }
+
+    //Everywhere it gets to between time0 and time1
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(self.centre0, self.radius).surrounding(&sphere_box(self.centre1, self.radius)))
+    }
+}
+//Sphere with its surface pushed in and out along the radius by fBm noise, for rocks,
+//asteroids and the like: the surface is where distance from the centre is radius +
+//amplitude * fbm(frequency * p). Found by sphere tracing, stepping along the ray by
+//the distance to the plain sphere scaled down by how steep the noise can get, so it's
+//a good deal slower than a plain sphere. Keep amplitude * frequency * octaves small
+//(around 1 or less) or the steps get tiny.
+pub struct DisplacedSphere {
+    centre: Point3,
+    radius: f64,
+    amplitude: f64,
+    frequency: f64,
+    octaves: u32,
+    noise: Perlin,
+    mat: Arc<dyn Scatter>,
+}
+
+impl DisplacedSphere {
+    //Most steps a ray takes looking for the surface
+    const MAX_STEPS: usize = 512;
+
+    pub fn new(centre: Point3, radius: f64, amplitude: f64, frequency: f64, mat: Arc<dyn Scatter>) -> DisplacedSphere {
+        DisplacedSphere { centre, radius, amplitude, frequency, octaves: 4, noise: Perlin::new(0), mat }
+    }
+
+    //More octaves add finer detail, 4 by default
+    pub fn with_octaves(mut self, octaves: u32) -> DisplacedSphere {
+        self.octaves = octaves;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> DisplacedSphere {
+        self.noise = Perlin::new(seed);
+        self
+    }
+
+    //Signed distance to the surface along the radius, positive outside. Not a true
+    //distance, see steepness.
+    fn height(&self, p: Point3) -> f64 {
+        let offset = p - self.centre;
+        let displacement = self.amplitude * self.noise.fbm(self.frequency * offset, self.octaves, 2.0, 0.5);
+        offset.length() - self.radius - displacement
+    }
+
+    //How much faster than distance height can change. Each octave's noise changes by
+    //up to about 2.5 per unit at its frequency, and with fbm's halving amplitudes and
+    //doubling frequencies every octave adds the same, before fbm divides by the sum of
+    //the amplitudes.
+    fn steepness(&self) -> f64 {
+        let octaves = self.octaves.max(1);
+        let amplitudes = 2.0 * (1.0 - 0.5f64.powi(octaves as i32));
+        1.0 + 2.5 * self.amplitude.abs() * self.frequency * octaves as f64 / amplitudes
+    }
+
+    fn normal(&self, p: Point3) -> Vec3 {
+        let h = 1.0e-4 * self.radius;
+        let d = |axis: Vec3| self.height(p + h * axis) - self.height(p - h * axis);
+        Vec3::new(d(Vec3::new(1.0, 0.0, 0.0)), d(Vec3::new(0.0, 1.0, 0.0)), d(Vec3::new(0.0, 0.0, 1.0))).normalized()
+    }
+}
+
+impl Hit for DisplacedSphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        //Only look inside the sphere the surface can reach
+        let outer = self.radius + self.amplitude.abs();
         let x = r.origin() - self.centre;
-        let a  = r.direction().length().powi(2);
+        let a = r.direction().dot(r.direction());
         let half_b = r.direction().dot(x);
-        let c = x.length().powi(2) - self.radius * self.radius;
-        let discrim = half_b * half_b - a * c;
-
-        //Doesn't hit
-        if discrim < 0.0 { return None }
-        
-        //Get nearest root in acceptable range (in front of camera)
+        let discrim = half_b * half_b - a * (x.dot(x) - outer * outer);
+        if discrim < 0.0 {
+            return None;
+        }
         let sqrtd = discrim.sqrt();
-        let mut root = (-half_b - sqrtd) / a;
-        if root < t_min || root > t_max {
-            root = (-half_b + sqrtd) / a;
-            if root < t_min || root > t_max {
-                return None
+        let mut t = ((-half_b - sqrtd) / a).max(t_min);
+        let end = ((-half_b + sqrtd) / a).min(t_max);
+
+        let speed = a.sqrt();
+        let epsilon = 1.0e-5 * self.radius;
+        let steepness = self.steepness();
+        //Outside the shell the noise can reach, the distance to the shell is a safe step
+        let step = |p: Point3, height: f64| {
+            let to_shell = (p - self.centre).length() - outer;
+            (height.abs() / steepness).max(to_shell) / speed
+        };
+        //Which side the ray starts on. Starting on the surface, as scattered rays do,
+        //it's whichever side it's heading for.
+        let mut height = self.height(r.at(t));
+        let outside = if height.abs() > epsilon {
+            height > 0.0
+        } else {
+            self.height(r.at(t + 4.0 * epsilon / speed)) > height
+        };
+
+        let mut last = t;
+        for _ in 0..Self::MAX_STEPS {
+            if t > end {
+                return None;
+            }
+            if (height > 0.0) != outside && height.abs() > epsilon {
+                break;
             }
+            last = t;
+            t += step(r.at(t), height).max(epsilon / speed);
+            height = self.height(r.at(t));
+        }
+        if (height > 0.0) == outside {
+            return None;
         }
 
-        let p = r.at(root);
-
-        //Get the surface normal
-        //Since p - centre gives vec from centre of sphere to p, 
-        //div by radius will normalize.
-        let normal = (p - self.centre) / self.radius;
-        
-        let mut rec = HitRecord {
-            p: r.at(root),
-            normal: Vec3::new(0.0, 0.0, 0.0),
-            t: root,
-            mat: Arc::clone(&self.mat),
-            front_face: false,
-        };
-        
-        //Calc the outward surface norm and determine whether ray 
-        //is hitting from front or back
-        let outward_normal = (rec.p - self.centre) / self.radius;
-        rec.set_face_normal(r, outward_normal);
+        //Crossed between last and t: narrow it down
+        let (mut lo, mut hi) = (last, t);
+        while (hi - lo) * speed > 0.1 * epsilon {
+            let mid = 0.5 * (lo + hi);
+            if (self.height(r.at(mid)) > 0.0) == outside {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let t = hi;
+        if t < t_min || t > t_max {
+            return None;
+        }
 
+        let p = r.at(t);
+        let radial = (p - self.centre).normalized();
+        let (u, v) = sphere_uv(radial);
+        let mut rec = HitRecord::new(r, t, self.normal(p), Arc::clone(&self.mat), u, v);
+        (rec.tangent, rec.bitangent) = sphere_tangents(radial);
         Some(rec)
     }
-}
\ No newline at end of file
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(self.centre, self.radius + self.amplitude.abs()))
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord};
+use super::material::Scatter;
+use super::packet::{RayPacket, PACKET};
+use super::ray::Ray;
+use super::simd::F64x4;
+use super::sphere::{sphere_tangents, sphere_uv, sphere_uv_size};
+use super::vec3::{Point3, Vec3, Vec3x4};
+
+
+
+//Number of spheres intersected together, one to a lane of an F64x4
+pub const LANES: usize = 4;
+
+//Structure-of-arrays storage for lots of spheres. Rather than a Box<dyn Hit> per
+//sphere (a virtual call and a pointer chase each), the centres and radii are packed
+//into flat arrays and a ray is tested against LANES spheres at a time with SIMD.
+//bvh::accelerate packs neighbouring spheres into these, so BVH leaves use them too.
+//The arrays are padded up to a multiple of LANES with dummy spheres that can never be hit.
+#[derive(Default)]
+pub struct SphereBatch {
+    cx: Vec<f64>,
+    cy: Vec<f64>,
+    cz: Vec<f64>,
+    radius: Vec<f64>,
+    radius_sq: Vec<f64>,
+    mats: Vec<Arc<dyn Scatter>>,
+}
+
+impl SphereBatch {
+    pub fn new() -> SphereBatch {
+        SphereBatch::default()
+    }
+
+    pub fn push(&mut self, centre: Point3, radius: f64, mat: Arc<dyn Scatter>) {
+        let n = self.mats.len();
+
+        //Grow by a whole chunk of padding when needed, then overwrite the next free slot
+        if n.is_multiple_of(LANES) {
+            for _ in 0..LANES {
+                //A sphere at the origin with r^2 = -1 gives c = |A|^2 + 1, and by
+                //Cauchy-Schwarz the discriminant (b.A)^2 - b^2 * c is then always negative
+                self.cx.push(0.0);
+                self.cy.push(0.0);
+                self.cz.push(0.0);
+                self.radius.push(1.0);
+                self.radius_sq.push(-1.0);
+            }
+        }
+
+        self.cx[n] = centre.x();
+        self.cy[n] = centre.y();
+        self.cz[n] = centre.z();
+        self.radius[n] = radius;
+        self.radius_sq[n] = radius * radius;
+        self.mats.push(mat);
+    }
+
+    fn record(&self, r: &Ray, i: usize, t: f64) -> HitRecord {
+        let centre = Point3::new(self.cx[i], self.cy[i], self.cz[i]);
+        let outward_normal = (r.at(t) - centre) / self.radius[i];
+        let (u, v) = sphere_uv(outward_normal);
+        let mut rec = HitRecord::new(r, t, outward_normal, Arc::clone(&self.mats[i]), u, v);
+        (rec.tangent, rec.bitangent) = sphere_tangents(outward_normal);
+        rec.uv_size = sphere_uv_size(self.radius[i], outward_normal);
+        rec
+    }
+}
+
+//The same maths as Sphere::hit, operation for operation so batching doesn't change
+//the image, done for LANES spheres at once with selects instead of early returns.
+impl Hit for SphereBatch {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (o, d) = (r.origin(), r.direction());
+        let (ox, oy, oz) = (F64x4::splat(o.x()), F64x4::splat(o.y()), F64x4::splat(o.z()));
+        let (dx, dy, dz) = (F64x4::splat(d.x()), F64x4::splat(d.y()), F64x4::splat(d.z()));
+        let a = F64x4::splat(d.length().powi(2));
+        //A miss is NaN, which is never <= anything, so never taken as the closest
+        let (zero, lowest, miss) = (F64x4::splat(0.0), F64x4::splat(t_min), F64x4::splat(f64::NAN));
+
+        let mut closest = t_max;
+        let mut best: Option<usize> = None;
+
+        for base in (0..self.cx.len()).step_by(LANES) {
+            let x0 = ox - F64x4::load(&self.cx[base..]);
+            let x1 = oy - F64x4::load(&self.cy[base..]);
+            let x2 = oz - F64x4::load(&self.cz[base..]);
+
+            let half_b = dx * x0 + dy * x1 + dz * x2;
+            let length = (x0 * x0 + x1 * x1 + x2 * x2).sqrt();
+            let c = length * length - F64x4::load(&self.radius_sq[base..]);
+            let discrim = half_b * half_b - a * c;
+
+            let sqrtd = discrim.max(zero).sqrt();
+            let near = (-half_b - sqrtd) / a;
+            let far = (-half_b + sqrtd) / a;
+
+            let highest = F64x4::splat(closest);
+            let hits = discrim.ge(zero);
+            let near_ok = hits & near.ge(lowest) & near.le(highest);
+            let far_ok = hits & far.ge(lowest) & far.le(highest);
+            let t = near_ok.select(near, far_ok.select(far, miss));
+
+            //<= so that, as with separate spheres, the later of two the same distance away wins
+            for (l, tl) in t.to_array().into_iter().enumerate() {
+                if tl <= closest {
+                    closest = tl;
+                    best = Some(base + l);
+                }
+            }
+        }
+
+        Some(self.record(r, best?, closest))
+    }
+
+    //The other way round from hit: each sphere against every ray of the packet at
+    //once, so it's the rays that fill the lanes
+    fn hit_packet(&self, packet: &RayPacket, t_min: f64, t_max: [f64; PACKET]) -> [Option<HitRecord>; PACKET] {
+        let length = packet.direction.length();
+        let a = length * length;
+        let (zero, lowest) = (F64x4::splat(0.0), F64x4::splat(t_min));
+
+        let mut closest = F64x4::load(&t_max);
+        let mut best = [usize::MAX; PACKET];
+        for i in 0..self.mats.len() {
+            let x = packet.origin - Vec3x4::splat(Point3::new(self.cx[i], self.cy[i], self.cz[i]));
+
+            let half_b = packet.direction.dot(x);
+            let length = x.length();
+            let c = length * length - F64x4::splat(self.radius_sq[i]);
+            let discrim = half_b * half_b - a * c;
+
+            let sqrtd = discrim.max(zero).sqrt();
+            let near = (-half_b - sqrtd) / a;
+            let far = (-half_b + sqrtd) / a;
+
+            let hits = discrim.ge(zero);
+            let near_ok = hits & near.ge(lowest) & near.le(closest);
+            let far_ok = hits & far.ge(lowest) & far.le(closest);
+            closest = near_ok.select(near, far_ok.select(far, closest));
+            for (l, hit) in (near_ok | far_ok).to_array().into_iter().enumerate() {
+                if hit {
+                    best[l] = i;
+                }
+            }
+        }
+
+        let closest = closest.to_array();
+        std::array::from_fn(|l| (best[l] != usize::MAX).then(|| self.record(&packet.rays[l], best[l], closest[l])))
+    }
+
+    //Padding spheres aren't real, so only look at the ones that were pushed
+    fn bounding_box(&self) -> Option<Aabb> {
+        (0..self.mats.len()).map(|i| {
+            let centre = Point3::new(self.cx[i], self.cy[i], self.cz[i]);
+            let r = self.radius[i].abs();
+            Aabb::new(centre - Vec3::new(r, r, r), centre + Vec3::new(r, r, r))
+        }).reduce(|a, b| a.surrounding(&b))
+    }
+}
@@ -0,0 +1,171 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+
+
+//Counts of what a render did, for finding out where the time goes. Counting is off
+//until enable is called, and then each thread counts into counters of its own, which
+//only it writes to, so threads don't slow each other down; report adds them up.
+#[derive(Clone, Copy)]
+pub enum Counter {
+    //Rays the camera sent out, one per sample
+    CameraRays,
+    //Rays traced to find what they hit: camera rays, scattered rays, rays carrying on
+    //through boundaries and rays sent to sample emitters
+    Rays,
+    //Rays only asking whether anything is in the way of a light
+    ShadowRays,
+    //BVH nodes whose boxes were tested, by single rays and packets
+    BvhNodes,
+    //Times a path scattered off a surface or in a medium and carried on
+    Bounces,
+}
+
+const COUNTERS: usize = 5;
+
+type Counts = [AtomicU64; COUNTERS];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+//Every thread's counters, so they can be added up from anywhere
+static THREADS: Mutex<Vec<Arc<Counts>>> = Mutex::new(Vec::new());
+//How long each tile has taken, over all its work items, by tile index
+static TILES: Mutex<Vec<Duration>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static LOCAL: RefCell<Option<Arc<Counts>>> = const { RefCell::new(None) };
+}
+
+//Start (or stop) counting. Counts carry on from where they were, see reset.
+pub fn enable(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+//Add n to counter, if counting
+#[inline]
+pub fn add(counter: Counter, n: u64) {
+    if !is_enabled() {
+        return;
+    }
+    LOCAL.with(|local| {
+        let mut local = local.borrow_mut();
+        let counts = local.get_or_insert_with(|| {
+            let counts = Arc::new(Counts::default());
+            THREADS.lock().unwrap().push(Arc::clone(&counts));
+            counts
+        });
+        //Only this thread writes to its counters, so there's no need for fetch_add
+        let count = &counts[counter as usize];
+        count.store(count.load(Ordering::Relaxed) + n, Ordering::Relaxed);
+    });
+}
+
+pub fn count(counter: Counter) {
+    add(counter, 1);
+}
+
+//Add time spent on tile, if counting
+pub fn tile_time(tile: usize, time: Duration) {
+    if !is_enabled() {
+        return;
+    }
+    let mut tiles = TILES.lock().unwrap();
+    if tiles.len() <= tile {
+        tiles.resize(tile + 1, Duration::ZERO);
+    }
+    tiles[tile] += time;
+}
+
+//Back to nothing counted
+pub fn reset() {
+    for counts in THREADS.lock().unwrap().iter() {
+        counts.iter().for_each(|c| c.store(0, Ordering::Relaxed));
+    }
+    TILES.lock().unwrap().clear();
+}
+
+//Everything counted so far, over all threads
+pub fn report() -> Report {
+    let mut totals = [0; COUNTERS];
+    for counts in THREADS.lock().unwrap().iter() {
+        for (total, c) in totals.iter_mut().zip(counts.iter()) {
+            *total += c.load(Ordering::Relaxed);
+        }
+    }
+    Report {
+        camera_rays: totals[Counter::CameraRays as usize],
+        rays: totals[Counter::Rays as usize],
+        shadow_rays: totals[Counter::ShadowRays as usize],
+        bvh_nodes: totals[Counter::BvhNodes as usize],
+        bounces: totals[Counter::Bounces as usize],
+        tiles: TILES.lock().unwrap().clone(),
+    }
+}
+
+//What report found, see Counter for what each count is
+#[derive(Clone)]
+pub struct Report {
+    pub camera_rays: u64,
+    pub rays: u64,
+    pub shadow_rays: u64,
+    pub bvh_nodes: u64,
+    pub bounces: u64,
+    //Time spent on each tile by index, summed over threads (so they add up to more
+    //than the render took with more than one thread)
+    pub tiles: Vec<Duration>,
+}
+
+impl Report {
+    //Mean surfaces (or medium events) a camera ray's path scattered off
+    pub fn mean_path_depth(&self) -> f64 {
+        self.bounces as f64 / self.camera_rays.max(1) as f64
+    }
+
+    //Mean BVH nodes visited per ray, shadow rays included
+    pub fn nodes_per_ray(&self) -> f64 {
+        self.bvh_nodes as f64 / (self.rays + self.shadow_rays).max(1) as f64
+    }
+
+    //Quickest, mean and slowest tile, in seconds, and the slowest tile's index
+    fn tile_times(&self) -> Option<(f64, f64, f64, usize)> {
+        let (slowest, max) = self.tiles.iter().enumerate().max_by_key(|&(_, t)| *t)?;
+        let min = self.tiles.iter().min()?.as_secs_f64();
+        let mean = self.tiles.iter().sum::<Duration>().as_secs_f64() / self.tiles.len() as f64;
+        Some((min, mean, max.as_secs_f64(), slowest))
+    }
+
+    //For reading, given how long the render took
+    pub fn summary(&self, elapsed: Duration) -> String {
+        let seconds = elapsed.as_secs_f64().max(1.0e-9);
+        let all_rays = self.rays + self.shadow_rays;
+        let mut lines = vec![
+            format!("Render time:      {:.3} s", seconds),
+            format!("Camera rays:      {}", self.camera_rays),
+            format!("Rays cast:        {} ({:.2} M/s)", self.rays, self.rays as f64 / seconds / 1.0e6),
+            format!("Shadow rays:      {}", self.shadow_rays),
+            format!("All rays:         {} ({:.2} M/s)", all_rays, all_rays as f64 / seconds / 1.0e6),
+            format!("BVH nodes:        {} ({:.1} per ray)", self.bvh_nodes, self.nodes_per_ray()),
+            format!("Mean path depth:  {:.2}", self.mean_path_depth()),
+        ];
+        if let Some((min, mean, max, slowest)) = self.tile_times() {
+            lines.push(format!("Tile times:       {:.4} s min, {:.4} s mean, {:.4} s max (tile {})", min, mean, max, slowest));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    //summary as a JSON object, with tile times in seconds
+    pub fn to_json(&self, elapsed: Duration) -> String {
+        let tiles: Vec<String> = self.tiles.iter().map(|t| format!("{:.6}", t.as_secs_f64())).collect();
+        format!(
+            "{{\"render_seconds\": {:.6}, \"camera_rays\": {}, \"rays\": {}, \"shadow_rays\": {}, \"bvh_nodes\": {}, \
+             \"bounces\": {}, \"mean_path_depth\": {:.6}, \"nodes_per_ray\": {:.6}, \"tile_seconds\": [{}]}}\n",
+            elapsed.as_secs_f64(), self.camera_rays, self.rays, self.shadow_rays, self.bvh_nodes,
+            self.bounces, self.mean_path_depth(), self.nodes_per_ray(), tiles.join(", "),
+        )
+    }
+}
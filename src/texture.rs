@@ -0,0 +1,541 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::hit::HitRecord;
+use super::image::Image;
+use super::noise::{self, Perlin, WorleyMode};
+use super::vec3::{Color, Point3, Vec3};
+
+
+
+pub trait Texture: Send + Sync {
+    //Colour at surface coords (u, v) / hit point p
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+
+    //What materials actually call. Textures that need more of the hit than
+    //(u, v, p) - e.g. vertex colours - override this instead.
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.value(rec.u, rec.v, rec.p)
+    }
+
+    //The colour everywhere, for textures that are one colour all over
+    fn constant(&self) -> Option<Color> {
+        None
+    }
+}
+
+
+
+//Interpolated vertex colours from the mesh that was hit. Anything without vertex
+//colours (or a plain (u, v, p) lookup) gets the fallback colour.
+pub struct VertexColor {
+    fallback: Color,
+}
+
+impl VertexColor {
+    pub fn new(fallback: Color) -> VertexColor {
+        VertexColor { fallback }
+    }
+}
+
+impl Texture for VertexColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        self.fallback
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        rec.vertex_color.unwrap_or(self.fallback)
+    }
+}
+
+
+
+//Placement of a texture on the surface: (u, v) is scaled, then rotated
+//counter-clockwise about the UV origin, then offset. Scaling by n tiles the texture n times.
+#[derive(Clone, Copy)]
+pub struct UvTransform {
+    scale: (f64, f64),
+    offset: (f64, f64),
+    //Stored as (sin, cos) of the rotation angle
+    rotation: (f64, f64),
+}
+
+impl UvTransform {
+    pub fn new(scale: (f64, f64), offset: (f64, f64), rotation_degrees: f64) -> UvTransform {
+        UvTransform {
+            scale,
+            offset,
+            rotation: rotation_degrees.to_radians().sin_cos(),
+        }
+    }
+
+    pub fn identity() -> UvTransform {
+        UvTransform::new((1.0, 1.0), (0.0, 0.0), 0.0)
+    }
+
+    pub fn apply(&self, u: f64, v: f64) -> (f64, f64) {
+        let su = u * self.scale.0;
+        let sv = v * self.scale.1;
+        let (sin, cos) = self.rotation;
+
+        (cos * su - sin * sv + self.offset.0, sin * su + cos * sv + self.offset.1)
+    }
+
+    //How many times bigger things get in (u, v), on average over the two directions
+    pub fn magnification(&self) -> f64 {
+        (self.scale.0 * self.scale.1).abs().sqrt()
+    }
+}
+
+//How the values a texture produces should be interpreted.
+//Colour maps are normally authored in sRGB and need decoding before they're used
+//in lighting maths; data maps (roughness, normals, masks) are already linear and
+//must be left alone.
+#[derive(Clone, Copy)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn to_linear(self, c: Color) -> Color {
+        match self {
+            ColorSpace::Linear => c,
+            ColorSpace::Srgb => Color::new(srgb_to_linear(c[0]), srgb_to_linear(c[1]), srgb_to_linear(c[2])),
+        }
+    }
+}
+
+//Standard sRGB EOTF, per channel
+fn srgb_to_linear(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+//Wraps any texture with a UV transform and a colour space flag, so the same
+//texture can be tiled/rotated differently on different objects without re-authoring it.
+pub struct MappedTexture {
+    inner: Arc<dyn Texture>,
+    uv: UvTransform,
+    space: ColorSpace,
+}
+
+impl MappedTexture {
+    pub fn new(inner: Arc<dyn Texture>, uv: UvTransform, space: ColorSpace) -> MappedTexture {
+        MappedTexture { inner, uv, space }
+    }
+}
+
+impl Texture for MappedTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let (u, v) = self.uv.apply(u, v);
+        self.space.to_linear(self.inner.value(u, v, p))
+    }
+
+    //Passed on as a whole hit, so the inner texture still gets the footprint (and
+    //anything else it looks at)
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        let mut rec = rec.clone();
+        (rec.u, rec.v) = self.uv.apply(rec.u, rec.v);
+        rec.uv_size /= self.uv.magnification();
+        self.space.to_linear(self.inner.value_at(&rec))
+    }
+}
+
+
+
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> SolidColor {
+        SolidColor { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        self.color
+    }
+
+    fn constant(&self) -> Option<Color> {
+        Some(self.color)
+    }
+}
+
+
+
+//3D checkerboard of two textures, in cubes of side 1 / scale. Being solid rather than
+//in UV space it needs no parameterization, and doesn't pinch at a sphere's poles.
+pub struct Checker {
+    scale: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl Checker {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Checker {
+        Checker { scale, even, odd }
+    }
+
+    pub fn from_colors(scale: f64, even: Color, odd: Color) -> Checker {
+        Checker::new(scale, Arc::new(SolidColor::new(even)), Arc::new(SolidColor::new(odd)))
+    }
+
+    fn is_even(&self, p: Point3) -> bool {
+        let q = self.scale * p;
+        let sum = q.x().floor() as i64 + q.y().floor() as i64 + q.z().floor() as i64;
+        sum.rem_euclid(2) == 0
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        if self.is_even(p) {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+
+    //Averaged over the hit's footprint, so squares too small to see fade to a mix of the
+    //two instead of flickering between them
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        let even = self.even_fraction(rec.p, rec.normal, rec.footprint);
+        if even >= 1.0 {
+            self.even.value_at(rec)
+        } else if even <= 0.0 {
+            self.odd.value_at(rec)
+        } else {
+            even * self.even.value_at(rec) + (1.0 - even) * self.odd.value_at(rec)
+        }
+    }
+}
+
+impl Checker {
+    //How much of a patch width across round p, on a surface facing normal, is even
+    //squares. The checkerboard is the product of a square wave (+1 on even cells, -1 on
+    //odd) along each axis, so its mean over the patch is about the product of each wave's
+    //mean over as much of the patch as lies along that axis, which comes from the wave's
+    //integral, a triangle wave.
+    fn even_fraction(&self, p: Point3, normal: Vec3, width: f64) -> f64 {
+        if self.scale * width < 1.0e-9 {
+            return if self.is_even(p) { 1.0 } else { 0.0 };
+        }
+        let integral = |x: f64| {
+            let m = x.rem_euclid(2.0);
+            if m < 1.0 { m } else { 2.0 - m }
+        };
+        let q = self.scale * p;
+        let mean = (0..3).map(|i| {
+            let w = self.scale * width * (1.0 - normal[i] * normal[i]).max(0.0).sqrt();
+            if w < 1.0e-9 {
+                if (q[i].floor() as i64).rem_euclid(2) == 0 { 1.0 } else { -1.0 }
+            } else {
+                (integral(q[i] + w / 2.0) - integral(q[i] - w / 2.0)) / w
+            }
+        }).product::<f64>();
+        0.5 + 0.5 * mean
+    }
+}
+
+
+
+//Which coordinate a gradient ramps along
+#[derive(Clone, Copy)]
+pub enum GradientAxis {
+    U,
+    V,
+    //World space, measured as p . dir so the length of dir scales the ramp
+    Along(Vec3),
+}
+
+//Piecewise-linear colour ramp. Stops are (position, colour) and are sorted on
+//construction; positions outside the first/last stop clamp to the end colours.
+pub struct Gradient {
+    stops: Vec<(f64, Color)>,
+    axis: GradientAxis,
+}
+
+impl Gradient {
+    pub fn new(mut stops: Vec<(f64, Color)>, axis: GradientAxis) -> Gradient {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Gradient { stops, axis }
+    }
+
+    fn sample(&self, x: f64) -> Color {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Color::new(0.0, 0.0, 0.0),
+        };
+        if x <= first.0 {
+            return first.1;
+        }
+        if x >= last.0 {
+            return last.1;
+        }
+
+        for w in self.stops.windows(2) {
+            let (x0, c0) = w[0];
+            let (x1, c1) = w[1];
+            if x <= x1 {
+                let t = if x1 > x0 { (x - x0) / (x1 - x0) } else { 1.0 };
+                return (1.0 - t) * c0 + t * c1;
+            }
+        }
+        last.1
+    }
+}
+
+impl Texture for Gradient {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let x = match self.axis {
+            GradientAxis::U => u,
+            GradientAxis::V => v,
+            GradientAxis::Along(dir) => p.dot(dir),
+        };
+        self.sample(x)
+    }
+}
+
+
+
+//Fractal Brownian motion: octaves of Perlin noise, each at lacunarity times the
+//frequency and gain times the amplitude of the last. The sum is remapped to [0, 1]
+//and used to blend between two colours.
+pub struct Fbm {
+    noise: Perlin,
+    scale: f64,
+    octaves: u32,
+    lacunarity: f64,
+    gain: f64,
+    low: Color,
+    high: Color,
+}
+
+impl Fbm {
+    pub fn new(scale: f64, octaves: u32, lacunarity: f64, gain: f64, low: Color, high: Color, seed: u64) -> Fbm {
+        Fbm { noise: Perlin::new(seed), scale, octaves, lacunarity, gain, low, high }
+    }
+}
+
+impl Texture for Fbm {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let sum = self.noise.fbm(self.scale * p, self.octaves, self.lacunarity, self.gain);
+        let t = (0.5 * (sum + 1.0)).clamp(0.0, 1.0);
+        (1.0 - t) * self.low + t * self.high
+    }
+}
+
+
+
+//Perlin turbulence: the sum of |noise| over octaves, which billows like smoke or cloud
+//with sharp creases between the billows. Blends from low at 0 to high at 1.
+pub struct Turbulence {
+    noise: Perlin,
+    scale: f64,
+    octaves: u32,
+    low: Color,
+    high: Color,
+}
+
+impl Turbulence {
+    pub fn new(scale: f64, octaves: u32, low: Color, high: Color, seed: u64) -> Turbulence {
+        Turbulence { noise: Perlin::new(seed), scale, octaves, low, high }
+    }
+}
+
+impl Texture for Turbulence {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let t = self.noise.turbulence(self.scale * p, self.octaves).clamp(0.0, 1.0);
+        (1.0 - t) * self.low + t * self.high
+    }
+}
+
+
+
+//Marble: stripes along z whose phase is pushed around by Perlin turbulence, so they
+//swirl like veins. scale sets how many stripes there are per unit.
+pub struct Marble {
+    noise: Perlin,
+    scale: f64,
+    low: Color,
+    high: Color,
+}
+
+impl Marble {
+    //How much the turbulence bends the stripes, and how many octaves it sums
+    const DISTORTION: f64 = 10.0;
+    const DEPTH: u32 = 7;
+
+    pub fn new(scale: f64, low: Color, high: Color, seed: u64) -> Marble {
+        Marble { noise: Perlin::new(seed), scale, low, high }
+    }
+}
+
+impl Texture for Marble {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let phase = self.scale * p.z() + Self::DISTORTION * self.noise.turbulence(p, Self::DEPTH);
+        let t = 0.5 * (1.0 + phase.sin());
+        (1.0 - t) * self.low + t * self.high
+    }
+}
+
+
+
+//Worley / cellular noise (see noise::Worley), scale cells per unit, blending from low
+//at distance 0 to high at 1
+pub struct Worley {
+    noise: noise::Worley,
+    scale: f64,
+    mode: WorleyMode,
+    low: Color,
+    high: Color,
+}
+
+impl Worley {
+    pub fn new(scale: f64, mode: WorleyMode, low: Color, high: Color, seed: u64) -> Worley {
+        Worley { noise: noise::Worley::new(seed), scale, mode, low, high }
+    }
+}
+
+impl Texture for Worley {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let t = self.noise.value(self.scale * p, self.mode).clamp(0.0, 1.0);
+        (1.0 - t) * self.low + t * self.high
+    }
+}
+
+
+
+//Running-bond brick pattern in UV space. Sizes are in UV units; every other row is
+//shifted by half a brick. mortar is the width of the joints, also in UV units.
+pub struct Brick {
+    brick: Color,
+    mortar: Color,
+    brick_width: f64,
+    brick_height: f64,
+    mortar_width: f64,
+}
+
+impl Brick {
+    pub fn new(brick: Color, mortar: Color, brick_width: f64, brick_height: f64, mortar_width: f64) -> Brick {
+        Brick { brick, mortar, brick_width, brick_height, mortar_width }
+    }
+}
+
+impl Texture for Brick {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        let row = (v / self.brick_height).floor();
+        let shift = if (row as i64).rem_euclid(2) == 1 { 0.5 * self.brick_width } else { 0.0 };
+
+        let x = (u + shift).rem_euclid(self.brick_width);
+        let y = v.rem_euclid(self.brick_height);
+
+        let half = 0.5 * self.mortar_width;
+        let in_mortar = x < half || x > self.brick_width - half
+            || y < half || y > self.brick_height - half;
+
+        if in_mortar {
+            self.mortar
+        } else {
+            self.brick
+        }
+    }
+}
+
+
+
+//Picture looked up by (u, v), with v = 0 at the bottom row and wrapping outside [0, 1].
+//The values are decoded from space up front, so an ordinary sRGB photo should be loaded
+//with ColorSpace::Srgb. Hits that know their footprint (see HitRecord::footprint) get a
+//trilinear lookup in a mipmap, the image halved again and again, picking the level whose
+//texels are about the size of the footprint, so a texture far away doesn't shimmer.
+//Anything else gets the nearest texel.
+pub struct ImageTexture {
+    //Full size first, down to a single texel
+    levels: Vec<Image>,
+}
+
+impl ImageTexture {
+    pub fn new(image: Image, space: ColorSpace) -> ImageTexture {
+        let decoded = Image { pixels: image.pixels.iter().map(|&c| space.to_linear(c)).collect(), ..image };
+        let mut levels = vec![decoded];
+        while let Some(next) = levels.last().and_then(halved) {
+            levels.push(next);
+        }
+        ImageTexture { levels }
+    }
+
+    //PNG or PPM, going by the extension
+    pub fn load(path: &Path, space: ColorSpace) -> io::Result<ImageTexture> {
+        Ok(ImageTexture::new(Image::read(path)?, space))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        let image = &self.levels[0];
+        let (w, h) = (image.width, image.height);
+        if w == 0 || h == 0 {
+            //Obviously wrong, so a missing image gets noticed
+            return Color::new(1.0, 0.0, 1.0);
+        }
+
+        let i = ((u.rem_euclid(1.0) * w as f64) as u64).min(w - 1);
+        let j = (((1.0 - v.rem_euclid(1.0)) * h as f64) as u64).min(h - 1);
+        image.pixels[(j * w + i) as usize]
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        let footprint = rec.uv_footprint();
+        let image = &self.levels[0];
+        if footprint == 0.0 || image.width == 0 || image.height == 0 {
+            return self.value(rec.u, rec.v, rec.p);
+        }
+        //Level 0 has texels 1 / size across, each level's are twice the last's
+        let size = image.width.max(image.height) as f64;
+        let level = (footprint * size).log2().clamp(0.0, (self.levels.len() - 1) as f64);
+        let below = level.floor() as usize;
+        let fine = bilinear(&self.levels[below], rec.u, rec.v);
+        match self.levels.get(below + 1) {
+            Some(coarse) if level > below as f64 => {
+                let t = level - below as f64;
+                (1.0 - t) * fine + t * bilinear(coarse, rec.u, rec.v)
+            }
+            _ => fine,
+        }
+    }
+}
+
+//image at half the width and height (rounding down, but at least 1), each texel the
+//mean of the ones it covers; None once it's down to one texel
+fn halved(image: &Image) -> Option<Image> {
+    let (w, h) = (image.width, image.height);
+    if w <= 1 && h <= 1 {
+        return None;
+    }
+    let (half_w, half_h) = ((w / 2).max(1), (h / 2).max(1));
+    let texel = |x: u64, y: u64| image.pixels[(y.min(h - 1) * w + x.min(w - 1)) as usize];
+    let pixels = (0..half_h).flat_map(|y| (0..half_w).map(move |x| (x, y))).map(|(x, y)| {
+        0.25 * (texel(2 * x, 2 * y) + texel(2 * x + 1, 2 * y) + texel(2 * x, 2 * y + 1) + texel(2 * x + 1, 2 * y + 1))
+    }).collect();
+    Some(Image { width: half_w, height: half_h, pixels })
+}
+
+//image at (u, v) blended from the four nearest texel centres, wrapping round the edges
+fn bilinear(image: &Image, u: f64, v: f64) -> Color {
+    let (w, h) = (image.width as i64, image.height as i64);
+    let x = u.rem_euclid(1.0) * w as f64 - 0.5;
+    let y = (1.0 - v.rem_euclid(1.0)) * h as f64 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let texel = |i: i64, j: i64| image.pixels[(j.rem_euclid(h) * w + i.rem_euclid(w)) as usize];
+    let (i, j) = (x0 as i64, y0 as i64);
+    (1.0 - fy) * ((1.0 - fx) * texel(i, j) + fx * texel(i + 1, j)) + fy * ((1.0 - fx) * texel(i, j + 1) + fx * texel(i + 1, j + 1))
+}
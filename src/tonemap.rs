@@ -0,0 +1,58 @@
+use clap::ValueEnum;
+
+use super::vec3::Color;
+
+
+
+//Post-processing from linear radiance to what's shown or saved. A tone mapping operator
+//squeezes the unbounded radiance into [0, 1], then sRGB encoding turns that into the
+//bytes image viewers expect. Everything that shows or saves a render goes through here,
+//so the terminal previews match the files.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum Tonemap {
+    //Clip anything brighter than 1, as renders always used to be
+    #[default]
+    Clamp,
+    //c / (1 + luminance): highlights roll off smoothly and keep their hue, but nothing
+    //quite reaches white
+    Reinhard,
+    //Narkowicz's fit to the ACES filmic curve, with a gentle toe and shoulder and more
+    //contrast in the midtones
+    Aces,
+}
+
+impl Tonemap {
+    //Linear radiance to linear display values in [0, 1]
+    pub fn apply(self, c: Color) -> Color {
+        let mapped = match self {
+            Tonemap::Clamp => c,
+            Tonemap::Reinhard => c / (1.0 + c.luminance().max(0.0)),
+            Tonemap::Aces => {
+                let f = |x: f64| {
+                    let x = x.max(0.0);
+                    x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)
+                };
+                Color::new(f(c[0]), f(c[1]), f(c[2]))
+            }
+        };
+        let clamp = |x: f64| x.clamp(0.0, 1.0);
+        Color::new(clamp(mapped[0]), clamp(mapped[1]), clamp(mapped[2]))
+    }
+
+    //Linear radiance to 8-bit sRGB
+    pub fn to_display(self, c: Color) -> [u8; 3] {
+        let c = self.apply(c);
+        let f = |x: f64| (255.0 * linear_to_srgb(x)).round() as u8;
+        [f(c[0]), f(c[1]), f(c[2])]
+    }
+}
+
+//Standard sRGB OETF, per channel, for x in [0, 1]. The inverse of the decoding
+//texture::ColorSpace::Srgb does.
+pub fn linear_to_srgb(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
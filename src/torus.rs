@@ -0,0 +1,161 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord};
+use super::material::Scatter;
+use super::ray::Ray;
+use super::vec3::{Point3, Vec3};
+
+
+
+//Ring doughnut around centre: a tube of radius minor_radius swept round a circle of
+//radius major_radius, lying flat (round the y axis) unless given another axis.
+//u goes round the ring and v round the tube, starting from the outside.
+pub struct Torus {
+    centre: Point3,
+    major_radius: f64,
+    minor_radius: f64,
+    //Local frame, the ring goes round axis
+    u_axis: Vec3,
+    axis: Vec3,
+    v_axis: Vec3,
+    mat: Arc<dyn Scatter>,
+}
+
+impl Torus {
+    pub fn new(centre: Point3, major_radius: f64, minor_radius: f64, mat: Arc<dyn Scatter>) -> Torus {
+        Torus {
+            centre,
+            major_radius,
+            minor_radius,
+            u_axis: Vec3::new(1.0, 0.0, 0.0),
+            axis: Vec3::new(0.0, 1.0, 0.0),
+            v_axis: Vec3::new(0.0, 0.0, 1.0),
+            mat,
+        }
+    }
+
+    //Stand the ring up round axis instead
+    pub fn with_axis(mut self, axis: Vec3) -> Torus {
+        self.axis = axis.normalized();
+        self.u_axis = self.axis.any_perpendicular();
+        self.v_axis = self.u_axis.cross(self.axis);
+        self
+    }
+
+    fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.u_axis), v.dot(self.axis), v.dot(self.v_axis))
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x() * self.u_axis + v.y() * self.axis + v.z() * self.v_axis
+    }
+}
+
+//Points p on the surface satisfy (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2), so putting
+//in the ray gives a quartic in t. It's solved along a unit direction starting from the
+//point nearest the centre, which keeps the coefficients small however far away the ray
+//comes from.
+impl Hit for Torus {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (big, small) = (self.major_radius, self.minor_radius);
+        let o = self.to_local(r.origin() - self.centre);
+        let d = self.to_local(r.direction());
+        let length = d.length();
+        let dir = d / length;
+
+        //Only the part of the ray inside the bounding sphere can hit
+        let outer = big + small;
+        let closest = -o.dot(dir);
+        let discrim = closest * closest - (o.dot(o) - outer * outer);
+        if discrim < 0.0 {
+            return None;
+        }
+        let half_chord = discrim.sqrt();
+        let lo = (t_min * length).max(closest - half_chord) - closest;
+        let hi = (t_max * length).min(closest + half_chord) - closest;
+        if lo > hi {
+            return None;
+        }
+
+        let o = o + closest * dir;
+        let b = 2.0 * o.dot(dir);
+        let e = o.dot(o) + big * big - small * small;
+        let four_r2 = 4.0 * big * big;
+        let coeffs = [
+            1.0,
+            2.0 * b,
+            b * b + 2.0 * e - four_r2 * (dir.x() * dir.x() + dir.z() * dir.z()),
+            2.0 * b * e - four_r2 * 2.0 * (o.x() * dir.x() + o.z() * dir.z()),
+            e * e - four_r2 * (o.x() * o.x() + o.z() * o.z()),
+        ];
+        let s = *real_roots(&coeffs, lo, hi).first()?;
+
+        let p = o + s * dir;
+        let phi = p.z().atan2(p.x()).rem_euclid(2.0 * PI);
+        let (cos, sin) = (phi.cos(), phi.sin());
+        //Out from the nearest point on the circle through the middle of the tube
+        let ring = Vec3::new(big * cos, 0.0, big * sin);
+        let outward = p - ring;
+        let theta = outward.y().atan2(outward.x() * cos + outward.z() * sin).rem_euclid(2.0 * PI);
+
+        let outward_normal = self.to_world(outward).normalized();
+        let t = (s + closest) / length;
+        let mut rec = HitRecord::new(r, t, outward_normal, Arc::clone(&self.mat), phi / (2.0 * PI), theta / (2.0 * PI));
+        rec.tangent = self.to_world(Vec3::new(-sin, 0.0, cos));
+        rec.bitangent = rec.tangent.cross(outward_normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        //The ring's circle, fattened by the tube all round
+        let w = self.axis;
+        let ring = |a: f64| self.major_radius * (1.0 - a * a).max(0.0).sqrt() + self.minor_radius;
+        let extent = Vec3::new(ring(w.x()), ring(w.y()), ring(w.z()));
+        Some(Aabb::new(self.centre - extent, self.centre + extent))
+    }
+}
+
+//Real roots, in increasing order, in [lo, hi] of the polynomial with these coefficients
+//(highest power first). Between one root of the derivative and the next the polynomial
+//only goes one way, so each such piece has at most one root, bracketed by a change of
+//sign, which bisection then closes in on. Slower than solving in closed form, but it
+//doesn't lose roots that are close together to rounding error. Roots where the
+//polynomial only touches zero are missed, which at worst means a grazing ray misses.
+fn real_roots(coeffs: &[f64], lo: f64, hi: f64) -> Vec<f64> {
+    let degree = coeffs.len() - 1;
+    if degree == 0 {
+        return Vec::new();
+    }
+    let derivative: Vec<f64> = coeffs[..degree].iter().enumerate().map(|(i, c)| c * (degree - i) as f64).collect();
+    let eval = |x: f64| coeffs.iter().fold(0.0, |acc, c| acc * x + c);
+
+    let mut bounds = vec![lo];
+    bounds.extend(real_roots(&derivative, lo, hi));
+    bounds.push(hi);
+
+    let mut roots = Vec::new();
+    for piece in bounds.windows(2) {
+        let (mut a, mut b) = (piece[0], piece[1]);
+        let (fa, fb) = (eval(a), eval(b));
+        if fa == 0.0 {
+            roots.push(a);
+            continue;
+        }
+        if fa.signum() == fb.signum() {
+            continue;
+        }
+        let tolerance = 1e-12 * (1.0 + lo.abs().max(hi.abs()));
+        while b - a > tolerance {
+            let mid = 0.5 * (a + b);
+            if eval(mid).signum() == fa.signum() {
+                a = mid;
+            } else {
+                b = mid;
+            }
+        }
+        roots.push(0.5 * (a + b));
+    }
+    roots
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord};
+use super::matrix::Mat4;
+use super::ray::Ray;
+use super::vec3::{Point3, Vec3};
+
+
+
+//An object placed somewhere else in the world by an affine transform. The object is
+//shared, so one mesh can be put down many times for the cost of one copy: rays are
+//taken into the object's own space to be tested, and the hit is brought back out.
+pub struct Transform {
+    object: Arc<dyn Hit>,
+    to_world: Mat4,
+    to_object: Mat4,
+    bbox: Option<Aabb>,
+}
+
+impl Transform {
+    //Panics if matrix can't be inverted, e.g. a scale of zero
+    pub fn new(object: Arc<dyn Hit>, matrix: Mat4) -> Transform {
+        let to_object = matrix.inverse().expect("transform must be invertible");
+        //Box around the corners of the object's box, wherever they end up
+        let bbox = object.bounding_box().and_then(|b| {
+            Aabb::around((0..8).map(|corner| {
+                let pick = |axis: usize| if corner & (1 << axis) == 0 { b.min[axis] } else { b.max[axis] };
+                matrix.transform_point(Point3::new(pick(0), pick(1), pick(2)))
+            }))
+        });
+        Transform { object, to_world: matrix, to_object, bbox }
+    }
+}
+
+impl Hit for Transform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        //The direction isn't renormalized, so distances along the ray are the same
+        //in both spaces
+        let local = Ray::new(self.to_object.transform_point(r.origin()), self.to_object.transform_vector(r.direction()))
+            .with_time(r.time())
+            .with_cone(r.cone());
+        let mut rec = self.object.hit(&local, t_min, t_max)?;
+
+        rec.p = self.to_world.transform_point(rec.p);
+        //Already facing the ray, and the transform doesn't change which side it's on
+        rec.normal = self.to_object.transform_normal(rec.normal).normalized();
+        let tangent = self.to_world.transform_vector(rec.tangent);
+        let bitangent = self.to_world.transform_vector(rec.bitangent);
+        //Stretched as much as the surface is along u and v
+        rec.uv_size *= (tangent.length() * bitangent.length()).sqrt();
+        rec.tangent = tangent.normalized();
+        rec.bitangent = bitangent.normalized();
+        //The cone is in world units, so measured against the world normal
+        rec.set_footprint(r);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    //Solid angles only come through rotations, translations and uniform scales unchanged,
+    //so emitters shouldn't be stretched
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.object.pdf_value(self.to_object.transform_point(origin), self.to_object.transform_vector(direction))
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        self.to_world.transform_vector(self.object.random_direction(self.to_object.transform_point(origin)))
+    }
+}
+
+//Object moved by offset
+pub struct Translate(Transform);
+
+impl Translate {
+    pub fn new(object: Arc<dyn Hit>, offset: Vec3) -> Translate {
+        Translate(Transform::new(object, Mat4::translation(offset)))
+    }
+}
+
+impl Hit for Translate {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.0.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.0.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.0.pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        self.0.random_direction(origin)
+    }
+}
+
+//Object turned about the y axis through the origin, anticlockwise seen from above
+pub struct RotateY(Transform);
+
+impl RotateY {
+    pub fn new(object: Arc<dyn Hit>, degrees: f64) -> RotateY {
+        RotateY(Transform::new(object, Mat4::rotation_y(degrees)))
+    }
+}
+
+impl Hit for RotateY {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.0.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.0.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+        self.0.pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Point3) -> Vec3 {
+        self.0.random_direction(origin)
+    }
+}
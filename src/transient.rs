@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use rayon::prelude::*;
+
+use super::image::Image;
+use super::scheduler::{CancelToken, ProgressReport};
+use super::vec3::Color;
+
+
+
+//Transient ("light in flight") rendering: instead of summing everything that reaches
+//a pixel, sort it by how far it travelled through the scene, so each frame of the
+//result shows only the light that arrived during one slice of time. This is what a
+//time-of-flight sensor sees, before it integrates.
+//Paths are timed from the camera to the last surface they hit before escaping to the
+//background, i.e. environment light is taken to arrive at the scene all at once.
+pub struct TransientSettings {
+    pub bins: usize,
+    //Path length covered by the last bin; longer paths are dropped
+    pub max_length: f64,
+}
+
+//trace(x, y, s) traces sample s for the pixel at image coords (x, y), returning its
+//radiance and path length. Returns one frame per time bin. Each frame is scaled by the
+//number of bins, so light spread evenly over the whole range looks as bright in every
+//frame as it would in an ordinary render. progress is called from the worker threads
+//as each row is finished, counting rows as the work items.
+pub fn render<F, P>(width: u64, height: u64, samples_per_pixel: u64, settings: &TransientSettings, cancel: &CancelToken, trace: F, progress: P) -> Vec<Image>
+where
+    F: Fn(u64, u64, u64) -> (Color, f64) + Sync,
+    P: Fn(&ProgressReport) + Sync,
+{
+    let bins = settings.bins.max(1);
+    let bin_length = settings.max_length / bins as f64;
+    let done = AtomicUsize::new(0);
+    let started = Instant::now();
+
+    //Row-major histograms, bins innermost
+    let rows: Vec<Vec<Color>> = (0..height).into_par_iter().map(|y| {
+        let mut histograms = vec![Color::new(0.0, 0.0, 0.0); width as usize * bins];
+        if cancel.is_cancelled() {
+            return histograms;
+        }
+
+        for x in 0..width {
+            for s in 0..samples_per_pixel {
+                let (color, length) = trace(x, y, s);
+                let bin = (length / bin_length) as usize;
+                if bin < bins {
+                    histograms[x as usize * bins + bin] += color;
+                }
+            }
+        }
+
+        let rows = done.fetch_add(1, Ordering::Relaxed) + 1;
+        progress(&ProgressReport::new(rows, height as usize, rows as u64 * width * samples_per_pixel, height * width * samples_per_pixel, started.elapsed()));
+        histograms
+    }).collect();
+
+    let scale = bins as f64 / samples_per_pixel.max(1) as f64;
+    let mut frames: Vec<Image> = (0..bins).map(|_| Image::new(width, height)).collect();
+    for (pixel, histogram) in rows.iter().flat_map(|row| row.chunks(bins)).enumerate() {
+        for (frame, &c) in frames.iter_mut().zip(histogram) {
+            frame.pixels[pixel] = scale * c;
+        }
+    }
+
+    frames
+}
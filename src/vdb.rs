@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+
+use super::aabb::Aabb;
+use super::exr::half_to_f64;
+use super::matrix::Mat4;
+use super::vec3::{Point3, Vec3};
+
+
+
+//OpenVDB files (.vdb, as Houdini and Blender write smoke and cloud simulations), read
+//far enough to get one float grid's voxels out: the tree of 4096-wide root tiles, 128-
+//and 8-wide internal nodes and 8x8x8 leaves, uncompressed, zipped or Blosc-compressed
+//(LZ4, BloscLZ or zlib inside), full or half floats. Only files from OpenVDB 3 on (file
+//format 222 and later) are read. NanoVDB files can be turned back into these with
+//nanovdb_convert.
+pub struct VdbGrid {
+    //Each leaf's voxels, x slowest, by the index coords of its first
+    leaves: HashMap<[i32; 3], Box<[f32; LEAF_VOXELS]>>,
+    //Tiles (regions of one value) as wide as a leaf, a lower and an upper internal node,
+    //by the index coords of their first voxel. Only those that aren't the background.
+    tiles: [HashMap<[i32; 3], f32>; 3],
+    background: f32,
+    to_world: Mat4,
+    to_index: Mat4,
+    //Index coords with anything other than the background in, inclusive
+    min: [i32; 3],
+    max: [i32; 3],
+    max_value: f32,
+}
+
+const LEAF_VOXELS: usize = 512;
+//log2 of the width in voxels of a leaf, and of what a lower and upper internal node's
+//children and tiles cover
+const TILE_LOG2: [u32; 3] = [3, 7, 12];
+
+//OpenVDB's magic number, as an i64
+const MAGIC: u64 = 0x5644_4220;
+//Per-grid compression and active mask compression, and the node value layouts that
+//came with them
+const FORMAT_VERSION: u32 = 222;
+
+//Compression flags
+const ZIP: u32 = 0x1;
+const ACTIVE_MASK: u32 = 0x2;
+const BLOSC: u32 = 0x4;
+
+//How the inactive values of a node were stored, see Reader::values
+const NO_MASK_AND_MINUS_BACKGROUND: u8 = 1;
+const NO_MASK_AND_ONE_INACTIVE: u8 = 2;
+const MASK_AND_NO_INACTIVE: u8 = 3;
+const MASK_AND_ONE_INACTIVE: u8 = 4;
+const MASK_AND_TWO_INACTIVE: u8 = 5;
+const NO_MASK_AND_ALL: u8 = 6;
+
+impl VdbGrid {
+    //The float grid called name in the file at path, or without a name the one called
+    //"density", or failing that the first float grid
+    pub fn load(path: &Path, name: Option<&str>) -> io::Result<VdbGrid> {
+        let data = fs::read(path)?;
+        read(&data, name).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+
+    //Box around every voxel that isn't the background, in world space
+    pub fn bounds(&self) -> Option<Aabb> {
+        if self.min[0] > self.max[0] {
+            return None;
+        }
+        //Voxels are centred on their index coords
+        let corner = |i: usize, k: usize| if k == 0 { self.min[i] as f64 - 0.5 } else { self.max[i] as f64 + 0.5 };
+        Aabb::around((0..8).map(|c| {
+            self.to_world.transform_point(Point3::new(corner(0, c & 1), corner(1, (c >> 1) & 1), corner(2, c >> 2)))
+        }))
+    }
+
+    //Largest value anywhere in the grid
+    pub fn max_value(&self) -> f64 {
+        self.max_value as f64
+    }
+
+    //Value at p in world space, interpolated between the eight nearest voxels
+    pub fn value(&self, p: Point3) -> f64 {
+        let q = self.to_index.transform_point(p);
+        let base = [q.x().floor(), q.y().floor(), q.z().floor()];
+        let f = [q.x() - base[0], q.y() - base[1], q.z() - base[2]];
+        let base = base.map(|b| b as i32);
+        let mut total = 0.0;
+        for c in 0..8 {
+            let (dx, dy, dz) = (c & 1, (c >> 1) & 1, c >> 2);
+            let weight = [dx, dy, dz].iter().zip(f).map(|(&d, f)| if d == 1 { f } else { 1.0 - f }).product::<f64>();
+            if weight > 0.0 {
+                total += weight * self.voxel([base[0] + dx, base[1] + dy, base[2] + dz]) as f64;
+            }
+        }
+        total
+    }
+
+    fn voxel(&self, ijk: [i32; 3]) -> f32 {
+        let origin = ijk.map(|i| i & !7);
+        if let Some(leaf) = self.leaves.get(&origin) {
+            let [x, y, z] = [0, 1, 2].map(|a| (ijk[a] - origin[a]) as usize);
+            return leaf[x << 6 | y << 3 | z];
+        }
+        for (tiles, log2) in self.tiles.iter().zip(TILE_LOG2) {
+            if let Some(&value) = tiles.get(&ijk.map(|i| i & !((1 << log2) - 1))) {
+                return value;
+            }
+        }
+        self.background
+    }
+
+    //A tile at level 0 (leaf-sized), 1 or 2 (a root tile)
+    fn add_tile(&mut self, level: usize, origin: [i32; 3], value: f32) {
+        if value != self.background {
+            self.cover(origin, 1 << TILE_LOG2[level], value);
+            self.tiles[level].insert(origin, value);
+        }
+    }
+
+    //Note a tile or leaf covering a cube of width from origin, whose largest value is
+    //highest
+    fn cover(&mut self, origin: [i32; 3], width: i32, highest: f32) {
+        for (a, start) in origin.into_iter().enumerate() {
+            self.min[a] = self.min[a].min(start);
+            self.max[a] = self.max[a].max(start + width - 1);
+        }
+        self.max_value = self.max_value.max(highest);
+    }
+}
+
+//The grid name asks for (see VdbGrid::load) out of a whole file
+fn read(data: &[u8], name: Option<&str>) -> Result<VdbGrid, String> {
+    let mut r = Reader { data, pos: 0, version: 0, compression: 0, background: 0.0 };
+    if r.u64()? != MAGIC {
+        return Err("not an OpenVDB file".to_string());
+    }
+    r.version = r.u32()?;
+    if r.version < FORMAT_VERSION {
+        return Err(format!("file format {} is too old (re-save it with OpenVDB 3 or later)", r.version));
+    }
+    r.bytes(8)?;
+    if r.bytes(1)?[0] == 0 {
+        return Err("files without grid offsets (written to a stream) aren't supported".to_string());
+    }
+    r.bytes(36)?;
+    r.skip_metadata()?;
+
+    //Each grid's descriptor is followed by the grid, up to its end position
+    let count = r.i32()?;
+    let offset = |pos: i64| usize::try_from(pos).ok().filter(|&pos| pos <= data.len()).ok_or_else(|| format!("grid offset {} is outside the file", pos));
+    let mut first_float = None;
+    let mut grids = Vec::new();
+    for _ in 0..count {
+        let unique_name = r.string()?;
+        let grid_name = unique_name.split('\u{1e}').next().unwrap_or("").to_string();
+        let grid_type = r.string()?;
+        let instance_of = r.string()?;
+        let (grid_pos, _block_pos, end_pos) = (r.i64()?, r.i64()?, r.i64()?);
+        let half = grid_type.ends_with("_HalfFloat");
+        let is_float = grid_type.trim_end_matches("_HalfFloat") == "Tree_float_5_4_3";
+        if is_float && first_float.is_none() {
+            first_float = Some(grids.len());
+        }
+        grids.push((grid_name, grid_type, instance_of, offset(grid_pos)?, half, is_float));
+        r.pos = offset(end_pos)?;
+    }
+
+    let chosen = match name {
+        Some(name) => grids.iter().position(|g| g.0 == name).ok_or_else(|| format!("no grid named '{}'", name))?,
+        None => grids.iter().position(|g| g.0 == "density" && g.5).or(first_float).ok_or("no float grids")?,
+    };
+    let (grid_name, grid_type, instance_of, grid_pos, half, is_float) = &grids[chosen];
+    if !is_float {
+        return Err(format!("grid '{}' is a {}, only float grids can be rendered", grid_name, grid_type));
+    }
+    if !instance_of.is_empty() {
+        return Err(format!("grid '{}' is an instance of '{}', which isn't supported", grid_name, instance_of));
+    }
+    r.pos = *grid_pos;
+    r.compression = r.u32()?;
+    r.skip_metadata()?;
+    let to_world = r.transform()?;
+    let to_index = to_world.inverse().ok_or("the grid's transform squashes it flat")?;
+    r.grid(to_world, to_index, *half)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    version: u32,
+    //Of the grid being read
+    compression: u32,
+    background: f32,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(n).ok_or("unexpected end of file")?;
+        let bytes = self.data.get(self.pos..end).ok_or("unexpected end of file")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(self.u64()? as i64)
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+
+    fn coord(&mut self) -> Result<[i32; 3], String> {
+        Ok([self.i32()?, self.i32()?, self.i32()?])
+    }
+
+    //Length first
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(length)?).into_owned())
+    }
+
+    //Bit mask of 2^(3 log2) bits, one per child or value of a node
+    fn mask(&mut self, log2: u32) -> Result<Vec<u64>, String> {
+        (0..(1 << (3 * log2)) / 64).map(|_| self.u64()).collect()
+    }
+
+    //Names, types and values, none of which are needed
+    fn skip_metadata(&mut self) -> Result<(), String> {
+        for _ in 0..self.u32()? {
+            self.string()?;
+            self.string()?;
+            let size = self.u32()? as usize;
+            self.bytes(size)?;
+        }
+        Ok(())
+    }
+
+    //From index space to world space
+    fn transform(&mut self) -> Result<Mat4, String> {
+        let kind = self.string()?;
+        match kind.as_str() {
+            "ScaleMap" | "UniformScaleMap" => {
+                let scale = self.vec3()?;
+                self.bytes(4 * 24)?;
+                Ok(Mat4::scaling(scale))
+            }
+            "ScaleTranslateMap" | "UniformScaleTranslateMap" => {
+                let translation = self.vec3()?;
+                let scale = self.vec3()?;
+                self.bytes(4 * 24)?;
+                Ok(Mat4::translation(translation) * Mat4::scaling(scale))
+            }
+            "TranslationMap" => Ok(Mat4::translation(self.vec3()?)),
+            //Row vectors, so the translation is along the bottom
+            "AffineMap" | "UnitaryMap" => {
+                let mut m = [[0.0; 4]; 4];
+                for i in 0..4 {
+                    for row in m.iter_mut() {
+                        row[i] = self.f64()?;
+                    }
+                }
+                Ok(Mat4::from_rows(m))
+            }
+            _ => Err(format!("{} transforms aren't supported", kind)),
+        }
+    }
+
+    //The tree: its shape, then the leaves' values in the same order
+    fn grid(&mut self, to_world: Mat4, to_index: Mat4, half: bool) -> Result<VdbGrid, String> {
+        if self.u32()? != 1 {
+            return Err("trees with more than one buffer aren't supported".to_string());
+        }
+        self.background = self.f32()?;
+        let mut grid = VdbGrid {
+            leaves: HashMap::new(),
+            tiles: [HashMap::new(), HashMap::new(), HashMap::new()],
+            background: self.background,
+            to_world,
+            to_index,
+            min: [i32::MAX; 3],
+            max: [i32::MIN; 3],
+            max_value: 0.0,
+        };
+
+        let (tile_count, child_count) = (self.u32()?, self.u32()?);
+        for _ in 0..tile_count {
+            let (origin, value) = (self.coord()?, self.f32()?);
+            self.bytes(1)?;
+            grid.add_tile(2, origin, value);
+        }
+        let mut leaves = Vec::new();
+        for _ in 0..child_count {
+            let origin = self.coord()?;
+            self.internal(&mut grid, origin, 1, half, &mut leaves)?;
+        }
+        for origin in leaves {
+            let mask = self.mask(3)?;
+            let mut values = Box::new([0.0; LEAF_VOXELS]);
+            self.values(&mut values[..], &mask, half)?;
+            let highest = values.iter().fold(f32::MIN, |a, &b| a.max(b));
+            grid.cover(origin, 8, highest);
+            grid.leaves.insert(origin, values);
+        }
+        Ok(grid)
+    }
+
+    //The shape of an internal node at origin, level 1 for the upper (32 children a side)
+    //and 0 the lower (16), its tiles into grid and its leaves' origins onto leaves
+    fn internal(&mut self, grid: &mut VdbGrid, origin: [i32; 3], level: usize, half: bool, leaves: &mut Vec<[i32; 3]>) -> Result<(), String> {
+        let log2 = TILE_LOG2[level + 1] - TILE_LOG2[level];
+        let children = self.mask(log2)?;
+        let active = self.mask(log2)?;
+        let mut values = vec![0.0; 1 << (3 * log2)];
+        self.values(&mut values, &active, half)?;
+
+        let side = (1 << log2) - 1;
+        let at = |n: usize| {
+            let local = [n >> (2 * log2), (n >> log2) & side, n & side];
+            [0, 1, 2].map(|a| origin[a] + ((local[a] as i32) << TILE_LOG2[level]))
+        };
+        for (n, &value) in values.iter().enumerate() {
+            if !is_on(&children, n) {
+                grid.add_tile(level, at(n), value);
+            }
+        }
+        for n in (0..values.len()).filter(|&n| is_on(&children, n)) {
+            match level {
+                0 => {
+                    //Only the mask, which the values come with again
+                    self.mask(3)?;
+                    leaves.push(at(n));
+                }
+                _ => self.internal(grid, at(n), level - 1, half, leaves)?,
+            }
+        }
+        Ok(())
+    }
+
+    //A node's values into out. Inactive ones may have been left out, to be filled in
+    //from the background or up to two values given before them, with a mask picking
+    //which where there are two.
+    fn values(&mut self, out: &mut [f32], active: &[u64], half: bool) -> Result<(), String> {
+        let metadata = self.bytes(1)?[0];
+        let (mut inactive0, mut inactive1) = (self.background, self.background);
+        if matches!(metadata, NO_MASK_AND_MINUS_BACKGROUND | MASK_AND_NO_INACTIVE) {
+            inactive0 = -self.background;
+        }
+        if matches!(metadata, NO_MASK_AND_ONE_INACTIVE | MASK_AND_ONE_INACTIVE | MASK_AND_TWO_INACTIVE) {
+            inactive0 = self.f32()?;
+            if metadata == MASK_AND_TWO_INACTIVE {
+                inactive1 = self.f32()?;
+            }
+        }
+        let selection = match metadata {
+            MASK_AND_NO_INACTIVE | MASK_AND_ONE_INACTIVE | MASK_AND_TWO_INACTIVE => Some(self.mask(out.len().ilog2() / 3)?),
+            _ => None,
+        };
+
+        let packed = self.compression & ACTIVE_MASK != 0 && metadata != NO_MASK_AND_ALL;
+        let count = if packed { (0..out.len()).filter(|&n| is_on(active, n)).count() } else { out.len() };
+        let size = if half { 2 } else { 4 };
+        let raw = self.block(count * size)?;
+        let value = |k: usize| match half {
+            true => half_to_f64(u16::from_le_bytes([raw[2 * k], raw[2 * k + 1]])) as f32,
+            false => f32::from_le_bytes(raw[4 * k..4 * k + 4].try_into().unwrap()),
+        };
+        let mut k = 0;
+        for (n, slot) in out.iter_mut().enumerate() {
+            *slot = if !packed || is_on(active, n) {
+                k += 1;
+                value(k - 1)
+            } else if selection.as_ref().is_some_and(|s| is_on(s, n)) {
+                inactive1
+            } else {
+                inactive0
+            };
+        }
+        Ok(())
+    }
+
+    //size bytes of values, compressed as the grid says. Both zip and Blosc give the
+    //compressed size first, negative for data stored as it is.
+    fn block(&mut self, size: usize) -> Result<Vec<u8>, String> {
+        if self.compression & (ZIP | BLOSC) == 0 {
+            return Ok(self.bytes(size)?.to_vec());
+        }
+        let stored = self.i64()?;
+        if stored <= 0 {
+            return Ok(self.bytes(stored.unsigned_abs() as usize)?.to_vec());
+        }
+        let packed = self.bytes(stored as usize)?;
+        //Blosc pads short buffers out to 48 bytes before compressing them
+        let mut out = if self.compression & BLOSC != 0 {
+            unblosc(packed, size.max(48))?
+        } else {
+            //One byte more than it should hold is enough to tell it's wrong
+            let mut out = Vec::with_capacity(size);
+            ZlibDecoder::new(packed).take(size as u64 + 1).read_to_end(&mut out).map_err(|e| e.to_string())?;
+            out
+        };
+        if out.len() > size && self.compression & BLOSC != 0 {
+            out.truncate(size);
+        }
+        if out.len() != size {
+            return Err(format!("expected {} bytes of values, found {}", size, out.len()));
+        }
+        Ok(out)
+    }
+}
+
+fn is_on(mask: &[u64], n: usize) -> bool {
+    mask[n / 64] >> (n % 64) & 1 == 1
+}
+
+//Blosc flags
+const SHUFFLE: u8 = 0x1;
+const MEMCPYED: u8 = 0x2;
+const BIT_SHUFFLE: u8 = 0x4;
+const DONT_SPLIT: u8 = 0x10;
+
+//A Blosc (version 1) buffer: a 16 byte header, then blocks each compressed on its own,
+//in one piece or one per byte of the type, after their bytes were shuffled so the first
+//bytes of every value come first, then the second and so on. A buffer that says it holds
+//more than most bytes is refused before anything is allocated for it.
+fn unblosc(packed: &[u8], most: usize) -> Result<Vec<u8>, String> {
+    let header = packed.get(..16).ok_or("truncated Blosc header")?;
+    let (flags, typesize) = (header[2], header[3] as usize);
+    let word = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap()) as usize;
+    let (size, block_size) = (word(4), word(8));
+    if size > most {
+        return Err(format!("Blosc buffer of {} bytes where at most {} were expected", size, most));
+    }
+    if flags & MEMCPYED != 0 {
+        return Ok(packed.get(16..16 + size).ok_or("truncated Blosc data")?.to_vec());
+    }
+    if flags & BIT_SHUFFLE != 0 {
+        return Err("bit-shuffled Blosc data isn't supported".to_string());
+    }
+    if block_size == 0 || typesize == 0 {
+        return Err("bad Blosc header".to_string());
+    }
+    let codec = flags >> 5;
+
+    let mut out = Vec::with_capacity(size);
+    let blocks = size.div_ceil(block_size);
+    for b in 0..blocks {
+        let start = packed.get(16 + 4 * b..20 + 4 * b).ok_or("truncated Blosc data")?;
+        let mut pos = u32::from_le_bytes(start.try_into().unwrap()) as usize;
+        let this_size = block_size.min(size - b * block_size);
+        let leftover = this_size < block_size;
+        let splits = if flags & DONT_SPLIT == 0 && !leftover && typesize <= 16 && block_size / typesize >= 128 { typesize } else { 1 };
+
+        let mut block = Vec::with_capacity(this_size);
+        for _ in 0..splits {
+            let split_size = this_size / splits;
+            let length = packed.get(pos..pos + 4).ok_or("truncated Blosc data")?;
+            let length = i32::from_le_bytes(length.try_into().unwrap()) as usize;
+            let data = packed.get(pos + 4..pos + 4 + length).ok_or("truncated Blosc data")?;
+            pos += 4 + length;
+            if length == split_size {
+                block.extend_from_slice(data);
+                continue;
+            }
+            let part = match codec {
+                0 => unblosclz(data, split_size)?,
+                1 => unlz4(data, split_size)?,
+                3 => {
+                    let mut part = Vec::with_capacity(split_size);
+                    ZlibDecoder::new(data).read_to_end(&mut part).map_err(|e| e.to_string())?;
+                    part
+                }
+                _ => return Err(format!("Blosc codec {} isn't supported (only BloscLZ, LZ4 and zlib)", codec)),
+            };
+            if part.len() != split_size {
+                return Err("Blosc block decompressed to the wrong size".to_string());
+            }
+            block.extend(part);
+        }
+
+        if flags & SHUFFLE != 0 && typesize > 1 {
+            let values = this_size / typesize;
+            for i in 0..values {
+                out.extend((0..typesize).map(|j| block[j * values + i]));
+            }
+            out.extend_from_slice(&block[values * typesize..]);
+        } else {
+            out.extend(block);
+        }
+    }
+    Ok(out)
+}
+
+//An LZ4 block: runs of literal bytes, each but the last followed by a copy of earlier
+//output
+fn unlz4(data: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    let truncated = || "truncated LZ4 block".to_string();
+    let mut out: Vec<u8> = Vec::with_capacity(size);
+    let mut i = 0;
+    //A length of 15 goes on in the following bytes, as long as they're 255
+    let extend = |i: &mut usize, mut length: usize| -> Result<usize, String> {
+        if length == 15 {
+            loop {
+                let b = *data.get(*i).ok_or_else(truncated)?;
+                *i += 1;
+                length += b as usize;
+                if b != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    };
+    loop {
+        let token = *data.get(i).ok_or_else(truncated)?;
+        i += 1;
+        let literals = extend(&mut i, (token >> 4) as usize)?;
+        out.extend_from_slice(data.get(i..i + literals).ok_or_else(truncated)?);
+        i += literals;
+        if i >= data.len() {
+            return Ok(out);
+        }
+        let offset = u16::from_le_bytes([data[i], *data.get(i + 1).ok_or_else(truncated)?]) as usize;
+        i += 2;
+        let length = extend(&mut i, (token & 15) as usize)? + 4;
+        copy_back(&mut out, offset, length)?;
+    }
+}
+
+//A BloscLZ block (a FastLZ variant): control bytes below 32 are a count of literals to
+//follow, less one; above, copies of earlier output, the top three bits giving the length
+//and the bottom five with the next byte the distance
+fn unblosclz(data: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    const MAX_DISTANCE: usize = 8191;
+    let truncated = || "truncated BloscLZ block".to_string();
+    let mut out: Vec<u8> = Vec::with_capacity(size);
+    let mut i = 1;
+    let mut ctrl = (*data.first().ok_or_else(truncated)? & 31) as usize;
+    loop {
+        if ctrl >= 32 {
+            let mut length = (ctrl >> 5) - 1;
+            let mut distance = (ctrl & 31) << 8;
+            if length == 6 {
+                loop {
+                    let b = *data.get(i).ok_or_else(truncated)?;
+                    i += 1;
+                    length += b as usize;
+                    if b != 255 {
+                        break;
+                    }
+                }
+            }
+            let code = *data.get(i).ok_or_else(truncated)? as usize;
+            i += 1;
+            distance += code;
+            if code == 255 && distance == (31 << 8) + 255 {
+                let far = data.get(i..i + 2).ok_or_else(truncated)?;
+                distance = ((far[0] as usize) << 8 | far[1] as usize) + MAX_DISTANCE;
+                i += 2;
+            }
+            copy_back(&mut out, distance + 1, length + 3)?;
+        } else {
+            let count = ctrl + 1;
+            out.extend_from_slice(data.get(i..i + count).ok_or_else(truncated)?);
+            i += count;
+        }
+        if i >= data.len() {
+            return Ok(out);
+        }
+        ctrl = data[i] as usize;
+        i += 1;
+    }
+}
+
+//Append length bytes copied from distance back in out, a byte at a time since they may
+//overlap what's being appended
+fn copy_back(out: &mut Vec<u8>, distance: usize, length: usize) -> Result<(), String> {
+    if distance == 0 || distance > out.len() {
+        return Err("compressed data refers back past its start".to_string());
+    }
+    let start = out.len() - distance;
+    for k in 0..length {
+        out.push(out[start + k]);
+    }
+    Ok(())
+}
@@ -3,7 +3,8 @@ use std::ops::Range;
 use std::fmt;
 use std::fmt::Display;
 
-use rand::Rng;
+use super::random::random_range;
+use super::simd::F64x4;
 
 #[derive(Clone, Copy)]
 pub struct Vec3{
@@ -20,22 +21,9 @@ impl Vec3{
         }
     }
 
-    //We sqrt as a gamma correction, specifically gamma-2, where all colors are raised
-    //to power of 1/gamma, in this case 1/2.
-    //Most image viewers assume some form of gamma correction, where the 0 to 1 values
-    //have some transform before being stored as a byte.
-    pub fn format_color(self, samples_per_pixel: u64) -> String {
-        let ir = (256.0 * (self[0] / (samples_per_pixel as f64)).sqrt().clamp(0.0, 0.999)) as u64;
-        let ig = (256.0 * (self[1] / (samples_per_pixel as f64)).sqrt().clamp(0.0, 0.999)) as u64;
-        let ib = (256.0 * (self[2] / (samples_per_pixel as f64)).sqrt().clamp(0.0, 0.999)) as u64;
-    
-        format!("{} {} {}", ir, ig, ib)
-    }
-
     pub fn random(r: Range<f64>) -> Vec3 {
-        let mut rng = rand::thread_rng();
         Vec3 {
-            e: [rng.gen_range(r.clone()), rng.gen_range(r.clone()), rng.gen_range(r.clone())],
+            e: [random_range(r.clone()), random_range(r.clone()), random_range(r)],
         }
     }
 
@@ -60,7 +48,7 @@ impl Vec3{
 
     pub fn near_zero(&self) -> bool{
         const EPS: f64 = 1.0e-8;
-        (self[0].abs() < EPS) && (self[1] < EPS) && (self[2] < EPS)
+        (self[0].abs() < EPS) && (self[1].abs() < EPS) && (self[2].abs() < EPS)
     }
 
     pub fn reflect(self, normal: Vec3) -> Vec3 {
@@ -75,9 +63,8 @@ impl Vec3{
     }
 
     pub fn random_in_unit_disk() -> Vec3 {
-        let mut rng = rand::thread_rng();
         loop {
-            let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
+            let p = Vec3::new(random_range(-1.0..1.0), random_range(-1.0..1.0), 0.0);
             if p.length() < 1.0 {
                 return p;
             }
@@ -119,6 +106,22 @@ impl Vec3{
         self / self.length()
     }
 
+    //Some unit vector perpendicular to self (assumed normalized).
+    //Crosses with whichever axis is least aligned so the result never degenerates.
+    pub fn any_perpendicular(self) -> Vec3 {
+        let axis = if self[0].abs() < 0.9 {
+            Vec3::new(1.0, 0.0, 0.0)
+        } else {
+            Vec3::new(0.0, 1.0, 0.0)
+        };
+        axis.cross(self).normalized()
+    }
+
+    //Brightness of a linear (Rec. 709) colour
+    pub fn luminance(self) -> f64 {
+        0.2126 * self[0] + 0.7152 * self[1] + 0.0722 * self[2]
+    }
+
 }
 
 impl Display for Vec3 {
@@ -152,7 +155,7 @@ impl Add for Vec3 {
 }
 
 impl AddAssign for Vec3 {
-    fn add_assign(&mut self, other: Vec3) -> () {
+    fn add_assign(&mut self, other: Vec3) {
         *self = Vec3 {
             e: [self[0] + other[0], self[1] + other[1], self[2] + other[2]]
         };
@@ -170,7 +173,7 @@ impl Sub for Vec3 {
 }
 
 impl SubAssign for Vec3 {
-    fn sub_assign(&mut self, other: Vec3) -> () {
+    fn sub_assign(&mut self, other: Vec3) {
         *self = Vec3 {
             e: [self[0] - other[0], self[1] - other[1], self[2] - other[2]]
         };
@@ -188,7 +191,7 @@ impl Mul<f64> for Vec3 {
 }
 
 impl MulAssign<f64> for Vec3 {
-    fn mul_assign(&mut self, other: f64) -> () {
+    fn mul_assign(&mut self, other: f64) {
         *self = Vec3 {
             e: [self[0] * other, self[1] * other, self[2] * other]
         };
@@ -216,7 +219,7 @@ impl Mul<Vec3> for Vec3 {
 }
 
 impl MulAssign<Vec3> for Vec3 {
-    fn mul_assign(&mut self, other: Vec3) -> () {
+    fn mul_assign(&mut self, other: Vec3) {
         *self = Vec3 {
             e: [self[0] * other[0], self[1] * other[1], self[2] * other[2]]
         };
@@ -234,9 +237,65 @@ impl Div<f64> for Vec3 {
 }
 
 impl DivAssign<f64> for Vec3 {
-    fn div_assign(&mut self, other: f64) -> () {
+    fn div_assign(&mut self, other: f64) {
         *self = Vec3 {
             e: [self[0] / other, self[1] / other, self[2] / other]
         };
     }
-}
\ No newline at end of file
+}
+
+//Four vectors at once, each component a lane per vector, for rays traced in packets.
+//The maths is Vec3's, operation for operation, so each lane comes out the same.
+#[derive(Clone, Copy)]
+pub struct Vec3x4 {
+    e: [F64x4; 3],
+}
+
+impl Vec3x4 {
+    pub fn new(v: [Vec3; 4]) -> Vec3x4 {
+        Vec3x4 {
+            e: std::array::from_fn(|axis| F64x4::load(&v.map(|v| v[axis])))
+        }
+    }
+
+    //v in every lane
+    pub fn splat(v: Vec3) -> Vec3x4 {
+        Vec3x4 {
+            e: [F64x4::splat(v[0]), F64x4::splat(v[1]), F64x4::splat(v[2])]
+        }
+    }
+
+    pub fn dot(self, other: Vec3x4) -> F64x4 {
+        self[0] * other[0] + self[1] * other[1] + self[2] * other[2]
+    }
+
+    pub fn length(self) -> F64x4 {
+        self.dot(self).sqrt()
+    }
+
+    //1 / each component, as for slab tests
+    pub fn recip(self) -> Vec3x4 {
+        let one = F64x4::splat(1.0);
+        Vec3x4 {
+            e: self.e.map(|x| one / x)
+        }
+    }
+}
+
+impl Index<usize> for Vec3x4 {
+    type Output = F64x4;
+
+    fn index(&self, i: usize) -> &F64x4 {
+        &self.e[i]
+    }
+}
+
+impl Sub for Vec3x4 {
+    type Output = Vec3x4;
+
+    fn sub(self, other: Vec3x4) -> Vec3x4 {
+        Vec3x4 {
+            e: [self[0] - other[0], self[1] - other[1], self[2] - other[2]]
+        }
+    }
+}
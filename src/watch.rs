@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use raytracer::scene_file;
+
+
+
+//How often the files are checked for changes
+const POLL: Duration = Duration::from_millis(250);
+
+//Render, then render again every time scene_file (or anything it includes) changes, at
+//samples per pixel. Each render is a run of this program with the same command line
+//minus --watch, so it writes to the same output, and one that fails (say, from a
+//half-written scene file) is reported and waited out. Runs until killed.
+pub fn run(scene_file: &Path, samples: u64) -> ! {
+    let exe = std::env::current_exe().unwrap_or_else(|e| {
+        eprintln!("Couldn't find this program to rerun it: {}", e);
+        std::process::exit(2);
+    });
+    let mut args = without_watch(std::env::args_os().skip(1));
+    args.extend(["--samples".into(), samples.to_string().into()]);
+
+    //Kept from the last time the scene could be read, for while it can't
+    let mut files = vec![scene_file.to_path_buf()];
+    loop {
+        let started = Instant::now();
+        match Command::new(&exe).args(&args).status() {
+            Ok(status) if status.success() => eprintln!("\nRendered in {:.1}s", started.elapsed().as_secs_f64()),
+            Ok(_) => eprintln!("\nRender failed"),
+            Err(e) => eprintln!("\nCouldn't start the render: {}", e),
+        }
+
+        files = scene_file::scene_files(scene_file).unwrap_or(files);
+        eprintln!("Watching {} for changes (Ctrl-C to stop)", scene_file.display());
+        let seen = modified(&files);
+        while modified(&files) == seen {
+            thread::sleep(POLL);
+        }
+        //Editors can take a moment to finish writing
+        thread::sleep(POLL);
+    }
+}
+
+//When each file was last changed, or None if it can't be read right now (say, while an
+//editor replaces it)
+fn modified(files: &[PathBuf]) -> HashMap<&PathBuf, Option<SystemTime>> {
+    files.iter().map(|path| (path, fs::metadata(path).and_then(|m| m.modified()).ok())).collect()
+}
+
+//The command line with --watch and -s/--samples, and their values, taken out, as the
+//samples are given again for each render
+fn without_watch(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut kept = Vec::new();
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        let text = arg.to_str().unwrap_or("");
+        if text == "--watch" {
+            //Only a number can have been its value
+            if args.peek().and_then(|next| next.to_str()).is_some_and(|next| next.parse::<u64>().is_ok()) {
+                args.next();
+            }
+        } else if text == "-s" || text == "--samples" {
+            args.next();
+        } else if !(text.starts_with("--watch=") || text.starts_with("--samples=") || (text.starts_with("-s") && !text.starts_with("--"))) {
+            kept.push(arg);
+        }
+    }
+    kept
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::without_watch;
+
+    fn stripped(args: &str) -> String {
+        let kept = without_watch(args.split(' ').map(Into::into));
+        kept.iter().map(|a| a.to_str().unwrap()).collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn takes_out_watch_and_samples() {
+        assert_eq!(stripped("--scene-file a.toml --watch 4 -o w.png"), "--scene-file a.toml -o w.png");
+        assert_eq!(stripped("--watch -o w.png"), "-o w.png");
+        assert_eq!(stripped("--watch=4 -s 2 --width 16"), "--width 16");
+        assert_eq!(stripped("--samples 2 --watch --samples=3 -s4 -o w.png"), "-o w.png");
+    }
+
+    #[test]
+    fn keeps_everything_else() {
+        assert_eq!(stripped("--scene spheres --seed 3 --width 16"), "--scene spheres --seed 3 --width 16");
+    }
+}
@@ -0,0 +1,87 @@
+use raytracer::scheduler::CancelToken;
+use raytracer::tonemap::Tonemap;
+use raytracer::vec3::Color;
+
+#[cfg(feature = "window")]
+use minifb::{Key, KeyRepeat, Scale, WindowOptions};
+
+
+
+//Live view of a render in a desktop window. Esc or Q (or closing the window) stops the
+//render, S saves the image as it stands. minifb wants to be driven from the thread that
+//made the window, so the render has to run somewhere else and the window refreshed
+//from a loop on this one.
+#[cfg(feature = "window")]
+pub struct Window {
+    window: minifb::Window,
+    buffer: Vec<u32>,
+    width: usize,
+    height: usize,
+    tonemap: Tonemap,
+    cancel: CancelToken,
+}
+
+#[cfg(feature = "window")]
+impl Window {
+    pub fn new(width: u64, height: u64, tonemap: Tonemap, cancel: CancelToken) -> Result<Window, String> {
+        let (width, height) = (width as usize, height as usize);
+        //Double up small images so there's something to look at
+        let scale = if width <= 640 && height <= 480 { Scale::X2 } else { Scale::X1 };
+        let options = WindowOptions { scale, ..WindowOptions::default() };
+        let mut window = minifb::Window::new("parhelia (Esc/Q: stop, S: save)", width, height, options)
+            .map_err(|e| e.to_string())?;
+        window.set_target_fps(30);
+        Ok(Window { window, buffer: vec![0; width * height], width, height, tonemap, cancel })
+    }
+
+    //Show framebuffer (mean colours, row-major from the top) and deal with any keys
+    //pressed since the last refresh. Blocks for long enough to hold the frame rate down.
+    pub fn refresh(&mut self, framebuffer: &[Color], save: impl Fn(&[Color])) {
+        if !self.window.is_open() {
+            return;
+        }
+        if self.show(framebuffer, save) {
+            self.cancel.cancel();
+        }
+    }
+
+    //Keep the finished image up until the window is closed
+    pub fn hold(mut self, framebuffer: &[Color], save: impl Fn(&[Color])) {
+        while self.window.is_open() && !self.show(framebuffer, &save) {}
+    }
+
+    //Draw a frame, returning whether the user asked to stop
+    fn show(&mut self, framebuffer: &[Color], save: impl Fn(&[Color])) -> bool {
+        for (pixel, &c) in self.buffer.iter_mut().zip(framebuffer) {
+            let [r, g, b] = self.tonemap.to_display(c);
+            *pixel = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        }
+        if let Err(e) = self.window.update_with_buffer(&self.buffer, self.width, self.height) {
+            eprintln!("Couldn't update the window: {}", e);
+            return false;
+        }
+
+        if self.window.is_key_pressed(Key::S, KeyRepeat::No) {
+            save(framebuffer);
+        }
+        !self.window.is_open()
+            || self.window.is_key_pressed(Key::Escape, KeyRepeat::No)
+            || self.window.is_key_pressed(Key::Q, KeyRepeat::No)
+    }
+}
+
+//Stand-in for builds without the window feature, so --window can say what's wrong
+//instead of not being there
+#[cfg(not(feature = "window"))]
+pub struct Window;
+
+#[cfg(not(feature = "window"))]
+impl Window {
+    pub fn new(_width: u64, _height: u64, _tonemap: Tonemap, _cancel: CancelToken) -> Result<Window, String> {
+        Err("this build doesn't include the window feature (rebuild with --features window)".to_string())
+    }
+
+    pub fn refresh(&mut self, _framebuffer: &[Color], _save: impl Fn(&[Color])) {}
+
+    pub fn hold(self, _framebuffer: &[Color], _save: impl Fn(&[Color])) {}
+}
@@ -0,0 +1,81 @@
+use raytracer::exr;
+use raytracer::vec3::Color;
+
+
+
+//Crafted files must be refused with an error, not a panic or an allocation the size of
+//whatever the header claims
+
+//A header with one half-float Y channel, no compression and the given data window
+fn header(window: [i32; 4]) -> Vec<u8> {
+    let mut data = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        data.extend(name.bytes().chain([0]));
+        data.extend(kind.bytes().chain([0]));
+        data.extend((value.len() as i32).to_le_bytes());
+        data.extend(value);
+    };
+    let mut channels = b"Y\0".to_vec();
+    channels.extend(1i32.to_le_bytes());
+    channels.extend([0; 4]);
+    channels.extend([1i32.to_le_bytes(), 1i32.to_le_bytes()].concat());
+    channels.push(0);
+    attribute("channels", "chlist", &channels);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>());
+    data.push(0);
+    data
+}
+
+fn written(width: u64, height: u64) -> Vec<u8> {
+    let pixels: Vec<Color> = (0..width * height).map(|i| Color::new(i as f64, 0.5, 0.25)).collect();
+    let mut data = Vec::new();
+    exr::write(&mut data, width, height, &pixels, None, false).unwrap();
+    data
+}
+
+#[test]
+fn round_trip() {
+    let image = exr::decode(&written(5, 3)).unwrap();
+    assert_eq!((image.width, image.height), (5, 3));
+    let p = image.pixels[7];
+    assert_eq!([p.x(), p.y(), p.z()], [7.0, 0.5, 0.25]);
+}
+
+#[test]
+fn truncated() {
+    let data = written(5, 3);
+    for len in [0, 3, 20, data.len() / 2, data.len() - 1] {
+        assert!(exr::decode(&data[..len]).is_err(), "{} bytes", len);
+    }
+}
+
+#[test]
+fn window_overflowing_i32() {
+    assert!(exr::decode(&header([i32::MIN, 0, i32::MAX, 0])).is_err());
+}
+
+#[test]
+fn window_bigger_than_file() {
+    assert!(exr::decode(&header([0, 0, i32::MAX - 1, 0])).is_err());
+    assert!(exr::decode(&header([0, 0, 65535, 65535])).is_err());
+}
+
+#[test]
+fn negative_block_size() {
+    let mut data = header([0, 0, 0, 0]);
+    data.extend(0u64.to_le_bytes());
+    data.extend(0i32.to_le_bytes());
+    data.extend((-8i32).to_le_bytes());
+    data.extend([0; 16]);
+    assert!(exr::decode(&data).is_err());
+}
+
+#[test]
+fn negative_attribute_size() {
+    let mut data = header([0, 0, 0, 0]);
+    data.pop();
+    data.extend(b"junk\0int\0");
+    data.extend((-1i32).to_le_bytes());
+    assert!(exr::decode(&data).is_err());
+}
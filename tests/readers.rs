@@ -0,0 +1,245 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use raytracer::checkpoint::Checkpoint;
+use raytracer::hit::Hit;
+use raytracer::image::Image;
+use raytracer::material::Lambertian;
+use raytracer::obj;
+use raytracer::renderer::RenderSettings;
+use raytracer::scheduler::{Scheduler, TileAccum};
+use raytracer::vdb::VdbGrid;
+use raytracer::vec3::{Color, Point3};
+
+
+
+//The file readers: what's written comes back, and files cut short or claiming to be
+//bigger than they are are refused with an error. EXR has its own, in exr.rs.
+
+//A file of its own in the temporary directory, for each test
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("parhelia_{}_{}", std::process::id(), name))
+}
+
+fn gradient(width: u64, height: u64) -> Image {
+    let pixels = (0..width * height).map(|i| Color::new(i as f64 / (width * height) as f64, 0.5, 1.0)).collect();
+    Image { width, height, pixels }
+}
+
+fn close(a: Color, b: Color, tolerance: f64) -> bool {
+    (0..3).all(|c| (a[c] - b[c]).abs() <= tolerance)
+}
+
+//The file at path cut to each of a few lengths short of its whole
+fn truncations(path: &PathBuf) -> Vec<Vec<u8>> {
+    let data = fs::read(path).unwrap();
+    [0, 2, data.len() / 3, data.len() - 1].iter().map(|&len| data[..len].to_vec()).collect()
+}
+
+#[test]
+fn hdr_round_trip() {
+    let path = temp("round_trip.hdr");
+    //Wide enough to be run-length encoded
+    let image = gradient(20, 3);
+    image.write_hdr(&path).unwrap();
+    let read = Image::read_hdr(&path).unwrap();
+    assert_eq!((read.width, read.height), (20, 3));
+    assert!(image.pixels.iter().zip(&read.pixels).all(|(&a, &b)| close(a, b, 1.0 / 128.0)));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn hdr_bad() {
+    let path = temp("bad.hdr");
+    gradient(20, 3).write_hdr(&path).unwrap();
+    for data in truncations(&path) {
+        fs::write(&path, data).unwrap();
+        assert!(Image::read_hdr(&path).is_err());
+    }
+    fs::write(&path, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 100000 +X 100000\n\x02\x02").unwrap();
+    assert!(Image::read_hdr(&path).is_err());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn ppm_round_trip() {
+    let path = temp("round_trip.ppm");
+    let image = gradient(5, 4);
+    image.write_ppm(&path).unwrap();
+    let read = Image::read_ppm(&path).unwrap();
+    assert_eq!((read.width, read.height), (5, 4));
+    assert!(image.pixels.iter().zip(&read.pixels).all(|(&a, &b)| close(a, b, 0.5 / 255.0)));
+    fs::write(&path, "P3\n2 1\n4\n0 1 2 3 4 4\n").unwrap();
+    let read = Image::read_ppm(&path).unwrap();
+    assert!(close(read.pixels[1], Color::new(0.75, 1.0, 1.0), 1.0e-12));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn ppm_bad() {
+    let path = temp("bad.ppm");
+    gradient(5, 4).write_ppm(&path).unwrap();
+    for data in truncations(&path) {
+        fs::write(&path, data).unwrap();
+        assert!(Image::read_ppm(&path).is_err());
+    }
+    for text in ["P6\n4294967295 4294967295\n255\n", "P6\n1 1\n0\n\0\0\0", "P5\n1 1\n255\n\0"] {
+        fs::write(&path, text).unwrap();
+        assert!(Image::read_ppm(&path).is_err(), "{:?}", text);
+    }
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn obj() {
+    let path = temp("quad.obj");
+    //A quad, with a relative index, made of two triangles
+    fs::write(&path, "v 0 0 0\nv 2 0 0\nv 2 1 0\nv 0 1 -3\nvn 0 0 1\nf 1//1 2//1 3//1 -1//1\n").unwrap();
+    let mesh = obj::load_obj(&path, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))).unwrap();
+    let bbox = mesh.bounding_box().unwrap();
+    assert!(close(bbox.min, Point3::new(0.0, 0.0, -3.0), 1.0e-3) && close(bbox.max, Point3::new(2.0, 1.0, 0.0), 1.0e-3));
+    for text in ["v 0 0 0\nf 1 2 3\n", "v 0 0\n", "v 0 0 0\nv 1 0 0\nf 1 2\n"] {
+        fs::write(&path, text).unwrap();
+        assert!(obj::load_obj(&path, Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))).is_err(), "{:?}", text);
+    }
+    fs::remove_file(path).unwrap();
+}
+
+//A float grid called density, a tenth of a unit to a voxel, compressed as compression
+//says, with tree after its background of 0
+fn vdb(compression: u32, tree: &[u8]) -> Vec<u8> {
+    let string = |data: &mut Vec<u8>, s: &str| {
+        data.extend((s.len() as u32).to_le_bytes());
+        data.extend(s.as_bytes());
+    };
+    let mut data = 0x5644_4220u64.to_le_bytes().to_vec();
+    data.extend(224u32.to_le_bytes());
+    data.extend([0; 8]);
+    //Has grid offsets, then the UUID, then no file metadata
+    data.push(1);
+    data.extend([b'0'; 36]);
+    data.extend(0u32.to_le_bytes());
+    data.extend(1i32.to_le_bytes());
+    string(&mut data, "density");
+    string(&mut data, "Tree_float_5_4_3");
+    string(&mut data, "");
+    let grid_pos = data.len() + 24;
+
+    let mut grid = compression.to_le_bytes().to_vec();
+    grid.extend(0u32.to_le_bytes());
+    string(&mut grid, "ScaleMap");
+    grid.extend([0.1f64; 3].iter().flat_map(|v| v.to_le_bytes()));
+    grid.extend([0; 4 * 24]);
+    //One buffer
+    grid.extend(1u32.to_le_bytes());
+    grid.extend(0f32.to_le_bytes());
+    grid.extend(tree);
+
+    for pos in [grid_pos, 0, grid_pos + grid.len()] {
+        data.extend((pos as i64).to_le_bytes());
+    }
+    data.extend(grid);
+    data
+}
+
+//value over one root tile from the origin, and nothing anywhere else
+fn tile(value: f32) -> Vec<u8> {
+    let mut tree = 1u32.to_le_bytes().to_vec();
+    tree.extend(0u32.to_le_bytes());
+    tree.extend([0i32; 3].iter().flat_map(|v| v.to_le_bytes()));
+    tree.extend(value.to_le_bytes());
+    tree.push(1);
+    tree
+}
+
+#[test]
+fn vdb_tile() {
+    let path = temp("tile.vdb");
+    fs::write(&path, vdb(0, &tile(0.5))).unwrap();
+    let grid = VdbGrid::load(&path, None).unwrap();
+    assert_eq!(grid.max_value(), 0.5);
+    assert!((grid.value(Point3::new(1.0, 2.0, 3.0)) - 0.5).abs() < 1.0e-6);
+    assert_eq!(grid.value(Point3::new(-1.0, 2.0, 3.0)), 0.0);
+    assert!(VdbGrid::load(&path, Some("temperature")).is_err());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn vdb_bad() {
+    let path = temp("bad.vdb");
+    fs::write(&path, vdb(0, &tile(0.5))).unwrap();
+    for data in truncations(&path) {
+        fs::write(&path, data).unwrap();
+        assert!(VdbGrid::load(&path, None).is_err());
+    }
+    //The grid said to be far past the end of the file, or before its start. Its
+    //position follows the type and the empty instance name.
+    let data = vdb(0, &tile(0.5));
+    let at = data.windows(16).position(|w| w == b"Tree_float_5_4_3").unwrap() + 16 + 4;
+    for pos in [i64::MAX, -1] {
+        let mut data = data.clone();
+        data[at..at + 8].copy_from_slice(&pos.to_le_bytes());
+        fs::write(&path, data).unwrap();
+        assert!(VdbGrid::load(&path, None).is_err_and(|e| e.to_string().contains("outside the file")));
+    }
+    fs::remove_file(path).unwrap();
+}
+
+//An upper internal node of tiles, their values Blosc-compressed in a buffer claiming
+//to be 4GiB
+#[test]
+fn vdb_blosc_too_big() {
+    let path = temp("blosc.vdb");
+    let mut tree = 0u32.to_le_bytes().to_vec();
+    tree.extend(1u32.to_le_bytes());
+    tree.extend([0i32; 3].iter().flat_map(|v| v.to_le_bytes()));
+    //No children, none active, then all the values
+    tree.extend(vec![0; 2 * 32768 / 8]);
+    tree.push(6);
+    tree.extend(16i64.to_le_bytes());
+    tree.extend([2, 1, 0, 4]);
+    tree.extend(u32::MAX.to_le_bytes());
+    tree.extend([0; 8]);
+    fs::write(&path, vdb(4, &tree)).unwrap();
+    assert!(VdbGrid::load(&path, None).is_err_and(|e| e.to_string().contains("at most")));
+    fs::remove_file(path).unwrap();
+}
+
+fn checkpoint() -> (RenderSettings, Checkpoint) {
+    let settings = RenderSettings { width: 20, height: 10, tile_size: 16, seed: Some(3), ..RenderSettings::default() };
+    let scheduler = Scheduler::new(settings.width, settings.height, settings.tile_size, 1, 1);
+    let tiles = scheduler.tiles().iter().enumerate().map(|(k, tile)| {
+        let mut accum = TileAccum::new(tile);
+        accum.sum.iter_mut().for_each(|c| *c = Color::new(k as f64, 1.0, 2.0));
+        accum.counts.iter_mut().for_each(|n| *n = 1);
+        accum.samples = 1;
+        accum
+    }).collect();
+    let checkpoint = Checkpoint::new(&settings, tiles);
+    (settings, checkpoint)
+}
+
+#[test]
+fn checkpoint_round_trip() {
+    let path = temp("round_trip.checkpoint");
+    let (settings, saved) = checkpoint();
+    saved.save(&path).unwrap();
+    let loaded = Checkpoint::load(&path).unwrap();
+    loaded.check(&settings).unwrap();
+    assert_eq!((loaded.seed, loaded.samples()), (Some(3), (1, 1)));
+    let image = loaded.image();
+    assert!(close(image.pixels[19], Color::new(1.0, 1.0, 2.0), 1.0e-12));
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn checkpoint_bad() {
+    let path = temp("bad.checkpoint");
+    checkpoint().1.save(&path).unwrap();
+    for data in truncations(&path) {
+        fs::write(&path, data).unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+    }
+    fs::remove_file(path).unwrap();
+}
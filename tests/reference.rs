@@ -0,0 +1,130 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use raytracer::bvh;
+use raytracer::diff::{check_reference, Threshold};
+use raytracer::gallery::{self, SceneName};
+use raytracer::image::Image;
+use raytracer::integrator::{IntegratorKind, Tracer};
+use raytracer::photon_map::PhotonMap;
+use raytracer::random::reseed;
+use raytracer::renderer::{RenderSettings, Renderer};
+use raytracer::restir::Resampling;
+use raytracer::Scene;
+
+
+
+//Golden-image tests: each renders a small scene with a fixed seed and checks it against
+//tests/references. After a change meant to alter renders, run them with PARHELIA_BLESS
+//set to write new references, and look them over before committing them.
+//
+//There's one for each integrator (and the photon map and ReSTIR on top of the default
+//one), on the Cornell box unless it needs something else to show, then one for each
+//family of materials, on the default integrator.
+
+fn settings(integrator: IntegratorKind) -> RenderSettings {
+    RenderSettings {
+        width: 24,
+        height: 24,
+        samples_per_pixel: 4,
+        max_depth: 8,
+        seed: Some(7),
+        integrator: Arc::new(Tracer::new(integrator)),
+        ..RenderSettings::default()
+    }
+}
+
+fn scene(name: SceneName, settings: &RenderSettings) -> Scene {
+    reseed(settings.seed.unwrap());
+    let mut scene = gallery::build(name, settings.aspect_ratio());
+    scene.flatten();
+    scene.world = bvh::accelerate(std::mem::take(&mut scene.world));
+    scene
+}
+
+fn render(name: SceneName, settings: RenderSettings) -> Image {
+    let scene = scene(name, &settings);
+    Renderer::new(settings).render(&scene)
+}
+
+fn check(image: &Image, name: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/references").join(name);
+    if let Err(e) = check_reference(image, &path, &Threshold::new()) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn cornell_box() {
+    check(&render(SceneName::CornellBox, settings(IntegratorKind::Hybrid)), "cornell_box.exr");
+}
+
+//Only what finds the ceiling panel by chance, so more samples
+#[test]
+fn path() {
+    let settings = RenderSettings { samples_per_pixel: 16, ..settings(IntegratorKind::Path) };
+    check(&render(SceneName::CornellBox, settings), "path.exr");
+}
+
+//Light paths start from lights, not emitters, so the open box with its point light
+#[test]
+fn bidirectional() {
+    check(&render(SceneName::Cornell, settings(IntegratorKind::Bidirectional)), "bidirectional.exr");
+}
+
+#[test]
+fn whitted() {
+    check(&render(SceneName::Cornell, settings(IntegratorKind::Whitted)), "whitted.exr");
+}
+
+#[test]
+fn direct() {
+    check(&render(SceneName::Cornell, settings(IntegratorKind::Direct)), "direct.exr");
+}
+
+//Caustics under the glass ball come from the photons
+#[test]
+fn photon_map() {
+    let mut settings = settings(IntegratorKind::Hybrid);
+    let scene = scene(SceneName::GlassCaustic, &settings);
+    settings.photons = Some(Arc::new(PhotonMap::new(&scene, &settings, 20000)));
+    check(&Renderer::new(settings).render(&scene), "photon_map.exr");
+}
+
+//Many point lights, for the resampling to choose between
+#[test]
+fn restir() {
+    let mut settings = settings(IntegratorKind::Hybrid);
+    settings.resampling = Some(Arc::new(Resampling::new(8)));
+    check(&render(SceneName::ManyLights, settings), "restir.exr");
+}
+
+//Lambertian walls, a mirror and a glass ball
+#[test]
+fn diffuse_metal_glass() {
+    check(&render(SceneName::Cornell, settings(IntegratorKind::Hybrid)), "diffuse_metal_glass.exr");
+}
+
+//Phong spheres
+#[test]
+fn phong() {
+    check(&render(SceneName::ManyLights, settings(IntegratorKind::Hybrid)), "phong.exr");
+}
+
+//Image, noise and checker textures
+#[test]
+fn textures() {
+    check(&render(SceneName::Textures, settings(IntegratorKind::Hybrid)), "textures.exr");
+}
+
+//Emitters as the only light
+#[test]
+fn emissive() {
+    check(&render(SceneName::Glow, settings(IntegratorKind::Hybrid)), "emissive.exr");
+}
+
+//Participating media
+#[test]
+fn smoke() {
+    check(&render(SceneName::CornellSmoke, settings(IntegratorKind::Hybrid)), "smoke.exr");
+}